@@ -0,0 +1,194 @@
+//! Drives the player over MPRIS like a desktop shell would, checking that it
+//! answers PlayPause, Seek and SetPosition and signals what they changed.
+//!
+//! Run it against a player on a bus of its own, playing a song of more than
+//! a minute:
+//!
+//!     dbus-run-session -- sh -c \
+//!         'blue-music song.flac & sleep 2; cargo run --example mpris_check'
+//!
+//! Each check is printed as it passes or fails, and the exit status tells
+//! whether they all passed.
+
+use std::collections::HashMap;
+use std::process;
+use std::time::{Duration, Instant};
+
+use dbus::arg::{RefArg, Variant};
+use dbus::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::{BusType, Connection, Message, Path, SignalArgs};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.blue_music";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// How long calls and signals are waited for, in milliseconds.
+const TIMEOUT: u32 = 2000;
+
+/// Positions signalled may be a little past the one asked for, as the song
+/// plays on meanwhile, in microseconds.
+const SLACK: i64 = 1_000_000;
+
+type PropertyMap = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+fn main() {
+    let connection = Connection::get_private(BusType::Session).expect("Cannot reach the bus");
+    let rule = format!("type='signal',sender='{}',path='{}'", BUS_NAME, OBJECT_PATH);
+    connection.add_match(&rule).expect("Cannot watch signals");
+    let mut checker = Checker {
+        connection,
+        failed: false,
+    };
+    checker.run();
+    if checker.failed {
+        process::exit(1);
+    }
+}
+
+struct Checker {
+    connection: Connection,
+    failed: bool,
+}
+
+impl Checker {
+    fn run(&mut self) {
+        let metadata: PropertyMap = match self.get("Metadata") {
+            Some(metadata) => metadata,
+            None => return self.fail("the player answers", "no Metadata"),
+        };
+        let track = metadata
+            .get("mpris:trackid")
+            .and_then(|id| id.0.as_str().map(str::to_string));
+        let track = match track {
+            Some(track) => track,
+            None => return self.fail("a song is playing", "no mpris:trackid"),
+        };
+        println!("Playing {}", track);
+
+        let mut status: String = self.get("PlaybackStatus").unwrap_or_default();
+        // Twice, to leave the song playing or paused as it was.
+        for _ in 0..2 {
+            self.call("PlayPause", |message| message);
+            match self.wait_changed("PlaybackStatus") {
+                Some(changed) if changed != status => {
+                    self.pass("PlayPause", &format!("PlaybackStatus {}", changed));
+                    status = changed;
+                }
+                Some(changed) => self.fail("PlayPause", &format!("still {}", changed)),
+                None => self.fail("PlayPause", "no PlaybackStatus change signalled"),
+            }
+            self.sync();
+        }
+
+        let before: i64 = self.get("Position").unwrap_or_default();
+        self.call("Seek", |message| message.append1(5_000_000_i64));
+        self.check_seeked("Seek", before + 5_000_000);
+
+        let path = Path::from(track.clone());
+        self.call("SetPosition", |message| {
+            message.append2(path, 10_000_000_i64)
+        });
+        self.check_seeked("SetPosition", 10_000_000);
+
+        // Positions meant for another song are ignored.
+        let stale = Path::from(format!("{}/stale", track));
+        self.call("SetPosition", |message| message.append2(stale, 0_i64));
+        match self.wait_seeked() {
+            None => self.pass("SetPosition of another song", "ignored"),
+            Some(position) => self.fail(
+                "SetPosition of another song",
+                &format!("seeked to {}", position),
+            ),
+        }
+    }
+
+    /// Reads a property of the player interface.
+    fn get<T: for<'b> dbus::arg::Get<'b>>(&self, name: &str) -> Option<T> {
+        self.connection
+            .with_path(BUS_NAME, OBJECT_PATH, TIMEOUT as i32)
+            .get(PLAYER_INTERFACE, name)
+            .map_err(|error| eprintln!("Unable to read {}: {}", name, error))
+            .ok()
+    }
+
+    /// Calls a method of the player interface, with the arguments `append`
+    /// adds.
+    fn call(&mut self, method: &str, append: impl FnOnce(Message) -> Message) {
+        let message = Message::new_method_call(BUS_NAME, OBJECT_PATH, PLAYER_INTERFACE, method)
+            .expect("Invalid method call");
+        if let Err(error) = self
+            .connection
+            .send_with_reply_and_block(append(message), TIMEOUT as i32)
+        {
+            self.fail(method, &format!("call failed: {}", error));
+        }
+    }
+
+    fn check_seeked(&mut self, method: &str, expected: i64) {
+        match self.wait_seeked() {
+            Some(position) if position >= expected && position <= expected + SLACK => {
+                self.pass(method, &format!("Seeked to {}", position))
+            }
+            Some(position) => self.fail(
+                method,
+                &format!("Seeked to {} rather than {}", position, expected),
+            ),
+            None => self.fail(method, "no Seeked signal"),
+        }
+        self.sync();
+    }
+
+    /// Waits for the `Seeked` signal, returning the position it tells.
+    fn wait_seeked(&self) -> Option<i64> {
+        self.wait(|message| {
+            let seeked = message.interface().as_deref() == Some(PLAYER_INTERFACE)
+                && message.member().as_deref() == Some("Seeked");
+            if seeked {
+                message.get1()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Waits for `PropertiesChanged` to tell the new value of the string
+    /// property `name`.
+    fn wait_changed(&self, name: &str) -> Option<String> {
+        self.wait(|message| {
+            let changed = PropertiesPropertiesChanged::from_message(message)?;
+            if changed.interface_name != PLAYER_INTERFACE {
+                return None;
+            }
+            let value = changed.changed_properties.get(name)?;
+            value.0.as_str().map(str::to_string)
+        })
+    }
+
+    /// Goes through the signals received until `matches` picks one, or until
+    /// none came for a while.
+    fn wait<T>(&self, mut matches: impl FnMut(&Message) -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + Duration::from_millis(u64::from(TIMEOUT));
+        while Instant::now() < deadline {
+            for message in self.connection.incoming(100) {
+                if let Some(found) = matches(&message) {
+                    return Some(found);
+                }
+            }
+        }
+        None
+    }
+
+    /// Drops the signals left, so that the next check only sees its own.
+    fn sync(&self) {
+        for _ in self.connection.incoming(500) {}
+    }
+
+    fn pass(&self, check: &str, detail: &str) {
+        println!("ok   {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, detail: &str) {
+        println!("FAIL {}: {}", check, detail);
+        self.failed = true;
+    }
+}
@@ -0,0 +1,81 @@
+//! A stand-in for the ListenBrainz API, printing the listens it is sent, to
+//! try scrobbling without an account.
+//!
+//!     cargo run --example scrobble_server -- 8080 [--unavailable]
+//!
+//! Then set the scrobbling server to `http://localhost:8080`, with any token.
+//! With `--unavailable` every submission fails, so that listens pile up in
+//! the queue until the server is started again without it.
+
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const DEFAULT_PORT: &str = "8080";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let unavailable = args.iter().any(|arg| arg == "--unavailable");
+    let port = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map_or(DEFAULT_PORT, String::as_str);
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).expect("Cannot listen");
+    println!("Listening on http://localhost:{}", port);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(error) = serve(stream, unavailable) {
+                    eprintln!("Unable to answer: {}", error);
+                }
+            }
+            Err(error) => eprintln!("Unable to accept: {}", error),
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, unavailable: bool) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut length = 0;
+    let mut authorization = String::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.to_lowercase().as_str() {
+                "content-length" => length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorization = value.trim().to_string(),
+                _ => (),
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    println!("{} ({})", request.trim_end(), authorization);
+    println!("{}\n", String::from_utf8_lossy(&body));
+    let (status, answer) = if unavailable {
+        (
+            "503 Service Unavailable",
+            "{\"code\":503,\"error\":\"Unavailable\"}",
+        )
+    } else if !authorization.starts_with("Token ") {
+        ("401 Unauthorized", "{\"code\":401,\"error\":\"No token\"}")
+    } else {
+        ("200 OK", "{\"status\":\"ok\"}")
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        status,
+        answer.len(),
+        answer
+    )
+}
@@ -0,0 +1,285 @@
+use gdk_pixbuf::{Colorspace, InterpType, Pixbuf, PixbufLoader, PixbufLoaderExt};
+use metaflac::block::{Block, BlockType, Picture, PictureType};
+use metaflac::Tag;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::cue;
+use crate::library::{Track, EMBEDDED_ARTWORK};
+use crate::tags;
+
+const INTERP_HYPER: InterpType = InterpType::Hyper;
+const IMAGE_SIZE: i32 = 256;
+const THUMBNAIL_SIZE: i32 = 64;
+
+/// Folder of the cache directory holding scaled artwork, so that it is not
+/// decoded from large images every time it is shown.
+const CACHE_DIR: &str = "covers";
+
+/// Returns the key artwork is cached by: the album for embedded artwork, which
+/// is rarely different between its songs, and the file otherwise.
+pub fn key(track: &Track) -> Option<String> {
+    let reference = track.artwork.as_ref()?;
+    if reference == EMBEDDED_ARTWORK {
+        Some(format!("{}\n{}", track.album_artist(), track.album()))
+    } else {
+        Some(reference.clone())
+    }
+}
+
+/// Returns the picture shown for a song: its front cover, or any picture when
+/// it has none.
+pub fn cover(tag: &Tag) -> Option<&Picture> {
+    let pictures = tag.pictures();
+    pictures
+        .iter()
+        .find(|picture| picture.picture_type == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .cloned()
+}
+
+/// Returns whether `tag` has a front cover.
+pub fn has_front_cover(tag: &Tag) -> bool {
+    tag.pictures()
+        .iter()
+        .any(|picture| picture.picture_type == PictureType::CoverFront)
+}
+
+/// Loads the artwork of a song as a thumbnail and a full size image.
+pub fn load(track: &Track) -> Option<(Pixbuf, Pixbuf)> {
+    let reference = track.artwork.as_ref()?;
+    // Virtual tracks show the artwork of their file.
+    let file = cue::file_path(&track.path);
+    let source = if reference == EMBEDDED_ARTWORK {
+        file.as_path()
+    } else {
+        Path::new(reference)
+    };
+    let cached = cache_path(source);
+    let pixbuf = match cached
+        .as_ref()
+        .and_then(|cached| Pixbuf::new_from_file(cached).ok())
+    {
+        Some(pixbuf) => pixbuf,
+        None => {
+            let pixbuf = if reference == EMBEDDED_ARTWORK {
+                Tag::read_from_path(&file)
+                    .ok()
+                    .and_then(|tag| cover(&tag).map(|picture| picture.data.clone()))
+                    .and_then(|data| load_pixbuf(&data))
+            } else {
+                Pixbuf::new_from_file_at_size(reference, IMAGE_SIZE, IMAGE_SIZE).ok()
+            }?;
+            if let Some(cached) = cached {
+                if let Err(error) = pixbuf.savev(&cached, "png", &[]) {
+                    eprintln!("Unable to cache artwork of {}: {}", source.display(), error);
+                }
+            }
+            pixbuf
+        }
+    };
+    let thumbnail = pixbuf.scale_simple(THUMBNAIL_SIZE, THUMBNAIL_SIZE, INTERP_HYPER)?;
+    Some((thumbnail, pixbuf))
+}
+
+/// Returns an image file showing the artwork of a song: the image next to it,
+/// or the cached copy of its embedded artwork once it has been loaded.
+pub fn image_file(track: &Track) -> Option<PathBuf> {
+    let reference = track.artwork.as_ref()?;
+    let file = if reference == EMBEDDED_ARTWORK {
+        cache_path(&cue::file_path(&track.path))?
+    } else {
+        PathBuf::from(reference)
+    };
+    Some(file).filter(|file| file.is_file())
+}
+
+/// Returns the file caching the scaled artwork read from `source`.
+fn cache_path(source: &Path) -> Option<PathBuf> {
+    let name = cache_name(source)?;
+    let dir = config::cache_dir().join(CACHE_DIR);
+    if let Err(error) = fs::create_dir_all(&dir) {
+        eprintln!("Unable to create {}: {}", dir.display(), error);
+        return None;
+    }
+    Some(dir.join(name))
+}
+
+/// Names the cached artwork of `source` after its path and modification time,
+/// so that artwork is decoded again once its source changes.
+fn cache_name(source: &Path) -> Option<String> {
+    let mtime = fs::metadata(source)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let stamp = format!("{}\n{:?}", source.display(), mtime);
+    Some(format!("{:016x}.png", fnv_hash(stamp.as_bytes())))
+}
+
+/// A hash which, unlike the one of the standard library, is guaranteed to
+/// stay the same between releases, as cache files are named after it.
+fn fnv_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Embeds the image at `image` as the front cover of the FLAC file at `path`,
+/// replacing the one it has.
+pub fn embed(path: &Path, image: &Path) -> metaflac::Result<()> {
+    let data = fs::read(image)?;
+    let (mime_type, width, height) = match Pixbuf::get_file_info(image) {
+        Some((format, width, height)) => {
+            let mime_type = format
+                .get_mime_types()
+                .first()
+                .map(|mime_type| mime_type.to_string())
+                .unwrap_or_default();
+            (mime_type, width as u32, height as u32)
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not an image", image.display()),
+            )
+            .into())
+        }
+    };
+    tags::modify(path, move |tag| {
+        tag.remove_picture_type(PictureType::CoverFront);
+        let mut picture = Picture::new();
+        picture.picture_type = PictureType::CoverFront;
+        picture.mime_type = mime_type;
+        picture.width = width;
+        picture.height = height;
+        picture.data = data;
+        tag.push_block(Block::Picture(picture));
+    })
+}
+
+/// Removes every picture embedded in the FLAC file at `path`.
+pub fn remove(path: &Path) -> metaflac::Result<()> {
+    tags::modify(path, |tag| tag.remove_blocks(BlockType::Picture))
+}
+
+/// Writes the cover embedded in the FLAC file at `path` to `destination`.
+pub fn extract(path: &Path, destination: &Path) -> metaflac::Result<()> {
+    let tag = Tag::read_from_path(path)?;
+    let picture = cover(&tag).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no embedded cover", path.display()),
+        )
+    })?;
+    fs::write(destination, &picture.data)?;
+    Ok(())
+}
+
+/// Returns the file extension matching the type of the cover embedded in the
+/// FLAC file at `path`.
+pub fn extension(path: &Path) -> Option<&'static str> {
+    let tag = Tag::read_from_path(path).ok()?;
+    match cover(&tag)?.mime_type.as_str() {
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/bmp" => Some("bmp"),
+        _ => Some("jpg"),
+    }
+}
+
+fn load_pixbuf(data: &[u8]) -> Option<Pixbuf> {
+    let pixbuf_loader = PixbufLoader::new();
+    pixbuf_loader.set_size(IMAGE_SIZE, IMAGE_SIZE);
+    pixbuf_loader.write(data).ok()?;
+    let pixbuf = pixbuf_loader.get_pixbuf();
+    pixbuf_loader.close().ok()?;
+    pixbuf
+}
+
+/// The pixels of an image decoded on a worker thread. Pixbufs cannot be sent
+/// between threads, so they are copied out and rebuilt on the main thread.
+pub struct Pixels {
+    data: Vec<u8>,
+    has_alpha: bool,
+    width: i32,
+    height: i32,
+    rowstride: i32,
+}
+
+impl Pixels {
+    pub fn new(pixbuf: &Pixbuf) -> Option<Pixels> {
+        let data = pixbuf.read_pixel_bytes()?.to_vec();
+        Some(Pixels {
+            data,
+            has_alpha: pixbuf.get_has_alpha(),
+            width: pixbuf.get_width(),
+            height: pixbuf.get_height(),
+            rowstride: pixbuf.get_rowstride(),
+        })
+    }
+
+    pub fn into_pixbuf(self) -> Pixbuf {
+        Pixbuf::new_from_mut_slice(
+            self.data,
+            Colorspace::Rgb,
+            self.has_alpha,
+            8,
+            self.width,
+            self.height,
+            self.rowstride,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn song(path: &str, album: &str, artwork: Option<&str>) -> Track {
+        let mut track = Track::unread(Path::new(path));
+        track.tags.album = Some(album.to_string());
+        track.artwork = artwork.map(str::to_string);
+        track
+    }
+
+    #[test]
+    fn embedded_artwork_is_shared_by_an_album() {
+        let first = song("/music/a.flac", "Kind of Blue", Some(EMBEDDED_ARTWORK));
+        let second = song("/music/b.flac", "Kind of Blue", Some(EMBEDDED_ARTWORK));
+        let other = song("/music/c.flac", "Milestones", Some(EMBEDDED_ARTWORK));
+        assert_eq!(key(&first), key(&second));
+        assert_ne!(key(&first), key(&other));
+
+        let external = song("/music/d.flac", "Kind of Blue", Some("/music/cover.jpg"));
+        assert_eq!(key(&external), Some("/music/cover.jpg".to_string()));
+        assert_eq!(key(&song("/music/e.flac", "Kind of Blue", None)), None);
+    }
+
+    #[test]
+    fn cached_artwork_is_renamed_when_its_source_changes() {
+        let dir = std::env::temp_dir().join(format!("blue-music-artwork-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("cover.jpg");
+        let file = File::create(&source).unwrap();
+        let name = cache_name(&source).unwrap();
+        assert_eq!(cache_name(&source), Some(name.clone()));
+        let other = dir.join("folder.jpg");
+        File::create(&other).unwrap();
+        assert_ne!(cache_name(&other), Some(name.clone()));
+
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert_ne!(cache_name(&source), Some(name));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(cache_name(&source), None);
+    }
+
+    #[test]
+    fn cache_names_hash_the_same_between_releases() {
+        assert_eq!(fnv_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
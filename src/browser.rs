@@ -0,0 +1,514 @@
+use crate::artwork::{self, Pixels};
+use crate::library::{Album, Library, Track};
+use crate::playlist::format_duration;
+use gdk::ModifierType;
+use gdk_pixbuf::{InterpType, Pixbuf};
+use gtk::Orientation::{Horizontal, Vertical};
+use gtk::{
+    BoxExt, ButtonExt, CellLayoutExt, CellRendererText, GtkListStoreExt, GtkListStoreExtManual,
+    IconViewExt, ListStore, OrientableExt, StaticType, ToValue, ToggleButtonExt, TreeModelExt,
+    TreePath, TreeView, TreeViewColumn, TreeViewColumnExt, TreeViewExt, Type, WidgetExt,
+};
+use relm::{Channel, Relm, Widget};
+use relm_derive::widget;
+use std::collections::{HashMap, HashSet};
+use std::thread;
+
+use self::Msg::*;
+
+const ALBUM_KEY_COLUMN: u32 = 0;
+const ALBUM_COVER_COLUMN: u32 = 1;
+const ALBUM_TITLE_COLUMN: u32 = 2;
+const ALBUM_ARTIST_COLUMN: u32 = 3;
+const ALBUM_YEAR_COLUMN: u32 = 4;
+const ALBUM_TRACKS_COLUMN: u32 = 5;
+const ALBUM_MARKUP_COLUMN: u32 = 6;
+
+const TRACK_NUMBER_COLUMN: u32 = 0;
+const TRACK_TITLE_COLUMN: u32 = 1;
+const TRACK_ARTIST_COLUMN: u32 = 2;
+const TRACK_DURATION_COLUMN: u32 = 3;
+
+/// Size of the covers shown in the album grid.
+const COVER_SIZE: i32 = 128;
+
+#[derive(Msg)]
+pub enum Msg {
+    AlbumActivated(TreePath),
+    AlbumSelected,
+    ArtistSelected,
+    CoverLoaded(String, Option<Pixels>),
+    EnqueueAlbum,
+    GenreSelected,
+    PlayAlbum,
+    Refresh,
+    ShowGrid(bool),
+    TrackActivated(TreePath),
+
+    // Listened by Win
+    Enqueue(Vec<Track>),
+    PlayTracks(Vec<Track>),
+}
+
+pub struct Model {
+    album: Option<usize>,
+    album_store: ListStore,
+    albums: Vec<Album>,
+    artist: Option<String>,
+    artist_store: ListStore,
+    artists: Vec<String>,
+    covers: HashMap<String, Option<Pixbuf>>,
+    covers_sender: relm::Sender<(String, Option<Pixels>)>,
+    genre: Option<String>,
+    genre_store: ListStore,
+    genres: Vec<String>,
+    grid: bool,
+    library: Library,
+    relm: Relm<Browser>,
+    requested_covers: HashSet<String>,
+    track_store: ListStore,
+    tracks: Vec<Track>,
+}
+
+#[widget]
+impl Widget for Browser {
+    fn model(relm: &Relm<Self>, _: ()) -> Model {
+        let stream = relm.stream().clone();
+        let (_channel, covers_sender) = Channel::new(move |(key, pixels)| {
+            stream.emit(CoverLoaded(key, pixels));
+        });
+        Model {
+            album: None,
+            album_store: ListStore::new(&[
+                Type::String,
+                Pixbuf::static_type(),
+                Type::String,
+                Type::String,
+                Type::String,
+                Type::String,
+                Type::String,
+            ]),
+            albums: Vec::new(),
+            artist: None,
+            artist_store: ListStore::new(&[Type::String]),
+            artists: Vec::new(),
+            covers: HashMap::new(),
+            covers_sender,
+            genre: None,
+            genre_store: ListStore::new(&[Type::String]),
+            genres: Vec::new(),
+            grid: false,
+            library: Library::open(),
+            relm: relm.clone(),
+            requested_covers: HashSet::new(),
+            track_store: ListStore::new(&[Type::String, Type::String, Type::String, Type::String]),
+            tracks: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, event: Msg) {
+        match event {
+            AlbumActivated(path) => {
+                if let Some(album) =
+                    path_index(&path).and_then(|index| self.model.albums.get(index))
+                {
+                    let tracks = self
+                        .model
+                        .library
+                        .album_tracks(album.id, self.model.genre.as_deref());
+                    self.activate(tracks);
+                }
+            }
+            AlbumSelected => self.select_album(),
+            ArtistSelected => {
+                let artist = self.selected_name(&self.artist_list, &self.model.artists);
+                if let Some(artist) = artist {
+                    if artist != self.model.artist {
+                        self.model.artist = artist;
+                        self.load_albums();
+                    }
+                }
+            }
+            CoverLoaded(key, pixels) => self.set_cover(key, pixels.map(Pixels::into_pixbuf)),
+            EnqueueAlbum => {
+                let tracks = self.model.tracks.clone();
+                self.model.relm.stream().emit(Enqueue(tracks));
+            }
+            GenreSelected => {
+                let genre = self.selected_name(&self.genre_list, &self.model.genres);
+                if let Some(genre) = genre {
+                    if genre != self.model.genre {
+                        self.model.genre = genre;
+                        self.load_artists();
+                        self.load_albums();
+                    }
+                }
+            }
+            PlayAlbum => {
+                let tracks = self.model.tracks.clone();
+                self.model.relm.stream().emit(PlayTracks(tracks));
+            }
+            Refresh => {
+                // Covers may have changed since they were loaded.
+                self.model.covers.clear();
+                self.model.requested_covers.clear();
+                self.load_genres();
+                self.load_artists();
+                self.load_albums();
+            }
+            ShowGrid(grid) => {
+                self.model.grid = grid;
+                self.show_selected_album();
+            }
+            TrackActivated(path) => {
+                if let Some(track) =
+                    path_index(&path).and_then(|index| self.model.tracks.get(index))
+                {
+                    let tracks = vec![track.clone()];
+                    self.model.relm.stream().emit(Enqueue(tracks));
+                }
+            }
+            Enqueue(_) | PlayTracks(_) => (),
+        }
+    }
+
+    fn init_view(&mut self) {
+        add_text_column(&self.genre_list, "Genre", 0);
+        add_text_column(&self.artist_list, "Album artist", 0);
+        add_text_column(&self.album_list, "Album", ALBUM_TITLE_COLUMN);
+        add_text_column(&self.album_list, "Artist", ALBUM_ARTIST_COLUMN);
+        add_text_column(&self.album_list, "Year", ALBUM_YEAR_COLUMN);
+        add_text_column(&self.album_list, "Tracks", ALBUM_TRACKS_COLUMN);
+        add_text_column(&self.track_list, "Track", TRACK_NUMBER_COLUMN);
+        add_text_column(&self.track_list, "Title", TRACK_TITLE_COLUMN);
+        add_text_column(&self.track_list, "Artist", TRACK_ARTIST_COLUMN);
+        add_text_column(&self.track_list, "Duration", TRACK_DURATION_COLUMN);
+
+        self.album_grid.set_pixbuf_column(ALBUM_COVER_COLUMN as i32);
+        self.album_grid
+            .set_markup_column(ALBUM_MARKUP_COLUMN as i32);
+        self.album_grid.set_item_width(COVER_SIZE);
+    }
+
+    view! {
+        gtk::Box {
+            orientation: Vertical,
+            spacing: 6,
+            gtk::Box {
+                orientation: Horizontal,
+                spacing: 6,
+                margin_start: 6,
+                margin_top: 6,
+                gtk::ToggleButton {
+                    label: "Album grid",
+                    toggled(button) => ShowGrid(button.get_active()),
+                },
+                gtk::Button {
+                    label: "Play album",
+                    tooltip_text: "Replace the playlist with the selected album",
+                    clicked => PlayAlbum,
+                },
+                gtk::Button {
+                    label: "Append album",
+                    tooltip_text: "Append the selected album to the playlist",
+                    clicked => EnqueueAlbum,
+                },
+            },
+            gtk::Box {
+                orientation: Horizontal,
+                spacing: 6,
+                vexpand: true,
+                gtk::ScrolledWindow {
+                    property_width_request: 160,
+                    #[name="genre_list"]
+                    gtk::TreeView {
+                        model: &self.model.genre_store,
+                        cursor_changed(_) => GenreSelected,
+                    },
+                },
+                gtk::ScrolledWindow {
+                    property_width_request: 200,
+                    #[name="artist_list"]
+                    gtk::TreeView {
+                        model: &self.model.artist_store,
+                        cursor_changed(_) => ArtistSelected,
+                    },
+                },
+                gtk::ScrolledWindow {
+                    hexpand: true,
+                    visible: !self.model.grid,
+                    #[name="album_list"]
+                    gtk::TreeView {
+                        model: &self.model.album_store,
+                        cursor_changed(_) => AlbumSelected,
+                        row_activated(_, path, _) => AlbumActivated(path.clone()),
+                    },
+                },
+                gtk::ScrolledWindow {
+                    hexpand: true,
+                    visible: self.model.grid,
+                    #[name="album_grid"]
+                    gtk::IconView {
+                        model: &self.model.album_store,
+                        selection_changed(_) => AlbumSelected,
+                        item_activated(_, path) => AlbumActivated(path.clone()),
+                    },
+                },
+            },
+            gtk::ScrolledWindow {
+                vexpand: true,
+                #[name="track_list"]
+                gtk::TreeView {
+                    model: &self.model.track_store,
+                    row_activated(_, path, _) => TrackActivated(path.clone()),
+                },
+            },
+        }
+    }
+}
+
+impl Browser {
+    /// Replaces the playlist with `tracks` on double click, or appends them
+    /// when Control is held.
+    fn activate(&self, tracks: Vec<Track>) {
+        let append = gtk::get_current_event_state()
+            .is_some_and(|state| state.contains(ModifierType::CONTROL_MASK));
+        let msg = if append {
+            Enqueue(tracks)
+        } else {
+            PlayTracks(tracks)
+        };
+        self.model.relm.stream().emit(msg);
+    }
+
+    /// Returns the name under the cursor of a pane, `Some(None)` standing for
+    /// its first row which matches every name, or `None` without a cursor.
+    fn selected_name(&self, view: &TreeView, names: &[String]) -> Option<Option<String>> {
+        let (path, _) = view.get_cursor();
+        match path_index(&path?)? {
+            0 => Some(None),
+            index => names.get(index - 1).cloned().map(Some),
+        }
+    }
+
+    fn load_genres(&mut self) {
+        self.model.genres = self.model.library.genres();
+        if !self
+            .model
+            .genres
+            .iter()
+            .any(|genre| Some(genre) == self.model.genre.as_ref())
+        {
+            self.model.genre = None;
+        }
+        fill_pane(
+            &self.genre_list,
+            &self.model.genre_store,
+            "All genres",
+            &self.model.genres,
+            self.model.genre.as_ref(),
+        );
+    }
+
+    fn load_artists(&mut self) {
+        self.model.artists = self
+            .model
+            .library
+            .album_artists(self.model.genre.as_deref());
+        if !self
+            .model
+            .artists
+            .iter()
+            .any(|artist| Some(artist) == self.model.artist.as_ref())
+        {
+            self.model.artist = None;
+        }
+        fill_pane(
+            &self.artist_list,
+            &self.model.artist_store,
+            "All artists",
+            &self.model.artists,
+            self.model.artist.as_ref(),
+        );
+    }
+
+    fn load_albums(&mut self) {
+        self.model.albums = self
+            .model
+            .library
+            .albums(self.model.artist.as_deref(), self.model.genre.as_deref());
+        self.model.album = None;
+        self.model.album_store.clear();
+        self.model.track_store.clear();
+        self.model.tracks.clear();
+
+        let mut missing = Vec::new();
+        for album in &self.model.albums {
+            let key = album_key(album);
+            let cover = match self.model.covers.get(&key) {
+                Some(cover) => cover.clone(),
+                None => {
+                    if self.model.requested_covers.insert(key.clone()) {
+                        missing.push((key.clone(), album.id));
+                    }
+                    None
+                }
+            };
+            let markup = format!(
+                "<b>{}</b>\n{}",
+                glib::markup_escape_text(&album.title),
+                glib::markup_escape_text(&album.artist)
+            );
+            let values: [(u32, &dyn ToValue); 7] = [
+                (ALBUM_KEY_COLUMN, &key),
+                (ALBUM_COVER_COLUMN, &cover),
+                (ALBUM_TITLE_COLUMN, &album.title),
+                (ALBUM_ARTIST_COLUMN, &album.artist),
+                (ALBUM_YEAR_COLUMN, &album.year),
+                (ALBUM_TRACKS_COLUMN, &album.tracks.to_string()),
+                (ALBUM_MARKUP_COLUMN, &markup),
+            ];
+            let row = self.model.album_store.append();
+            for (column, value) in values.iter() {
+                self.model
+                    .album_store
+                    .set_value(&row, *column, &value.to_value());
+            }
+        }
+        if !missing.is_empty() {
+            load_covers(missing, self.model.covers_sender.clone());
+        }
+    }
+
+    /// Shows the songs of the album selected in the visible album view.
+    fn select_album(&mut self) {
+        let path = if self.model.grid {
+            self.album_grid.get_selected_items().into_iter().next()
+        } else {
+            self.album_list.get_cursor().0
+        };
+        let index = match path.as_ref().and_then(path_index) {
+            Some(index) if self.model.album != Some(index) => index,
+            _ => return,
+        };
+        let album = match self.model.albums.get(index) {
+            Some(album) => album,
+            None => return,
+        };
+        self.model.album = Some(index);
+        self.model.tracks = self
+            .model
+            .library
+            .album_tracks(album.id, self.model.genre.as_deref());
+
+        self.model.track_store.clear();
+        for track in &self.model.tracks {
+            let duration = track.duration.map(format_duration).unwrap_or_default();
+            let values: [(u32, &dyn ToValue); 4] = [
+                (TRACK_NUMBER_COLUMN, &track.track_number()),
+                (TRACK_TITLE_COLUMN, &track.title()),
+                (TRACK_ARTIST_COLUMN, &track.artist()),
+                (TRACK_DURATION_COLUMN, &duration),
+            ];
+            let row = self.model.track_store.append();
+            for (column, value) in values.iter() {
+                self.model
+                    .track_store
+                    .set_value(&row, *column, &value.to_value());
+            }
+        }
+    }
+
+    /// Selects the current album in the album view being switched to.
+    fn show_selected_album(&self) {
+        let path = match self.model.album {
+            Some(index) => TreePath::new_from_indicesv(&[index as i32]),
+            None => return,
+        };
+        if self.model.grid {
+            self.album_grid.select_path(&path);
+            self.album_grid.scroll_to_path(&path, false, 0.0, 0.0);
+        } else {
+            self.album_list
+                .set_cursor(&path, None::<&TreeViewColumn>, false);
+        }
+    }
+
+    fn set_cover(&mut self, key: String, cover: Option<Pixbuf>) {
+        for (index, album) in self.model.albums.iter().enumerate() {
+            if album_key(album) != key {
+                continue;
+            }
+            let path = TreePath::new_from_indicesv(&[index as i32]);
+            if let Some(row) = self.model.album_store.get_iter(&path) {
+                self.model
+                    .album_store
+                    .set_value(&row, ALBUM_COVER_COLUMN, &cover.to_value());
+            }
+        }
+        self.model.covers.insert(key, cover);
+    }
+}
+
+/// Fills a pane with a first row matching every name followed by `names`,
+/// putting the cursor on `selected`.
+fn fill_pane(
+    view: &TreeView,
+    store: &ListStore,
+    all: &str,
+    names: &[String],
+    selected: Option<&String>,
+) {
+    store.clear();
+    store.insert_with_values(None, &[0], &[&all]);
+    for name in names {
+        store.insert_with_values(None, &[0], &[name]);
+    }
+    let index = selected
+        .and_then(|selected| names.iter().position(|name| name == selected))
+        .map_or(0, |index| index + 1);
+    let path = TreePath::new_from_indicesv(&[index as i32]);
+    view.set_cursor(&path, None::<&TreeViewColumn>, false);
+}
+
+fn add_text_column(view: &TreeView, title: &str, column: u32) {
+    let view_column = TreeViewColumn::new();
+    view_column.set_title(title);
+    let cell = CellRendererText::new();
+    view_column.set_expand(true);
+    view_column.pack_start(&cell, true);
+    view_column.add_attribute(&cell, "text", column as i32);
+    view.append_column(&view_column);
+}
+
+/// Loads the covers of albums in the background, sending them by key as they
+/// are ready.
+fn load_covers(albums: Vec<(String, i64)>, sender: relm::Sender<(String, Option<Pixels>)>) {
+    thread::spawn(move || {
+        let library = Library::open();
+        for (key, id) in albums {
+            let pixels = library
+                .album_tracks(id, None)
+                .iter()
+                .find(|track| track.artwork.is_some())
+                .and_then(artwork::load)
+                .and_then(|(_, pixbuf)| {
+                    pixbuf.scale_simple(COVER_SIZE, COVER_SIZE, InterpType::Bilinear)
+                })
+                .and_then(|cover| Pixels::new(&cover));
+            if sender.send((key, pixels)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Returns the key album covers are cached by, which stays the same when the
+/// library is rescanned.
+fn album_key(album: &Album) -> String {
+    format!("{}\n{}", album.artist, album.title)
+}
+
+fn path_index(path: &TreePath) -> Option<usize> {
+    path.get_indices().first().map(|&index| index as usize)
+}
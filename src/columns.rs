@@ -0,0 +1,168 @@
+use gtk::SortType;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::config;
+
+const LAYOUT_FILE: &str = "columns";
+
+/// The order, visibility, width and sorting of the playlist columns,
+/// identified by their keys so that the saved layout survives columns being
+/// added.
+pub struct Layout {
+    pub columns: Vec<(String, bool)>,
+    /// The widths of the columns the user resized, in pixels.
+    pub widths: HashMap<String, i32>,
+    pub sort: Option<(String, SortType)>,
+}
+
+impl Layout {
+    /// Reads the saved layout. Each line is either `<key> <shown|hidden>
+    /// [width]` for a column, in display order, or `sort <key>
+    /// <ascending|descending>`.
+    pub fn load() -> Option<Layout> {
+        let contents = config::read(LAYOUT_FILE)?;
+        let mut layout = Layout {
+            columns: Vec::new(),
+            widths: HashMap::new(),
+            sort: None,
+        };
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["sort", key, order] => {
+                    let order = match order {
+                        "descending" => SortType::Descending,
+                        _ => SortType::Ascending,
+                    };
+                    layout.sort = Some((key.to_string(), order));
+                }
+                [key, visibility] => layout
+                    .columns
+                    .push((key.to_string(), visibility == "shown")),
+                [key, visibility, width] => {
+                    layout
+                        .columns
+                        .push((key.to_string(), visibility == "shown"));
+                    if let Ok(width) = width.parse() {
+                        layout.widths.insert(key.to_string(), width);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Some(layout)
+    }
+
+    pub fn save(&self) {
+        let mut contents = String::new();
+        for (key, visible) in &self.columns {
+            let visibility = if *visible { "shown" } else { "hidden" };
+            match self.widths.get(key) {
+                Some(width) => contents.push_str(&format!("{} {} {}\n", key, visibility, width)),
+                None => contents.push_str(&format!("{} {}\n", key, visibility)),
+            }
+        }
+        if let Some((ref key, order)) = self.sort {
+            let order = match order {
+                SortType::Descending => "descending",
+                _ => "ascending",
+            };
+            contents.push_str(&format!("sort {} {}\n", key, order));
+        }
+        config::write(LAYOUT_FILE, &contents);
+    }
+}
+
+/// Compares strings case-insensitively, treating runs of digits as numbers so
+/// that "2" sorts before "10". Empty values sort last.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        _ => (),
+    }
+
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consumes a run of digits, dropping leading zeros so that the length of the
+/// result orders numbers by magnitude.
+fn take_number<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> String {
+    let mut number = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        if !(number.is_empty() && c == '0') {
+            number.push(c);
+        }
+        chars.next();
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::natural_cmp;
+    use std::cmp::Ordering::*;
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(natural_cmp("2", "10"), Less);
+        assert_eq!(natural_cmp("Track 9", "Track 10"), Less);
+        assert_eq!(natural_cmp("disc 2 track 1", "disc 10 track 1"), Less);
+        assert_eq!(natural_cmp("100", "99"), Greater);
+    }
+
+    #[test]
+    fn leading_zeros_are_ignored() {
+        assert_eq!(natural_cmp("007", "7"), Equal);
+        assert_eq!(natural_cmp("01", "2"), Less);
+        assert_eq!(natural_cmp("0", "00"), Equal);
+    }
+
+    #[test]
+    fn letters_ignore_case() {
+        assert_eq!(natural_cmp("abba", "ABBA"), Equal);
+        assert_eq!(natural_cmp("apple", "Banana"), Less);
+        assert_eq!(natural_cmp("Zebra", "apple"), Greater);
+    }
+
+    #[test]
+    fn prefixes_come_first() {
+        assert_eq!(natural_cmp("Love", "Love Song"), Less);
+        assert_eq!(natural_cmp("Track 1a", "Track 1"), Greater);
+    }
+
+    #[test]
+    fn empty_values_come_last() {
+        assert_eq!(natural_cmp("", "a"), Greater);
+        assert_eq!(natural_cmp("1", ""), Less);
+        assert_eq!(natural_cmp("", ""), Equal);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+const APP_DIR: &str = "blue-music";
+
+/// Returns the directory holding the player's settings, creating it if needed.
+pub fn config_dir() -> PathBuf {
+    app_dir(glib::get_user_config_dir())
+}
+
+/// Returns the directory holding the player's data, such as the library
+/// database, creating it if needed.
+pub fn data_dir() -> PathBuf {
+    app_dir(glib::get_user_data_dir())
+}
+
+/// Returns the directory holding files that can be rebuilt, such as scaled
+/// artwork, creating it if needed.
+pub fn cache_dir() -> PathBuf {
+    app_dir(glib::get_user_cache_dir())
+}
+
+/// Returns the directory holding files that only matter while the player
+/// runs, such as its control socket, creating it if needed.
+pub fn runtime_dir() -> PathBuf {
+    app_dir(glib::get_user_runtime_dir())
+}
+
+fn app_dir(base: Option<PathBuf>) -> PathBuf {
+    let dir = base.unwrap_or_else(|| PathBuf::from(".")).join(APP_DIR);
+    if let Err(error) = fs::create_dir_all(&dir) {
+        eprintln!("Unable to create {}: {}", dir.display(), error);
+    }
+    dir
+}
+
+/// Reads a settings file, returning `None` if it has never been written.
+pub fn read(name: &str) -> Option<String> {
+    fs::read_to_string(config_dir().join(name)).ok()
+}
+
+pub fn write(name: &str, contents: &str) {
+    let path = config_dir().join(name);
+    if let Err(error) = fs::write(&path, contents) {
+        eprintln!("Unable to save {}: {}", path.display(), error);
+    }
+}
+
+/// Writes a settings file only the user can read, for secrets such as
+/// tokens.
+pub fn write_private(name: &str, contents: &str) {
+    let path = config_dir().join(name);
+    let result = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| {
+            // Files written before keep their permissions when opened.
+            file.set_permissions(Permissions::from_mode(0o600))?;
+            file.write_all(contents.as_bytes())
+        });
+    if let Err(error) = result {
+        eprintln!("Unable to save {}: {}", path.display(), error);
+    }
+}
@@ -0,0 +1,418 @@
+use metaflac::block::{Block, BlockType};
+use metaflac::Tag;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::library::Track;
+use crate::playlist_file::decode;
+
+/// Separates the path of a file from a track number in the paths of virtual
+/// tracks, as in `album.flac#3`.
+const TRACK_SEPARATOR: char = '#';
+
+/// CUE sheets count time in frames of a CD, 75 to a second.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// Key of the Vorbis comment some rippers embed the CUE sheet in.
+const CUESHEET_COMMENT: &str = "CUESHEET";
+
+/// A track of a CUE sheet.
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub songwriter: Option<String>,
+    /// The name of the file holding the track, as written in the sheet.
+    file: Option<String>,
+    /// Where the track starts, at its INDEX 01, in samples.
+    pub start: u64,
+    /// Where the track ends, in samples, or `None` for the last one, which
+    /// ends with the file.
+    pub end: Option<u64>,
+}
+
+/// The tracks a single file album is made of.
+#[derive(Default)]
+pub struct Sheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+    /// The samples of each channel of the file in a second.
+    sample_rate: u64,
+    /// The duration of the whole file, in samples.
+    length: Option<u64>,
+}
+
+impl Sheet {
+    pub fn track(&self, number: u32) -> Option<&CueTrack> {
+        self.tracks.iter().find(|track| track.number == number)
+    }
+
+    /// Returns the virtual tracks of `whole`, the track of the file itself.
+    /// They keep its tags, except for those the sheet gives for each track.
+    pub fn virtual_tracks(&self, whole: &Track) -> Vec<Track> {
+        self.tracks
+            .iter()
+            .map(|cue_track| self.virtual_track(whole, cue_track))
+            .collect()
+    }
+
+    /// Returns the virtual track of `whole` for one of the tracks of the sheet.
+    pub fn virtual_track(&self, whole: &Track, cue_track: &CueTrack) -> Track {
+        let mut track = whole.clone();
+        track.path = track_path(Path::new(&whole.path), cue_track.number);
+        track.cue_sheet = false;
+        let tags = &mut track.tags;
+        tags.title = Some(
+            cue_track
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Track {}", cue_track.number)),
+        );
+        if let Some(performer) = cue_track.performer.as_ref().or(self.performer.as_ref()) {
+            tags.artists = vec![performer.clone()];
+        }
+        if tags.album_artists.is_empty() {
+            tags.album_artists = self.performer.iter().cloned().collect();
+        }
+        if let Some(title) = &self.title {
+            tags.album = Some(title.clone());
+        }
+        if let Some(songwriter) = &cue_track.songwriter {
+            tags.composers = vec![songwriter.clone()];
+        }
+        tags.track_number = Some(cue_track.number.to_string());
+        tags.track_total = Some(self.tracks.len().to_string());
+        let end = cue_track.end.or(self.length);
+        track.duration = end
+            .filter(|_| self.sample_rate > 0)
+            .map(|end| end.saturating_sub(cue_track.start) / self.sample_rate);
+        track
+    }
+}
+
+/// Returns the path of a virtual track of the file at `file`.
+pub fn track_path(file: &Path, number: u32) -> String {
+    format!("{}{}{}", file.display(), TRACK_SEPARATOR, number)
+}
+
+/// Splits the path of a virtual track into the path of its file and its
+/// number. Returns `None` for the paths of files.
+pub fn split_path(path: &str) -> Option<(PathBuf, u32)> {
+    let (file, number) = path.rsplit_once(TRACK_SEPARATOR)?;
+    let number = number.parse::<u32>().ok()?;
+    let file = PathBuf::from(file);
+    let is_flac = file
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("flac"));
+    // File names may end with what looks like a track number.
+    if !is_flac || Path::new(path).exists() {
+        return None;
+    }
+    Some((file, number))
+}
+
+/// Returns the path of the file a song is read from, which is the file
+/// holding it for virtual tracks.
+pub fn file_path(path: &str) -> PathBuf {
+    match split_path(path) {
+        Some((file, _)) => file,
+        None => PathBuf::from(path),
+    }
+}
+
+/// Reads the CUE sheet splitting the FLAC file at `file` into tracks: a
+/// `.cue` file next to it, or one embedded in the file. Returns `None` when
+/// there is none, or when it lists a single track.
+pub fn read(file: &Path) -> Option<Sheet> {
+    let tag = Tag::read_from_path(file).ok()?;
+    read_with_tag(file, &tag)
+}
+
+/// Reads the CUE sheet of the FLAC file at `file`, whose tag is `tag`.
+pub fn read_with_tag(file: &Path, tag: &Tag) -> Option<Sheet> {
+    let (sample_rate, length) = stream_info(tag)?;
+    let mut sheet = external_sheet(file, sample_rate)
+        .or_else(|| {
+            let text = tag.get_vorbis(CUESHEET_COMMENT)?.first()?.clone();
+            Some(parse(&text, sample_rate))
+        })
+        .filter(|sheet| sheet.tracks.len() > 1)
+        .or_else(|| embedded_sheet(tag, sample_rate))?;
+
+    // Sheets listing several files only describe this one in part.
+    let name = file.file_name()?.to_string_lossy().to_lowercase();
+    let stem = file.file_stem()?.to_string_lossy().to_lowercase();
+    let files: Vec<&String> = sheet
+        .tracks
+        .iter()
+        .filter_map(|track| track.file.as_ref())
+        .collect();
+    if files.iter().any(|other| *other != files[0]) {
+        sheet.tracks.retain(|track| {
+            track.file.as_ref().is_some_and(|other| {
+                let other = Path::new(other);
+                let other_name = other.file_name().unwrap_or_default();
+                let other_stem = other.file_stem().unwrap_or_default();
+                other_name.to_string_lossy().to_lowercase() == name
+                    || other_stem.to_string_lossy().to_lowercase() == stem
+            })
+        });
+    }
+    if sheet.tracks.len() < 2 {
+        return None;
+    }
+    for index in 0..sheet.tracks.len() - 1 {
+        sheet.tracks[index].end = Some(sheet.tracks[index + 1].start);
+    }
+    sheet.length = length;
+    Some(sheet)
+}
+
+/// Returns the sample rate of the stream and its length in samples, when
+/// known.
+fn stream_info(tag: &Tag) -> Option<(u64, Option<u64>)> {
+    tag.get_blocks(BlockType::StreamInfo)
+        .into_iter()
+        .find_map(|block| match block {
+            Block::StreamInfo(info) => Some(info),
+            _ => None,
+        })
+        .filter(|info| info.sample_rate > 0)
+        .map(|info| {
+            let length = Some(info.total_samples).filter(|&samples| samples > 0);
+            (u64::from(info.sample_rate), length)
+        })
+}
+
+/// Reads `album.cue` or `album.flac.cue` next to `album.flac`.
+fn external_sheet(file: &Path, sample_rate: u64) -> Option<Sheet> {
+    let name = file.file_name()?.to_string_lossy().to_string();
+    let candidates = [
+        file.with_extension("cue"),
+        file.with_file_name(name + ".cue"),
+    ];
+    candidates
+        .iter()
+        .filter_map(|candidate| fs::read(candidate).ok())
+        .map(|bytes| parse(&decode(&bytes), sample_rate))
+        .find(|sheet| sheet.tracks.len() > 1)
+}
+
+/// Reads the CUESHEET metadata block, which only gives where tracks start.
+fn embedded_sheet(tag: &Tag, sample_rate: u64) -> Option<Sheet> {
+    let cue_sheet = tag.blocks().iter().find_map(|block| match block {
+        Block::CueSheet(sheet) => Some(sheet),
+        _ => None,
+    })?;
+    let tracks = cue_sheet
+        .tracks
+        .iter()
+        // The lead-out track only marks the end of the last one.
+        .filter(|track| track.is_audio && track.number != 170 && track.number != 255)
+        .map(|track| {
+            let index = track
+                .indices
+                .iter()
+                .find(|index| index.point_num == 1)
+                .map_or(0, |index| index.offset);
+            CueTrack {
+                number: u32::from(track.number),
+                title: None,
+                performer: None,
+                songwriter: None,
+                file: None,
+                start: track.offset + index,
+                end: None,
+            }
+        })
+        .collect();
+    Some(Sheet {
+        tracks,
+        sample_rate,
+        ..Sheet::default()
+    })
+}
+
+/// Parses the text of a CUE sheet splitting a stream of `sample_rate`
+/// samples a second. Unknown commands are ignored.
+pub fn parse(text: &str, sample_rate: u64) -> Sheet {
+    let mut sheet = Sheet {
+        sample_rate,
+        ..Sheet::default()
+    };
+    let mut file = None;
+    for line in text.lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let current = sheet.tracks.last_mut();
+        match (command.to_uppercase().as_str(), current) {
+            ("FILE", _) => file = Some(file_name(rest)),
+            ("TRACK", _) => {
+                let number = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+                if let Some(number) = number {
+                    sheet.tracks.push(CueTrack {
+                        number,
+                        title: None,
+                        performer: None,
+                        songwriter: None,
+                        file: file.clone(),
+                        start: 0,
+                        end: None,
+                    });
+                }
+            }
+            ("TITLE", Some(track)) => track.title = Some(unquote(rest)),
+            ("TITLE", None) => sheet.title = Some(unquote(rest)),
+            ("PERFORMER", Some(track)) => track.performer = Some(unquote(rest)),
+            ("PERFORMER", None) => sheet.performer = Some(unquote(rest)),
+            ("SONGWRITER", Some(track)) => track.songwriter = Some(unquote(rest)),
+            ("INDEX", Some(track)) => {
+                let mut parts = rest.split_whitespace();
+                if parts.next().and_then(|n| n.parse::<u32>().ok()) == Some(1) {
+                    if let Some(frames) = parts.next().and_then(parse_time) {
+                        track.start = frames * sample_rate / FRAMES_PER_SECOND;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    sheet
+}
+
+/// Parses a time written as `mm:ss:ff` into frames.
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+    if parts.next().is_some() || seconds >= 60 || frames >= FRAMES_PER_SECOND {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+fn unquote(text: &str) -> String {
+    match text.strip_prefix('"') {
+        Some(quoted) => match quoted.find('"') {
+            Some(end) => quoted[..end].to_string(),
+            None => quoted.to_string(),
+        },
+        None => text.to_string(),
+    }
+}
+
+/// Reads the name in `FILE "album.flac" WAVE`, where the name may be left
+/// unquoted.
+fn file_name(text: &str) -> String {
+    if text.starts_with('"') {
+        return unquote(text);
+    }
+    match text.rsplit_once(char::is_whitespace) {
+        Some((name, _)) => name.trim().to_string(),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "REM GENRE Jazz
+PERFORMER \"Miles Davis\"
+TITLE \"Kind of Blue\"
+FILE \"Kind of Blue.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"So What\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Freddie Freeloader\"
+    PERFORMER \"Miles Davis Sextet\"
+    SONGWRITER \"Miles Davis\"
+    INDEX 00 09:20:50
+    INDEX 01 09:22:00
+  TRACK 03 AUDIO
+    TITLE Blue in Green
+    INDEX 01 19:07:74
+";
+
+    #[test]
+    fn parses_sheets() {
+        let sheet = parse(SHEET, 44_100);
+        assert_eq!(sheet.title.as_deref(), Some("Kind of Blue"));
+        assert_eq!(sheet.performer.as_deref(), Some("Miles Davis"));
+        let numbers: Vec<u32> = sheet.tracks.iter().map(|track| track.number).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+
+        let second = sheet.track(2).unwrap();
+        assert_eq!(second.title.as_deref(), Some("Freddie Freeloader"));
+        assert_eq!(second.performer.as_deref(), Some("Miles Davis Sextet"));
+        assert_eq!(second.songwriter.as_deref(), Some("Miles Davis"));
+        assert_eq!(second.file.as_deref(), Some("Kind of Blue.flac"));
+        // Tracks start at INDEX 01, past their pregap.
+        assert_eq!(second.start, (9 * 60 + 22) * 44_100);
+        assert_eq!(
+            sheet.track(3).unwrap().title.as_deref(),
+            Some("Blue in Green")
+        );
+        assert!(sheet.track(4).is_none());
+    }
+
+    #[test]
+    fn converts_frames_to_samples() {
+        let start = |rate| parse(SHEET, rate).track(3).unwrap().start;
+        // A frame is 588 samples at 44.1 kHz.
+        assert_eq!(start(44_100), (19 * 60 + 7) * 44_100 + 74 * 588);
+        assert_eq!(start(96_000), (19 * 60 + 7) * 96_000 + 74 * 1280);
+    }
+
+    #[test]
+    fn ignores_unknown_commands_and_case() {
+        let sheet = parse(
+            "title \"Album\"\nCATALOG 0000\ntrack 5 audio\nflags DCP\nindex 01 01:00:00\n",
+            44_100,
+        );
+        assert_eq!(sheet.title.as_deref(), Some("Album"));
+        assert_eq!(sheet.tracks.len(), 1);
+        assert_eq!(sheet.tracks[0].number, 5);
+        assert_eq!(sheet.tracks[0].start, 60 * 44_100);
+        assert!(parse("", 44_100).tracks.is_empty());
+    }
+
+    #[test]
+    fn reads_unquoted_file_names() {
+        let sheet = parse("FILE album side a.flac WAVE\nTRACK 1 AUDIO\n", 44_100);
+        assert_eq!(sheet.tracks[0].file.as_deref(), Some("album side a.flac"));
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("00:01:00"), Some(75));
+        assert_eq!(parse_time("03:25:40"), Some((3 * 60 + 25) * 75 + 40));
+        // Minutes go past an hour on long discs.
+        assert_eq!(parse_time("79:59:74"), Some((79 * 60 + 59) * 75 + 74));
+        assert_eq!(parse_time("00:60:00"), None);
+        assert_eq!(parse_time("00:00:75"), None);
+        assert_eq!(parse_time("01:02"), None);
+        assert_eq!(parse_time("01:02:03:04"), None);
+        assert_eq!(parse_time("aa:bb:cc"), None);
+        assert_eq!(parse_time(""), None);
+    }
+
+    #[test]
+    fn splits_virtual_track_paths() {
+        let path = track_path(Path::new("/music/missing album.flac"), 7);
+        assert_eq!(path, "/music/missing album.flac#7");
+        assert_eq!(
+            split_path(&path),
+            Some((PathBuf::from("/music/missing album.flac"), 7))
+        );
+        assert_eq!(file_path(&path), PathBuf::from("/music/missing album.flac"));
+        assert_eq!(split_path("/music/song.flac"), None);
+        assert_eq!(split_path("/music/song.mp3#2"), None);
+        assert_eq!(split_path("/music/song.flac#two"), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Runs the player without a window, for machines without a display. The
+//! command line controls it as it does the window, and it can be shown in the
+//! terminal.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use glib::{Continue, MainContext, MainLoop};
+use relm::Channel;
+
+use crate::engine::Engine;
+use crate::listing::Failure;
+use crate::remote::{Lock, Reply, Request, Seek, Server};
+use crate::tui::Screen;
+
+/// Plays `files`, starting with the first of them when `play` is set, or the
+/// library when there are none, until asked to quit. The terminal shows the
+/// player when `terminal` is set. The command line is answered once `lock`
+/// lets the socket be bound.
+pub fn run(files: Vec<PathBuf>, play: bool, terminal: bool, lock: Lock) {
+    let context = MainContext::default();
+    context.acquire();
+    let main_loop = MainLoop::new(&context, false);
+
+    let engine = Engine::start();
+    let unopened = if files.is_empty() {
+        engine.borrow_mut().open_library();
+        Vec::new()
+    } else {
+        engine.borrow_mut().open(files, play)
+    };
+
+    let requests_engine = engine.clone();
+    let requests_loop = main_loop.clone();
+    let (_channel, requests) = Channel::new(move |(request, reply): (Request, Sender<Reply>)| {
+        let answer = answer(&requests_engine, &requests_loop, request);
+        // The command line may have given up waiting.
+        let _ = reply.send(answer);
+    });
+    let _remote = Server::start(lock, requests);
+
+    for signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        let main_loop = main_loop.clone();
+        glib::unix_signal_add(*signal, move || {
+            main_loop.quit();
+            Continue(false)
+        });
+    }
+
+    let _screen = if terminal {
+        let message = unopened_message(&unopened);
+        match Screen::start(engine.clone(), main_loop.clone(), message) {
+            Ok(screen) => Some(screen),
+            Err(error) => {
+                eprintln!("Unable to show the player in the terminal: {}", error);
+                return;
+            }
+        }
+    } else {
+        if !unopened.is_empty() {
+            eprintln!("{}", unopened_message(&unopened));
+        }
+        None
+    };
+
+    main_loop.run();
+    engine.borrow_mut().stop();
+}
+
+/// Does what the command line of another instance asks for.
+fn answer(engine: &RefCell<Engine>, main_loop: &MainLoop, request: Request) -> Reply {
+    let mut engine = engine.borrow_mut();
+    match request {
+        Request::Open(files, play) => {
+            let unopened = engine.open(files, play);
+            if !unopened.is_empty() {
+                return Err(unopened_message(&unopened));
+            }
+        }
+        Request::Raise => return Err("Blue Music is running without a window".to_string()),
+        Request::PlayPause => engine.play_pause(),
+        Request::Next => engine.next(),
+        Request::Previous => engine.previous(),
+        Request::Stop => engine.stop(),
+        Request::Seek(_) if engine.report().track.is_none() => {
+            return Err("Nothing is playing".to_string());
+        }
+        Request::Seek(Seek::By(offset)) => engine.seek_by(offset),
+        Request::Seek(Seek::To(position)) => engine.seek(position),
+        Request::Status => return Ok(engine.report().describe()),
+        Request::Quit => main_loop.quit(),
+    }
+    Ok(String::new())
+}
+
+/// Tells on a line what could not be opened, and why.
+fn unopened_message(unopened: &[Failure]) -> String {
+    let failures: Vec<String> = unopened
+        .iter()
+        .map(|failure| format!("{} {}", failure.heading, failure.names.join(", ")))
+        .collect();
+    failures.join("; ")
+}
@@ -0,0 +1,700 @@
+use gtk::Orientation::Vertical;
+use gtk::{
+    Adjustment, Align, BoxExt, CellLayoutExt, CellRendererText, CheckButton, ComboBoxExtManual,
+    ComboBoxText, ComboBoxTextExt, ContainerExt, Dialog, DialogExt, DialogFlags, EditableSignals,
+    Entry, EntryExt, FileChooserAction, FileChooserButton, FileChooserButtonExt, FileChooserExt,
+    GridExt, GtkListStoreExt, GtkListStoreExtManual, GtkWindowExt, Label, LabelExt, ListStore,
+    ResponseType, ScrolledWindow, ScrolledWindowExt, ShadowType, TextBufferExt, TextView,
+    TextViewExt, ToggleButtonExt, TreeView, TreeViewColumn, TreeViewColumnExt, TreeViewExt, Type,
+    WidgetExt, Window, WrapMode,
+};
+use gtk_sys::GTK_RESPONSE_ACCEPT;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::config;
+use crate::library::{self, Track};
+use crate::pattern::{self, Pattern};
+use crate::tags::{Tags, SEPARATOR};
+
+const TAG_PATTERN_FILE: &str = "tag-pattern";
+const RENAME_PATTERN_FILE: &str = "rename-pattern";
+const DEFAULT_TAG_PATTERN: &str = "%artist%/%album%/%track% - %title%";
+const DEFAULT_RENAME_PATTERN: &str = "%albumartist%/%album%/%track% - %title%";
+
+/// The height of the comment field, in pixels, about four lines.
+const COMMENT_HEIGHT: i32 = 80;
+
+/// A field of the editor, read from and written to the tags of each song.
+struct Field {
+    label: &'static str,
+    get: fn(&Tags) -> String,
+    set: fn(&mut Tags, &str),
+    /// Whether the field is free text spanning several lines.
+    multiline: bool,
+}
+
+/// The case normalisations offered, in the order of the case selector.
+const CASES: [&str; 5] = [
+    "Keep case",
+    "Title Case",
+    "Sentence case",
+    "lower case",
+    "UPPER CASE",
+];
+
+fn fields() -> Vec<Field> {
+    vec![
+        Field {
+            label: "Title",
+            get: |tags| one(&tags.title),
+            set: |tags, text| tags.title = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Artist",
+            get: |tags| many(&tags.artists),
+            set: |tags, text| tags.artists = set_many(text),
+            multiline: false,
+        },
+        Field {
+            label: "Album",
+            get: |tags| one(&tags.album),
+            set: |tags, text| tags.album = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Album artist",
+            get: |tags| many(&tags.album_artists),
+            set: |tags, text| tags.album_artists = set_many(text),
+            multiline: false,
+        },
+        Field {
+            label: "Genre",
+            get: |tags| many(&tags.genres),
+            set: |tags, text| tags.genres = set_many(text),
+            multiline: false,
+        },
+        Field {
+            label: "Date",
+            get: |tags| one(&tags.date),
+            set: |tags, text| tags.date = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Original date",
+            get: |tags| one(&tags.original_date),
+            set: |tags, text| tags.original_date = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Track",
+            get: |tags| one(&tags.track_number),
+            set: |tags, text| tags.track_number = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Tracks",
+            get: |tags| one(&tags.track_total),
+            set: |tags, text| tags.track_total = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Disc",
+            get: |tags| one(&tags.disc_number),
+            set: |tags, text| tags.disc_number = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Discs",
+            get: |tags| one(&tags.disc_total),
+            set: |tags, text| tags.disc_total = set_one(text),
+            multiline: false,
+        },
+        Field {
+            label: "Composer",
+            get: |tags| many(&tags.composers),
+            set: |tags, text| tags.composers = set_many(text),
+            multiline: false,
+        },
+        Field {
+            label: "Performer",
+            get: |tags| many(&tags.performers),
+            set: |tags, text| tags.performers = set_many(text),
+            multiline: false,
+        },
+        Field {
+            label: "Comment",
+            get: |tags| tags.comments.join("\n"),
+            set: |tags, text| tags.comments = set_one(text).into_iter().collect(),
+            multiline: true,
+        },
+    ]
+}
+
+/// Lets the user edit the tags of `tracks`. Fields holding different values
+/// across the songs are kept as they are unless edited. Returns the new tags
+/// of each song, or `None` if the dialog was cancelled.
+pub fn show_tag_editor(parent: &Window, tracks: &[Track]) -> Option<Vec<Tags>> {
+    let title = match tracks {
+        [track] => format!("Edit tags of {}", track.title()),
+        _ => format!("Edit tags of {} songs", tracks.len()),
+    };
+    let dialog = Dialog::new_with_buttons(
+        Some(title.as_str()),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("Cancel", ResponseType::Cancel),
+            ("Save", ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_size(520, -1);
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(10);
+    grid.set_margin_start(10);
+    grid.set_margin_end(10);
+    grid.set_margin_top(10);
+    grid.set_margin_bottom(10);
+    dialog.get_content_area().add(&grid);
+
+    let fields = fields();
+    let mut rows = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let mut values = tracks.iter().map(|track| (field.get)(&track.tags));
+        let first = values.next().unwrap_or_default();
+        // The value of every song, or `None` when they differ.
+        let shared = if values.all(|value| value == first) {
+            Some(first)
+        } else {
+            None
+        };
+
+        let label = Label::new(Some(field.label));
+        label.set_halign(Align::End);
+        label.set_valign(Align::Start);
+        let entry = FieldEntry::new(field.multiline);
+        grid.attach(&label, 0, index as i32, 1, 1);
+        entry.attach(&grid, index as i32);
+
+        let keep = CheckButton::new_with_label("Keep existing");
+        keep.set_valign(Align::Start);
+        match shared {
+            Some(ref value) => entry.set_text(value),
+            None => {
+                entry.set_placeholder_text("Mixed values");
+                keep.set_active(true);
+                grid.attach(&keep, 2, index as i32, 1, 1);
+                let keep = keep.clone();
+                entry.connect_changed(move || keep.set_active(false));
+            }
+        }
+        rows.push((entry, keep, shared));
+    }
+
+    let whitespace = CheckButton::new_with_label("Trim and collapse whitespace");
+    whitespace.set_active(true);
+    let case = ComboBoxText::new();
+    for name in CASES.iter() {
+        case.append_text(name);
+    }
+    case.set_active(0);
+    let options = fields.len() as i32;
+    grid.attach(&whitespace, 1, options, 1, 1);
+    grid.attach(&case, 1, options + 1, 1, 1);
+    dialog.show_all();
+
+    let result = if dialog.run() == GTK_RESPONSE_ACCEPT {
+        let case = case.get_active().unwrap_or(0) as usize;
+        let whitespace = whitespace.get_active();
+        // The fields edited are tidied up on their own, so that those left
+        // alone are written back as they were.
+        let mut entered = Tags::default();
+        let mut edited = Vec::new();
+        for (field, (entry, keep, shared)) in fields.iter().zip(rows.iter()) {
+            let text = entry.text();
+            let unchanged = match shared {
+                Some(shared) => text == *shared,
+                None => keep.get_active(),
+            };
+            if !unchanged {
+                (field.set)(&mut entered, &text);
+                edited.push(field);
+            }
+        }
+        normalise(&mut entered, |value| {
+            let value = if whitespace {
+                collapse_whitespace(value)
+            } else {
+                value.to_string()
+            };
+            change_case(&value, case)
+        });
+        let tags = tracks
+            .iter()
+            .map(|track| {
+                let mut tags = track.tags.clone();
+                for field in &edited {
+                    (field.set)(&mut tags, &(field.get)(&entered));
+                }
+                tags
+            })
+            .collect();
+        Some(tags)
+    } else {
+        None
+    };
+    dialog.destroy();
+    result
+}
+
+/// The widget a field is edited in: an entry, or a text view for the fields
+/// spanning several lines.
+enum FieldEntry {
+    Line(Entry),
+    Lines(ScrolledWindow, TextView),
+}
+
+impl FieldEntry {
+    fn new(multiline: bool) -> FieldEntry {
+        if !multiline {
+            let entry = Entry::new();
+            entry.set_hexpand(true);
+            return FieldEntry::Line(entry);
+        }
+        let view = TextView::new();
+        view.set_wrap_mode(WrapMode::WordChar);
+        view.set_accepts_tab(false);
+        let scrolled = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+        scrolled.set_hexpand(true);
+        scrolled.set_min_content_height(COMMENT_HEIGHT);
+        scrolled.set_shadow_type(ShadowType::In);
+        scrolled.add(&view);
+        FieldEntry::Lines(scrolled, view)
+    }
+
+    fn attach(&self, grid: &gtk::Grid, row: i32) {
+        match self {
+            FieldEntry::Line(entry) => grid.attach(entry, 1, row, 1, 1),
+            FieldEntry::Lines(scrolled, _) => grid.attach(scrolled, 1, row, 1, 1),
+        }
+    }
+
+    fn text(&self) -> String {
+        let text = match self {
+            FieldEntry::Line(entry) => entry.get_text(),
+            FieldEntry::Lines(_, view) => view.get_buffer().and_then(|buffer| {
+                let (start, end) = buffer.get_bounds();
+                buffer.get_text(&start, &end, false)
+            }),
+        };
+        text.map(|text| text.to_string()).unwrap_or_default()
+    }
+
+    fn set_text(&self, text: &str) {
+        match self {
+            FieldEntry::Line(entry) => entry.set_text(text),
+            FieldEntry::Lines(_, view) => {
+                if let Some(buffer) = view.get_buffer() {
+                    buffer.set_text(text);
+                }
+            }
+        }
+    }
+
+    /// Hints at what the field holds while it is empty. Text views, which
+    /// cannot show a placeholder, tell it in their tooltip instead.
+    fn set_placeholder_text(&self, text: &str) {
+        match self {
+            FieldEntry::Line(entry) => entry.set_placeholder_text(text),
+            FieldEntry::Lines(_, view) => view.set_tooltip_text(text),
+        }
+    }
+
+    fn connect_changed<F: Fn() + 'static>(&self, changed: F) {
+        match self {
+            FieldEntry::Line(entry) => {
+                entry.connect_changed(move |_| changed());
+            }
+            FieldEntry::Lines(_, view) => {
+                if let Some(buffer) = view.get_buffer() {
+                    buffer.connect_changed(move |_| changed());
+                }
+            }
+        }
+    }
+}
+
+/// Lets the user read tags out of the paths of `tracks` following a pattern,
+/// previewing the tags found. Returns the new tags of the songs whose path
+/// follows the pattern, or `None` if the dialog was cancelled.
+pub fn show_tags_from_paths_dialog(
+    parent: &Window,
+    tracks: &[Track],
+) -> Option<Vec<(PathBuf, Tags)>> {
+    let dialog = PatternDialog::new(
+        parent,
+        "Tags from file names",
+        TAG_PATTERN_FILE,
+        DEFAULT_TAG_PATTERN,
+        ["File", "Tags"],
+    );
+    let tracks = Rc::new(tracks.to_vec());
+
+    let preview_tracks = tracks.clone();
+    dialog.connect_preview(move |pattern| {
+        preview_tracks
+            .iter()
+            .map(|track| {
+                let tags = match pattern.extract(Path::new(&track.path)) {
+                    Some(values) => values
+                        .iter()
+                        .map(|(field, value)| format!("{}: {}", field, value))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    None => "Does not match".to_string(),
+                };
+                [track.path.clone(), tags]
+            })
+            .collect()
+    });
+
+    let pattern = dialog.run()?;
+    let edits = tracks
+        .iter()
+        .filter_map(|track| {
+            let values = pattern.extract(Path::new(&track.path))?;
+            let mut tags = track.tags.clone();
+            for (field, value) in values {
+                pattern::set(&mut tags, field, value);
+            }
+            Some((PathBuf::from(&track.path), tags))
+        })
+        .collect();
+    Some(edits)
+}
+
+/// Lets the user move `tracks` into a folder layout derived from their tags,
+/// previewing where each song would go. Returns the moves to make, or `None`
+/// if the dialog was cancelled.
+pub fn show_rename_dialog(parent: &Window, tracks: &[Track]) -> Option<Vec<(PathBuf, PathBuf)>> {
+    let dialog = PatternDialog::new(
+        parent,
+        "Rename files from tags",
+        RENAME_PATTERN_FILE,
+        DEFAULT_RENAME_PATTERN,
+        ["File", "New path"],
+    );
+    let destination = FileChooserButton::new("Destination folder", FileChooserAction::SelectFolder);
+    if let Some(track) = tracks.first() {
+        let path = Path::new(&track.path);
+        let root = library::roots()
+            .into_iter()
+            .find(|root| path.starts_with(root))
+            .or_else(|| path.parent().map(Path::to_path_buf));
+        if let Some(root) = root {
+            destination.set_filename(root);
+        }
+    }
+    let label = Label::new(Some("Move into"));
+    label.set_halign(Align::Start);
+    dialog.content.pack_start(&label, false, false, 0);
+    dialog.content.pack_start(&destination, false, false, 0);
+    let tracks = Rc::new(tracks.to_vec());
+
+    let preview_tracks = tracks.clone();
+    let preview_destination = destination.clone();
+    dialog.connect_preview(move |pattern| {
+        let root = preview_destination.get_filename().unwrap_or_default();
+        plan_moves(pattern, &preview_tracks, &root)
+            .into_iter()
+            .map(|(from, to, problem)| {
+                let to = match problem {
+                    Some(problem) => format!("{} ({})", to.display(), problem),
+                    None => to.display().to_string(),
+                };
+                [from.display().to_string(), to]
+            })
+            .collect()
+    });
+    let refresh = dialog.refresh.clone();
+    destination.connect_file_set(move |_| refresh());
+
+    let pattern = dialog.run()?;
+    let root = destination.get_filename().unwrap_or_default();
+    let moves = plan_moves(&pattern, &tracks, &root)
+        .into_iter()
+        .filter(|(_, _, problem)| problem.is_none())
+        .map(|(from, to, _)| (from, to))
+        .collect();
+    Some(moves)
+}
+
+/// Returns where each song would be moved, along with the reason it cannot be
+/// when it cannot.
+fn plan_moves(
+    pattern: &Pattern,
+    tracks: &[Track],
+    root: &Path,
+) -> Vec<(PathBuf, PathBuf, Option<&'static str>)> {
+    let planned: Vec<(PathBuf, PathBuf)> = tracks
+        .iter()
+        .map(|track| (PathBuf::from(&track.path), pattern.format(track, root)))
+        .collect();
+    let mut destinations: HashMap<&PathBuf, usize> = HashMap::new();
+    for (_, to) in &planned {
+        *destinations.entry(to).or_default() += 1;
+    }
+    planned
+        .iter()
+        .map(|(from, to)| {
+            let problem = if from == to {
+                Some("unchanged")
+            } else if destinations[to] > 1 {
+                Some("same path as another song")
+            } else if to.exists() {
+                Some("already exists")
+            } else {
+                None
+            };
+            (from.clone(), to.clone(), problem)
+        })
+        .collect()
+}
+
+/// Returns the rows previewing the effect of a pattern.
+type Preview = Box<dyn Fn(&Pattern) -> Vec<[String; 2]>>;
+
+/// A dialog asking for a pattern, showing a preview of its effect on the
+/// songs as it is typed.
+struct PatternDialog {
+    content: gtk::Box,
+    dialog: Dialog,
+    entry: Entry,
+    setting: &'static str,
+    refresh: Rc<dyn Fn()>,
+    preview: Rc<RefCell<Preview>>,
+}
+
+impl PatternDialog {
+    fn new(
+        parent: &Window,
+        title: &str,
+        setting: &'static str,
+        default: &str,
+        headers: [&str; 2],
+    ) -> PatternDialog {
+        let dialog = Dialog::new_with_buttons(
+            Some(title),
+            Some(parent),
+            DialogFlags::MODAL,
+            &[
+                ("Cancel", ResponseType::Cancel),
+                ("Apply", ResponseType::Accept),
+            ],
+        );
+        dialog.set_default_size(720, 480);
+        let content = gtk::Box::new(Vertical, 6);
+        content.set_margin_start(10);
+        content.set_margin_end(10);
+        content.set_margin_top(10);
+        content.set_margin_bottom(10);
+        dialog
+            .get_content_area()
+            .pack_start(&content, true, true, 0);
+
+        let entry = Entry::new();
+        let pattern = config::read(setting)
+            .map(|pattern| pattern.trim().to_string())
+            .unwrap_or_else(|| default.to_string());
+        entry.set_text(&pattern);
+        let fields: Vec<String> = pattern::FIELDS
+            .iter()
+            .map(|field| format!("%{}%", field))
+            .collect();
+        let help = Label::new(Some(
+            format!("Fields: {}. Slashes separate folders.", fields.join(" ")).as_str(),
+        ));
+        help.set_halign(Align::Start);
+        help.set_line_wrap(true);
+        let error = Label::new(None);
+        error.set_halign(Align::Start);
+        content.pack_start(&entry, false, false, 0);
+        content.pack_start(&help, false, false, 0);
+        content.pack_start(&error, false, false, 0);
+
+        let store = ListStore::new(&[Type::String, Type::String]);
+        let view = TreeView::new_with_model(&store);
+        for (column, header) in headers.iter().enumerate() {
+            let view_column = TreeViewColumn::new();
+            view_column.set_title(header);
+            view_column.set_expand(true);
+            view_column.set_resizable(true);
+            let cell = CellRendererText::new();
+            view_column.pack_start(&cell, true);
+            view_column.add_attribute(&cell, "text", column as i32);
+            view.append_column(&view_column);
+        }
+        let scrolled = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+        scrolled.set_vexpand(true);
+        scrolled.add(&view);
+        content.pack_start(&scrolled, true, true, 0);
+
+        // The preview is filled by the closure given to `connect_preview`.
+        let preview: Rc<RefCell<Preview>> = Rc::new(RefCell::new(Box::new(|_| Vec::new())));
+        let refresh_entry = entry.clone();
+        let refresh_preview = preview.clone();
+        let refresh: Rc<dyn Fn()> = Rc::new(move || {
+            store.clear();
+            let text = refresh_entry
+                .get_text()
+                .map(|text| text.to_string())
+                .unwrap_or_default();
+            match Pattern::parse(&text) {
+                Ok(pattern) => {
+                    error.set_text("");
+                    for row in (refresh_preview.borrow())(&pattern) {
+                        store.insert_with_values(None, &[0, 1], &[&row[0], &row[1]]);
+                    }
+                }
+                Err(message) => error.set_text(&message),
+            }
+        });
+        let changed = refresh.clone();
+        entry.connect_changed(move |_| changed());
+
+        PatternDialog {
+            content,
+            dialog,
+            entry,
+            setting,
+            refresh,
+            preview,
+        }
+    }
+
+    fn connect_preview<F: Fn(&Pattern) -> Vec<[String; 2]> + 'static>(&self, preview: F) {
+        *self.preview.borrow_mut() = Box::new(preview);
+    }
+
+    /// Runs the dialog until it is cancelled or applied with a valid pattern,
+    /// which is returned and remembered.
+    fn run(self) -> Option<Pattern> {
+        (self.refresh)();
+        self.dialog.show_all();
+        let pattern = loop {
+            if self.dialog.run() != GTK_RESPONSE_ACCEPT {
+                break None;
+            }
+            let text = self
+                .entry
+                .get_text()
+                .map(|text| text.to_string())
+                .unwrap_or_default();
+            if let Ok(pattern) = Pattern::parse(&text) {
+                config::write(self.setting, &text);
+                break Some(pattern);
+            }
+        };
+        self.dialog.destroy();
+        pattern
+    }
+}
+
+/// Applies `normalise` to every value of the text fields. Numbers, dates and
+/// identifiers are only trimmed, and comments are kept as they are.
+fn normalise<F: Fn(&str) -> String>(tags: &mut Tags, normalise: F) {
+    let one = |value: &mut Option<String>| {
+        if let Some(value) = value.as_mut() {
+            *value = normalise(value);
+        }
+    };
+    one(&mut tags.title);
+    one(&mut tags.album);
+    let many = |values: &mut Vec<String>| {
+        for value in values.iter_mut() {
+            *value = normalise(value);
+        }
+    };
+    many(&mut tags.artists);
+    many(&mut tags.album_artists);
+    many(&mut tags.genres);
+    many(&mut tags.composers);
+    many(&mut tags.performers);
+
+    for value in [
+        &mut tags.date,
+        &mut tags.original_date,
+        &mut tags.track_number,
+        &mut tags.track_total,
+        &mut tags.disc_number,
+        &mut tags.disc_total,
+    ] {
+        if let Some(value) = value.as_mut() {
+            *value = value.trim().to_string();
+        }
+    }
+}
+
+fn collapse_whitespace(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Changes the case of `value` as selected among `CASES`.
+fn change_case(value: &str, case: usize) -> String {
+    match case {
+        1 => {
+            let mut start = true;
+            value
+                .chars()
+                .map(|c| {
+                    let changed = if start {
+                        c.to_uppercase().collect::<String>()
+                    } else {
+                        c.to_lowercase().collect::<String>()
+                    };
+                    start = c.is_whitespace();
+                    changed
+                })
+                .collect()
+        }
+        2 => {
+            let mut chars = value.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.as_str().to_lowercase().chars())
+                    .collect(),
+                None => String::new(),
+            }
+        }
+        3 => value.to_lowercase(),
+        4 => value.to_uppercase(),
+        _ => value.to_string(),
+    }
+}
+
+fn one(value: &Option<String>) -> String {
+    value.clone().unwrap_or_default()
+}
+
+fn many(values: &[String]) -> String {
+    values.join(SEPARATOR)
+}
+
+fn set_one(text: &str) -> Option<String> {
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// Splits the values of a field holding several, entered separated by
+/// semicolons.
+fn set_many(text: &str) -> Vec<String> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,485 @@
+//! The player and a playlist of its own, apart from the widgets of the window,
+//! so that it plays where there is no display. Like the window, it is driven
+//! from the main loop of GLib, which unlike GTK needs no display.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+use relm::Channel;
+
+use crate::import::{Outcome, Pool};
+use crate::library::{Library, Track};
+use crate::listing::{self, Failure, Listing, Rows};
+use crate::order::Repeat;
+use crate::playback::{Playback, Songs};
+use crate::player::{Player, PlayerMsg};
+use crate::remote::Report;
+use crate::scrobble::Scrobbler;
+
+pub struct Engine {
+    /// The row of the song playing or paused, or of the one last played.
+    current: Option<usize>,
+    /// The length of the current song, in milliseconds, or 0 when unknown.
+    duration: u64,
+    library: Library,
+    listing: Listing<usize>,
+    /// The row given to the next song listed.
+    next_row: usize,
+    paused: bool,
+    playback: Playback<usize>,
+    player: Player,
+    /// Whether the player is playing, as it last told.
+    playing: bool,
+    pool: Pool,
+    /// Where the current song is, in milliseconds.
+    position: u64,
+    /// The rows of `songs`, which stay the same as songs are added and
+    /// removed around them.
+    rows: Vec<usize>,
+    scrobbler: Scrobbler,
+    songs: Vec<Track>,
+    volume: f64,
+}
+
+/// The songs of the engine, as the playback steps through them.
+struct Listed<'a> {
+    current: Option<usize>,
+    rows: &'a [usize],
+    songs: &'a [Track],
+}
+
+impl Songs for Listed<'_> {
+    type Row = usize;
+
+    fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    fn count(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn position(&self, row: &usize) -> Option<usize> {
+        self.rows.iter().position(|other| other == row)
+    }
+
+    fn row(&self, position: usize) -> Option<usize> {
+        self.rows.get(position).copied()
+    }
+
+    fn is_listed(&self, row: &usize) -> bool {
+        self.position(row).is_some()
+    }
+
+    fn path(&self, row: &usize) -> Option<String> {
+        let index = self.position(row)?;
+        Some(self.songs[index].path.clone())
+    }
+
+    fn after(&self, row: &usize) -> Option<usize> {
+        self.row(self.position(row)? + 1)
+    }
+}
+
+impl Rows for Engine {
+    type Row = usize;
+
+    fn listing(&mut self) -> &mut Listing<usize> {
+        &mut self.listing
+    }
+
+    fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    fn library(&self) -> &Library {
+        &self.library
+    }
+
+    fn insert_row(&mut self, track: &Track, position: Option<usize>) -> Option<usize> {
+        let row = self.next_row;
+        self.next_row += 1;
+        let index = position.map_or(self.rows.len(), |position| position.min(self.rows.len()));
+        self.rows.insert(index, row);
+        self.songs.insert(index, track.clone());
+        Some(row)
+    }
+
+    fn insert_unread(&mut self, path: &Path, position: Option<usize>) -> Option<usize> {
+        self.insert_row(&Track::unread(path), position)
+    }
+
+    fn show_row(&mut self, row: &usize, track: &Track) {
+        if let Some(index) = self.index(*row) {
+            self.songs[index] = track.clone();
+        }
+    }
+
+    fn show_duration(&mut self, row: &usize, duration: u64) {
+        if let Some(index) = self.index(*row) {
+            self.songs[index].duration = Some(duration);
+        }
+    }
+
+    fn remove_row(&mut self, row: &usize) {
+        if let Some(index) = self.index(*row) {
+            self.rows.remove(index);
+            self.songs.remove(index);
+        }
+    }
+
+    fn row_position(&self, row: &usize) -> Option<usize> {
+        self.index(*row)
+    }
+
+    fn is_playing(&self, row: &usize) -> bool {
+        (self.playing || self.paused) && self.current == Some(*row)
+    }
+}
+
+impl Engine {
+    /// Creates the engine, which hears from its player and its import workers
+    /// on the main loop.
+    pub fn start() -> Rc<RefCell<Engine>> {
+        Rc::new_cyclic(|engine: &Weak<RefCell<Engine>>| {
+            let player_engine = engine.clone();
+            let (_channel, sender) = Channel::new(move |msg| {
+                if let Some(engine) = player_engine.upgrade() {
+                    engine.borrow_mut().player_message(msg);
+                }
+            });
+            let importer = engine.clone();
+            let (_channel, pool_sender) = Channel::new(move |outcomes: Vec<Outcome>| {
+                if let Some(engine) = importer.upgrade() {
+                    engine.borrow_mut().imported(&outcomes);
+                }
+            });
+            RefCell::new(Engine {
+                current: None,
+                duration: 0,
+                library: Library::open(),
+                listing: Listing::default(),
+                next_row: 0,
+                paused: false,
+                playback: Playback::default(),
+                player: Player::new(sender),
+                playing: false,
+                pool: Pool::new(pool_sender),
+                position: 0,
+                rows: Vec::new(),
+                scrobbler: Scrobbler::start(),
+                songs: Vec::new(),
+                volume: 1.0,
+            })
+        })
+    }
+
+    pub fn songs(&self) -> &[Track] {
+        &self.songs
+    }
+
+    /// Returns the position of the song playing or paused, or of the one last
+    /// played when stopped.
+    pub fn current(&self) -> Option<usize> {
+        self.current.and_then(|row| self.index(row))
+    }
+
+    /// Returns the positions of the songs queued, the first one played
+    /// first.
+    pub fn queued(&self) -> Vec<usize> {
+        let queued = self.playback.queued().iter();
+        queued.filter_map(|&row| self.index(row)).collect()
+    }
+
+    pub fn report(&self) -> Report<'_> {
+        let state = if self.playing {
+            "playing"
+        } else if self.paused {
+            "paused"
+        } else {
+            return Report {
+                state: "stopped",
+                track: None,
+                position: 0,
+                duration: 0,
+                volume: self.volume,
+                repeat: self.playback.repeat(),
+                shuffle: self.playback.shuffle(),
+            };
+        };
+        Report {
+            state,
+            track: self.current().map(|index| &self.songs[index]),
+            position: self.position,
+            duration: self.duration,
+            volume: self.volume,
+            repeat: self.playback.repeat(),
+            shuffle: self.playback.shuffle(),
+        }
+    }
+
+    /// Lists every song of the library, by album artist, album, disc and
+    /// track.
+    pub fn open_library(&mut self) {
+        for track in self.library.tracks() {
+            listing::add_track(self, &track, None);
+        }
+    }
+
+    /// Adds songs, the songs of folders and the songs listed by playlists,
+    /// playing the first of them when `play` is set. Returns what could not
+    /// be opened.
+    pub fn open(&mut self, files: Vec<PathBuf>, play: bool) -> Vec<Failure> {
+        let first = self.rows.len();
+        let (_, failures) = listing::add(self, &files, None);
+        if play {
+            self.play_index(first);
+        }
+        failures
+    }
+
+    /// Returns the position of `row` among the songs.
+    fn index(&self, row: usize) -> Option<usize> {
+        self.rows.iter().position(|&other| other == row)
+    }
+
+    /// Takes the song at `index` out of the playlist, stopping it if it is
+    /// playing.
+    pub fn remove(&mut self, index: usize) {
+        let row = match self.rows.get(index) {
+            Some(&row) => row,
+            None => return,
+        };
+        if self.current == Some(row) {
+            self.stop();
+            self.current = None;
+        }
+        self.remove_row(&row);
+        let rows = &self.rows;
+        self.playback
+            .update_rows(|row| Some(*row).filter(|row| rows.contains(row)));
+        let remaining: HashSet<&str> = self.songs.iter().map(|track| track.path.as_str()).collect();
+        self.listing
+            .retain_durations(|path| remaining.contains(path));
+        self.refresh_continuation();
+    }
+
+    /// Plays the song at `index` once the song playing and those queued
+    /// before it have.
+    pub fn queue(&mut self, index: usize) {
+        if let Some(&row) = self.rows.get(index) {
+            self.playback.queue(Some(row));
+            self.refresh_continuation();
+        }
+    }
+
+    /// Plays the song at `index`, or the first one after it when its file is
+    /// missing, as songs listed by playlists may be.
+    pub fn play_index(&mut self, index: usize) {
+        if let Some(&row) = self.rows.get(index) {
+            self.play_row(row);
+        }
+    }
+
+    /// Plays the song in `row`, or the first one after it whose file is
+    /// there, telling about the songs passed over.
+    fn play_row(&mut self, row: usize) {
+        let songs = Listed {
+            current: self.current,
+            rows: &self.rows,
+            songs: &self.songs,
+        };
+        let (start, missing) = self.playback.start(&songs, row);
+        if !missing.is_empty() {
+            eprintln!(
+                "Could not play, as the file is missing: {}",
+                missing.join(", ")
+            );
+        }
+        let start = match start {
+            Some(start) => start,
+            None => return,
+        };
+        self.finish_listen();
+        self.current = Some(start.row);
+        self.paused = false;
+        self.song_changed();
+        if start.spans.is_empty() {
+            self.player.load(&start.file);
+        } else {
+            self.player.load_spans(&start.file, start.spans);
+        }
+    }
+
+    /// Fills the songs read by the import workers, and stores the durations
+    /// they computed.
+    fn imported(&mut self, outcomes: &[Outcome]) {
+        for outcome in outcomes {
+            if let Outcome::Duration(path, duration) = outcome {
+                self.library.set_duration(path, *duration);
+            }
+        }
+        let imported = listing::imported(self, outcomes);
+        let current = self.current().map(|index| self.songs[index].path.clone());
+        for (path, duration) in imported.durations {
+            if current.as_ref() == Some(&path) {
+                self.duration = duration;
+            }
+            self.playback.set_duration(&path, duration);
+        }
+        match imported.restart {
+            Some(row) => self.play_row(row),
+            None => self.refresh_continuation(),
+        }
+    }
+
+    /// Works out again which virtual tracks play on from the one playing,
+    /// and has the player follow.
+    fn refresh_continuation(&mut self) {
+        if !self.playing && !self.paused {
+            return;
+        }
+        let songs = Listed {
+            current: self.current,
+            rows: &self.rows,
+            songs: &self.songs,
+        };
+        if let Some(spans) = self.playback.refresh(&songs) {
+            self.player.set_spans(spans);
+        }
+    }
+
+    /// Moves the current song on to the next virtual track, which the player
+    /// went on to by itself.
+    fn next_virtual_track(&mut self) {
+        if let Some(row) = self.playback.next_virtual_track() {
+            self.finish_listen();
+            self.current = Some(row);
+            self.song_changed();
+        }
+    }
+
+    /// Starts counting the current song, which just started playing.
+    fn song_changed(&mut self) {
+        let path = match self.current() {
+            Some(index) => self.songs[index].path.clone(),
+            None => return,
+        };
+        let duration = self.listing.duration(&path);
+        self.duration = duration.unwrap_or(0);
+        self.position = 0;
+        self.playback.listen(path, duration);
+    }
+
+    /// Resumes the song paused, or plays the current song again, or the
+    /// first one.
+    pub fn play(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.player.resume();
+            return;
+        }
+        if !self.playing {
+            self.play_index(self.current().unwrap_or(0));
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if self.playing {
+            self.paused = true;
+            self.player.pause();
+        }
+    }
+
+    pub fn play_pause(&mut self) {
+        if self.playing {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.finish_listen();
+        self.paused = false;
+        self.position = 0;
+        self.playback.stop();
+        self.player.stop();
+    }
+
+    pub fn next(&mut self) {
+        self.step(|playback, songs| playback.next(songs));
+    }
+
+    pub fn previous(&mut self) {
+        self.step(|playback, songs| playback.previous(songs));
+    }
+
+    /// Plays the song `pick` chooses from the playback.
+    fn step(&mut self, pick: impl FnOnce(&mut Playback<usize>, &Listed<'_>) -> Option<usize>) {
+        let songs = Listed {
+            current: self.current,
+            rows: &self.rows,
+            songs: &self.songs,
+        };
+        if let Some(row) = pick(&mut self.playback, &songs) {
+            self.play_row(row);
+        }
+    }
+
+    /// Moves playback of the current song `offset` milliseconds forward, or
+    /// back when negative.
+    pub fn seek_by(&mut self, offset: i64) {
+        self.seek((self.position as i64 + offset).max(0) as u64);
+    }
+
+    /// Moves playback of the current song to `position`, in milliseconds.
+    /// Seeking past the end goes on to the next song.
+    pub fn seek(&mut self, position: u64) {
+        if !self.playing && !self.paused {
+            return;
+        }
+        if self.duration > 0 && position >= self.duration {
+            self.next();
+            return;
+        }
+        if self.current.is_some() {
+            self.player.skip(position as u32);
+            self.position = position;
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.player.set_volume(self.volume);
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.playback.set_repeat(repeat);
+        self.refresh_continuation();
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.playback.set_shuffle(shuffle);
+        self.refresh_continuation();
+    }
+
+    fn player_message(&mut self, msg: PlayerMsg) {
+        match msg {
+            PlayerMsg::Play => self.playing = true,
+            PlayerMsg::Stop => self.playing = false,
+            PlayerMsg::Time(time) => {
+                self.position = time;
+                self.playback.listened(time, &self.library, &self.scrobbler);
+            }
+            PlayerMsg::End => self.step(|playback, songs| playback.ended(songs)),
+            PlayerMsg::NextTrack => self.next_virtual_track(),
+        }
+    }
+
+    fn finish_listen(&mut self) {
+        self.playback.finish_listen(&self.library);
+    }
+}
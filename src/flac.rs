@@ -1,34 +1,149 @@
+use claxon::frame::FrameReader;
+use claxon::input::BufferedReader;
 use claxon::FlacReader;
 use std::fs::File;
-use std::path::Path;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// How many bytes are looked through at a time for the start of a frame.
+const SEARCH_CHUNK: usize = 16 * 1024;
+/// The longest a frame header can be, from its sync code to its CRC.
+const MAX_HEADER_LENGTH: usize = 16;
 
 pub struct FlacDecoder {
-    pub reader: FlacReader<File>,
-    current_time: u32,
+    frames: FrameReader<BufferedReader<File>>,
+    path: PathBuf,
+    /// Where the first frame starts in the file, after the metadata.
+    audio_start: u64,
+    /// The number of samples in each frame of streams of fixed size frames.
+    block_size: u64,
+    /// The number of samples of each channel in the stream, when known.
+    samples: Option<u64>,
+    /// The first sample of the next block read.
+    position: u64,
+    /// The first sample of the block played last.
+    current_sample: u64,
+    /// The first sample wanted from the next block read, which may start
+    /// earlier once seeking.
+    wanted: u64,
+    buffer: Vec<i32>,
     pub sample_rate: u32,
-    max_block_len: usize,
     pub num_channels: u32,
+    /// The sample playback stops at, at the end of the last span.
+    end: Option<u64>,
 }
 
 impl FlacDecoder {
     pub fn new(data: &Path) -> Self {
-        
         let reader = FlacReader::open(data).expect("failed to open FLAC stream");
-        let num_channels = reader.streaminfo().channels;
-        let sample_rate = reader.streaminfo().sample_rate;
-        let max_block_len = reader.streaminfo().max_block_size as usize * num_channels as usize;
+        let info = reader.streaminfo();
+        let max_block_len = info.max_block_size as usize * info.channels as usize;
+        let mut file = File::open(data).expect("failed to open FLAC stream");
+        let audio_start = audio_start(&mut file).expect("failed to read FLAC metadata");
+        file.seek(SeekFrom::Start(audio_start))
+            .expect("failed to open FLAC stream");
 
         FlacDecoder {
-            reader: FlacReader::open(data).expect("failed to open FLAC stream"),
-            current_time: 0,
-            sample_rate,
-            max_block_len,
-            num_channels,
+            frames: FrameReader::new(BufferedReader::new(file)),
+            path: data.to_path_buf(),
+            audio_start,
+            block_size: u64::from(info.max_block_size),
+            samples: info.samples,
+            position: 0,
+            current_sample: 0,
+            wanted: 0,
+            buffer: Vec::with_capacity(max_block_len),
+            sample_rate: info.sample_rate,
+            num_channels: info.channels,
+            end: None,
+        }
+    }
+
+    /// Only plays the stream from sample `start` to sample `end`.
+    pub fn set_range(&mut self, start: u64, end: Option<u64>) {
+        self.end = end;
+        self.seek(start);
+    }
+
+    /// Stops playback at sample `end`, or at the end of the stream.
+    pub fn set_end(&mut self, end: Option<u64>) {
+        self.end = end;
+    }
+
+    /// Goes on playing from `sample`, reading from the frame holding it
+    /// without decoding those before.
+    pub fn seek(&mut self, sample: u64) {
+        match self.find_frame(sample) {
+            Ok((file, first)) => {
+                self.frames = FrameReader::new(BufferedReader::new(file));
+                self.position = first;
+                self.current_sample = sample;
+                self.wanted = sample;
+            }
+            Err(error) => eprintln!("Unable to seek in {}: {}", self.path.display(), error),
         }
     }
 
-    pub fn current_time(&self) -> u32 {
-        self.current_time
+    /// Returns the file at the start of the last frame starting at or before
+    /// `sample`, found by bisecting the file, and the first sample of that
+    /// frame.
+    fn find_frame(&self, sample: u64) -> io::Result<(File, u64)> {
+        let mut file = File::open(&self.path)?;
+        let mut low = self.audio_start;
+        let mut high = file.metadata()?.len();
+        let mut found = (self.audio_start, 0);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.next_frame(&mut file, middle, high)? {
+                Some((offset, first)) if first <= sample => {
+                    found = (offset, first);
+                    low = offset + 1;
+                }
+                _ => high = middle,
+            }
+        }
+        file.seek(SeekFrom::Start(found.0))?;
+        Ok((file, found.1))
+    }
+
+    /// Looks in `file` for the first frame starting from `from` and before
+    /// `limit`, returning where it starts and its first sample.
+    fn next_frame(&self, file: &mut File, from: u64, limit: u64) -> io::Result<Option<(u64, u64)>> {
+        let mut buffer = Vec::with_capacity(SEARCH_CHUNK + MAX_HEADER_LENGTH);
+        let mut offset = from;
+        while offset < limit {
+            file.seek(SeekFrom::Start(offset))?;
+            buffer.clear();
+            (&mut *file)
+                .take((SEARCH_CHUNK + MAX_HEADER_LENGTH) as u64)
+                .read_to_end(&mut buffer)?;
+            let candidates = buffer
+                .len()
+                .min(SEARCH_CHUNK)
+                .min((limit - offset) as usize);
+            for index in 0..candidates {
+                let sample = frame_sample(&buffer[index..], self.block_size, self.num_channels)
+                    .filter(|&sample| self.samples.is_none_or(|samples| sample < samples));
+                // Audio data may look like a header by chance, but not like a
+                // whole frame, whose checksum is checked once decoded.
+                if let Some(sample) = sample {
+                    let start = offset + index as u64;
+                    if is_frame(file, start)? {
+                        return Ok(Some((start, sample)));
+                    }
+                }
+            }
+            if buffer.len() <= SEARCH_CHUNK {
+                break;
+            }
+            offset += SEARCH_CHUNK as u64;
+        }
+        Ok(None)
+    }
+
+    /// Returns the first sample played last.
+    pub fn current_sample(&self) -> u64 {
+        self.current_sample
     }
 
     pub fn sample_rate(&self) -> u32 {
@@ -39,83 +154,154 @@ impl FlacDecoder {
 pub fn next_sample(decoder: &mut FlacDecoder) -> Option<Vec<[i16; 2]>> {
     let mut data = Vec::new();
 
-    let mut f_reader = decoder.reader.blocks();
-    let sample_buffer = Vec::with_capacity(decoder.max_block_len); // TODO: Re-use buffer
-
-    match f_reader.read_next_or_eof(sample_buffer) {
-        Ok(Some(block)) => {
-            decoder.current_time =
-                ((block.time() as u64 * 1000) / decoder.sample_rate as u64) as u32;
-            for s in block.stereo_samples() {
-                data.push([s.0 as i16, s.1 as i16]); // Maybe i16??
-            }
+    while data.is_empty() {
+        let buffer = std::mem::take(&mut decoder.buffer);
+        let block = match decoder.frames.read_next_or_eof(buffer) {
+            Ok(Some(block)) => block,
+            Ok(None) => return None,
+            Err(_) => panic!("Failed to decode"),
+        };
+        // The times of blocks are counted rather than taken from claxon, which
+        // gets the last frame of streams of fixed size frames wrong.
+        let time = decoder.position;
+        let len = u64::from(block.duration());
+        decoder.position += len;
+        if decoder.end.is_some_and(|end| time >= end) {
+            return None;
         }
-        Ok(None) => return None,
-        Err(_) => panic!("Failed to decode"),
+        let first = decoder.wanted.saturating_sub(time).min(len);
+        let last = decoder.end.map_or(len, |end| len.min(end - time));
+        decoder.current_sample = time + first;
+        for s in block
+            .stereo_samples()
+            .take(last as usize)
+            .skip(first as usize)
+        {
+            data.push([s.0 as i16, s.1 as i16]); // Maybe i16??
+        }
+        decoder.buffer = block.into_buffer();
     }
 
     Some(data)
 }
 
-// pub fn next_sample(decoder: &mut FlacDecoder) -> Option<Vec<[i16; 2]>> {
-//     let mut data = Vec::new();
-
-//     let mut s_reader = decoder.reader.samples();
-
-//     let mut count = 0;
-//     for _ in 0..decoder.max_block_len {
-//         if let Some(s1) = s_reader.next() {
-//             if let Ok(s1) = s1 {
-//                 if let Some(s2) = s_reader.next() {
-//                     if let Ok(s2) = s2 {
-//                         count += 1;
-//                         data.push([s1 as i16, s2 as i16]);
-//                     }
-//                 }
-//             }
-//         }
-//     }
-
-//     decoder.current_time += (decoder.sample_time * count as f64) as u32;
-
-//     Some(data)
-// }
-
-pub fn skip_to(data: &Path, time: u32, decoder: &mut FlacDecoder) {
-    if cfg!(USE_FRAMES) {
-        decoder.reader = FlacReader::open(data).expect("failed to open FLAC stream");
-        let mut sample_buffer = Vec::with_capacity(decoder.max_block_len);
-        let time = ((time * decoder.sample_rate) / 1000) as u64;
-
-        let mut f_reader = decoder.reader.blocks();
-        loop {
-            match f_reader.read_next_or_eof(sample_buffer) {
-                Ok(Some(block)) => {
-                    let block_time = block.time();
-                    if block_time >= time {
-                        decoder.current_time = (time as u32 * 100) / decoder.sample_rate;
-                        break;
-                    }
+/// Returns where the frames of the FLAC stream in `file` start, past the
+/// `fLaC` marker and the metadata blocks.
+fn audio_start(file: &mut File) -> io::Result<u64> {
+    let mut marker = [0; 4];
+    file.read_exact(&mut marker)?;
+    if &marker != b"fLaC" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a FLAC stream",
+        ));
+    }
+    let mut offset = 4;
+    loop {
+        let mut header = [0; 4];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let length = u64::from(header[1]) << 16 | u64::from(header[2]) << 8 | u64::from(header[3]);
+        offset += 4 + length;
+        // The first bit marks the last block.
+        if header[0] & 0x80 != 0 {
+            return Ok(offset);
+        }
+    }
+}
 
-                    sample_buffer = block.into_buffer();
-                }
-                Ok(None) => panic!("Skip position out of range!"),
-                Err(_) => panic!("Failed to decode"),
-            }
+/// Tells whether a frame starting at `offset` in `file` decodes.
+fn is_frame(file: &mut File, offset: u64) -> io::Result<bool> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut frames = FrameReader::new(BufferedReader::new(&mut *file));
+    Ok(matches!(frames.read_next_or_eof(Vec::new()), Ok(Some(_))))
+}
+
+/// Reads the frame header at the start of `bytes`, returning the first
+/// sample of the frame, or `None` if there is no valid header there for a
+/// stream of `channels` channels. Frames of streams of fixed size frames
+/// hold `block_size` samples.
+fn frame_sample(bytes: &[u8], block_size: u64, channels: u32) -> Option<u64> {
+    if bytes.len() < 5 || bytes[0] != 0xFF || bytes[1] & 0xFE != 0xF8 {
+        return None;
+    }
+    let size_code = bytes[2] >> 4;
+    let rate_code = bytes[2] & 0x0F;
+    // Codes past 7 stand for the ways of coding stereo.
+    let assignment = bytes[3] >> 4;
+    let depth = (bytes[3] >> 1) & 0x07;
+    if size_code == 0 || rate_code == 0x0F || depth == 3 || depth == 7 {
+        return None;
+    }
+    match assignment {
+        0..=7 if u32::from(assignment) + 1 == channels => (),
+        8..=10 if channels == 2 => (),
+        _ => return None,
+    }
+    if bytes[3] & 0x01 != 0 {
+        return None;
+    }
+
+    // The frame or sample number is coded like a UTF-8 character.
+    let lead = bytes[4];
+    let extra = lead.leading_ones() as usize;
+    let (mut number, extra) = match extra {
+        0 => (u64::from(lead), 0),
+        2..=7 => (u64::from(lead & (0x7F >> extra)), extra - 1),
+        _ => return None,
+    };
+    for index in 0..extra {
+        let byte = *bytes.get(5 + index)?;
+        if byte & 0xC0 != 0x80 {
+            return None;
         }
+        number = number << 6 | u64::from(byte & 0x3F);
+    }
+
+    let mut length = 5 + extra;
+    length += match size_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    length += match rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if crc8(bytes.get(..length)?) != *bytes.get(length)? {
+        return None;
+    }
+    // The last bit of the sync code tells frames of variable size, which
+    // give their first sample rather than their number.
+    if bytes[1] & 0x01 == 0 {
+        Some(number * block_size)
     } else {
-        decoder.reader = FlacReader::open(data).expect("failed to open FLAC stream");
-        let n = (time / 1000) * decoder.sample_rate;
-        let mut s_reader = decoder.reader.samples();
-        s_reader.nth(n as usize * 2);
-        decoder.current_time = time;
+        Some(number)
     }
 }
 
-pub fn compute_duration(data: &Path) -> u64 {
-    let reader = FlacReader::open(data).expect("failed to open FLAC stream");
-    let sample_rate = reader.streaminfo().sample_rate;
-    let samples = reader.streaminfo().samples;
-    let total_duration = samples.unwrap() / sample_rate as u64;
-    total_duration
+/// Computes the checksum of frame headers, with the polynomial
+/// x^8 + x^2 + x + 1.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Returns the length of the stream at `data`, in seconds, or `None` when it
+/// cannot be read or does not tell how many samples it holds.
+pub fn compute_duration(data: &Path) -> Option<u64> {
+    let reader = FlacReader::open(data).ok()?;
+    let info = reader.streaminfo();
+    match (info.samples, info.sample_rate) {
+        (Some(samples), sample_rate) if sample_rate > 0 => Some(samples / u64::from(sample_rate)),
+        _ => None,
+    }
 }
@@ -0,0 +1,303 @@
+use crossbeam::channel::{self, Receiver};
+use relm::Sender;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::artwork::{self, Pixels};
+use crate::cue;
+use crate::library::{Library, Track};
+use crate::player::Player;
+
+/// Upper bound on the number of worker threads, whatever the number of cores.
+const MAX_WORKERS: usize = 4;
+
+/// Results are sent to the main thread once this many are ready, or once the
+/// oldest of them has waited for `BATCH_INTERVAL`.
+const BATCH_SIZE: usize = 50;
+const BATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+enum Job {
+    Artwork(String, Box<Track>),
+    Scan(usize, PathBuf),
+    Split(usize, Box<Track>),
+    Duration(PathBuf),
+}
+
+pub enum Outcome {
+    /// The artwork cached under a key, sent before the first song using it.
+    Artwork(String, Option<(Pixels, Pixels)>),
+    /// The song read for the scan with the given id.
+    Scanned(usize, Box<Track>),
+    /// The virtual tracks the file of the scan or split with the given id is
+    /// made of, according to its CUE sheet.
+    Split(usize, Vec<Track>),
+    /// The duration of a song, in seconds.
+    Duration(PathBuf, u64),
+}
+
+/// A fixed set of threads reading tags, durations and artwork away from the
+/// main thread, shared by the playlists. Results come back in batches through
+/// the sender given to `Pool::new`.
+pub struct Pool {
+    /// The ids of the scans dropped before they started.
+    cancelled: Arc<Mutex<HashSet<usize>>>,
+    claimed_artwork: Arc<Mutex<HashSet<String>>>,
+    jobs: channel::Sender<Job>,
+    next_id: Cell<usize>,
+}
+
+impl Pool {
+    pub fn new(sender: Sender<Vec<Outcome>>) -> Pool {
+        let (jobs, queue) = channel::unbounded();
+        let (outcomes, results) = mpsc::channel();
+        let claimed_artwork = Arc::new(Mutex::new(HashSet::new()));
+        let cancelled = Arc::new(Mutex::new(HashSet::new()));
+
+        let workers = thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+            .min(MAX_WORKERS);
+        for _ in 0..workers {
+            let worker = Worker {
+                cancelled: cancelled.clone(),
+                claimed_artwork: claimed_artwork.clone(),
+                outcomes: outcomes.clone(),
+            };
+            let queue = queue.clone();
+            thread::spawn(move || worker.run(&queue));
+        }
+        thread::spawn(move || send_batches(&results, |batch| sender.send(batch).is_ok()));
+
+        Pool {
+            cancelled,
+            claimed_artwork,
+            jobs,
+            next_id: Cell::new(0),
+        }
+    }
+
+    /// Reads the song at `path` into the library. Returns the id it is
+    /// reported under.
+    pub fn scan(&self, path: PathBuf) -> usize {
+        let id = self.next_id();
+        self.push(Job::Scan(id, path));
+        id
+    }
+
+    /// Splits `track` into the virtual tracks of its CUE sheet. Returns the id
+    /// they are reported under. Nothing is reported for files without a sheet.
+    pub fn split(&self, track: &Track) -> usize {
+        let id = self.next_id();
+        self.push(Job::Split(id, Box::new(track.clone())));
+        id
+    }
+
+    pub fn compute_duration(&self, path: PathBuf) {
+        self.push(Job::Duration(path));
+    }
+
+    /// Drops the scans and splits with the given ids that have not started
+    /// yet.
+    pub fn cancel(&self, ids: impl IntoIterator<Item = usize>) {
+        self.cancelled.lock().unwrap().extend(ids);
+    }
+
+    /// Loads the artwork of `track`, unless the artwork under the same key has
+    /// already been loaded or is being loaded.
+    pub fn load_artwork(&self, track: &Track) {
+        if let Some(key) = artwork::key(track) {
+            if self.claimed_artwork.lock().unwrap().insert(key.clone()) {
+                self.push(Job::Artwork(key, Box::new(track.clone())));
+            }
+        }
+    }
+
+    /// Lets the artwork under `key` be loaded again, after it changed.
+    pub fn release_artwork(&self, key: &str) {
+        self.claimed_artwork.lock().unwrap().remove(key);
+    }
+
+    fn next_id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    fn push(&self, job: Job) {
+        self.jobs.send(job).expect("Cannot queue import job");
+    }
+}
+
+struct Worker {
+    cancelled: Arc<Mutex<HashSet<usize>>>,
+    claimed_artwork: Arc<Mutex<HashSet<String>>>,
+    outcomes: mpsc::Sender<Outcome>,
+}
+
+impl Worker {
+    /// Runs jobs until the pool is dropped.
+    fn run(&self, queue: &Receiver<Job>) {
+        // Opened by the first scan, as the other jobs do without.
+        let mut library = None;
+        for job in queue.iter() {
+            let outcome = match job {
+                Job::Artwork(key, track) => Outcome::Artwork(key, load_pixels(&track)),
+                Job::Scan(id, path) => {
+                    if self.cancelled.lock().unwrap().remove(&id) {
+                        continue;
+                    }
+                    // Virtual tracks are kept out of the library, which holds
+                    // their file.
+                    if cue::split_path(&path.to_string_lossy()).is_some() {
+                        let track = Track::read(&path);
+                        Outcome::Scanned(id, Box::new(track))
+                    } else {
+                        self.scan(library.get_or_insert_with(Library::open), id, &path)
+                    }
+                }
+                Job::Split(id, track) => {
+                    if self.cancelled.lock().unwrap().remove(&id) {
+                        continue;
+                    }
+                    match cue::read(Path::new(&track.path)) {
+                        Some(sheet) => Outcome::Split(id, sheet.virtual_tracks(&track)),
+                        None => continue,
+                    }
+                }
+                Job::Duration(path) => {
+                    // Virtual tracks last until the next one starts.
+                    let duration = match cue::split_path(&path.to_string_lossy()) {
+                        Some(_) => Some(Track::read(&path).duration.unwrap_or_default()),
+                        None => Player::compute_duration(&path),
+                    };
+                    // Songs whose length cannot be told are left without one.
+                    match duration {
+                        Some(duration) => Outcome::Duration(path, duration),
+                        None => continue,
+                    }
+                }
+            };
+            if self.outcomes.send(outcome).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn scan(&self, library: &Library, id: usize, path: &Path) -> Outcome {
+        let mut track = library.scan(path);
+        if track.duration.is_none() {
+            track.duration = Player::compute_duration(path);
+            if let Some(duration) = track.duration {
+                library.set_duration(path, duration);
+            }
+        }
+        if let Some(artwork) = self.claim_artwork(&track) {
+            let _ = self.outcomes.send(artwork);
+        }
+        let sheet = if track.cue_sheet {
+            cue::read(path)
+        } else {
+            None
+        };
+        match sheet {
+            Some(sheet) => Outcome::Split(id, sheet.virtual_tracks(&track)),
+            None => Outcome::Scanned(id, Box::new(track)),
+        }
+    }
+
+    /// Loads the artwork of `track` unless another song already caused it to
+    /// be loaded.
+    fn claim_artwork(&self, track: &Track) -> Option<Outcome> {
+        let key = artwork::key(track)?;
+        if !self.claimed_artwork.lock().unwrap().insert(key.clone()) {
+            return None;
+        }
+        Some(Outcome::Artwork(key, load_pixels(track)))
+    }
+}
+
+fn load_pixels(track: &Track) -> Option<(Pixels, Pixels)> {
+    let (thumbnail, pixbuf) = artwork::load(track)?;
+    Some((Pixels::new(&thumbnail)?, Pixels::new(&pixbuf)?))
+}
+
+/// Passes the results on to `send` in batches, until it returns false.
+fn send_batches<T, F>(results: &mpsc::Receiver<T>, mut send: F)
+where
+    F: FnMut(Vec<T>) -> bool,
+{
+    // Wait as long as needed for the first result of a batch, then only until
+    // the batch is due.
+    while let Ok(first) = results.recv() {
+        let deadline = Instant::now() + BATCH_INTERVAL;
+        let mut batch = vec![first];
+        while batch.len() < BATCH_SIZE {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match results.recv_timeout(timeout) {
+                Ok(outcome) => batch.push(outcome),
+                Err(_) => break,
+            }
+        }
+        if !send(batch) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_scans_are_dropped() {
+        let (outcomes, results) = mpsc::channel();
+        let worker = Worker {
+            cancelled: Arc::new(Mutex::new(vec![0, 2].into_iter().collect())),
+            claimed_artwork: Arc::new(Mutex::new(HashSet::new())),
+            outcomes,
+        };
+        let (jobs, queue) = channel::unbounded();
+        for id in 0..3 {
+            let path = PathBuf::from(format!("/nowhere/album.flac#{}", id + 1));
+            jobs.send(Job::Scan(id, path)).unwrap();
+        }
+        drop(jobs);
+        worker.run(&queue);
+
+        let scanned: Vec<usize> = results
+            .try_iter()
+            .filter_map(|outcome| match outcome {
+                Outcome::Scanned(id, _) => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(scanned, vec![1]);
+        assert!(worker.cancelled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn results_are_sent_in_batches() {
+        let (outcomes, results) = mpsc::channel();
+        for number in 0..BATCH_SIZE * 2 + 1 {
+            outcomes.send(number).unwrap();
+        }
+        let (batches, received) = mpsc::channel();
+        let sender =
+            thread::spawn(move || send_batches(&results, |batch| batches.send(batch).is_ok()));
+        let sizes: Vec<usize> = received.iter().take(3).map(|batch| batch.len()).collect();
+        assert_eq!(sizes, vec![BATCH_SIZE, BATCH_SIZE, 1]);
+
+        // A lone result waits for the interval, not for the batch to fill.
+        let start = Instant::now();
+        outcomes.send(0).unwrap();
+        assert_eq!(received.recv().unwrap(), vec![0]);
+        assert!(start.elapsed() >= BATCH_INTERVAL);
+        drop(outcomes);
+        sender.join().unwrap();
+    }
+}
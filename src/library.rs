@@ -0,0 +1,1054 @@
+use metaflac::block::{Block, BlockType};
+use metaflac::Tag;
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, OptionalExtension, Row, NO_PARAMS};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::artwork;
+use crate::config;
+use crate::cue;
+use crate::tags::{self, Tags};
+
+/// Shown for the fields a song is not tagged with.
+const UNKNOWN: &str = "Unknown";
+
+const DATABASE_FILE: &str = "library.db";
+const ROOTS_FILE: &str = "library-roots";
+/// Remembers whether ratings are also written to the files.
+const RATINGS_IN_FILES_FILE: &str = "ratings-in-files";
+
+/// How long a connection waits for another one, such as the one of the
+/// watcher thread, to finish writing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Artwork reference of tracks carrying their own picture blocks.
+pub const EMBEDDED_ARTWORK: &str = "embedded";
+
+/// Names of the image files looked for next to tracks without an embedded
+/// front cover, in order of preference and matched ignoring case.
+const FOLDER_ARTWORK: [&str; 3] = ["cover", "folder", "front"];
+
+const IMAGE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS artists (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS albums (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL,
+        artist_id INTEGER NOT NULL REFERENCES artists(id),
+        year TEXT NOT NULL,
+        artwork TEXT,
+        UNIQUE (title, artist_id)
+    );
+    CREATE TABLE IF NOT EXISTS tracks (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        title TEXT NOT NULL,
+        artist_id INTEGER NOT NULL REFERENCES artists(id),
+        album_id INTEGER NOT NULL REFERENCES albums(id),
+        genre TEXT NOT NULL,
+        year TEXT NOT NULL,
+        track TEXT NOT NULL,
+        disc TEXT NOT NULL,
+        composer TEXT NOT NULL,
+        sample_rate INTEGER,
+        duration INTEGER,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        artwork TEXT,
+        play_count INTEGER NOT NULL DEFAULT 0,
+        comments TEXT NOT NULL DEFAULT '',
+        added INTEGER NOT NULL DEFAULT 0,
+        skip_count INTEGER NOT NULL DEFAULT 0,
+        last_played INTEGER,
+        rating INTEGER,
+        cue_sheet INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS tracks_album ON tracks (album_id);
+    CREATE TABLE IF NOT EXISTS genres (
+        track_id INTEGER NOT NULL REFERENCES tracks(id),
+        name TEXT NOT NULL,
+        PRIMARY KEY (track_id, name)
+    );
+    CREATE INDEX IF NOT EXISTS genres_name ON genres (name);
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        played INTEGER NOT NULL
+    );
+";
+
+const TRACK_ORDER: &str = "album_artists.name, albums.title, \
+    CAST(tracks.disc AS INTEGER), CAST(tracks.track AS INTEGER), tracks.path";
+
+const SELECT_TRACKS: &str = "
+    SELECT tracks.path, tracks.title, artists.name, album_artists.name, albums.title,
+        tracks.genre, tracks.year, tracks.track, tracks.disc, tracks.composer,
+        tracks.sample_rate, tracks.duration, tracks.size, tracks.mtime, tracks.artwork,
+        tracks.play_count, tracks.comments, tracks.added, tracks.skip_count,
+        tracks.last_played, tracks.rating, tracks.cue_sheet
+    FROM tracks
+    JOIN artists ON artists.id = tracks.artist_id
+    JOIN albums ON albums.id = tracks.album_id
+    JOIN artists AS album_artists ON album_artists.id = albums.artist_id
+";
+
+/// A song of the collection as stored in the library.
+#[derive(Clone)]
+pub struct Track {
+    pub path: String,
+    pub tags: Tags,
+    pub sample_rate: Option<u32>,
+    /// Length in seconds, unknown until the stream has been decoded once.
+    pub duration: Option<u64>,
+    pub size: u64,
+    pub mtime: i64,
+    /// Either `EMBEDDED_ARTWORK` or the path of an image next to the file.
+    pub artwork: Option<String>,
+    pub play_count: u32,
+    /// When the song was first added to the library, in seconds since the
+    /// Unix epoch.
+    pub added: i64,
+    /// How many times the song was left before it counted as played.
+    pub skip_count: u32,
+    /// When the song last counted as played, in seconds since the Unix epoch.
+    pub last_played: Option<i64>,
+    /// From none to `tags::MAX_RATING` stars.
+    pub rating: Option<u8>,
+    /// Whether a CUE sheet splits the file into virtual tracks.
+    pub cue_sheet: bool,
+}
+
+impl Track {
+    /// Returns the song at `path` as known before it is read: by its file
+    /// name alone.
+    pub fn unread(path: &Path) -> Track {
+        let title = path.file_stem().unwrap_or_default().to_string_lossy();
+        Track {
+            path: path.to_string_lossy().to_string(),
+            tags: Tags {
+                title: Some(title.to_string()),
+                ..Tags::default()
+            },
+            sample_rate: None,
+            duration: None,
+            size: 0,
+            mtime: 0,
+            artwork: None,
+            play_count: 0,
+            added: 0,
+            skip_count: 0,
+            last_played: None,
+            rating: None,
+            cue_sheet: false,
+        }
+    }
+
+    /// Reads the tags and file attributes of the song at `path`.
+    pub fn read(path: &Path) -> Track {
+        if let Some((file, number)) = cue::split_path(&path.to_string_lossy()) {
+            let whole = Track::read(&file);
+            let sheet = cue::read(&file);
+            let cue_track = sheet.as_ref().and_then(|sheet| sheet.track(number));
+            return match (&sheet, cue_track) {
+                (Some(sheet), Some(cue_track)) => sheet.virtual_track(&whole, cue_track),
+                _ => Track {
+                    path: path.to_string_lossy().to_string(),
+                    ..whole
+                },
+            };
+        }
+
+        let (size, mtime) = file_stamp(path).unwrap_or((0, 0));
+        let mut track = Track {
+            path: path.to_string_lossy().to_string(),
+            tags: Tags::default(),
+            sample_rate: None,
+            duration: None,
+            size,
+            mtime,
+            artwork: folder_artwork(path),
+            play_count: 0,
+            added: now(),
+            skip_count: 0,
+            last_played: None,
+            rating: None,
+            cue_sheet: false,
+        };
+
+        if let Ok(tag) = Tag::read_from_path(path) {
+            track.tags = Tags::read(&tag);
+            track.rating = tags::rating(&tag);
+            track.cue_sheet = cue::read_with_tag(path, &tag).is_some();
+            for block in tag.get_blocks(BlockType::StreamInfo) {
+                if let Block::StreamInfo(ref info) = *block {
+                    track.sample_rate = Some(info.sample_rate);
+                }
+            }
+            // Other embedded pictures, such as the back cover, are only
+            // shown when there is no better image.
+            if artwork::has_front_cover(&tag)
+                || (track.artwork.is_none() && !tag.pictures().is_empty())
+            {
+                track.artwork = Some(EMBEDDED_ARTWORK.to_string());
+            }
+        }
+        track
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Track> {
+        let comments: String = row.get(16)?;
+        let tags = if comments.is_empty() {
+            legacy_tags(row)?
+        } else {
+            Tags::parse(&comments)
+        };
+        Ok(Track {
+            path: row.get(0)?,
+            tags,
+            sample_rate: row.get::<_, Option<i64>>(10)?.map(|rate| rate as u32),
+            duration: row
+                .get::<_, Option<i64>>(11)?
+                .map(|duration| duration as u64),
+            size: row.get::<_, i64>(12)? as u64,
+            mtime: row.get(13)?,
+            artwork: row.get(14)?,
+            play_count: row.get::<_, i64>(15)? as u32,
+            added: row.get(17)?,
+            skip_count: row.get::<_, i64>(18)? as u32,
+            last_played: row.get(19)?,
+            rating: row.get::<_, Option<i64>>(20)?.map(|rating| rating as u8),
+            cue_sheet: row.get(21)?,
+        })
+    }
+
+    /// Returns the title, or the file name of untitled songs.
+    pub fn title(&self) -> String {
+        self.tags.title.clone().unwrap_or_else(|| {
+            Path::new(&self.path)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+    }
+
+    pub fn artist(&self) -> String {
+        tags::join(&self.tags.artists).unwrap_or_else(|| UNKNOWN.to_string())
+    }
+
+    /// Returns the ALBUMARTIST of the song, falling back to its artist.
+    pub fn album_artist(&self) -> String {
+        tags::join(&self.tags.album_artists).unwrap_or_else(|| self.artist())
+    }
+
+    pub fn album(&self) -> String {
+        self.tags
+            .album
+            .clone()
+            .unwrap_or_else(|| UNKNOWN.to_string())
+    }
+
+    pub fn genre(&self) -> String {
+        tags::join(&self.tags.genres).unwrap_or_else(|| UNKNOWN.to_string())
+    }
+
+    /// Returns each genre of the song, as the browser lists them.
+    fn genres(&self) -> Vec<String> {
+        if self.tags.genres.is_empty() {
+            vec![UNKNOWN.to_string()]
+        } else {
+            self.tags.genres.clone()
+        }
+    }
+
+    pub fn year(&self) -> String {
+        self.tags.year().unwrap_or(UNKNOWN).to_string()
+    }
+
+    /// Returns the track number, followed by the number of tracks when known.
+    pub fn track_number(&self) -> String {
+        with_total(&self.tags.track_number, &self.tags.track_total)
+    }
+
+    /// Returns the disc number, followed by the number of discs when known.
+    pub fn disc_number(&self) -> String {
+        with_total(&self.tags.disc_number, &self.tags.disc_total)
+    }
+
+    pub fn composer(&self) -> String {
+        tags::join(&self.tags.composers).unwrap_or_default()
+    }
+
+    pub fn performer(&self) -> String {
+        tags::join(&self.tags.performers).unwrap_or_default()
+    }
+}
+
+fn with_total(number: &Option<String>, total: &Option<String>) -> String {
+    match (number, total) {
+        (Some(number), Some(total)) => format!("{} / {}", number, total),
+        (Some(number), None) => number.clone(),
+        (None, _) => String::new(),
+    }
+}
+
+/// Builds the tags of a song stored before every comment was, from the fields
+/// that were.
+fn legacy_tags(row: &Row) -> rusqlite::Result<Tags> {
+    let known = |index: usize| -> rusqlite::Result<Option<String>> {
+        let value: String = row.get(index)?;
+        Ok(Some(value).filter(|value| !value.is_empty() && value != UNKNOWN))
+    };
+    Ok(Tags {
+        title: known(1)?,
+        artists: known(2)?.into_iter().collect(),
+        album_artists: known(3)?.into_iter().collect(),
+        album: known(4)?,
+        genres: known(5)?.into_iter().collect(),
+        date: known(6)?,
+        track_number: known(7)?,
+        disc_number: known(8)?,
+        composers: known(9)?.into_iter().collect(),
+        ..Tags::default()
+    })
+}
+
+/// An album of the library, as listed by the browser.
+pub struct Album {
+    pub id: i64,
+    pub title: String,
+    /// The album artist, which is the artist of its songs unless they are
+    /// tagged with ALBUMARTIST.
+    pub artist: String,
+    pub year: String,
+    pub tracks: u32,
+}
+
+/// The collection of songs the player knows about, kept in an SQLite
+/// database so that it is available immediately on startup.
+pub struct Library {
+    connection: Connection,
+}
+
+impl Library {
+    /// Opens the library database, falling back to a library held in memory
+    /// for this session when it cannot be opened.
+    pub fn open() -> Library {
+        let path = config::data_dir().join(DATABASE_FILE);
+        let connection = Connection::open(&path)
+            .and_then(|connection| {
+                connection.busy_timeout(BUSY_TIMEOUT)?;
+                connection.execute_batch(SCHEMA)?;
+                migrate(&connection)?;
+                Ok(connection)
+            })
+            .unwrap_or_else(|error| {
+                eprintln!("Unable to open library {}: {}", path.display(), error);
+                let connection = Connection::open_in_memory().unwrap();
+                connection.execute_batch(SCHEMA).unwrap();
+                connection
+            });
+        Library { connection }
+    }
+
+    /// Returns the song at `path`, reading it again and updating the library
+    /// when it is new or has been modified since it was last stored.
+    pub fn scan(&self, path: &Path) -> Track {
+        self.refresh(path)
+            .or_else(|| self.track(path))
+            .unwrap_or_else(|| Track::read(path))
+    }
+
+    /// Reads the song at `path` into the library if it is new or its size or
+    /// modification time changed. Returns `None` when it is up to date.
+    pub fn refresh(&self, path: &Path) -> Option<Track> {
+        if let Some(track) = self.track(path) {
+            if file_stamp(path) == Some((track.size, track.mtime)) {
+                return None;
+            }
+        }
+        Some(self.reload(path))
+    }
+
+    /// Reads the song at `path` into the library again, keeping its play
+    /// statistics and when it was added. Its rating is taken from the file
+    /// only when ratings are stored in files and the file has one.
+    pub fn reload(&self, path: &Path) -> Track {
+        let mut track = Track::read(path);
+        if let Some(stored) = self.track(path) {
+            track.play_count = stored.play_count;
+            track.added = stored.added;
+            track.skip_count = stored.skip_count;
+            track.last_played = stored.last_played;
+            if !ratings_in_files() || track.rating.is_none() {
+                track.rating = stored.rating;
+            }
+        }
+        self.save(&track);
+        track
+    }
+
+    pub fn track(&self, path: &Path) -> Option<Track> {
+        let sql = format!("{} WHERE tracks.path = ?", SELECT_TRACKS);
+        self.connection
+            .query_row(&sql, params![path.to_string_lossy()], Track::from_row)
+            .optional()
+            .unwrap_or_else(|error| {
+                eprintln!("Unable to read {} from library: {}", path.display(), error);
+                None
+            })
+    }
+
+    /// Returns every song, ordered by album artist, album, disc and track.
+    pub fn tracks(&self) -> Vec<Track> {
+        let sql = format!("{} ORDER BY {}", SELECT_TRACKS, TRACK_ORDER);
+        self.query(&sql, NO_PARAMS, Track::from_row)
+    }
+
+    /// Returns the genres of the songs, in alphabetical order.
+    pub fn genres(&self) -> Vec<String> {
+        self.query(
+            "SELECT DISTINCT name FROM genres ORDER BY name COLLATE NOCASE",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns the album artists having songs of `genre`, or of any genre when
+    /// it is `None`.
+    pub fn album_artists(&self, genre: Option<&str>) -> Vec<String> {
+        self.query(
+            "SELECT DISTINCT artists.name FROM tracks
+            JOIN albums ON albums.id = tracks.album_id
+            JOIN artists ON artists.id = albums.artist_id
+            WHERE ?1 IS NULL OR tracks.id IN (SELECT track_id FROM genres WHERE name = ?1)
+            ORDER BY artists.name COLLATE NOCASE",
+            params![genre],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns the albums of `artist` having songs of `genre`, either being
+    /// `None` matching any.
+    pub fn albums(&self, artist: Option<&str>, genre: Option<&str>) -> Vec<Album> {
+        self.query(
+            "SELECT albums.id, albums.title, artists.name, albums.year, COUNT(tracks.id)
+            FROM albums
+            JOIN artists ON artists.id = albums.artist_id
+            JOIN tracks ON tracks.album_id = albums.id
+            WHERE (?1 IS NULL OR artists.name = ?1)
+                AND (?2 IS NULL OR tracks.id IN (SELECT track_id FROM genres WHERE name = ?2))
+            GROUP BY albums.id
+            ORDER BY artists.name COLLATE NOCASE, albums.year, albums.title COLLATE NOCASE",
+            params![artist, genre],
+            |row| {
+                Ok(Album {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    artist: row.get(2)?,
+                    year: row.get(3)?,
+                    tracks: row.get::<_, i64>(4)? as u32,
+                })
+            },
+        )
+    }
+
+    /// Returns the songs of an album in disc and track order, keeping only
+    /// those of `genre` unless it is `None`.
+    pub fn album_tracks(&self, album: i64, genre: Option<&str>) -> Vec<Track> {
+        let sql = format!(
+            "{} WHERE albums.id = ?1
+                AND (?2 IS NULL OR tracks.id IN (SELECT track_id FROM genres WHERE name = ?2))
+            ORDER BY {}",
+            SELECT_TRACKS, TRACK_ORDER
+        );
+        self.query(&sql, params![album, genre], Track::from_row)
+    }
+
+    /// Runs a query returning several rows, reporting errors as no rows.
+    fn query<T, F>(&self, sql: &str, params: &[&dyn ToSql], map: F) -> Vec<T>
+    where
+        F: FnMut(&Row) -> rusqlite::Result<T>,
+    {
+        let rows = self.connection.prepare(sql).and_then(|mut statement| {
+            statement
+                .query_map(params, map)?
+                .collect::<rusqlite::Result<Vec<T>>>()
+        });
+        rows.unwrap_or_else(|error| {
+            eprintln!("Unable to read library: {}", error);
+            Vec::new()
+        })
+    }
+
+    pub fn save(&self, track: &Track) {
+        if let Err(error) = self.try_save(track) {
+            eprintln!("Unable to add {} to library: {}", track.path, error);
+        }
+    }
+
+    fn try_save(&self, track: &Track) -> rusqlite::Result<()> {
+        let artist_id = self.artist_id(&track.artist())?;
+        let album_artist_id = self.artist_id(&track.album_artist())?;
+        let album = track.album();
+        let year = track.year();
+        self.connection.execute(
+            "INSERT OR IGNORE INTO albums (title, artist_id, year) VALUES (?, ?, ?)",
+            params![album, album_artist_id, year],
+        )?;
+        let album_id: i64 = self.connection.query_row(
+            "SELECT id FROM albums WHERE title = ? AND artist_id = ?",
+            params![album, album_artist_id],
+            |row| row.get(0),
+        )?;
+        if track.artwork.is_some() {
+            self.connection.execute(
+                "UPDATE albums SET artwork = ? WHERE id = ? AND artwork IS NULL",
+                params![track.artwork, album_id],
+            )?;
+        }
+        self.connection.execute(
+            "INSERT INTO tracks (path, title, artist_id, album_id, genre, year, track, disc,
+                composer, sample_rate, duration, size, mtime, artwork, play_count, comments,
+                added, skip_count, last_played, rating, cue_sheet)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (path) DO UPDATE SET title = excluded.title,
+                artist_id = excluded.artist_id, album_id = excluded.album_id,
+                genre = excluded.genre, year = excluded.year, track = excluded.track,
+                disc = excluded.disc, composer = excluded.composer,
+                sample_rate = excluded.sample_rate, duration = excluded.duration,
+                size = excluded.size, mtime = excluded.mtime, artwork = excluded.artwork,
+                play_count = excluded.play_count, comments = excluded.comments,
+                skip_count = excluded.skip_count, last_played = excluded.last_played,
+                rating = excluded.rating, cue_sheet = excluded.cue_sheet",
+            params![
+                track.path,
+                track.title(),
+                artist_id,
+                album_id,
+                track.genre(),
+                year,
+                track.tags.track_number.clone().unwrap_or_default(),
+                track.tags.disc_number.clone().unwrap_or_default(),
+                track.composer(),
+                track.sample_rate,
+                track.duration.map(|duration| duration as i64),
+                track.size as i64,
+                track.mtime,
+                track.artwork,
+                track.play_count,
+                track.tags.serialize(),
+                track.added,
+                track.skip_count,
+                track.last_played,
+                track.rating,
+                track.cue_sheet,
+            ],
+        )?;
+        let track_id: i64 = self.connection.query_row(
+            "SELECT id FROM tracks WHERE path = ?",
+            params![track.path],
+            |row| row.get(0),
+        )?;
+        self.connection
+            .execute("DELETE FROM genres WHERE track_id = ?", params![track_id])?;
+        for genre in track.genres() {
+            self.connection.execute(
+                "INSERT OR IGNORE INTO genres (track_id, name) VALUES (?, ?)",
+                params![track_id, genre],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn artist_id(&self, name: &str) -> rusqlite::Result<i64> {
+        self.connection.execute(
+            "INSERT OR IGNORE INTO artists (name) VALUES (?)",
+            params![name],
+        )?;
+        self.connection.query_row(
+            "SELECT id FROM artists WHERE name = ?",
+            params![name],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns the paths of every song in the library.
+    pub fn paths(&self) -> Vec<String> {
+        self.query("SELECT path FROM tracks", NO_PARAMS, |row| row.get(0))
+    }
+
+    /// Lists the songs at `path` or below it, through the index of paths.
+    fn paths_below(&self, path: &Path) -> Vec<String> {
+        let path = path.to_string_lossy();
+        let folder = path.trim_end_matches('/');
+        // Every path below "folder/" sorts before "folder0", as '0' follows '/'.
+        self.query(
+            "SELECT path FROM tracks WHERE path = ?1 OR (path >= ?2 AND path < ?3)",
+            params![path, format!("{}/", folder), format!("{}0", folder)],
+            |row| row.get(0),
+        )
+    }
+
+    /// Forgets the song at `path`, or every song below it when it is a
+    /// directory. Returns the paths of the songs removed.
+    pub fn remove(&self, path: &Path) -> Vec<String> {
+        let removed = self.paths_below(path);
+        self.forget(&removed);
+        removed
+    }
+
+    /// Forgets the songs at `paths` at once.
+    pub fn forget(&self, paths: &[String]) {
+        if paths.is_empty() {
+            return;
+        }
+        let result = self.connection.execute_batch("BEGIN").and_then(|_| {
+            let deleted = self
+                .connection
+                .prepare(
+                    "DELETE FROM genres
+                    WHERE track_id IN (SELECT id FROM tracks WHERE path = ?)",
+                )
+                .and_then(|mut genres| {
+                    let mut tracks = self
+                        .connection
+                        .prepare("DELETE FROM tracks WHERE path = ?")?;
+                    paths.iter().try_for_each(|path| {
+                        genres.execute(params![path])?;
+                        tracks.execute(params![path]).map(|_| ())
+                    })
+                })
+                .and_then(|_| self.prune());
+            match deleted {
+                Ok(()) => self.connection.execute_batch("COMMIT"),
+                Err(error) => {
+                    let _ = self.connection.execute_batch("ROLLBACK");
+                    Err(error)
+                }
+            }
+        });
+        if let Err(error) = result {
+            eprintln!("Unable to remove songs from library: {}", error);
+        }
+    }
+
+    /// Moves the song at `from`, or every song below it when it is a
+    /// directory, to `to`. Returns the old and new paths of the songs moved.
+    pub fn rename(&self, from: &Path, to: &Path) -> Vec<(String, String)> {
+        let moved: Vec<(String, String)> = self
+            .paths_below(from)
+            .into_iter()
+            .filter_map(|song| {
+                let relative = Path::new(&song).strip_prefix(from).ok()?;
+                let destination = to.join(relative).to_string_lossy().to_string();
+                Some((song, destination))
+            })
+            .collect();
+        for (song, destination) in &moved {
+            let result = self
+                .connection
+                .execute(
+                    "UPDATE tracks SET path = ? WHERE path = ?",
+                    params![destination, song],
+                )
+                .and_then(|_| {
+                    self.connection.execute(
+                        "UPDATE history SET path = ? WHERE path = ?",
+                        params![destination, song],
+                    )
+                });
+            if let Err(error) = result {
+                eprintln!("Unable to move {} in library: {}", song, error);
+            }
+        }
+        moved
+    }
+
+    /// Deletes albums and artists no song refers to anymore.
+    fn prune(&self) -> rusqlite::Result<()> {
+        self.connection.execute_batch(
+            "DELETE FROM albums WHERE id NOT IN (SELECT album_id FROM tracks);
+            DELETE FROM artists WHERE id NOT IN (SELECT artist_id FROM tracks)
+                AND id NOT IN (SELECT artist_id FROM albums);",
+        )
+    }
+
+    /// Counts a play of the song at `path`, at `time`, and logs it in the
+    /// history.
+    pub fn record_play(&self, path: &str, time: i64) {
+        let result = self
+            .connection
+            .execute(
+                "UPDATE tracks SET play_count = play_count + 1, last_played = ? WHERE path = ?",
+                params![time, path],
+            )
+            .and_then(|_| {
+                self.connection.execute(
+                    "INSERT INTO history (path, played) VALUES (?, ?)",
+                    params![path, time],
+                )
+            });
+        if let Err(error) = result {
+            eprintln!("Unable to count play of {}: {}", path, error);
+        }
+    }
+
+    pub fn record_skip(&self, path: &str) {
+        let result = self.connection.execute(
+            "UPDATE tracks SET skip_count = skip_count + 1 WHERE path = ?",
+            params![path],
+        );
+        if let Err(error) = result {
+            eprintln!("Unable to count skip of {}: {}", path, error);
+        }
+    }
+
+    pub fn set_rating(&self, path: &str, rating: Option<u8>) {
+        let result = self.connection.execute(
+            "UPDATE tracks SET rating = ? WHERE path = ?",
+            params![rating, path],
+        );
+        if let Err(error) = result {
+            eprintln!("Unable to rate {}: {}", path, error);
+        }
+    }
+
+    /// Returns the paths of the last `limit` songs played and when they were
+    /// played, the latest first.
+    pub fn history(&self, limit: u32) -> Vec<(String, i64)> {
+        self.query(
+            "SELECT path, played FROM history ORDER BY played DESC, id DESC LIMIT ?",
+            params![limit],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    pub fn set_duration(&self, path: &Path, duration: u64) {
+        let result = self.connection.execute(
+            "UPDATE tracks SET duration = ? WHERE path = ?",
+            params![duration as i64, path.to_string_lossy()],
+        );
+        if let Err(error) = result {
+            eprintln!("Unable to store duration of {}: {}", path.display(), error);
+        }
+    }
+}
+
+/// Upgrades a database created by an earlier version.
+fn migrate(connection: &Connection) -> rusqlite::Result<()> {
+    if !has_column(connection, "added")? {
+        // Songs already in the library count as added when last modified,
+        // before the upgrades below have them scanned again.
+        connection.execute_batch(
+            "ALTER TABLE tracks ADD COLUMN added INTEGER NOT NULL DEFAULT 0;
+            UPDATE tracks SET added = mtime;",
+        )?;
+    }
+    if !has_column(connection, "comments")? {
+        // Only some of the tags used to be stored: have the songs read again
+        // on the next scan.
+        connection.execute_batch(
+            "ALTER TABLE tracks ADD COLUMN comments TEXT NOT NULL DEFAULT '';
+            UPDATE tracks SET mtime = 0;",
+        )?;
+    }
+    if !has_column(connection, "rating")? {
+        // Ratings are read from the files on the next scan.
+        connection.execute_batch(
+            "ALTER TABLE tracks ADD COLUMN skip_count INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE tracks ADD COLUMN last_played INTEGER;
+            ALTER TABLE tracks ADD COLUMN rating INTEGER;
+            UPDATE tracks SET mtime = 0;",
+        )?;
+    }
+    if !has_column(connection, "cue_sheet")? {
+        // Single file albums are found on the next scan.
+        connection.execute_batch(
+            "ALTER TABLE tracks ADD COLUMN cue_sheet INTEGER NOT NULL DEFAULT 0;
+            UPDATE tracks SET mtime = 0;",
+        )?;
+    }
+    // Songs stored before each genre was are listed under each of the genres
+    // joined in their genre column.
+    let unlisted: Vec<(i64, String)> = connection
+        .prepare("SELECT id, genre FROM tracks WHERE id NOT IN (SELECT track_id FROM genres)")?
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (track_id, genre) in unlisted {
+        for name in genre.split(tags::SEPARATOR) {
+            connection.execute(
+                "INSERT OR IGNORE INTO genres (track_id, name) VALUES (?, ?)",
+                params![track_id, name],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn has_column(connection: &Connection, name: &str) -> rusqlite::Result<bool> {
+    let count: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('tracks') WHERE name = ?",
+        params![name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Returns the folders the library is built from.
+pub fn roots() -> Vec<PathBuf> {
+    config::read(ROOTS_FILE)
+        .map(|contents| contents.lines().map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+pub fn save_roots(roots: &[PathBuf]) {
+    let contents: String = roots
+        .iter()
+        .map(|root| format!("{}\n", root.display()))
+        .collect();
+    config::write(ROOTS_FILE, &contents);
+}
+
+/// Tells whether ratings are also written to the files, as FMPS_RATING.
+pub fn ratings_in_files() -> bool {
+    config::read(RATINGS_IN_FILES_FILE).is_some_and(|value| value.trim() == "true")
+}
+
+pub fn set_ratings_in_files(store: bool) {
+    config::write(RATINGS_IN_FILES_FILE, &store.to_string());
+}
+
+/// Tells whether `path` names a file the library can hold.
+pub fn is_track(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case("flac"))
+}
+
+/// Returns the size and modification time used to tell whether a file changed
+/// since it was stored.
+fn file_stamp(path: &Path) -> Option<(u64, i64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((metadata.len(), mtime as i64))
+}
+
+/// Returns the current time, in seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+fn folder_artwork(path: &Path) -> Option<String> {
+    let dir = path.parent()?;
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|image| {
+            let stem = image.file_stem()?.to_str()?.to_lowercase();
+            let extension = image.extension()?.to_str()?.to_lowercase();
+            let rank = FOLDER_ARTWORK.iter().position(|name| *name == stem)?;
+            let extension = IMAGE_EXTENSIONS
+                .iter()
+                .position(|known| *known == extension)?;
+            Some(((rank, extension), image))
+        })
+        .min()
+        .map(|(_, image)| image.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Library {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        Library { connection }
+    }
+
+    fn song(path: &str, album: &str, genres: &[&str]) -> Track {
+        let mut track = Track::unread(Path::new(path));
+        track.tags.album = Some(album.to_string());
+        track.tags.genres = genres.iter().map(|genre| genre.to_string()).collect();
+        track
+    }
+
+    fn titles(albums: Vec<Album>) -> Vec<String> {
+        albums.into_iter().map(|album| album.title).collect()
+    }
+
+    fn paths(tracks: Vec<Track>) -> Vec<String> {
+        tracks.into_iter().map(|track| track.path).collect()
+    }
+
+    #[test]
+    fn songs_are_read_back_as_saved() {
+        let library = library();
+        let mut track = song("/music/a.flac", "Album", &["Jazz"]);
+        track.tags.artists = vec!["One".to_string(), "Two".to_string()];
+        track.tags.track_number = Some("3".to_string());
+        track.duration = Some(200);
+        track.size = 1000;
+        track.mtime = 42;
+        library.save(&track);
+        track.play_count = 2;
+        library.save(&track);
+
+        let stored = library.track(Path::new("/music/a.flac")).unwrap();
+        assert_eq!(stored.title(), "a");
+        assert_eq!(stored.artist(), "One; Two");
+        assert_eq!(stored.album(), "Album");
+        assert_eq!(stored.track_number(), "3");
+        assert_eq!(stored.duration, Some(200));
+        assert_eq!(
+            (stored.size, stored.mtime, stored.play_count),
+            (1000, 42, 2)
+        );
+        assert_eq!(paths(library.tracks()), vec!["/music/a.flac"]);
+        assert!(library.track(Path::new("/music/b.flac")).is_none());
+    }
+
+    #[test]
+    fn forgetting_songs_prunes_their_albums_and_artists() {
+        let library = library();
+        library.save(&song("/music/a.flac", "First", &[]));
+        library.save(&song("/music/b.flac", "Second", &[]));
+        library.forget(&["/music/a.flac".to_string()]);
+        assert_eq!(library.paths(), vec!["/music/b.flac"]);
+        assert_eq!(titles(library.albums(None, None)), vec!["Second"]);
+        library.forget(&["/music/b.flac".to_string()]);
+        assert!(library.albums(None, None).is_empty());
+        assert!(library.album_artists(None).is_empty());
+    }
+
+    #[test]
+    fn folders_hold_only_the_songs_below_them() {
+        let library = library();
+        for path in &[
+            "/music/a/one.flac",
+            "/music/a/b/two.flac",
+            "/music/a-b.flac",
+            "/music/ab/three.flac",
+        ] {
+            library.save(&song(path, "Album", &[]));
+        }
+        let below = vec!["/music/a/b/two.flac", "/music/a/one.flac"];
+        assert_eq!(library.paths_below(Path::new("/music/a")), below);
+        assert_eq!(library.paths_below(Path::new("/music/a/")), below);
+        assert_eq!(
+            library.paths_below(Path::new("/music/a-b.flac")),
+            vec!["/music/a-b.flac"]
+        );
+        assert_eq!(library.remove(Path::new("/music/a")), below);
+        let mut left = library.paths();
+        left.sort();
+        assert_eq!(left, vec!["/music/a-b.flac", "/music/ab/three.flac"]);
+    }
+
+    #[test]
+    fn renaming_a_folder_moves_its_songs_and_their_history() {
+        let library = library();
+        library.save(&song("/music/a/one.flac", "Album", &[]));
+        library.save(&song("/music/ab.flac", "Album", &[]));
+        library.record_play("/music/a/one.flac", 100);
+        let moved = library.rename(Path::new("/music/a"), Path::new("/music/c"));
+        assert_eq!(
+            moved,
+            vec![(
+                "/music/a/one.flac".to_string(),
+                "/music/c/one.flac".to_string()
+            )]
+        );
+        assert!(library.track(Path::new("/music/c/one.flac")).is_some());
+        assert!(library.track(Path::new("/music/ab.flac")).is_some());
+        assert_eq!(
+            library.history(10),
+            vec![("/music/c/one.flac".to_string(), 100)]
+        );
+    }
+
+    #[test]
+    fn first_databases_are_upgraded() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE artists (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+                CREATE TABLE albums (id INTEGER PRIMARY KEY, title TEXT NOT NULL,
+                    artist_id INTEGER NOT NULL, year TEXT NOT NULL, artwork TEXT,
+                    UNIQUE (title, artist_id));
+                CREATE TABLE tracks (id INTEGER PRIMARY KEY, path TEXT NOT NULL UNIQUE,
+                    title TEXT NOT NULL, artist_id INTEGER NOT NULL, album_id INTEGER NOT NULL,
+                    genre TEXT NOT NULL, year TEXT NOT NULL, track TEXT NOT NULL,
+                    disc TEXT NOT NULL, composer TEXT NOT NULL, sample_rate INTEGER,
+                    duration INTEGER, size INTEGER NOT NULL, mtime INTEGER NOT NULL,
+                    artwork TEXT, play_count INTEGER NOT NULL DEFAULT 0);
+                INSERT INTO artists VALUES (1, 'Artist');
+                INSERT INTO albums VALUES (1, 'Album', 1, '1999', NULL);
+                INSERT INTO tracks VALUES (1, '/music/a.flac', 'Title', 1, 1, 'Jazz', '1999',
+                    '2', '1', 'Unknown', 44100, 180, 1000, 42, NULL, 3);",
+            )
+            .unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        migrate(&connection).unwrap();
+        let library = Library { connection };
+
+        let track = library.track(Path::new("/music/a.flac")).unwrap();
+        assert_eq!(track.title(), "Title");
+        assert_eq!(track.artist(), "Artist");
+        assert_eq!(track.genre(), "Jazz");
+        assert_eq!(track.play_count, 3);
+        assert_eq!(track.added, 42);
+        // The song is read again on the next scan, to fill the new columns.
+        assert_eq!(track.mtime, 0);
+        assert_eq!((track.rating, track.cue_sheet), (None, false));
+        assert_eq!(library.genres(), vec!["Jazz"]);
+    }
+
+    #[test]
+    fn songs_are_listed_under_each_of_their_genres() {
+        let library = library();
+        library.save(&song("/music/a.flac", "Both", &["Jazz", "Rock"]));
+        library.save(&song("/music/b.flac", "Rock only", &["Rock"]));
+        library.save(&song("/music/c.flac", "Untagged", &[]));
+        assert_eq!(library.genres(), vec!["Jazz", "Rock", UNKNOWN]);
+        assert_eq!(titles(library.albums(None, Some("Jazz"))), vec!["Both"]);
+        assert_eq!(
+            titles(library.albums(None, Some("Rock"))),
+            vec!["Both", "Rock only"]
+        );
+        assert!(library.albums(None, Some("Jazz; Rock")).is_empty());
+        let album = library.albums(None, Some(UNKNOWN)).remove(0);
+        assert_eq!(library.album_tracks(album.id, Some(UNKNOWN)).len(), 1);
+        assert_eq!(library.album_artists(Some("Jazz")), vec![UNKNOWN]);
+
+        library.save(&song("/music/a.flac", "Both", &["Jazz"]));
+        library.forget(&["/music/b.flac".to_string()]);
+        assert_eq!(library.genres(), vec!["Jazz", UNKNOWN]);
+    }
+
+    #[test]
+    fn joined_genres_are_split_on_upgrade() {
+        let library = library();
+        library.save(&song("/music/a.flac", "Both", &["Jazz", "Rock"]));
+        library
+            .connection
+            .execute_batch("DELETE FROM genres")
+            .unwrap();
+        migrate(&library.connection).unwrap();
+        assert_eq!(library.genres(), vec!["Jazz", "Rock"]);
+    }
+}
@@ -0,0 +1,379 @@
+//! The songs of a playlist as they are added and read, apart from how they
+//! are shown, so that the playlists of the window and the player run headless
+//! list them alike. Songs are read, split into the virtual tracks of their CUE
+//! sheet and timed by the import workers, their rows showing what is known of
+//! them meanwhile.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use walkdir::{DirEntry, WalkDir};
+
+use crate::cue;
+use crate::import::{Outcome, Pool};
+use crate::library::{self, Library, Track};
+use crate::playlist_file::{self, Entry};
+
+/// The rows songs are listed in, as the listing fills them.
+pub trait Rows {
+    type Row: Clone;
+
+    fn listing(&mut self) -> &mut Listing<Self::Row>;
+
+    fn pool(&self) -> &Pool;
+
+    fn library(&self) -> &Library;
+
+    /// Lists `track` before the row at `position`, or last when it is
+    /// `None`, returning its row.
+    fn insert_row(&mut self, track: &Track, position: Option<usize>) -> Option<Self::Row>;
+
+    /// Lists the song at `path`, which has not been read yet, before the row
+    /// at `position`, or last when it is `None`, returning its row.
+    fn insert_unread(&mut self, path: &Path, position: Option<usize>) -> Option<Self::Row>;
+
+    /// Shows `track` in `row`, as read again.
+    fn show_row(&mut self, row: &Self::Row, track: &Track);
+
+    /// Shows the length of the song in `row`, in seconds, once computed.
+    fn show_duration(&mut self, row: &Self::Row, duration: u64);
+
+    fn remove_row(&mut self, row: &Self::Row);
+
+    /// Returns the position of `row`, or `None` once it is removed.
+    fn row_position(&self, row: &Self::Row) -> Option<usize>;
+
+    /// Tells whether the song in `row` is playing or paused.
+    fn is_playing(&self, row: &Self::Row) -> bool;
+}
+
+/// What the listing keeps track of while songs are read.
+pub struct Listing<R> {
+    /// The length of the songs listed, in milliseconds, by path.
+    durations: HashMap<String, u64>,
+    import_done: usize,
+    import_total: usize,
+    pending_durations: HashMap<String, Vec<R>>,
+    pending_scans: HashMap<usize, R>,
+    pending_splits: HashMap<usize, R>,
+}
+
+impl<R> Default for Listing<R> {
+    fn default() -> Listing<R> {
+        Listing {
+            durations: HashMap::new(),
+            import_done: 0,
+            import_total: 0,
+            pending_durations: HashMap::new(),
+            pending_scans: HashMap::new(),
+            pending_splits: HashMap::new(),
+        }
+    }
+}
+
+impl<R> Listing<R> {
+    /// Returns the length of the song at `path`, in milliseconds, when known.
+    pub fn duration(&self, path: &str) -> Option<u64> {
+        self.durations.get(path).copied()
+    }
+
+    /// Forgets the lengths of the songs `keep` does not hold for, as they are
+    /// no longer listed.
+    pub fn retain_durations(&mut self, keep: impl Fn(&str) -> bool) {
+        self.durations.retain(|path, _| keep(path));
+    }
+
+    /// Follows the song moved from `from` to `to`.
+    pub fn moved(&mut self, from: &str, to: &str) {
+        if let Some(duration) = self.durations.remove(from) {
+            self.durations.insert(to.to_string(), duration);
+        }
+    }
+
+    /// Returns how many of the songs being read have been, and how many there
+    /// are, starting the count over once they all have.
+    pub fn progress(&mut self) -> (usize, usize) {
+        if self.pending_scans.is_empty() {
+            self.import_done = 0;
+            self.import_total = 0;
+        }
+        (self.import_done, self.import_total)
+    }
+
+    /// Stops reading the songs not read yet, returning their rows.
+    pub fn cancel_scans(&mut self, pool: &Pool) -> Vec<R> {
+        let (ids, rows): (Vec<usize>, Vec<R>) = self.pending_scans.drain().unzip();
+        pool.cancel(ids);
+        rows
+    }
+
+    /// Forgets every song, as every row is removed.
+    pub fn clear(&mut self, pool: &Pool) {
+        self.cancel_scans(pool);
+        pool.cancel(self.pending_splits.drain().map(|(id, _)| id));
+        self.durations.clear();
+        self.pending_durations.clear();
+    }
+}
+
+/// Songs that could not be listed, under a heading telling why.
+pub struct Failure {
+    pub heading: String,
+    pub names: Vec<String>,
+}
+
+/// What the import workers sent changed in the rows.
+pub struct Imported<R> {
+    /// The first virtual track of the album playing, split since, which is to
+    /// play from its start.
+    pub restart: Option<R>,
+    /// Whether rows were split into the virtual tracks of their file.
+    pub split: bool,
+    /// The lengths computed, in milliseconds, by path.
+    pub durations: Vec<(String, u64)>,
+}
+
+/// Lists the songs at `paths`, the songs of folders and the songs listed by
+/// playlists, before the row at `position`, or last when it is `None`.
+/// Returns how many rows were listed, and what could not be.
+pub fn add<L: Rows>(
+    rows: &mut L,
+    paths: &[PathBuf],
+    mut position: Option<usize>,
+) -> (usize, Vec<Failure>) {
+    let mut listed = 0;
+    let mut failures = Vec::new();
+    let mut unopened = Vec::new();
+    for path in paths {
+        let added = if path.is_dir() {
+            // Playlists found in folders list songs of those folders, which
+            // would be added twice, so only the songs are.
+            let songs: Vec<PathBuf> = collect_files(path)
+                .into_iter()
+                .filter(|song| library::is_track(song))
+                .collect();
+            for (index, song) in songs.iter().enumerate() {
+                add_unread(rows, song, position.map(|position| position + index));
+            }
+            songs.len()
+        } else if playlist_file::is_playlist(path) {
+            let (added, failure) = load(rows, path, position);
+            failures.extend(failure);
+            added
+        } else if library::is_track(path) && path.is_file() {
+            add_unread(rows, path, position);
+            1
+        } else {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            unopened.push(name.to_string());
+            0
+        };
+        listed += added;
+        position = position.map(|position| position + added);
+    }
+    if !unopened.is_empty() {
+        failures.push(Failure {
+            heading: "Could not open:".to_string(),
+            names: unopened,
+        });
+    }
+    (listed, failures)
+}
+
+/// Lists the songs of the playlist file at `path` before the row at
+/// `position`, or last when it is `None`. Returns how many rows were listed,
+/// and the songs that could not be found, or why the file could not be read.
+pub fn load<L: Rows>(
+    rows: &mut L,
+    path: &Path,
+    position: Option<usize>,
+) -> (usize, Option<Failure>) {
+    let entries = match playlist_file::read(path) {
+        Ok(entries) => entries,
+        Err(error) => {
+            let failure = Failure {
+                heading: format!("Could not open {}:", path.display()),
+                names: vec![error.to_string()],
+            };
+            return (0, Some(failure));
+        }
+    };
+    let (listed, unresolved) = add_entries(rows, entries, position);
+    let failure = Some(Failure {
+        heading: format!("Some songs of {} could not be found:", path.display()),
+        names: unresolved,
+    });
+    (listed, failure.filter(|failure| !failure.names.is_empty()))
+}
+
+/// Lists the songs of a playlist before the row at `position`, or last when
+/// it is `None`. Songs known to the library are listed right away, and songs
+/// that cannot be found with what the playlist says about them. Returns how
+/// many rows were listed, and the locations of the songs not found.
+pub fn add_entries<L: Rows>(
+    rows: &mut L,
+    entries: Vec<Entry>,
+    position: Option<usize>,
+) -> (usize, Vec<String>) {
+    let mut listed = 0;
+    let mut unresolved = Vec::new();
+    for entry in entries {
+        let position = position.map(|position| position + listed);
+        match entry.path {
+            Some(ref file) if cue::file_path(&file.to_string_lossy()).is_file() => {
+                match rows.library().track(file) {
+                    Some(track) => {
+                        add_track(rows, &track, position);
+                    }
+                    None => add_unread(rows, file, position),
+                }
+            }
+            _ => {
+                unresolved.push(entry.location.clone());
+                add_track(rows, &entry.placeholder(), position);
+            }
+        }
+        listed += 1;
+    }
+    (listed, unresolved)
+}
+
+/// Lists the song at `path` before the row at `position`, or last when it is
+/// `None`, and has the import workers read it.
+pub fn add_unread<L: Rows>(rows: &mut L, path: &Path, position: Option<usize>) {
+    if let Some(row) = rows.insert_unread(path, position) {
+        let id = rows.pool().scan(path.to_path_buf());
+        let listing = rows.listing();
+        listing.pending_scans.insert(id, row);
+        listing.import_total += 1;
+    }
+}
+
+/// Lists `track`, read already, before the row at `position`, or last when it
+/// is `None`. Returns its row.
+pub fn add_track<L: Rows>(rows: &mut L, track: &Track, position: Option<usize>) -> Option<L::Row> {
+    let row = rows.insert_row(track, position)?;
+    timed(rows, &row, track);
+
+    // Single file albums are split once their CUE sheet has been read.
+    if track.cue_sheet && Path::new(&track.path).is_file() {
+        let id = rows.pool().split(track);
+        rows.listing().pending_splits.insert(id, row.clone());
+    }
+    Some(row)
+}
+
+/// Shows `track` in `row`, as read again.
+pub fn fill<L: Rows>(rows: &mut L, row: &L::Row, track: &Track) {
+    rows.show_row(row, track);
+    timed(rows, row, track);
+}
+
+/// Notes the length of `track`, listed in `row`, or has the import workers
+/// compute it when unknown.
+fn timed<L: Rows>(rows: &mut L, row: &L::Row, track: &Track) {
+    match track.duration {
+        Some(duration) => {
+            let listing = rows.listing();
+            listing
+                .durations
+                .insert(track.path.clone(), duration * 1000);
+        }
+        None if cue::file_path(&track.path).is_file() => {
+            rows.pool().compute_duration(PathBuf::from(&track.path));
+            rows.listing()
+                .pending_durations
+                .entry(track.path.clone())
+                .or_default()
+                .push(row.clone());
+        }
+        None => (),
+    }
+}
+
+/// Fills the rows waiting for what the import workers sent.
+pub fn imported<L: Rows>(rows: &mut L, outcomes: &[Outcome]) -> Imported<L::Row> {
+    let mut imported = Imported {
+        restart: None,
+        split: false,
+        durations: Vec::new(),
+    };
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Artwork(_, _) => (),
+            Outcome::Scanned(id, track) => {
+                let row = rows.listing().pending_scans.remove(id);
+                if let Some(row) = row.filter(|row| rows.row_position(row).is_some()) {
+                    fill(rows, &row, track);
+                    rows.listing().import_done += 1;
+                }
+            }
+            Outcome::Split(id, tracks) => {
+                let listing = rows.listing();
+                let row = match listing.pending_scans.remove(id) {
+                    Some(row) => {
+                        listing.import_done += 1;
+                        Some(row)
+                    }
+                    None => listing.pending_splits.remove(id),
+                };
+                let position = row.as_ref().and_then(|row| rows.row_position(row));
+                if let (Some(row), Some(position)) = (row, position) {
+                    let first = split(rows, &row, position, tracks);
+                    imported.restart = first.or(imported.restart);
+                    imported.split = true;
+                }
+            }
+            Outcome::Duration(path, duration) => {
+                let path = path.to_string_lossy().to_string();
+                let waiting = rows.listing().pending_durations.remove(&path);
+                for row in waiting.unwrap_or_default() {
+                    rows.show_duration(&row, *duration);
+                }
+                rows.listing()
+                    .durations
+                    .insert(path.clone(), duration * 1000);
+                imported.durations.push((path, duration * 1000));
+            }
+        }
+    }
+    imported
+}
+
+/// Replaces `row`, at `position`, which lists a single file album, with the
+/// rows of its virtual tracks. Returns the first of them when the album was
+/// playing, as it starts over from there.
+fn split<L: Rows>(rows: &mut L, row: &L::Row, position: usize, tracks: &[Track]) -> Option<L::Row> {
+    let playing = rows.is_playing(row);
+    let mut first = None;
+    for (offset, track) in tracks.iter().enumerate() {
+        let inserted = add_track(rows, track, Some(position + offset));
+        first = first.or(inserted);
+    }
+    rows.remove_row(row);
+    first.filter(|_| playing)
+}
+
+/// Recursively lists the files under `root` in filename order, skipping
+/// hidden entries. A plain file yields itself.
+pub fn collect_files(root: &Path) -> Vec<PathBuf> {
+    let walker = WalkDir::new(root)
+        .contents_first(false)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter();
+
+    walker
+        .filter_entry(|e| !is_hidden(e))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.path().to_path_buf())
+        .collect()
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| s.starts_with('.'))
+        .unwrap_or(false)
+}
@@ -1,4 +1,6 @@
+extern crate gdk;
 extern crate gdk_pixbuf;
+extern crate glib;
 extern crate gtk;
 extern crate metaflac;
 #[macro_use]
@@ -10,77 +12,414 @@ extern crate walkdir;
 
 use gdk_pixbuf::Pixbuf;
 use gtk::Orientation::{Horizontal, Vertical};
+use gdk::EventButton;
 use gtk::{
-    Adjustment, AdjustmentExt, Align, BoxExt, ButtonsType, DialogExt, DialogFlags,
-    FileChooserAction, FileChooserDialog, FileChooserExt, FileFilter, GtkWindowExt, Image,
-    ImageExt, Inhibit, LabelExt, MessageDialog, MessageType, OrientableExt, RangeExt, ScaleExt,
-    ToolButtonExt, WidgetExt, Window, WindowPosition,
+    Adjustment, AdjustmentExt, Align, BoxExt, Button, ButtonExt, ButtonsType, CheckButton,
+    ContainerExt, Dialog, DialogExt, DialogFlags, Entry, EntryExt, EventBox, FileChooserAction,
+    FileChooserDialog, FileChooserExt, FileFilter, GtkMenuExtManual, GtkMenuItemExt,
+    GtkWindowExt, GtkWindowExtManual, IconSize, Image, ImageExt, Inhibit, Label, LabelExt,
+    ListBox, ListBoxExt, ListBoxRowExt, Menu, MenuItem, MenuShellExt, MessageDialog, MessageType,
+    NotebookExt, NotebookExtManual, OrientableExt, PackType, ProgressBarExt, RangeExt,
+    ReliefStyle, ResponseType, ScaleButtonExt, ScaleExt, ScrolledWindow, SearchEntryExt,
+    ToggleButtonExt, ToggleToolButtonExt, ToolButtonExt, WidgetExt, Window, WindowPosition,
 };
+use browser::Browser;
+use browser::Msg::{Enqueue, PlayTracks, Refresh};
+use library::{Library, Track};
+use listing::collect_files;
 use playlist::Msg::{
-    AddSong, NextSong, PauseSong, PlaySong, PlayerMsgRecv, PreviousSong, RemoveSong, SaveSong,
-    Skip, SongDuration, SongMeta, SongStarted, StopSong,
+    AddPaths, AddTracks, CancelImport, ChooseCover, ColumnsChanged, Duplicate, Duplicated,
+    EditTags, EmbedCover, ExtractCover, FilesFailed, Filter, ImportProgress, LibraryChanged,
+    MoveFiles, NextSong, PauseSong, Persist, PlaySong, PlayerMsgRecv, PreviousSong, RemoveSong,
+    RenameFiles, ReplaceTracks, Resume, SaveSong, SaveTags, SetFile, SetRepeat, SetRoots, SetRule,
+    SetShuffle, SetVolume, Skip, SongDuration, SongMeta, SongStarted, StopSong, TagFromPaths,
 };
-use playlist::PlayerMsg;
-use playlist::Playlist;
-use relm::{Relm, Widget};
+use mpris::{Command, Mpris, Status};
+use order::Repeat;
+use player::PlayerMsg;
+use playlist::{Playlist, Shared};
+use relm::{Channel, Component, Relm, Widget};
 use relm_derive::widget;
-use std::path::PathBuf;
-use walkdir::{DirEntry, WalkDir};
+use remote::{Lock, Reply, Report, Request, Seek, Server};
+use scanner::Change;
+use session::Session;
+use stats::millis_to_minutes;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
 
 use gtk_sys::GTK_RESPONSE_ACCEPT;
 pub const PAUSE_ICON: &str = "gtk-media-pause";
 pub const PLAY_ICON: &str = "gtk-media-play";
 
+/// Remembers whether playlists are saved with relative paths.
+const RELATIVE_PATHS_FILE: &str = "playlist-relative-paths";
+
+/// Folder of the configuration directory keeping the playlists shown as tabs.
+const PLAYLISTS_DIR: &str = "playlists";
+const PLAYLIST_EXTENSION: &str = "m3u8";
+/// Lists the names of the playlists in the order of their tabs.
+const TABS_FILE: &str = "playlist-tabs";
+
+/// Starts with the song restored from the last session paused.
+const PAUSED_FLAG: &str = "--paused";
+/// Adds the files opened to the playlist without playing them.
+const ENQUEUE_FLAG: &str = "--enqueue";
+const SEEK_FLAG: &str = "--seek";
+/// Runs the player without a window, controlled from the command line.
+const HEADLESS_FLAG: &str = "--headless";
+/// Runs the player without a window, in the terminal.
+const TUI_FLAG: &str = "--tui";
+
+const USAGE: &str = "Usage: blue-music [--paused] [--enqueue] [FILE|FOLDER|PLAYLIST...]
+       blue-music --headless | --tui [FILE|FOLDER|PLAYLIST...]
+       blue-music --play-pause | --next | --previous | --stop | --status | --quit
+       blue-music --seek [+|-]SECONDS | --seek MINUTES:SECONDS
+
+Songs, folders and playlists are played, or added with --enqueue. When the
+player is already running it is handed the files and the controls instead.
+Run headless or in the terminal, the player plays the library when no files
+are given.";
+
+/// How many of the last songs played the history shows.
+const HISTORY_LENGTH: u32 = 200;
+
+/// Title of the tab listing the whole library.
+const LIBRARY_TAB: &str = "Library";
+
+const RIGHT_BUTTON: u32 = 3;
+
+const ADD_RESPONSE: i32 = 1;
+const REMOVE_RESPONSE: i32 = 2;
+
+mod artwork;
+mod browser;
+mod columns;
+mod config;
+mod cue;
+mod daemon;
+mod editor;
+mod engine;
 mod flac;
+mod import;
+mod library;
+mod listing;
+mod mpris;
+mod order;
+mod organize;
+mod pattern;
+mod playback;
 mod player;
 mod playlist;
+mod playlist_file;
+mod query;
+mod remote;
+mod scanner;
+mod scrobble;
+mod session;
+mod smart;
+mod stats;
+mod tags;
+mod tui;
+
+/// How the player is shown when it starts.
+#[derive(Clone, Copy, PartialEq)]
+enum Frontend {
+    Window,
+    Headless,
+    Terminal,
+}
+
+/// What the command line asks for.
+struct Args {
+    request: Request,
+    frontend: Frontend,
+    /// Whether the song restored from the last session starts paused.
+    start_paused: bool,
+}
 
 fn main() {
-    Win::run(()).unwrap();
+    let args = match parse_args(env::args_os().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    // Only one player runs, which the others hand their request to.
+    let lock = Lock::take();
+    if let Some(stream) = remote::connect() {
+        if args.frontend != Frontend::Window {
+            eprintln!("Blue Music is already running");
+            process::exit(1);
+        }
+        match remote::send(stream, &args.request) {
+            Ok(Ok(answer)) => print!("{}", answer),
+            Ok(Err(error)) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+            Err(error) => {
+                eprintln!("Unable to reach the running player: {}", error);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let (files, play) = match args.request {
+        Request::Open(files, play) => (files, play),
+        Request::Raise => (Vec::new(), false),
+        _ => {
+            eprintln!("Blue Music is not running");
+            process::exit(1);
+        }
+    };
+    match args.frontend {
+        Frontend::Window => Win::run((files, play, args.start_paused, lock)).unwrap(),
+        Frontend::Headless => daemon::run(files, play, false, lock),
+        Frontend::Terminal => daemon::run(files, play, true, lock),
+    }
+}
+
+/// Reads the command line into the request it makes.
+fn parse_args(mut args: impl Iterator<Item = OsString>) -> Result<Args, String> {
+    let mut files = Vec::new();
+    let mut enqueue = false;
+    let mut frontend = Frontend::Window;
+    let mut start_paused = false;
+    let mut control = None;
+    while let Some(arg) = args.next() {
+        let flag = match arg.to_str() {
+            Some(flag) if flag.starts_with("--") => flag,
+            // Files are found from where the command was run, not the player.
+            _ => {
+                files.push(env::current_dir().unwrap_or_default().join(arg));
+                continue;
+            }
+        };
+        let request = match flag {
+            PAUSED_FLAG => {
+                start_paused = true;
+                continue;
+            }
+            ENQUEUE_FLAG => {
+                enqueue = true;
+                continue;
+            }
+            HEADLESS_FLAG | TUI_FLAG => {
+                if frontend != Frontend::Window {
+                    return Err(format!("{} and {} cannot be combined", HEADLESS_FLAG, TUI_FLAG));
+                }
+                frontend = if flag == TUI_FLAG {
+                    Frontend::Terminal
+                } else {
+                    Frontend::Headless
+                };
+                continue;
+            }
+            "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--play-pause" => Request::PlayPause,
+            "--next" => Request::Next,
+            "--previous" => Request::Previous,
+            "--stop" => Request::Stop,
+            "--status" => Request::Status,
+            "--quit" => Request::Quit,
+            SEEK_FLAG => {
+                let position = args.next().unwrap_or_default();
+                let position = position.to_string_lossy();
+                match Seek::parse(&position) {
+                    Some(seek) => Request::Seek(seek),
+                    None => return Err(format!("Invalid position to seek to: {}", position)),
+                }
+            }
+            _ => return Err(format!("Unknown option: {}", flag)),
+        };
+        if control.is_some() {
+            return Err("Only one control can be given at a time".to_string());
+        }
+        control = Some(request);
+    }
+
+    let request = match control {
+        Some(_) if !files.is_empty() => {
+            return Err("Files cannot be opened with a control".to_string());
+        }
+        Some(_) if frontend != Frontend::Window => {
+            return Err("Controls are given to the player already running".to_string());
+        }
+        Some(request) => request,
+        None if files.is_empty() => Request::Raise,
+        None => Request::Open(files, !enqueue),
+    };
+    Ok(Args {
+        request,
+        frontend,
+        start_paused,
+    })
 }
 
 #[derive(Msg)]
 pub enum Msg {
+    Browse(bool),
+    CancelImport,
+    ChooseCover(Vec<Track>),
+    CycleRepeat,
+    DeleteTab(usize),
+    DuplicateTab(usize),
+    Duplicated(usize, Vec<Track>),
+    EditRule(usize),
+    EditTags(Vec<Track>),
+    Enqueue(Vec<Track>),
+    ExportTab(usize),
+    ExtractCover(Box<Track>),
+    Failed(String, Vec<String>),
+    History,
     Open,
+    OpenFiles,
     PlayPause,
     Previous,
     Stop,
-    Meta(Vec<String>),
-    MsgRecv(PlayerMsg),
+    ImportProgress(usize, usize, usize),
+    LibraryChanged(Vec<Change>),
+    Meta(usize, Box<Track>),
+    MsgRecv(usize, PlayerMsg),
+    Mpris(Command),
+    NewSmartTab,
+    NewTab,
     Next,
     Remove,
+    Rename(Vec<Track>),
+    RenameTab(usize),
+    Roots,
     Save,
-    Started(Option<Pixbuf>),
+    Scrobbling,
+    Search(String),
+    ShowTabMenu(usize, u32, u32),
+    Shuffle(bool),
+    Started(usize, Option<Pixbuf>),
+    SwitchTab(u32),
+    Volume(f64),
+    PlayTracks(Vec<Track>),
+    TagFromPaths(Vec<Track>),
     Quit,
-    Duration(u64),
+    Remote(Request, Sender<Reply>),
+    Duration(usize, u64),
     Changed,
 }
 
+/// A playlist shown as a tab.
+struct Tab {
+    id: usize,
+    /// The name the playlist is kept under, or `None` for the playlist of the
+    /// library, which cannot be renamed or deleted.
+    name: Option<String>,
+    /// Whether the playlist lists the songs matching a rule.
+    smart: bool,
+    label: Label,
+    playlist: Component<Playlist>,
+}
+
 pub struct Model {
     adjustment: Adjustment,
+    browsing: bool,
     cover_pixbuf: Option<Pixbuf>,
     cover_visible: bool,
     current_duration: u64,
+    /// The song playing, kept for the session and told to the command line.
+    current_track: Option<Box<Track>>,
     current_time: u64,
+    import_fraction: f64,
+    import_text: String,
+    importing: bool,
+    /// The tab whose playlist is being imported into.
+    importing_tab: Option<usize>,
+    /// Offers the player to media keys and desktop shells.
+    mpris: Mpris,
+    next_tab: usize,
+    paused: bool,
     play_image: Image,
+    /// Whether the files opened from the command line play, rather than being
+    /// added to the playlist.
+    play_startup: bool,
+    /// The tab the player last played from, which keeps playing while other
+    /// tabs are shown.
+    playing_tab: Option<usize>,
+    repeat: Repeat,
+    /// Where the song restored paused from the last session picks up.
+    restoring: Option<u64>,
+    search: String,
+    /// The player, import workers, library and scrobbler the playlists
+    /// share.
+    shared: Rc<Shared>,
+    shuffle: bool,
     stopped: bool,
     last_adjustment: f64,
     relm: Relm<Win>,
+    /// Answers the command line of other instances until dropped, `None` if
+    /// the socket could not be bound.
+    _remote: Option<Server>,
+    start_paused: bool,
+    startup_files: Vec<PathBuf>,
+    tab_menu: Menu,
+    tabs: Vec<Tab>,
+    visible_tab: usize,
+    volume: f64,
 }
 
 #[widget]
 impl Widget for Win {
-    fn model(relm: &Relm<Self>, _: ()) -> Model {
+    fn model(
+        relm: &Relm<Self>,
+        (startup_files, play_startup, start_paused, lock): (Vec<PathBuf>, bool, bool, Lock),
+    ) -> Model {
+        let stream = relm.stream().clone();
+        let (_channel, commands) = Channel::new(move |command| stream.emit(Msg::Mpris(command)));
+        let stream = relm.stream().clone();
+        let (_channel, requests) =
+            Channel::new(move |(request, reply)| stream.emit(Msg::Remote(request, reply)));
         Model {
             adjustment: Adjustment::new(0.0, 0.0, 0.0, 0.0, 0.0, 0.0),
+            browsing: false,
             cover_pixbuf: None,
             cover_visible: false,
             current_duration: 0,
+            current_track: None,
             current_time: 0,
+            import_fraction: 0.0,
+            import_text: String::new(),
+            importing: false,
+            importing_tab: None,
+            mpris: Mpris::start(commands),
+            next_tab: 0,
+            paused: false,
             play_image: new_icon(PLAY_ICON),
+            play_startup,
+            playing_tab: None,
+            repeat: Repeat::Off,
+            restoring: None,
+            search: String::new(),
+            shared: Shared::new(),
+            shuffle: false,
             stopped: true,
             last_adjustment: 0.0,
             relm: relm.clone(),
+            _remote: Server::start(lock, requests),
+            start_paused,
+            startup_files,
+            tab_menu: Menu::new(),
+            tabs: Vec::new(),
+            visible_tab: 0,
+            volume: 1.0,
         }
     }
 
@@ -90,21 +429,100 @@ impl Widget for Win {
 
     fn player_message(&mut self, player_msg: PlayerMsg) {
         match player_msg {
-            playlist::PlayerMsg::PlayerPlay => {
+            PlayerMsg::Play => {
                 self.model.stopped = false;
+                self.model.paused = false;
                 self.set_play_icon(PAUSE_ICON);
+                self.show_status();
             }
-            playlist::PlayerMsg::PlayerStop => {
+            PlayerMsg::Stop => {
                 self.set_play_icon(PLAY_ICON);
                 self.model.stopped = true;
+                self.show_status();
             }
-            playlist::PlayerMsg::PlayerTime(time) => self.set_current_time(time),
+            PlayerMsg::Time(time) => self.set_current_time(time),
+            // Followed by the playlist
+            PlayerMsg::NextTrack | PlayerMsg::End => (),
         }
     }
 
     fn update(&mut self, event: Msg) {
         match event {
-            Msg::MsgRecv(player_msg) => self.player_message(player_msg),
+            Msg::Browse(browsing) => {
+                self.model.browsing = browsing;
+                if browsing {
+                    self.browser.emit(Refresh);
+                }
+            }
+            Msg::EditTags(tracks) => {
+                if let Some(tags) = editor::show_tag_editor(&self.window, &tracks) {
+                    let paths = tracks.iter().map(|track| PathBuf::from(&track.path));
+                    self.emit_visible(SaveTags(paths.zip(tags).collect()));
+                }
+            }
+            Msg::ChooseCover(tracks) => {
+                if let Some(image) = show_image_dialog(&self.window) {
+                    let paths = tracks.iter().map(|track| PathBuf::from(&track.path));
+                    self.emit_visible(EmbedCover(paths.collect(), image));
+                }
+            }
+            Msg::CancelImport => {
+                let tab = self.model.importing_tab.and_then(|id| self.tab_index(id));
+                if let Some(index) = tab {
+                    self.model.tabs[index].playlist.emit(CancelImport);
+                }
+            }
+            Msg::CycleRepeat => {
+                let repeat = self.model.repeat.next();
+                self.set_repeat(repeat);
+            }
+            Msg::DeleteTab(id) => self.delete_tab(id),
+            Msg::DuplicateTab(id) => {
+                if let Some(index) = self.tab_index(id) {
+                    self.model.tabs[index].playlist.emit(Duplicate);
+                }
+            }
+            Msg::Duplicated(id, tracks) => {
+                let name = match self.tab_index(id) {
+                    Some(index) => self.model.tabs[index].title(),
+                    None => return,
+                };
+                if let Some(index) = self.create_tab(&format!("{} copy", name)) {
+                    self.model.tabs[index].playlist.emit(AddTracks(tracks));
+                }
+            }
+            Msg::EditRule(id) => self.edit_rule(id),
+            Msg::Enqueue(tracks) => self.emit_edited(AddTracks(tracks)),
+            Msg::ExportTab(id) => {
+                let index = match self.tab_index(id) {
+                    Some(index) => index,
+                    None => return,
+                };
+                let tab = &self.model.tabs[index];
+                if let Some((file, relative)) = show_save_dialog(&self.window, &tab.title()) {
+                    tab.playlist.emit(SaveSong(file, relative));
+                }
+            }
+            Msg::ExtractCover(track) => {
+                let path = Path::new(&track.path);
+                if let Some(destination) = show_extract_dialog(&self.window, path) {
+                    if let Err(error) = artwork::extract(path, &destination) {
+                        show_error_dialog(
+                            &self.window,
+                            &format!("Could not extract the cover:\n{}", error),
+                        );
+                    }
+                }
+            }
+            Msg::Failed(heading, errors) => {
+                show_error_dialog(&self.window, &format!("{}\n{}", heading, errors.join("\n")))
+            }
+            // Only the playlist playing drives the controls.
+            Msg::MsgRecv(id, player_msg) => {
+                if self.model.playing_tab == Some(id) {
+                    self.player_message(player_msg);
+                }
+            }
             Msg::Changed => {
                 // NOTES:
                 // - This causes the stop button malfunction
@@ -122,71 +540,421 @@ impl Widget for Win {
                 //     self.model.relm.stream().emit(Msg::Next);
                 // }
             }
-            Msg::Meta(metadata) => {
-                self.title
-                    .set_markup(&format!("<span size='large'>{}</span>", metadata[0])[..]);
-                self.artist
-                    .set_markup(&format!("<span size='medium'>{}</span>", metadata[1])[..]);
-                self.album
-                    .set_markup(&format!("<span size='medium'>{}</span>", metadata[2])[..]);
-                self.genre
-                    .set_markup(&format!("<span size='medium'>{}</span>", metadata[3])[..]);
-                self.year
-                    .set_markup(&format!("<span size='medium'>{}</span>", metadata[4])[..]);
+            Msg::History => show_history_dialog(&self.window),
+            Msg::ImportProgress(id, done, total) => {
+                self.model.importing_tab = Some(id);
+                self.model.importing = total > 0;
+                self.model.import_fraction = done as f64 / total.max(1) as f64;
+                self.model.import_text = format!("Imported {} of {} songs", done, total);
+            }
+            Msg::LibraryChanged(changes) => {
+                // The playlist of the library watches its folders for the
+                // others.
+                for tab in self.model.tabs.iter().filter(|tab| tab.name.is_some()) {
+                    tab.playlist.emit(LibraryChanged(changes.clone()));
+                }
+            }
+            Msg::Meta(id, track) => {
+                self.follow(id);
+                self.show_meta(&track);
+                self.model.mpris.set_track(Some(&track));
+                self.model.current_track = Some(track);
+            }
+            Msg::Mpris(command) => self.mpris_command(command),
+            Msg::NewSmartTab => self.create_smart_tab(),
+            Msg::NewTab => {
+                self.create_tab("Playlist");
             }
             Msg::Open => self.open(),
+            Msg::OpenFiles => {
+                let files = show_files_dialog(&self.window);
+                self.open_files(files, false);
+            }
             Msg::PlayPause => {
                 if self.model.stopped {
                     self.model.last_adjustment = 0.0;
-                    self.playlist.emit(PlaySong);
+                    self.emit_controlled(PlaySong);
                 } else {
-                    self.playlist.emit(PauseSong);
+                    self.emit_controlled(PauseSong);
+                    self.model.paused = true;
                     self.set_play_icon(PLAY_ICON);
                 }
+                self.show_status();
             }
             Msg::Previous => {
                 self.model.last_adjustment = 0.0;
-                self.playlist.emit(PreviousSong);
+                self.emit_controlled(PreviousSong);
             }
             Msg::Stop => {
                 self.set_current_time(0);
                 self.model.last_adjustment = 0.0;
                 self.model.current_duration = 0;
-                self.playlist.emit(StopSong);
+                self.emit_controlled(StopSong);
+                self.model.current_track = None;
+                self.model.paused = false;
                 self.model.cover_visible = false;
                 self.set_play_icon(PLAY_ICON);
+                self.model.mpris.set_track(None);
+                self.show_status();
             }
             Msg::Next => {
                 self.model.last_adjustment = 0.0;
-                self.playlist.emit(NextSong);
+                self.emit_controlled(NextSong);
+            }
+            Msg::Remove => self.emit_visible(RemoveSong),
+            Msg::Rename(tracks) => {
+                if let Some(moves) = editor::show_rename_dialog(&self.window, &tracks) {
+                    self.emit_visible(MoveFiles(moves));
+                }
+            }
+            Msg::Roots => {
+                if let Some(roots) = show_roots_dialog(&self.window, library::roots()) {
+                    let library = self.model.tabs.iter().find(|tab| tab.name.is_none());
+                    if let Some(tab) = library {
+                        tab.playlist.emit(SetRoots(roots));
+                    }
+                }
+            }
+            Msg::RenameTab(id) => self.rename_tab(id),
+            Msg::ShowTabMenu(id, button, time) => self.show_tab_menu(id, button, time),
+            Msg::Shuffle(shuffle) => {
+                self.model.shuffle = shuffle;
+                for tab in &self.model.tabs {
+                    tab.playlist.emit(SetShuffle(shuffle));
+                }
+                self.model.mpris.set_shuffle(shuffle);
+            }
+            Msg::SwitchTab(page) => {
+                self.model.visible_tab = page as usize;
+                // Every playlist is filtered by the search shown.
+                let search = self.model.search.clone();
+                self.emit_visible(Filter(search));
             }
-            Msg::Remove => self.playlist.emit(RemoveSong),
             Msg::Save => {
-                let file = show_save_dialog(&self.window);
-                if let Some(file) = file {
-                    self.playlist.emit(SaveSong(file));
+                let name = self.model.tabs[self.model.visible_tab].title();
+                if let Some((file, relative)) = show_save_dialog(&self.window, &name) {
+                    self.emit_visible(SaveSong(file, relative));
                 }
             }
-            Msg::Started(pixbuf) => {
-                self.set_play_icon(PAUSE_ICON);
+            Msg::Remote(request, reply) => {
+                let answer = self.answer(request);
+                // The command line may have given up waiting.
+                let _ = reply.send(answer);
+            }
+            Msg::Scrobbling => {
+                if let Some(settings) = show_scrobbling_dialog(&self.window) {
+                    settings.save();
+                    self.model.shared.restart_scrobbler();
+                }
+            }
+            Msg::Search(text) => {
+                self.model.search = text.clone();
+                self.emit_visible(Filter(text));
+            }
+            Msg::PlayTracks(tracks) => {
+                self.emit_edited(ReplaceTracks(tracks));
+                self.browse_button.set_active(false);
+            }
+            Msg::Started(id, pixbuf) => {
+                self.follow(id);
+                // The song restored paused is not loaded until played.
+                if let Some(position) = self.model.restoring.take() {
+                    self.model.paused = true;
+                    self.model.stopped = true;
+                    self.set_current_time(position);
+                    self.set_play_icon(PLAY_ICON);
+                } else {
+                    self.model.paused = false;
+                    self.set_play_icon(PAUSE_ICON);
+                }
                 self.model.cover_visible = true;
                 self.model.cover_pixbuf = pixbuf;
+                self.show_status();
             }
-            Msg::Duration(duration) => {
+            Msg::TagFromPaths(tracks) => {
+                if let Some(edits) = editor::show_tags_from_paths_dialog(&self.window, &tracks) {
+                    self.emit_visible(SaveTags(edits));
+                }
+            }
+            Msg::Duration(id, duration) => {
+                self.follow(id);
                 self.model.current_duration = duration;
                 self.model.adjustment.set_upper(duration as f64);
+                self.model.mpris.set_length(duration);
+            }
+            Msg::Volume(volume) => {
+                self.model.volume = volume;
+                for tab in &self.model.tabs {
+                    tab.playlist.emit(SetVolume(volume));
+                }
+                self.model.mpris.set_volume(volume);
+            }
+            Msg::Quit => {
+                self.save_session();
+                // The playlists share the layout of their columns, which is
+                // saved from the one shown.
+                self.emit_visible(ColumnsChanged);
+                for tab in &self.model.tabs {
+                    tab.playlist.emit(Persist);
+                }
+                // Idle callbacks run once the playlists have been written.
+                glib::idle_add(|| {
+                    gtk::main_quit();
+                    glib::Continue(false)
+                });
             }
-            Msg::Quit => gtk::main_quit(),
         }
     }
 
     fn init_view(&mut self) {
         self.toolbar.show_all();
+
+        let new_button = Button::new_from_icon_name("list-add-symbolic", IconSize::Menu);
+        new_button.set_relief(ReliefStyle::None);
+        new_button.set_tooltip_text("New playlist");
+        connect!(self.model.relm, new_button, connect_clicked(_), Msg::NewTab);
+        let smart_button = Button::new_from_icon_name("edit-find-symbolic", IconSize::Menu);
+        smart_button.set_relief(ReliefStyle::None);
+        smart_button.set_tooltip_text("New smart playlist");
+        connect!(self.model.relm, smart_button, connect_clicked(_), Msg::NewSmartTab);
+        let buttons = gtk::Box::new(Horizontal, 0);
+        buttons.add(&new_button);
+        buttons.add(&smart_button);
+        buttons.show_all();
+        self.notebook.set_action_widget(&buttons, PackType::End);
+
+        self.add_tab(None);
+        for name in saved_playlists() {
+            self.add_tab(Some(name));
+        }
+
+        let session = Session::load();
+        self.restore_session(&session);
+
+        let files = mem::take(&mut self.model.startup_files);
+        self.open_files(files, self.model.play_startup);
+    }
+
+    /// Shows the window, the tab and the song of the last session as they
+    /// were left.
+    fn restore_session(&mut self, session: &Session) {
+        if session.maximized {
+            self.window.maximize();
+        } else if let Some((width, height, x, y)) = session.geometry {
+            self.window.resize(width, height);
+            self.window.move_(x, y);
+        }
+
+        if let Some(index) = self.titled_tab(&session.tab) {
+            self.notebook.set_current_page(index as u32);
+        }
+
+        self.model.volume = session.volume;
+        self.volume_button.set_value(session.volume);
+        for tab in &self.model.tabs {
+            tab.playlist.emit(SetVolume(session.volume));
+        }
+        self.set_repeat(session.repeat);
+        self.shuffle_button.set_active(session.shuffle);
+
+        // Songs opened from the command line play instead.
+        if self.model.play_startup && !self.model.startup_files.is_empty() {
+            return;
+        }
+        let playing_tab = self.titled_tab(&session.playing_tab);
+        if let (Some(index), Some(song)) = (playing_tab, &session.song) {
+            let paused = session.paused || self.model.start_paused;
+            if paused {
+                self.model.restoring = Some(session.position);
+            }
+            let playlist = &self.model.tabs[index].playlist;
+            playlist.emit(Resume(song.clone(), session.position, paused));
+        }
+    }
+
+    /// Returns the position of the tab titled `title`.
+    fn titled_tab(&self, title: &Option<String>) -> Option<usize> {
+        let title = title.as_ref()?;
+        self.model.tabs.iter().position(|tab| tab.title() == *title)
+    }
+
+    fn save_session(&self) {
+        let playing_tab = self.model.playing_tab.and_then(|id| self.tab_index(id));
+        let (width, height) = self.window.get_size();
+        let (x, y) = self.window.get_position();
+        let position = self.model.restoring.unwrap_or(self.model.current_time);
+        Session {
+            tab: self.model.tabs.get(self.model.visible_tab).map(Tab::title),
+            playing_tab: playing_tab.map(|index| self.model.tabs[index].title()),
+            song: self.model.current_track.as_ref().map(|track| track.path.clone()),
+            position,
+            paused: self.model.paused || self.model.stopped,
+            volume: self.model.volume,
+            repeat: self.model.repeat,
+            shuffle: self.model.shuffle,
+            geometry: Some((width, height, x, y)),
+            maximized: self.window.is_maximized(),
+        }
+        .save();
+    }
+
+    /// Fills the now playing panel with the tags of `track`.
+    fn show_meta(&self, track: &Track) {
+        let tags = &track.tags;
+        let mut artist = track.artist();
+        let performer = track.performer();
+        if !performer.is_empty() {
+            artist = format!("{} (performed by {})", artist, performer);
+        }
+        let mut album = track.album();
+        if track.album_artist() != track.artist() {
+            album = format!("{} by {}", album, track.album_artist());
+        }
+        if tags.disc_total.as_ref().is_some_and(|total| total != "1") {
+            album = format!("{}, disc {}", album, track.disc_number());
+        }
+        let mut date = tags.date.clone().unwrap_or_else(|| track.year());
+        if let Some(original) = &tags.original_date {
+            if Some(original) != tags.date.as_ref() {
+                date = format!("{} (originally {})", date, original);
+            }
+        }
+
+        let labels = [
+            (&self.title, "large", track.title()),
+            (&self.artist, "medium", artist),
+            (&self.album, "medium", album),
+            (&self.genre, "medium", track.genre()),
+            (&self.year, "medium", date),
+        ];
+        for (label, size, text) in labels.iter() {
+            label.set_markup(&format!(
+                "<span size='{}'>{}</span>",
+                size,
+                glib::markup_escape_text(text)
+            ));
+        }
     }
 
     fn set_current_time(&mut self, time: u64) {
         self.model.current_time = time;
         self.model.adjustment.set_value(time as f64);
+        self.model.mpris.set_position(time);
+    }
+
+    /// Tells media controls whether a song is playing, paused or stopped.
+    fn show_status(&self) {
+        let status = if self.model.paused {
+            Status::Paused
+        } else if self.model.stopped {
+            Status::Stopped
+        } else {
+            Status::Playing
+        };
+        self.model.mpris.set_status(status);
+    }
+
+    fn set_repeat(&mut self, repeat: Repeat) {
+        self.model.repeat = repeat;
+        for tab in &self.model.tabs {
+            tab.playlist.emit(SetRepeat(repeat));
+        }
+        let (icon, tooltip) = match repeat {
+            Repeat::Off => ("media-playlist-consecutive-symbolic", "Repeat: off"),
+            Repeat::Playlist => ("media-playlist-repeat-symbolic", "Repeat: playlist"),
+            Repeat::Track => ("media-playlist-repeat-song-symbolic", "Repeat: song"),
+        };
+        self.repeat_button.set_icon_name(icon);
+        self.repeat_button.set_tooltip_text(tooltip);
+        self.model.mpris.set_repeat(repeat);
+    }
+
+    /// Does what media controls ask for.
+    fn mpris_command(&mut self, command: Command) {
+        let stream = self.model.relm.stream().clone();
+        match command {
+            Command::Raise => self.window.present(),
+            Command::Quit => stream.emit(Msg::Quit),
+            Command::Play if self.model.stopped => stream.emit(Msg::PlayPause),
+            Command::Pause if !self.model.stopped => stream.emit(Msg::PlayPause),
+            Command::Play | Command::Pause => (),
+            Command::PlayPause => stream.emit(Msg::PlayPause),
+            Command::Stop => stream.emit(Msg::Stop),
+            Command::Next => stream.emit(Msg::Next),
+            Command::Previous => stream.emit(Msg::Previous),
+            Command::Seek(offset) => self.seek_by(offset),
+            Command::SetPosition(position) => self.seek(position),
+            Command::Open(file) => self.open_files(vec![file], false),
+            // The volume button tells the playlists, like when it is clicked.
+            Command::Volume(volume) => self.volume_button.set_value(volume),
+            Command::Repeat(repeat) => self.set_repeat(repeat),
+            Command::Shuffle(shuffle) => self.shuffle_button.set_active(shuffle),
+        }
+    }
+
+    /// Moves playback of the current song `offset` milliseconds forward, or
+    /// back when negative. Seeking past the end goes on to the next song.
+    fn seek_by(&mut self, offset: i64) {
+        let position = (self.model.current_time as i64 + offset).max(0) as u64;
+        if self.model.current_duration > 0 && position >= self.model.current_duration {
+            self.model.relm.stream().emit(Msg::Next);
+        } else {
+            self.seek(position);
+        }
+    }
+
+    /// Moves playback of the current song to `position`, in milliseconds.
+    fn seek(&mut self, position: u64) {
+        if self.model.current_track.is_none() {
+            return;
+        }
+        self.emit_controlled(Skip(position as u32));
+        self.set_current_time(position);
+        self.model.mpris.seeked(position);
+    }
+
+    /// Does what the command line of another instance asks for.
+    fn answer(&mut self, request: Request) -> Reply {
+        let stream = self.model.relm.stream().clone();
+        match request {
+            Request::Open(files, play) => self.open_files(files, play),
+            Request::Raise => self.window.present(),
+            Request::PlayPause => stream.emit(Msg::PlayPause),
+            Request::Next => stream.emit(Msg::Next),
+            Request::Previous => stream.emit(Msg::Previous),
+            Request::Stop => stream.emit(Msg::Stop),
+            Request::Seek(_) if self.model.current_track.is_none() => {
+                return Err("Nothing is playing".to_string());
+            }
+            Request::Seek(Seek::By(offset)) => self.seek_by(offset),
+            Request::Seek(Seek::To(position)) => {
+                self.seek_by(position as i64 - self.model.current_time as i64)
+            }
+            Request::Status => return Ok(self.status()),
+            Request::Quit => stream.emit(Msg::Quit),
+        }
+        Ok(String::new())
+    }
+
+    /// Describes the player for `--status`.
+    fn status(&self) -> String {
+        let state = if self.model.paused {
+            "paused"
+        } else if self.model.stopped {
+            "stopped"
+        } else {
+            "playing"
+        };
+        Report {
+            state,
+            track: self.model.current_track.as_deref(),
+            position: self.model.current_time,
+            duration: self.model.current_duration,
+            volume: self.model.volume,
+            repeat: self.model.repeat,
+            shuffle: self.model.shuffle,
+        }
+        .describe()
     }
 
     fn set_play_icon(&self, icon: &str) {
@@ -211,16 +979,42 @@ impl Widget for Win {
                         clicked => Msg::Open,
                         tooltip_text: "Open folder",
                     },
+                    gtk::ToolButton {
+                        icon_name: "audio-x-generic",
+                        clicked => Msg::OpenFiles,
+                        tooltip_text: "Open songs or playlists",
+                    },
                     gtk::ToolButton {
                         icon_widget: &new_icon("document-save"),
                         clicked => Msg::Save,
                         tooltip_text: "Save playlist",
                     },
+                    gtk::ToolButton {
+                        icon_name: "folder-music",
+                        clicked => Msg::Roots,
+                        tooltip_text: "Library folders",
+                    },
+                    gtk::ToolButton {
+                        icon_name: "document-open-recent",
+                        clicked => Msg::History,
+                        tooltip_text: "Listening history",
+                    },
+                    gtk::ToolButton {
+                        icon_name: "network-transmit-receive",
+                        clicked => Msg::Scrobbling,
+                        tooltip_text: "Scrobbling",
+                    },
+                    #[name="browse_button"]
+                    gtk::ToggleToolButton {
+                        icon_name: "view-grid-symbolic",
+                        toggled(button) => Msg::Browse(button.get_active()),
+                        tooltip_text: "Browse library",
+                    },
                     gtk::SeparatorToolItem {
                     },
                     gtk::ToolButton {
                         icon_widget: &new_icon("gtk-media-previous"),
-                        clicked => playlist@PreviousSong,
+                        clicked => Msg::Previous,
                         tooltip_text: "Previous song",
                     },
                     gtk::ToolButton {
@@ -235,19 +1029,26 @@ impl Widget for Win {
                     },
                     gtk::ToolButton {
                         icon_widget: &new_icon("gtk-media-next"),
-                        clicked => playlist@NextSong,
+                        clicked => Msg::Next,
                         tooltip_text: "Next song",
                     },
-                    gtk::ToolButton {
+                    #[name="shuffle_button"]
+                    gtk::ToggleToolButton {
                         icon_widget: &new_icon("shuffle"),
-                        clicked => playlist@NextSong,
+                        toggled(button) => Msg::Shuffle(button.get_active()),
                         tooltip_text: "Shuffle",
                     },
+                    #[name="repeat_button"]
+                    gtk::ToolButton {
+                        icon_name: "media-playlist-consecutive-symbolic",
+                        clicked => Msg::CycleRepeat,
+                        tooltip_text: "Repeat: off",
+                    },
                     gtk::SeparatorToolItem {
                     },
                     gtk::ToolButton {
                         icon_widget: &new_icon("remove"),
-                        clicked => playlist@RemoveSong,
+                        clicked => Msg::Remove,
                         tooltip_text: "Remove selected",
                     },
                     gtk::SeparatorToolItem {
@@ -258,12 +1059,48 @@ impl Widget for Win {
                         tooltip_text: "Quit",
                     },
                 },
-                #[name="playlist"]
-                Playlist {
-                    PlayerMsgRecv(ref player_msg) => Msg::MsgRecv(player_msg.clone()),
-                    SongStarted(ref pixbuf) => Msg::Started(pixbuf.clone()),
-                    SongDuration(duration) => Msg::Duration(duration),
-                    SongMeta(ref metadata) => Msg::Meta(metadata.clone()),
+                gtk::SearchEntry {
+                    placeholder_text: "Search, e.g. artist:davis year:<1970 -genre:jazz",
+                    search_changed(entry) => Msg::Search(
+                        entry.get_text().map(|text| text.to_string()).unwrap_or_default()
+                    ),
+                },
+                gtk::Box {
+                    visible: self.model.importing,
+                    orientation: Horizontal,
+                    spacing: 10,
+                    gtk::ProgressBar {
+                        valign: Align::Center,
+                        fraction: self.model.import_fraction,
+                        hexpand: true,
+                        margin_start: 10,
+                        show_text: true,
+                        text: Some(self.model.import_text.as_str()),
+                    },
+                    gtk::Button {
+                        label: "Cancel",
+                        margin_end: 10,
+                        clicked => Msg::CancelImport,
+                    },
+                },
+                gtk::Box {
+                    visible: !self.model.browsing,
+                    vexpand: true,
+                    #[name="notebook"]
+                    gtk::Notebook {
+                        hexpand: true,
+                        scrollable: true,
+                        switch_page(_, _, page) => Msg::SwitchTab(page),
+                    },
+                },
+                gtk::Box {
+                    visible: self.model.browsing,
+                    vexpand: true,
+                    #[name="browser"]
+                    Browser {
+                        Enqueue(ref tracks) => Msg::Enqueue(tracks.clone()),
+                        PlayTracks(ref tracks) => Msg::PlayTracks(tracks.clone()),
+                    },
                 },
                 gtk::Box {
                     visible: self.model.cover_visible,
@@ -333,36 +1170,370 @@ impl Widget for Win {
                         text: "/",
                     },
                     gtk::Label {
-                        margin_end: 10,
                         text: &millis_to_minutes(self.model.current_duration),
                     },
+                    #[name="volume_button"]
+                    gtk::VolumeButton {
+                        margin_end: 10,
+                        value_changed(_, value) => Msg::Volume(value),
+                    },
                 }
             },
             // Use a tuple when you want to both send a message and return a value to
             // the GTK+ callback.
-            delete_event(_, _) => (Msg::Quit, Inhibit(false)),
+            // The window is kept until the session has been saved from it.
+            delete_event(_, _) => (Msg::Quit, Inhibit(true)),
         }
     }
 }
 
+impl Tab {
+    fn title(&self) -> String {
+        self.name.clone().unwrap_or_else(|| LIBRARY_TAB.to_string())
+    }
+
+    /// Returns the file the playlist is kept in.
+    fn file(&self) -> Option<PathBuf> {
+        self.name.as_ref().map(|name| tab_file(name))
+    }
+}
+
 impl Win {
-    fn open(&self) {
-        let files = show_open_dialog(&self.window);
-        let mut unopened = Vec::new();
-        for file in files {
-            let ext = file
-                .extension()
-                .map(|ext| ext.to_str().unwrap().to_string());
-            if let Some(ext) = ext {
-                match ext.as_str() {
-                    "flac" => self.playlist.emit(AddSong(file)),
-                    "mp3" => (),
-                    "m3u" => (),
-                    _ => {
-                        unopened.push(file.file_name().unwrap().to_string_lossy().to_string());
-                    }
+    /// Adds a tab for the playlist kept under `name`, or for the playlist of
+    /// the library. Returns its position.
+    fn add_tab(&mut self, name: Option<String>) -> usize {
+        let file = name.as_ref().map(|name| tab_file(name));
+        let smart = file.as_ref().is_some_and(|file| smart::is_smart(file));
+        let id = self.model.next_tab;
+        self.model.next_tab += 1;
+        let playlist = relm::init::<Playlist>((file, id, self.model.shared.clone()))
+            .expect("Cannot create playlist");
+
+        let relm = &self.model.relm;
+        connect!(playlist@ChooseCover(ref tracks), relm, Msg::ChooseCover(tracks.clone()));
+        connect!(playlist@Duplicated(ref tracks), relm, Msg::Duplicated(id, tracks.clone()));
+        connect!(playlist@EditTags(ref tracks), relm, Msg::EditTags(tracks.clone()));
+        connect!(playlist@ExtractCover(ref track), relm, Msg::ExtractCover(track.clone()));
+        connect!(playlist@FilesFailed(ref heading, ref errors), relm,
+            Msg::Failed(heading.clone(), errors.clone()));
+        connect!(playlist@ImportProgress(done, total), relm,
+            Msg::ImportProgress(id, done, total));
+        connect!(playlist@PlayerMsgRecv(ref player_msg), relm,
+            Msg::MsgRecv(id, player_msg.clone()));
+        connect!(playlist@RenameFiles(ref tracks), relm, Msg::Rename(tracks.clone()));
+        connect!(playlist@SongDuration(duration), relm, Msg::Duration(id, duration));
+        connect!(playlist@SongMeta(ref track), relm, Msg::Meta(id, track.clone()));
+        connect!(playlist@SongStarted(ref pixbuf), relm, Msg::Started(id, pixbuf.clone()));
+        connect!(playlist@TagFromPaths(ref tracks), relm, Msg::TagFromPaths(tracks.clone()));
+        playlist.emit(SetVolume(self.model.volume));
+        playlist.emit(SetRepeat(self.model.repeat));
+        playlist.emit(SetShuffle(self.model.shuffle));
+        if name.is_none() {
+            connect!(playlist@LibraryChanged(ref changes), relm,
+                Msg::LibraryChanged(changes.clone()));
+        }
+
+        let tab = Tab {
+            id,
+            name,
+            smart,
+            label: Label::new(None),
+            playlist,
+        };
+        tab.label.set_text(&tab.title());
+        // Tabs are renamed, duplicated and deleted from a menu.
+        let event_box = EventBox::new();
+        event_box.add(&tab.label);
+        event_box.show_all();
+        connect!(relm, event_box, connect_button_press_event(_, event),
+            return tab_click(id, event));
+
+        let page = self.notebook.append_page(tab.playlist.widget(), Some(&event_box));
+        self.model.tabs.push(tab);
+        page as usize
+    }
+
+    /// Adds a tab for a new, empty playlist named after `name`, and shows it.
+    /// Returns its position.
+    fn create_tab(&mut self, name: &str) -> Option<usize> {
+        let name = self.unique_name(name);
+        let path = playlist_path(&name);
+        if let Err(error) = playlist_file::write(&path, &[], false) {
+            show_error_dialog(
+                &self.window,
+                &format!("Could not create {}:\n{}", path.display(), error),
+            );
+            return None;
+        }
+        let index = self.add_tab(Some(name));
+        self.save_tab_order();
+        self.notebook.set_current_page(index as u32);
+        Some(index)
+    }
+
+    /// Adds a tab for a new smart playlist listing the songs matching the rule
+    /// the user is asked for, and shows it.
+    fn create_smart_tab(&mut self) {
+        let rule = match show_rule_dialog(&self.window, "") {
+            Some(rule) => rule,
+            None => return,
+        };
+        let name = self.unique_name("Smart playlist");
+        let path = smart_path(&name);
+        if let Err(error) = smart::write(&path, &rule) {
+            show_error_dialog(
+                &self.window,
+                &format!("Could not create {}:\n{}", path.display(), error),
+            );
+            return;
+        }
+        let index = self.add_tab(Some(name));
+        self.save_tab_order();
+        self.notebook.set_current_page(index as u32);
+    }
+
+    fn edit_rule(&mut self, id: usize) {
+        let index = match self.tab_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let file = match self.model.tabs[index].file() {
+            Some(file) => file,
+            None => return,
+        };
+        let rule = smart::read(&file).unwrap_or_default();
+        if let Some(rule) = show_rule_dialog(&self.window, &rule) {
+            self.model.tabs[index].playlist.emit(SetRule(rule));
+        }
+    }
+
+    /// Returns `name`, numbered if another playlist already has it.
+    fn unique_name(&self, name: &str) -> String {
+        let taken = |candidate: &str| {
+            candidate == LIBRARY_TAB
+                || self.model.tabs.iter().any(|tab| tab.name.as_deref() == Some(candidate))
+        };
+        let mut candidate = name.to_string();
+        let mut number = 1;
+        while taken(&candidate) {
+            number += 1;
+            candidate = format!("{} {}", name, number);
+        }
+        candidate
+    }
+
+    fn rename_tab(&mut self, id: usize) {
+        let index = match self.tab_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let old_name = match self.model.tabs[index].name.clone() {
+            Some(name) => name,
+            None => return,
+        };
+        let name = match show_name_dialog(&self.window, &old_name) {
+            Some(name) => name,
+            None => return,
+        };
+        if name == old_name {
+            return;
+        }
+        let taken = name == LIBRARY_TAB
+            || self.model.tabs.iter().any(|tab| tab.name.as_ref() == Some(&name));
+        if taken {
+            show_error_dialog(
+                &self.window,
+                &format!("There already is a playlist named {}.", name),
+            );
+            return;
+        }
+
+        let old_path = tab_file(&old_name);
+        let path = if self.model.tabs[index].smart {
+            smart_path(&name)
+        } else {
+            playlist_path(&name)
+        };
+        if let Err(error) = fs::rename(old_path, &path) {
+            show_error_dialog(
+                &self.window,
+                &format!("Could not rename {}:\n{}", old_name, error),
+            );
+            return;
+        }
+        let tab = &mut self.model.tabs[index];
+        tab.playlist.emit(SetFile(path));
+        tab.label.set_text(&name);
+        tab.name = Some(name);
+        self.save_tab_order();
+    }
+
+    /// Deletes a playlist and its file, once the user agrees.
+    fn delete_tab(&mut self, id: usize) {
+        let index = match self.tab_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let name = match self.model.tabs[index].name.clone() {
+            Some(name) => name,
+            None => return,
+        };
+        let question = format!("Delete the playlist {}?", name);
+        if !show_confirm_dialog(&self.window, &question, "Delete") {
+            return;
+        }
+
+        if self.model.playing_tab == Some(id) {
+            self.model.playing_tab = None;
+            self.model.stopped = true;
+            self.model.paused = false;
+            self.model.cover_visible = false;
+            self.model.current_duration = 0;
+            self.set_current_time(0);
+            self.set_play_icon(PLAY_ICON);
+        }
+        if self.model.importing_tab == Some(id) {
+            self.model.importing_tab = None;
+            self.model.importing = false;
+        }
+        self.notebook.remove_page(index as u32);
+        // The player is silenced if it played for the playlist.
+        self.model.shared.remove(id);
+        self.model.tabs.remove(index);
+        if let Err(error) = fs::remove_file(tab_file(&name)) {
+            eprintln!("Unable to delete the playlist {}: {}", name, error);
+        }
+        self.save_tab_order();
+    }
+
+    fn show_tab_menu(&mut self, id: usize, button: u32, time: u32) {
+        let index = match self.tab_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let kept = self.model.tabs[index].name.is_some();
+        let smart = self.model.tabs[index].smart;
+        let menu = Menu::new();
+        let rename = MenuItem::new_with_label("Rename…");
+        rename.set_sensitive(kept);
+        connect!(self.model.relm, rename, connect_activate(_), Msg::RenameTab(id));
+        menu.append(&rename);
+        if smart {
+            let edit = MenuItem::new_with_label("Edit rule…");
+            connect!(self.model.relm, edit, connect_activate(_), Msg::EditRule(id));
+            menu.append(&edit);
+        }
+        // Duplicating a smart playlist freezes the songs it lists.
+        let label = if smart { "Freeze as playlist" } else { "Duplicate" };
+        let duplicate = MenuItem::new_with_label(label);
+        connect!(self.model.relm, duplicate, connect_activate(_), Msg::DuplicateTab(id));
+        menu.append(&duplicate);
+        let export = MenuItem::new_with_label("Export…");
+        connect!(self.model.relm, export, connect_activate(_), Msg::ExportTab(id));
+        menu.append(&export);
+        let delete = MenuItem::new_with_label("Delete…");
+        delete.set_sensitive(kept);
+        connect!(self.model.relm, delete, connect_activate(_), Msg::DeleteTab(id));
+        menu.append(&delete);
+        menu.show_all();
+        menu.popup_easy(button, time);
+        self.model.tab_menu = menu;
+    }
+
+    /// Remembers the order of the tabs of the playlists kept in files.
+    fn save_tab_order(&self) {
+        let names: Vec<&str> = self
+            .model
+            .tabs
+            .iter()
+            .filter_map(|tab| tab.name.as_deref())
+            .collect();
+        config::write(TABS_FILE, &names.join("\n"));
+    }
+
+    fn tab_index(&self, id: usize) -> Option<usize> {
+        self.model.tabs.iter().position(|tab| tab.id == id)
+    }
+
+    /// Sends `msg` to the playlist shown.
+    fn emit_visible(&self, msg: playlist::Msg) {
+        if let Some(tab) = self.model.tabs.get(self.model.visible_tab) {
+            tab.playlist.emit(msg);
+        }
+    }
+
+    /// Sends `msg`, which adds or replaces songs, to the playlist shown, or
+    /// else to the first playlist kept in a file when the library is shown,
+    /// which is not edited. That playlist is then shown, and created if there
+    /// is none.
+    fn emit_edited(&mut self, msg: playlist::Msg) {
+        let visible = self.model.visible_tab;
+        let index = if self.model.tabs.get(visible).is_some_and(|tab| tab.name.is_some()) {
+            Some(visible)
+        } else {
+            let index = self
+                .model
+                .tabs
+                .iter()
+                .position(|tab| tab.name.is_some() && !tab.smart);
+            match index {
+                Some(index) => {
+                    self.notebook.set_current_page(index as u32);
+                    Some(index)
                 }
+                None => self.create_tab("Playlist"),
             }
+        };
+        if let Some(index) = index {
+            self.model.tabs[index].playlist.emit(msg);
+        }
+    }
+
+    /// Sends `msg` to the playlist the playback buttons act on: the one
+    /// playing or paused, or else the one shown.
+    fn emit_controlled(&self, msg: playlist::Msg) {
+        let playing = self
+            .model
+            .playing_tab
+            .filter(|_| !self.model.stopped || self.model.paused)
+            .and_then(|id| self.tab_index(id));
+        let index = playing.unwrap_or(self.model.visible_tab);
+        if let Some(tab) = self.model.tabs.get(index) {
+            tab.playlist.emit(msg);
+        }
+    }
+
+    /// Makes the tab `id` the one playing, stopping the one that was.
+    fn follow(&mut self, id: usize) {
+        if self.model.playing_tab == Some(id) {
+            return;
+        }
+        let previous = self.model.playing_tab.and_then(|id| self.tab_index(id));
+        if let Some(index) = previous {
+            self.model.tabs[index].playlist.emit(StopSong);
+        }
+        self.model.playing_tab = Some(id);
+    }
+
+    fn open(&mut self) {
+        // The playlists of a folder list the songs it holds, which would be
+        // added twice.
+        let files = show_open_dialog(&self.window)
+            .into_iter()
+            .filter(|file| !playlist_file::is_playlist(file))
+            .collect();
+        self.open_files(files, false);
+    }
+
+    /// Adds songs, and the songs listed by playlists, to the playlist, playing
+    /// the first of them when `play` is set. Folders are opened like with
+    /// `open`.
+    fn open_files(&mut self, files: Vec<PathBuf>, play: bool) {
+        let mut paths = Vec::new();
+        let mut unopened = Vec::new();
+        sort_opened(files, &mut paths, &mut unopened);
+        if !paths.is_empty() {
+            self.emit_edited(AddPaths(paths, play));
         }
 
         if !unopened.is_empty() {
@@ -372,34 +1543,142 @@ impl Win {
                 display.push('\n');
             }
 
-            let dialog = MessageDialog::new(
-                Some(&self.window),
-                DialogFlags::empty(),
-                MessageType::Error,
-                ButtonsType::Ok,
-                &format!(
-                    "Finished opening folder but could not open the following files:\n{}",
-                    display
-                ),
+            show_error_dialog(
+                &self.window,
+                &format!("Could not open the following files:\n{}", display),
             );
-            dialog.run();
-            dialog.destroy();
         }
     }
 }
 
-fn millis_to_minutes(millis: u64) -> String {
-    let mut seconds = millis / 1_000;
-    let minutes = seconds / 60;
-    seconds %= 60;
-    format!("{}:{:02}", minutes, seconds)
+/// Sorts the files opened into the songs and playlists the playlist can
+/// open, looking through folders, and the names of the other files.
+fn sort_opened(files: Vec<PathBuf>, paths: &mut Vec<PathBuf>, unopened: &mut Vec<String>) {
+    for file in files {
+        if file.is_dir() {
+            // The playlists of a folder list the songs it holds, which would
+            // be added twice.
+            let songs = collect_files(&file)
+                .into_iter()
+                .filter(|file| !playlist_file::is_playlist(file))
+                .collect();
+            sort_opened(songs, paths, unopened);
+            continue;
+        }
+        if playlist_file::is_playlist(&file) {
+            paths.push(file);
+            continue;
+        }
+        let ext = file
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match ext.as_deref() {
+            Some("flac") => paths.push(file),
+            Some("mp3") => (),
+            _ => {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                unopened.push(name.to_string());
+            }
+        }
+    }
+}
+
+/// Returns the path of the file the playlist `name` is kept in.
+fn playlist_path(name: &str) -> PathBuf {
+    let dir = config::config_dir().join(PLAYLISTS_DIR);
+    if let Err(error) = fs::create_dir_all(&dir) {
+        eprintln!("Unable to create {}: {}", dir.display(), error);
+    }
+    dir.join(format!("{}.{}", name, PLAYLIST_EXTENSION))
+}
+
+/// Returns the path of the file the smart playlist `name` keeps its rule in.
+fn smart_path(name: &str) -> PathBuf {
+    playlist_path(name).with_extension(smart::EXTENSION)
+}
+
+/// Returns the path of the file of the playlist `name`, whether smart or not.
+fn tab_file(name: &str) -> PathBuf {
+    let path = smart_path(name);
+    if path.is_file() {
+        return path;
+    }
+    playlist_path(name)
+}
+
+/// Returns the names of the playlists kept in files, in the order of their
+/// tabs.
+fn saved_playlists() -> Vec<String> {
+    let exists = |name: &String| tab_file(name).is_file();
+    if let Some(order) = config::read(TABS_FILE) {
+        return order
+            .lines()
+            .map(str::to_string)
+            .filter(|name| !name.is_empty())
+            .filter(exists)
+            .collect();
+    }
+    let mut names: Vec<String> = fs::read_dir(config::config_dir().join(PLAYLISTS_DIR))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    let extension = path.extension().and_then(|ext| ext.to_str());
+                    extension == Some(PLAYLIST_EXTENSION) || smart::is_smart(path)
+                })
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+fn tab_click(id: usize, event: &EventButton) -> (Option<Msg>, Inhibit) {
+    if event.get_button() == RIGHT_BUTTON {
+        let msg = Msg::ShowTabMenu(id, event.get_button(), event.get_time());
+        return (Some(msg), Inhibit(true));
+    }
+    (None, Inhibit(false))
 }
 
 fn new_icon(icon: &str) -> Image {
     Image::new_from_file(format!("./assets/{}.png", icon))
 }
 
+/// Lets the user pick songs and playlists.
+fn show_files_dialog(parent: &Window) -> Vec<PathBuf> {
+    let dialog = FileChooserDialog::new(
+        Some("Open songs or playlists"),
+        Some(parent),
+        FileChooserAction::Open,
+    );
+    dialog.set_select_multiple(true);
+    let filter = FileFilter::new();
+    filter.add_pattern("*.flac");
+    for extension in playlist_file::EXTENSIONS.iter() {
+        filter.add_pattern(&format!("*.{}", extension));
+    }
+    filter.set_name("Songs and playlists");
+    dialog.add_filter(&filter);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button("Open", gtk::ResponseType::Accept);
+    let mut files = Vec::new();
+    if dialog.run() == GTK_RESPONSE_ACCEPT {
+        files = dialog.get_filenames();
+    }
+    dialog.destroy();
+    files
+}
+
 fn show_open_dialog(parent: &Window) -> Vec<PathBuf> {
+    show_folder_dialog(parent)
+        .map(|f| collect_files(&f))
+        .unwrap_or_default()
+}
+
+fn show_folder_dialog(parent: &Window) -> Option<PathBuf> {
     let mut folder = None;
     let dialog = FileChooserDialog::new(
         Some("Select a music folder"),
@@ -425,40 +1704,298 @@ fn show_open_dialog(parent: &Window) -> Vec<PathBuf> {
     }
     dialog.destroy();
     println!("Selected folder: {:?}", folder);
+    folder
+}
 
-    let mut files = Vec::new();
-    if let Some(f) = folder {
-        let path = WalkDir::new(f.as_path())
-            .contents_first(false)
-            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
-            .into_iter();
+/// Lets the user edit the folders the library is built from. Returns the new
+/// folders, or `None` if the dialog was cancelled.
+fn show_roots_dialog(parent: &Window, mut roots: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
+    let dialog = Dialog::new_with_buttons(
+        Some("Library folders"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("Add…", ResponseType::Other(ADD_RESPONSE as u16)),
+            ("Remove", ResponseType::Other(REMOVE_RESPONSE as u16)),
+            ("Cancel", ResponseType::Cancel),
+            ("Save", ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_size(480, 300);
+    let list = ListBox::new();
+    list.set_vexpand(true);
+    dialog.get_content_area().add(&list);
 
-        // Iterate over directories and skip if hidden
-        for entry in path.filter_entry(|e| !is_hidden(e)) {
-            if let Ok(entry) = entry {
-                let entry = entry.path();
+    let result = loop {
+        for row in list.get_children() {
+            list.remove(&row);
+        }
+        for root in &roots {
+            let label = Label::new(Some(root.to_string_lossy().as_ref()));
+            label.set_halign(Align::Start);
+            list.add(&label);
+        }
+        dialog.show_all();
 
-                files.push(entry.to_path_buf());
+        match dialog.run() {
+            ADD_RESPONSE => {
+                if let Some(folder) = show_folder_dialog(parent) {
+                    if !roots.contains(&folder) {
+                        roots.push(folder);
+                    }
+                }
             }
+            REMOVE_RESPONSE => {
+                if let Some(row) = list.get_selected_row() {
+                    roots.remove(row.get_index() as usize);
+                }
+            }
+            GTK_RESPONSE_ACCEPT => break Some(roots),
+            _ => break None,
         }
+    };
+    dialog.destroy();
+    result
+}
+
+/// Asks for a new name for a playlist. Names that cannot be file names are
+/// refused.
+fn show_name_dialog(parent: &Window, name: &str) -> Option<String> {
+    let dialog = Dialog::new_with_buttons(
+        Some("Rename playlist"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", ResponseType::Cancel), ("Rename", ResponseType::Accept)],
+    );
+    dialog.set_default_response(ResponseType::Accept);
+    let entry = Entry::new();
+    entry.set_text(name);
+    entry.set_activates_default(true);
+    entry.set_margin_start(10);
+    entry.set_margin_end(10);
+    dialog.get_content_area().add(&entry);
+    dialog.show_all();
+
+    let mut result = None;
+    while dialog.run() == GTK_RESPONSE_ACCEPT {
+        let name = entry
+            .get_text()
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default();
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            show_error_dialog(
+                parent,
+                "Playlist names cannot be empty, start with a dot or hold a slash.",
+            );
+            continue;
+        }
+        result = Some(name);
+        break;
     }
+    dialog.destroy();
+    result
+}
 
-    files
+/// Asks for the rule of a smart playlist, written like a search. Empty rules,
+/// which would list the whole library, are refused.
+fn show_rule_dialog(parent: &Window, rule: &str) -> Option<String> {
+    let dialog = Dialog::new_with_buttons(
+        Some("Smart playlist"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", ResponseType::Cancel), ("Save", ResponseType::Accept)],
+    );
+    dialog.set_default_response(ResponseType::Accept);
+    let content = dialog.get_content_area();
+    content.set_spacing(10);
+    let hint = Label::new(Some(
+        "List the songs matching, for example:\n\
+        genre:jazz year:<1970\n\
+        added:<30 (added in the last 30 days)\n\
+        playcount:=0 (never played)\n\
+        rating:>=4",
+    ));
+    hint.set_halign(Align::Start);
+    hint.set_margin_start(10);
+    hint.set_margin_end(10);
+    content.add(&hint);
+    let entry = Entry::new();
+    entry.set_text(rule);
+    entry.set_activates_default(true);
+    entry.set_margin_start(10);
+    entry.set_margin_end(10);
+    content.add(&entry);
+    dialog.show_all();
+
+    let mut result = None;
+    while dialog.run() == GTK_RESPONSE_ACCEPT {
+        let rule = entry
+            .get_text()
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default();
+        if rule.is_empty() {
+            show_error_dialog(parent, "Smart playlists need a rule.");
+            continue;
+        }
+        result = Some(rule);
+        break;
+    }
+    dialog.destroy();
+    result
+}
+
+/// Asks where and as whom listens are submitted. The token is the user token
+/// of a ListenBrainz account, or of any service offering its API.
+fn show_scrobbling_dialog(parent: &Window) -> Option<scrobble::Settings> {
+    let settings = scrobble::Settings::load();
+    let dialog = Dialog::new_with_buttons(
+        Some("Scrobbling"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", ResponseType::Cancel), ("Save", ResponseType::Accept)],
+    );
+    dialog.set_default_response(ResponseType::Accept);
+    let content = dialog.get_content_area();
+    content.set_spacing(10);
+    let enabled = CheckButton::new_with_label("Submit the songs listened to");
+    enabled.set_active(settings.enabled);
+    content.add(&enabled);
+    let endpoint = Entry::new();
+    endpoint.set_text(&settings.endpoint);
+    endpoint.set_placeholder_text(Some(scrobble::DEFAULT_ENDPOINT));
+    let token = Entry::new();
+    token.set_text(&settings.token);
+    token.set_visibility(false);
+    token.set_placeholder_text(Some("User token"));
+    token.set_activates_default(true);
+    for (label, entry) in [("Server", &endpoint), ("Token", &token)].iter() {
+        let label = Label::new(Some(*label));
+        label.set_halign(Align::Start);
+        content.add(&label);
+        content.add(*entry);
+    }
+    for child in content.get_children() {
+        child.set_margin_start(10);
+        child.set_margin_end(10);
+    }
+    dialog.show_all();
+
+    let mut result = None;
+    if dialog.run() == GTK_RESPONSE_ACCEPT {
+        let text = |entry: &Entry| {
+            entry
+                .get_text()
+                .map(|text| text.trim().to_string())
+                .unwrap_or_default()
+        };
+        let endpoint = Some(text(&endpoint)).filter(|endpoint| !endpoint.is_empty());
+        result = Some(scrobble::Settings {
+            enabled: enabled.get_active(),
+            endpoint: endpoint.unwrap_or_else(|| scrobble::DEFAULT_ENDPOINT.to_string()),
+            token: text(&token),
+        });
+    }
+    dialog.destroy();
+    result
+}
+
+/// Lists the songs last played, the latest first.
+fn show_history_dialog(parent: &Window) {
+    let dialog = Dialog::new_with_buttons(
+        Some("Listening history"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Close", ResponseType::Close)],
+    );
+    dialog.set_default_size(500, 400);
+    let list = ListBox::new();
+    let library = Library::open();
+    for (path, played) in library.history(HISTORY_LENGTH) {
+        let song = match library.track(Path::new(&path)) {
+            Some(track) => format!("{} – {}", track.artist(), track.title()),
+            None => path,
+        };
+        let text = format!("{}    {}", stats::format_time(played), song);
+        let label = Label::new(Some(text.as_str()));
+        label.set_halign(Align::Start);
+        label.set_margin_start(10);
+        list.add(&label);
+    }
+    let scrolled = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+    scrolled.set_vexpand(true);
+    scrolled.add(&list);
+    dialog.get_content_area().add(&scrolled);
+    dialog.show_all();
+    dialog.run();
+    dialog.destroy();
 }
 
-fn show_save_dialog(parent: &Window) -> Option<PathBuf> {
+/// Asks the user to confirm `question`, with `action` as the label of the
+/// button doing it.
+fn show_confirm_dialog(parent: &Window, question: &str, action: &str) -> bool {
+    let dialog = MessageDialog::new(
+        Some(parent),
+        DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::None,
+        question,
+    );
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button(action, ResponseType::Accept);
+    let confirmed = dialog.run() == GTK_RESPONSE_ACCEPT;
+    dialog.destroy();
+    confirmed
+}
+
+fn show_error_dialog(parent: &Window, message: &str) {
+    let dialog = MessageDialog::new(
+        Some(parent),
+        DialogFlags::empty(),
+        MessageType::Error,
+        ButtonsType::Ok,
+        message,
+    );
+    dialog.run();
+    dialog.destroy();
+}
+
+fn show_image_dialog(parent: &Window) -> Option<PathBuf> {
     let mut file = None;
     let dialog = FileChooserDialog::new(
-        Some("Choose a destination M3U playlist file"),
+        Some("Choose a cover image"),
         Some(parent),
-        FileChooserAction::Save,
+        FileChooserAction::Open,
     );
     let filter = FileFilter::new();
-    filter.add_mime_type("audio/x-mpegurl");
-    filter.set_name("M3U playlist file");
-    dialog.set_do_overwrite_confirmation(true);
+    filter.add_pixbuf_formats();
+    filter.set_name("Image file");
     dialog.add_filter(&filter);
     dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button("Open", gtk::ResponseType::Accept);
+    let result = dialog.run();
+    if result == GTK_RESPONSE_ACCEPT {
+        file = dialog.get_filename();
+    }
+    dialog.destroy();
+    file
+}
+
+/// Asks where to save the cover embedded in the song at `path`, suggesting a
+/// cover file next to it.
+fn show_extract_dialog(parent: &Window, path: &Path) -> Option<PathBuf> {
+    let mut file = None;
+    let dialog = FileChooserDialog::new(
+        Some("Choose where to save the cover"),
+        Some(parent),
+        FileChooserAction::Save,
+    );
+    if let Some(folder) = path.parent() {
+        dialog.set_current_folder(folder);
+    }
+    let extension = artwork::extension(path).unwrap_or("jpg");
+    dialog.set_current_name(format!("cover.{}", extension));
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
     dialog.add_button("Save", gtk::ResponseType::Accept);
     let result = dialog.run();
     if result == GTK_RESPONSE_ACCEPT {
@@ -468,10 +2005,39 @@ fn show_save_dialog(parent: &Window) -> Option<PathBuf> {
     file
 }
 
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with("."))
-        .unwrap_or(false)
+/// Asks where to save the playlist, and whether to list songs by their path
+/// from the playlist file.
+fn show_save_dialog(parent: &Window, name: &str) -> Option<(PathBuf, bool)> {
+    let mut file = None;
+    let dialog = FileChooserDialog::new(
+        Some("Choose a destination playlist file"),
+        Some(parent),
+        FileChooserAction::Save,
+    );
+    let filter = FileFilter::new();
+    for extension in playlist_file::EXTENSIONS.iter() {
+        filter.add_pattern(&format!("*.{}", extension));
+    }
+    filter.set_name("Playlist file (M3U, PLS or XSPF)");
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.add_filter(&filter);
+    dialog.set_current_name(format!("{}.m3u8", name));
+    let relative = CheckButton::new_with_label("List songs by their path from the playlist");
+    let remembered = config::read(RELATIVE_PATHS_FILE).is_some_and(|value| value.trim() == "true");
+    relative.set_active(remembered);
+    dialog.set_extra_widget(&relative);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button("Save", gtk::ResponseType::Accept);
+    let result = dialog.run();
+    if result == GTK_RESPONSE_ACCEPT {
+        file = dialog.get_filename().map(|mut file| {
+            if file.extension().is_none() {
+                file.set_extension("m3u8");
+            }
+            (file, relative.get_active())
+        });
+    }
+    config::write(RELATIVE_PATHS_FILE, &relative.get_active().to_string());
+    dialog.destroy();
+    file
 }
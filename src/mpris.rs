@@ -0,0 +1,537 @@
+//! Lets desktop shells, media keys and tools such as `playerctl` control the
+//! player through the MPRIS D-Bus interfaces.
+//!
+//! To try it without touching the desktop session, run the player on a bus of
+//! its own:
+//!
+//!     dbus-run-session -- sh -c 'blue-music & sleep 2; playerctl metadata'
+
+use std::collections::HashMap;
+use std::mem;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use dbus::arg::{Append, Arg, RefArg, Variant};
+use dbus::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::tree::{
+    Access, EmitsChangedSignal, Factory, MTFn, Method, MethodErr, Property, Signal, Tree,
+};
+use dbus::{BusType, Connection, NameFlag, Path, RequestNameReply, SignalArgs};
+
+use crate::artwork;
+use crate::cue;
+use crate::library::Track;
+use crate::order::Repeat;
+use crate::tags::MAX_RATING;
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.blue_music";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Songs are told apart by an object path under this one, numbered in the
+/// order they started playing.
+const TRACK_PATH: &str = "/org/blue_music/track";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+const IDENTITY: &str = "Blue Music";
+
+/// How long the bus is waited on before changes are signalled, in
+/// milliseconds.
+const POLL_INTERVAL: u32 = 100;
+
+/// What the player is asked to do over the bus. Times are in milliseconds.
+pub enum Command {
+    Raise,
+    Quit,
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+    /// Moves playback by an offset, backwards when negative.
+    Seek(i64),
+    SetPosition(u64),
+    Open(PathBuf),
+    Volume(f64),
+    Repeat(Repeat),
+    Shuffle(bool),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// What the player tells over the bus.
+struct State {
+    status: Status,
+    track: Option<Track>,
+    /// Counts the songs started, which the path identifying a song is made
+    /// from.
+    track_count: u64,
+    /// Location of the artwork of the song playing, found when it starts so
+    /// that the file system is not searched with the state locked.
+    art_url: Option<String>,
+    /// Length of the song playing, in milliseconds.
+    length: Option<u64>,
+    position: u64,
+    volume: f64,
+    repeat: Repeat,
+    shuffle: bool,
+}
+
+enum Change {
+    /// A property of the player interface changed.
+    Property(&'static str),
+    /// Playback jumped to a position, in milliseconds.
+    Seeked(u64),
+}
+
+/// Offers the MPRIS interfaces from a background thread, which passes the
+/// calls it receives on as commands and signals the changes it is told about.
+pub struct Mpris {
+    state: Arc<Mutex<State>>,
+    changes: Sender<Change>,
+}
+
+impl Mpris {
+    /// Takes a name on the session bus, sending what is asked over it to
+    /// `commands`.
+    pub fn start(commands: relm::Sender<Command>) -> Mpris {
+        let state = Arc::new(Mutex::new(State {
+            status: Status::Stopped,
+            track: None,
+            track_count: 0,
+            art_url: None,
+            length: None,
+            position: 0,
+            volume: 1.0,
+            repeat: Repeat::Off,
+            shuffle: false,
+        }));
+        let (changes, receiver) = mpsc::channel();
+        let shared = state.clone();
+        thread::spawn(move || {
+            if let Err(error) = run(&shared, &commands, &receiver) {
+                eprintln!("Unable to offer media controls over D-Bus: {}", error);
+            }
+        });
+        Mpris { state, changes }
+    }
+
+    pub fn set_status(&self, status: Status) {
+        self.update("PlaybackStatus", |state| {
+            mem::replace(&mut state.status, status) != status
+        });
+    }
+
+    /// Shows `track` as the song playing, or no song.
+    pub fn set_track(&self, track: Option<&Track>) {
+        let art_url = track
+            .and_then(artwork::image_file)
+            .and_then(|image| file_url(&image));
+        self.update("Metadata", |state| {
+            if track.is_some() {
+                state.track_count += 1;
+            }
+            state.track = track.cloned();
+            state.art_url = art_url;
+            state.length = track
+                .and_then(|track| track.duration)
+                .map(|duration| duration * 1000);
+            state.position = 0;
+            true
+        });
+    }
+
+    /// Sets the length of the song playing, once it has been computed, in
+    /// milliseconds.
+    pub fn set_length(&self, length: u64) {
+        self.update("Metadata", |state| {
+            state.length.replace(length) != Some(length)
+        });
+    }
+
+    /// Follows playback, in milliseconds. Clients work the position out
+    /// themselves from the rate, so it is not signalled.
+    pub fn set_position(&self, position: u64) {
+        self.state.lock().unwrap().position = position;
+    }
+
+    /// Tells that playback jumped to `position`, in milliseconds.
+    pub fn seeked(&self, position: u64) {
+        self.set_position(position);
+        let _ = self.changes.send(Change::Seeked(position));
+    }
+
+    pub fn set_volume(&self, volume: f64) {
+        self.update("Volume", |state| {
+            mem::replace(&mut state.volume, volume) != volume
+        });
+    }
+
+    pub fn set_repeat(&self, repeat: Repeat) {
+        self.update("LoopStatus", |state| {
+            mem::replace(&mut state.repeat, repeat) != repeat
+        });
+    }
+
+    pub fn set_shuffle(&self, shuffle: bool) {
+        self.update("Shuffle", |state| {
+            mem::replace(&mut state.shuffle, shuffle) != shuffle
+        });
+    }
+
+    /// Applies `change` to the state, signalling `property` when it returns
+    /// that it changed.
+    fn update<F: FnOnce(&mut State) -> bool>(&self, property: &'static str, change: F) {
+        if change(&mut self.state.lock().unwrap()) {
+            let _ = self.changes.send(Change::Property(property));
+        }
+    }
+}
+
+/// Serves the bus until the `Mpris` is dropped.
+fn run(
+    state: &Arc<Mutex<State>>,
+    commands: &relm::Sender<Command>,
+    changes: &Receiver<Change>,
+) -> Result<(), dbus::Error> {
+    let connection = Connection::get_private(BusType::Session)?;
+    // Other instances take a name of their own, as the specification asks.
+    let reply = connection.register_name(BUS_NAME, NameFlag::DoNotQueue as u32)?;
+    if reply != RequestNameReply::PrimaryOwner {
+        let name = format!("{}.instance{}", BUS_NAME, process::id());
+        connection.register_name(&name, NameFlag::DoNotQueue as u32)?;
+    }
+    let (tree, seeked) = create_tree(state, commands);
+    tree.set_registered(&connection, true)?;
+    connection.add_handler(tree);
+
+    let path = Path::from(OBJECT_PATH);
+    let interface = PLAYER_INTERFACE.into();
+    loop {
+        // Method calls are answered by the tree while waiting.
+        for _ in connection.incoming(POLL_INTERVAL) {}
+
+        // Properties changed several times are signalled once.
+        let mut properties = Vec::new();
+        loop {
+            match changes.try_recv() {
+                Ok(Change::Property(property)) => {
+                    if !properties.contains(&property) {
+                        properties.push(property);
+                    }
+                }
+                Ok(Change::Seeked(position)) => {
+                    let message = seeked.msg(&path, &interface).append1(micros(position));
+                    let _ = connection.send(message);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        if !properties.is_empty() {
+            let state = state.lock().unwrap();
+            let changed = PropertiesPropertiesChanged {
+                interface_name: PLAYER_INTERFACE.to_string(),
+                changed_properties: properties
+                    .into_iter()
+                    .map(|property| (property.to_string(), Variant(value(&state, property))))
+                    .collect(),
+                invalidated_properties: Vec::new(),
+            };
+            let _ = connection.send(changed.to_emit_message(&path));
+        }
+    }
+}
+
+type Mt = MTFn<()>;
+
+/// Builds the object answering the calls of both interfaces. Returns it with
+/// the `Seeked` signal.
+fn create_tree(
+    state: &Arc<Mutex<State>>,
+    commands: &relm::Sender<Command>,
+) -> (Tree<Mt, ()>, Arc<Signal<()>>) {
+    let f = Factory::new_fn::<()>();
+
+    let root = f
+        .interface(ROOT_INTERFACE, ())
+        .add_m(command(&f, commands, "Raise", || Command::Raise))
+        .add_m(command(&f, commands, "Quit", || Command::Quit))
+        .add_p(constant(&f, "CanQuit", true))
+        .add_p(constant(&f, "CanRaise", true))
+        .add_p(constant(&f, "HasTrackList", false))
+        .add_p(constant(&f, "Identity", IDENTITY))
+        .add_p(constant(&f, "SupportedUriSchemes", vec!["file"]))
+        .add_p(constant(&f, "SupportedMimeTypes", vec!["audio/flac"]));
+
+    let seeked = Arc::new(f.signal("Seeked", ()).sarg::<i64, _>("Position"));
+    let sender = commands.clone();
+    let seek = f
+        .method("Seek", (), move |m| {
+            let offset: i64 = m.msg.read1()?;
+            send(&sender, Command::Seek(offset / 1000));
+            Ok(vec![m.msg.method_return()])
+        })
+        .inarg::<i64, _>("Offset");
+    let sender = commands.clone();
+    let shared = state.clone();
+    let set_position = f
+        .method("SetPosition", (), move |m| {
+            let (track, position): (Path, i64) = m.msg.read2()?;
+            // Calls meant for a song that is no longer playing are ignored.
+            let state = shared.lock().unwrap();
+            let length = state.length.map_or(0, micros);
+            if track == track_id(&state) && position >= 0 && position <= length {
+                send(&sender, Command::SetPosition(position as u64 / 1000));
+            }
+            Ok(vec![m.msg.method_return()])
+        })
+        .inarg::<Path, _>("TrackId")
+        .inarg::<i64, _>("Position");
+    let sender = commands.clone();
+    let open_uri = f
+        .method("OpenUri", (), move |m| {
+            let uri: &str = m.msg.read1()?;
+            let (file, _) =
+                glib::filename_from_uri(uri).map_err(|_| MethodErr::invalid_arg(&uri))?;
+            send(&sender, Command::Open(file));
+            Ok(vec![m.msg.method_return()])
+        })
+        .inarg::<&str, _>("Uri");
+
+    // Changes are signalled once the player has made them.
+    let sender = commands.clone();
+    let volume = property::<f64>(&f, state, "Volume")
+        .access(Access::ReadWrite)
+        .auto_emit_on_set(false)
+        .on_set(move |i, _| {
+            let volume: f64 = i.read()?;
+            send(&sender, Command::Volume(volume.clamp(0.0, 1.0)));
+            Ok(())
+        });
+    let sender = commands.clone();
+    let loop_status = property::<&str>(&f, state, "LoopStatus")
+        .access(Access::ReadWrite)
+        .auto_emit_on_set(false)
+        .on_set(move |i, _| {
+            let status: &str = i.read()?;
+            let repeat = match status {
+                "None" => Repeat::Off,
+                "Track" => Repeat::Track,
+                "Playlist" => Repeat::Playlist,
+                _ => return Err(MethodErr::invalid_arg(&status)),
+            };
+            send(&sender, Command::Repeat(repeat));
+            Ok(())
+        });
+    let sender = commands.clone();
+    let shuffle = property::<bool>(&f, state, "Shuffle")
+        .access(Access::ReadWrite)
+        .auto_emit_on_set(false)
+        .on_set(move |i, _| {
+            send(&sender, Command::Shuffle(i.read()?));
+            Ok(())
+        });
+    // Only the normal rate is supported, and setting it does nothing.
+    let rate = constant(&f, "Rate", 1.0)
+        .access(Access::ReadWrite)
+        .auto_emit_on_set(false)
+        .on_set(|i, _| {
+            let _: f64 = i.read()?;
+            Ok(())
+        });
+
+    let player = f
+        .interface(PLAYER_INTERFACE, ())
+        .add_m(command(&f, commands, "Next", || Command::Next))
+        .add_m(command(&f, commands, "Previous", || Command::Previous))
+        .add_m(command(&f, commands, "Pause", || Command::Pause))
+        .add_m(command(&f, commands, "PlayPause", || Command::PlayPause))
+        .add_m(command(&f, commands, "Stop", || Command::Stop))
+        .add_m(command(&f, commands, "Play", || Command::Play))
+        .add_m(seek)
+        .add_m(set_position)
+        .add_m(open_uri)
+        .add_s(seeked.clone())
+        .add_p(property::<&str>(&f, state, "PlaybackStatus"))
+        .add_p(loop_status)
+        .add_p(rate)
+        .add_p(shuffle)
+        .add_p(property::<HashMap<&str, Variant<Box<dyn RefArg>>>>(
+            &f, state, "Metadata",
+        ))
+        .add_p(volume)
+        .add_p(property::<i64>(&f, state, "Position").emits_changed(EmitsChangedSignal::False))
+        .add_p(constant(&f, "MinimumRate", 1.0))
+        .add_p(constant(&f, "MaximumRate", 1.0))
+        .add_p(constant(&f, "CanGoNext", true))
+        .add_p(constant(&f, "CanGoPrevious", true))
+        .add_p(constant(&f, "CanPlay", true))
+        .add_p(constant(&f, "CanPause", true))
+        .add_p(constant(&f, "CanSeek", true))
+        .add_p(constant(&f, "CanControl", true));
+
+    let tree = f.tree(()).add(
+        f.object_path(OBJECT_PATH, ())
+            .introspectable()
+            .add(root)
+            .add(player),
+    );
+    (tree, seeked)
+}
+
+/// A method without arguments sending the command `make` returns.
+fn command(
+    f: &Factory<Mt, ()>,
+    commands: &relm::Sender<Command>,
+    name: &'static str,
+    make: fn() -> Command,
+) -> Method<Mt, ()> {
+    let commands = commands.clone();
+    f.method(name, (), move |m| {
+        send(&commands, make());
+        Ok(vec![m.msg.method_return()])
+    })
+}
+
+/// A property reading the state, as `value` gives it.
+fn property<A: Arg>(
+    f: &Factory<Mt, ()>,
+    state: &Arc<Mutex<State>>,
+    name: &'static str,
+) -> Property<Mt, ()> {
+    let state = state.clone();
+    f.property::<A, _>(name, ()).on_get(move |i, _| {
+        RefArg::append(&*value(&state.lock().unwrap(), name), i);
+        Ok(())
+    })
+}
+
+/// A property which never changes.
+fn constant<A: Arg + Append + Clone + 'static>(
+    f: &Factory<Mt, ()>,
+    name: &'static str,
+    value: A,
+) -> Property<Mt, ()> {
+    f.property::<A, _>(name, ())
+        .emits_changed(EmitsChangedSignal::Const)
+        .on_get(move |i, _| {
+            i.append(value.clone());
+            Ok(())
+        })
+}
+
+fn send(commands: &relm::Sender<Command>, command: Command) {
+    if commands.send(command).is_err() {
+        eprintln!("Unable to pass on a media control");
+    }
+}
+
+/// Returns the value of the property `name` of the player interface, which
+/// changes as the player does.
+fn value(state: &State, name: &str) -> Box<dyn RefArg> {
+    match name {
+        "PlaybackStatus" => {
+            let status = match state.status {
+                Status::Playing => "Playing",
+                Status::Paused => "Paused",
+                Status::Stopped => "Stopped",
+            };
+            Box::new(status.to_string())
+        }
+        "LoopStatus" => {
+            let status = match state.repeat {
+                Repeat::Off => "None",
+                Repeat::Track => "Track",
+                Repeat::Playlist => "Playlist",
+            };
+            Box::new(status.to_string())
+        }
+        "Shuffle" => Box::new(state.shuffle),
+        "Metadata" => Box::new(metadata(state)),
+        "Volume" => Box::new(state.volume),
+        "Position" => Box::new(micros(state.position)),
+        _ => unreachable!("{} is not a property that changes", name),
+    }
+}
+
+/// Describes the song playing with the fields of the MPRIS and Xesam
+/// specifications.
+fn metadata(state: &State) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+    let mut fields: Vec<(&str, Box<dyn RefArg>)> =
+        vec![("mpris:trackid", Box::new(track_id(state)))];
+    if let Some(ref track) = state.track {
+        let tags = &track.tags;
+        let file = cue::file_path(&track.path);
+        if let Some(url) = file_url(&file) {
+            fields.push(("xesam:url", Box::new(url)));
+        }
+        fields.push(("xesam:title", Box::new(track.title())));
+        let lists = [
+            ("xesam:artist", &tags.artists),
+            ("xesam:albumArtist", &tags.album_artists),
+            ("xesam:genre", &tags.genres),
+            ("xesam:composer", &tags.composers),
+        ];
+        for (field, values) in lists.iter() {
+            if !values.is_empty() {
+                fields.push((field, Box::new(values.to_vec())));
+            }
+        }
+        if let Some(ref album) = tags.album {
+            fields.push(("xesam:album", Box::new(album.clone())));
+        }
+        let numbers = [
+            ("xesam:trackNumber", &tags.track_number),
+            ("xesam:discNumber", &tags.disc_number),
+        ];
+        for (field, number) in numbers.iter() {
+            if let Some(number) = number.as_ref().and_then(|n| n.parse::<i32>().ok()) {
+                fields.push((field, Box::new(number)));
+            }
+        }
+        fields.push(("xesam:useCount", Box::new(track.play_count as i32)));
+        if let Some(rating) = track.rating {
+            let rating = f64::from(rating) / f64::from(MAX_RATING);
+            fields.push(("xesam:userRating", Box::new(rating)));
+        }
+        if let Some(ref url) = state.art_url {
+            fields.push(("mpris:artUrl", Box::new(url.clone())));
+        }
+    }
+    if let Some(length) = state.length {
+        fields.push(("mpris:length", Box::new(micros(length))));
+    }
+    fields
+        .into_iter()
+        .map(|(field, value)| (field.to_string(), Variant(value)))
+        .collect()
+}
+
+fn track_id(state: &State) -> Path<'static> {
+    if state.track.is_none() {
+        return Path::from(NO_TRACK);
+    }
+    Path::from(format!("{}/{}", TRACK_PATH, state.track_count))
+}
+
+fn file_url(file: &std::path::Path) -> Option<String> {
+    glib::filename_to_uri(file, None)
+        .ok()
+        .map(|url| url.to_string())
+}
+
+/// Converts milliseconds to the microseconds MPRIS counts time in.
+fn micros(millis: u64) -> i64 {
+    millis as i64 * 1000
+}
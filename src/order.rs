@@ -0,0 +1,140 @@
+//! Steps through the songs of a playlist, given by their position among the
+//! `count` taking part, for `playback::Playback` to pick the song played next.
+
+/// What plays once a song has played to its end.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Repeat {
+    /// The next song, stopping after the last one.
+    Off,
+    /// The same song again.
+    Track,
+    /// The next song, starting over after the last one.
+    Playlist,
+}
+
+impl Repeat {
+    /// Returns the mode the repeat button switches to from this one.
+    pub fn next(self) -> Repeat {
+        match self {
+            Repeat::Off => Repeat::Playlist,
+            Repeat::Playlist => Repeat::Track,
+            Repeat::Track => Repeat::Off,
+        }
+    }
+
+    /// Returns how the mode is written in the session and told to the command
+    /// line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Repeat::Off => "off",
+            Repeat::Track => "track",
+            Repeat::Playlist => "playlist",
+        }
+    }
+}
+
+/// Returns the position of the song after the one at `current`, or of the
+/// first song when none is current. Past the last song, the playlist only
+/// starts over when it repeats.
+pub fn following(current: Option<usize>, count: usize, repeat: Repeat) -> Option<usize> {
+    match current {
+        _ if count == 0 => None,
+        Some(current) if current + 1 < count => Some(current + 1),
+        Some(_) if repeat != Repeat::Playlist => None,
+        _ => Some(0),
+    }
+}
+
+/// Returns the position of the song before the one at `current`, or of the
+/// last song when none is current. Before the first song, the playlist only
+/// starts over from the end when it repeats.
+pub fn preceding(current: Option<usize>, count: usize, repeat: Repeat) -> Option<usize> {
+    match current {
+        _ if count == 0 => None,
+        Some(current) if current > 0 => Some(current - 1),
+        Some(_) if repeat != Repeat::Playlist => None,
+        _ => Some(count - 1),
+    }
+}
+
+/// Picks the position of a song at random, other than `current` when there is
+/// a choice.
+pub fn random_other(current: Option<usize>, count: usize) -> Option<usize> {
+    match current {
+        _ if count == 0 => None,
+        Some(current) if count > 1 && current < count => {
+            let index = glib::random_int_range(0, count as i32 - 1) as usize;
+            Some(if index >= current { index + 1 } else { index })
+        }
+        _ => Some(glib::random_int_range(0, count as i32) as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn following_steps_forward() {
+        assert_eq!(following(None, 3, Repeat::Off), Some(0));
+        assert_eq!(following(Some(0), 3, Repeat::Off), Some(1));
+        assert_eq!(following(Some(1), 3, Repeat::Track), Some(2));
+        assert_eq!(following(Some(2), 3, Repeat::Off), None);
+        assert_eq!(following(Some(2), 3, Repeat::Track), None);
+        assert_eq!(following(Some(2), 3, Repeat::Playlist), Some(0));
+    }
+
+    #[test]
+    fn following_past_the_songs_left() {
+        // The current song may have been removed from the end.
+        assert_eq!(following(Some(5), 3, Repeat::Off), None);
+        assert_eq!(following(Some(5), 3, Repeat::Playlist), Some(0));
+        assert_eq!(following(None, 0, Repeat::Playlist), None);
+        assert_eq!(following(Some(0), 0, Repeat::Playlist), None);
+    }
+
+    #[test]
+    fn preceding_steps_back() {
+        assert_eq!(preceding(None, 3, Repeat::Off), Some(2));
+        assert_eq!(preceding(Some(2), 3, Repeat::Off), Some(1));
+        assert_eq!(preceding(Some(1), 3, Repeat::Track), Some(0));
+        assert_eq!(preceding(Some(0), 3, Repeat::Off), None);
+        assert_eq!(preceding(Some(0), 3, Repeat::Track), None);
+        assert_eq!(preceding(Some(0), 3, Repeat::Playlist), Some(2));
+        assert_eq!(preceding(None, 0, Repeat::Playlist), None);
+    }
+
+    #[test]
+    fn random_other_avoids_the_current_song() {
+        for _ in 0..200 {
+            let index = random_other(Some(2), 4).unwrap();
+            assert!(index < 4 && index != 2);
+        }
+        for _ in 0..50 {
+            assert_eq!(random_other(Some(0), 2), Some(1));
+            assert_eq!(random_other(Some(1), 2), Some(0));
+        }
+    }
+
+    #[test]
+    fn random_other_without_a_choice() {
+        assert_eq!(random_other(Some(0), 1), Some(0));
+        assert_eq!(random_other(None, 1), Some(0));
+        assert_eq!(random_other(None, 0), None);
+        assert_eq!(random_other(Some(3), 0), None);
+        for _ in 0..50 {
+            assert!(random_other(None, 3).unwrap() < 3);
+            // A current song no longer listed may be picked against.
+            assert!(random_other(Some(7), 3).unwrap() < 3);
+        }
+    }
+
+    #[test]
+    fn random_other_reaches_every_other_song() {
+        let mut seen = [false; 5];
+        for _ in 0..500 {
+            seen[random_other(Some(0), 5).unwrap()] = true;
+        }
+        assert_eq!(seen, [false, true, true, true, true]);
+    }
+}
@@ -0,0 +1,226 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::library;
+
+/// Moves done by `move_files`, most recent last. Each batch is a block of
+/// `<from>\t<to>` lines set apart by empty lines, the paths being written as
+/// bytes with backslashes, tabs and newlines escaped.
+const UNDO_FILE: &str = "moves.log";
+
+/// Moves each file of `moves` from its first path to its second, creating
+/// folders as needed and never overwriting a file. Each move is logged as it
+/// is done, in a batch that `undo_last` can revert. Returns them along with
+/// the errors of those that failed.
+pub fn move_files(moves: &[(PathBuf, PathBuf)]) -> (Vec<(PathBuf, PathBuf)>, Vec<String>) {
+    let path = config::data_dir().join(UNDO_FILE);
+    // The empty line starts a batch of its own, after any left unfinished.
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut log| log.write_all(b"\n").map(|_| log));
+    if let Err(ref error) = log {
+        eprintln!("Unable to open {}: {}", path.display(), error);
+    }
+    apply(moves, |from, to| {
+        if let Ok(ref mut log) = log {
+            if let Err(error) = log.write_all(&undo_line(from, to)) {
+                eprintln!("Unable to save {}: {}", path.display(), error);
+            }
+        }
+    })
+}
+
+/// Moves back the files of the last batch of `move_files`. Returns the moves
+/// done and the errors of those that failed, which are kept in the log.
+pub fn undo_last() -> (Vec<(PathBuf, PathBuf)>, Vec<String>) {
+    let mut batches = undo_batches();
+    let batch = match batches.pop() {
+        Some(batch) => batch,
+        None => return (Vec::new(), vec!["There are no moves to undo".to_string()]),
+    };
+    let reverse: Vec<(PathBuf, PathBuf)> = batch
+        .iter()
+        .rev()
+        .map(|(from, to)| (to.clone(), from.clone()))
+        .collect();
+    let (done, errors) = apply(&reverse, |_, _| ());
+
+    let remaining: Vec<(PathBuf, PathBuf)> = batch
+        .into_iter()
+        .filter(|(_, to)| !done.iter().any(|(from, _)| from == to))
+        .collect();
+    if !remaining.is_empty() {
+        batches.push(remaining);
+    }
+    save_undo_batches(&batches);
+    (done, errors)
+}
+
+/// Moves the files of `moves`, calling `moved` after each one that is.
+fn apply<F>(moves: &[(PathBuf, PathBuf)], mut moved: F) -> (Vec<(PathBuf, PathBuf)>, Vec<String>)
+where
+    F: FnMut(&Path, &Path),
+{
+    let mut done = Vec::new();
+    let mut errors = Vec::new();
+    for (from, to) in moves {
+        match move_file(from, to) {
+            Ok(()) => {
+                moved(from, to);
+                done.push((from.clone(), to.clone()));
+            }
+            Err(error) => errors.push(format!("{}: {}", from.display(), error)),
+        }
+    }
+    (done, errors)
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Renaming fails across file systems, where the file must be copied.
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        if let Err(error) = fs::remove_file(from) {
+            // Leave the file where it was rather than in both places.
+            let _ = fs::remove_file(to);
+            return Err(error);
+        }
+    }
+
+    // Leave no empty folders behind in the library folder the file was in,
+    // up to that folder. Removing a folder that is not empty fails, which
+    // ends the walk up. Files from outside the library leave theirs alone.
+    let root = library::roots()
+        .into_iter()
+        .filter(|root| from.starts_with(root))
+        .max_by_key(|root| root.components().count());
+    if let Some(root) = root {
+        let mut folder = from.parent();
+        while let Some(dir) = folder {
+            if dir == root || !dir.starts_with(&root) || fs::remove_dir(dir).is_err() {
+                break;
+            }
+            folder = dir.parent();
+        }
+    }
+    Ok(())
+}
+
+fn undo_batches() -> Vec<Vec<(PathBuf, PathBuf)>> {
+    let contents = fs::read(config::data_dir().join(UNDO_FILE)).unwrap_or_default();
+    parse_undo_batches(&contents)
+}
+
+fn parse_undo_batches(contents: &[u8]) -> Vec<Vec<(PathBuf, PathBuf)>> {
+    let mut batches = vec![Vec::new()];
+    for line in contents.split(|&byte| byte == b'\n') {
+        let mut fields = line.splitn(2, |&byte| byte == b'\t');
+        match (fields.next(), fields.next()) {
+            (Some(from), Some(to)) => {
+                let batch = batches.last_mut().unwrap();
+                batch.push((unescape(from), unescape(to)));
+            }
+            _ => batches.push(Vec::new()),
+        }
+    }
+    batches.retain(|batch| !batch.is_empty());
+    batches
+}
+
+fn save_undo_batches(batches: &[Vec<(PathBuf, PathBuf)>]) {
+    let mut contents = Vec::new();
+    for batch in batches {
+        for (from, to) in batch {
+            contents.extend(undo_line(from, to));
+        }
+        contents.push(b'\n');
+    }
+    let path = config::data_dir().join(UNDO_FILE);
+    if let Err(error) = fs::write(&path, contents) {
+        eprintln!("Unable to save {}: {}", path.display(), error);
+    }
+}
+
+fn undo_line(from: &Path, to: &Path) -> Vec<u8> {
+    let mut line = escape(from);
+    line.push(b'\t');
+    line.extend(escape(to));
+    line.push(b'\n');
+    line
+}
+
+/// Writes the bytes of `path`, escaping those separating the fields and lines
+/// of the log.
+fn escape(path: &Path) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'\\' => escaped.extend(b"\\\\"),
+            b'\t' => escaped.extend(b"\\t"),
+            b'\n' => escaped.extend(b"\\n"),
+            _ => escaped.push(byte),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &[u8]) -> PathBuf {
+    let mut bytes = Vec::new();
+    let mut iter = escaped.iter();
+    while let Some(&byte) = iter.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match iter.next() {
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'n') => bytes.push(b'\n'),
+            Some(&other) => bytes.push(other),
+            None => bytes.push(byte),
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_log_keeps_any_path() {
+        let odd = PathBuf::from(OsString::from_vec(b"/music/a\tb\nc\\d\xff.flac".to_vec()));
+        let batches = vec![
+            vec![(odd.clone(), PathBuf::from("/music/sorted/a.flac"))],
+            vec![
+                (PathBuf::from("/music/b.flac"), odd),
+                (
+                    PathBuf::from("/music/c.flac"),
+                    PathBuf::from("/music/d.flac"),
+                ),
+            ],
+        ];
+        let mut contents = Vec::new();
+        for batch in &batches {
+            // Batches left unfinished lack the empty line ending them.
+            contents.push(b'\n');
+            for (from, to) in batch {
+                contents.extend(undo_line(from, to));
+            }
+        }
+        assert_eq!(parse_undo_batches(&contents), batches);
+    }
+}
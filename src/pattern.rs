@@ -0,0 +1,390 @@
+use std::path::{Path, PathBuf};
+
+use crate::library::Track;
+use crate::tags::Tags;
+
+/// Fields that can be named in a pattern as `%field%`.
+pub const FIELDS: [&str; 10] = [
+    "artist",
+    "albumartist",
+    "album",
+    "title",
+    "track",
+    "disc",
+    "genre",
+    "date",
+    "year",
+    "composer",
+];
+
+/// Used in paths for the fields a song is not tagged with.
+const UNKNOWN: &str = "Unknown";
+
+/// Characters that cannot, or should not, appear in file names.
+const RESERVED: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+/// A file name layout such as `%artist%/%album%/%track% - %title%`, where
+/// slashes separate folders. It is used both to read tags out of the paths of
+/// songs and to derive their paths from their tags.
+pub struct Pattern {
+    parts: Vec<Part>,
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Pattern, String> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('%') {
+            let end = rest[start + 1..]
+                .find('%')
+                .map(|end| start + 1 + end)
+                .ok_or_else(|| "Unclosed % in pattern".to_string())?;
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let name = rest[start + 1..end].to_lowercase();
+            let field = FIELDS
+                .iter()
+                .find(|field| **field == name)
+                .ok_or_else(|| format!("Unknown field %{}%", name))?;
+            if let Some(Part::Field(_)) = parts.last() {
+                return Err("Fields must be separated by some text".to_string());
+            }
+            parts.push(Part::Field(field));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err("Empty pattern".to_string());
+        }
+        Ok(Pattern { parts })
+    }
+
+    /// Reads the fields out of the end of `path`, without its extension.
+    /// Returns `None` when the path does not follow the pattern.
+    pub fn extract(&self, path: &Path) -> Option<Vec<(&'static str, String)>> {
+        let depth = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.matches('/').count(),
+                Part::Field(_) => 0,
+            })
+            .sum::<usize>()
+            + 1;
+        let mut components: Vec<String> = path
+            .with_extension("")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect();
+        if components.len() < depth {
+            return None;
+        }
+        let text = components.split_off(components.len() - depth).join("/");
+
+        let mut values = Vec::new();
+        if match_parts(&self.parts, &text, &mut values) {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the path of `track` below `root` following the pattern, keeping
+    /// its extension. Empty and dot folders are left out, so that the path
+    /// never leaves `root`.
+    pub fn format(&self, track: &Track, root: &Path) -> PathBuf {
+        let relative: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => {
+                    let value = sanitize(&value(track, field));
+                    if value.is_empty() {
+                        UNKNOWN.to_string()
+                    } else {
+                        value
+                    }
+                }
+            })
+            .collect();
+        let mut path = root.to_path_buf();
+        for name in relative.split('/').map(str::trim) {
+            if !matches!(name, "" | "." | "..") {
+                path.push(name);
+            }
+        }
+        debug_assert!(path.starts_with(root));
+        // Appended rather than set, as titles such as "Vol. 2" would lose the
+        // part after their dot.
+        if let Some(extension) = Path::new(&track.path).extension() {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(".");
+            name.push(extension);
+            path.set_file_name(name);
+        }
+        path
+    }
+}
+
+/// Matches `text` against `parts`, pushing the value of each field. Fields
+/// match at least one character and never span folders.
+fn match_parts(parts: &[Part], text: &str, values: &mut Vec<(&'static str, String)>) -> bool {
+    match parts.split_first() {
+        None => text.is_empty(),
+        Some((Part::Text(literal), rest)) => {
+            text.starts_with(literal.as_str()) && match_parts(rest, &text[literal.len()..], values)
+        }
+        Some((Part::Field(field), rest)) => {
+            let candidates: Vec<usize> = match rest.first() {
+                Some(Part::Text(literal)) => text
+                    .match_indices(literal.as_str())
+                    .map(|(index, _)| index)
+                    .collect(),
+                _ => vec![text.len()],
+            };
+            for end in candidates {
+                let value = &text[..end];
+                if value.trim().is_empty() || value.contains('/') {
+                    continue;
+                }
+                values.push((field, value.trim().to_string()));
+                if match_parts(rest, &text[end..], values) {
+                    return true;
+                }
+                values.pop();
+            }
+            false
+        }
+    }
+}
+
+/// Returns the value of a field of `track`, with track and disc numbers
+/// padded so that files sort in order.
+fn value(track: &Track, field: &str) -> String {
+    let tags = &track.tags;
+    let number = |number: &Option<String>| match number {
+        Some(number) => match number.parse::<u32>() {
+            Ok(number) => format!("{:02}", number),
+            Err(_) => number.clone(),
+        },
+        None => String::new(),
+    };
+    match field {
+        "artist" => track.artist(),
+        "albumartist" => track.album_artist(),
+        "album" => track.album(),
+        "title" => track.title(),
+        "track" => number(&tags.track_number),
+        "disc" => number(&tags.disc_number),
+        "genre" => track.genre(),
+        "date" => tags.date.clone().unwrap_or_else(|| track.year()),
+        "year" => track.year(),
+        "composer" => track.composer(),
+        _ => String::new(),
+    }
+}
+
+/// Sets a field read out of a path.
+pub fn set(tags: &mut Tags, field: &str, value: String) {
+    // Numbers read from file names are usually padded.
+    let number = |value: String| match value.parse::<u32>() {
+        Ok(number) => number.to_string(),
+        Err(_) => value,
+    };
+    match field {
+        "artist" => tags.artists = vec![value],
+        "albumartist" => tags.album_artists = vec![value],
+        "album" => tags.album = Some(value),
+        "title" => tags.title = Some(value),
+        "track" => tags.track_number = Some(number(value)),
+        "disc" => tags.disc_number = Some(number(value)),
+        "genre" => tags.genres = vec![value],
+        "date" | "year" => tags.date = Some(value),
+        "composer" => tags.composers = vec![value],
+        _ => (),
+    }
+}
+
+/// Makes a tag value usable as a file name.
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| {
+            if RESERVED.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    // Leading dots would hide the file.
+    value.trim().trim_start_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(path: &str, tags: Tags) -> Track {
+        Track {
+            path: path.to_string(),
+            tags,
+            sample_rate: None,
+            duration: None,
+            size: 0,
+            mtime: 0,
+            artwork: None,
+            play_count: 0,
+            added: 0,
+            skip_count: 0,
+            last_played: None,
+            rating: None,
+            cue_sheet: false,
+        }
+    }
+
+    fn song() -> Track {
+        track(
+            "/music/old/01 so what.flac",
+            Tags {
+                title: Some("So What".to_string()),
+                artists: vec!["Miles Davis".to_string()],
+                album: Some("Kind of Blue".to_string()),
+                track_number: Some("1".to_string()),
+                date: Some("1959-08-17".to_string()),
+                ..Tags::default()
+            },
+        )
+    }
+
+    fn extract(pattern: &str, path: &str) -> Option<Vec<(&'static str, String)>> {
+        Pattern::parse(pattern).unwrap().extract(Path::new(path))
+    }
+
+    fn format(pattern: &str, track: &Track) -> PathBuf {
+        Pattern::parse(pattern)
+            .unwrap()
+            .format(track, Path::new("/music"))
+    }
+
+    fn pairs(values: &[(&'static str, &str)]) -> Option<Vec<(&'static str, String)>> {
+        Some(
+            values
+                .iter()
+                .map(|(field, value)| (*field, value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_rejects_malformed_patterns() {
+        assert!(Pattern::parse("").is_err());
+        assert!(Pattern::parse("%artist").is_err());
+        assert!(Pattern::parse("%mood%").is_err());
+        assert!(Pattern::parse("%artist%%title%").is_err());
+        assert!(Pattern::parse("%ARTIST% - %Title%").is_ok());
+        assert!(Pattern::parse("no fields").is_ok());
+    }
+
+    #[test]
+    fn extract_reads_fields_from_the_end_of_the_path() {
+        assert_eq!(
+            extract(
+                "%artist%/%album%/%track% - %title%",
+                "/music/Miles Davis/Kind of Blue/01 - So What.flac"
+            ),
+            pairs(&[
+                ("artist", "Miles Davis"),
+                ("album", "Kind of Blue"),
+                ("track", "01"),
+                ("title", "So What"),
+            ])
+        );
+        assert_eq!(
+            extract("%track% %title%", "/music/a/07 Blue in Green.flac"),
+            pairs(&[("track", "07"), ("title", "Blue in Green")])
+        );
+    }
+
+    #[test]
+    fn extract_tries_every_split_of_the_text() {
+        assert_eq!(
+            extract("%artist% - %title%", "/a - b - c.flac"),
+            pairs(&[("artist", "a"), ("title", "b - c")])
+        );
+    }
+
+    #[test]
+    fn extract_rejects_paths_not_following_the_pattern() {
+        assert_eq!(extract("%artist%/%album%/%title%", "song.flac"), None);
+        assert_eq!(extract("%track% - %title%", "/music/So What.flac"), None);
+        assert_eq!(extract("%track% - %title%", "/music/ - So What.flac"), None);
+    }
+
+    #[test]
+    fn format_pads_numbers_and_keeps_the_extension() {
+        assert_eq!(
+            format("%artist%/%year%/%track% - %title%", &song()),
+            PathBuf::from("/music/Miles Davis/1959/01 - So What.flac")
+        );
+        let mut volume = song();
+        volume.tags.title = Some("Vol. 2".to_string());
+        assert_eq!(
+            format("%title%", &volume),
+            PathBuf::from("/music/Vol. 2.flac")
+        );
+    }
+
+    #[test]
+    fn format_fills_missing_fields() {
+        assert_eq!(
+            format("%genre%/%composer%/%title%", &song()),
+            PathBuf::from("/music/Unknown/Unknown/So What.flac")
+        );
+    }
+
+    #[test]
+    fn format_sanitizes_values() {
+        let mut odd = song();
+        odd.tags.title = Some("AC/DC: What?".to_string());
+        odd.tags.album = Some("..hidden".to_string());
+        odd.tags.artists = vec!["../../etc".to_string()];
+        assert_eq!(
+            format("%artist%/%album%/%title%", &odd),
+            PathBuf::from("/music/_.._etc/hidden/AC_DC_ What_.flac")
+        );
+        odd.tags.album = Some("..".to_string());
+        assert_eq!(
+            format("%album%/%title%", &odd),
+            PathBuf::from("/music/Unknown/AC_DC_ What_.flac")
+        );
+    }
+
+    #[test]
+    fn format_stays_below_the_root() {
+        let song = song();
+        for pattern in &[
+            "/%title%",
+            "../%title%",
+            "%album%/../../%title%",
+            "./%album%//%title%",
+        ] {
+            let path = format(pattern, &song);
+            assert!(path.starts_with("/music"), "{}", path.display());
+            assert!(!path.to_string_lossy().contains(".."));
+        }
+        assert_eq!(
+            format("../../%title%", &song),
+            PathBuf::from("/music/So What.flac")
+        );
+    }
+}
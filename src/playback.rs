@@ -0,0 +1,475 @@
+//! What plays next, which virtual tracks play on from it without a gap and
+//! how what played is counted, apart from how the songs are listed, so that
+//! the playlists of the window and the player run headless go through their
+//! songs alike. The songs listed are reached through `Songs`, by rows which
+//! stay valid as others are added or removed.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::cue;
+use crate::library::{self, Library, Track};
+use crate::order::{self, Repeat};
+use crate::player::Span;
+use crate::scrobble::Scrobbler;
+use crate::stats::{Listen, Milestone};
+
+/// The songs a playlist lists, as the playback steps through them.
+pub trait Songs {
+    type Row: Clone;
+
+    /// Returns the row of the song playing, or of the one last played.
+    fn current(&self) -> Option<Self::Row>;
+
+    /// Returns how many songs take part in the order, which may be fewer
+    /// than those listed, as when they are searched.
+    fn count(&self) -> usize;
+
+    /// Returns the position of `row` among the songs taking part, or `None`
+    /// when it takes no part.
+    fn position(&self, row: &Self::Row) -> Option<usize>;
+
+    /// Returns the row at `position` among the songs taking part.
+    fn row(&self, position: usize) -> Option<Self::Row>;
+
+    /// Tells whether `row` is still listed, as rows queued or played before
+    /// may have been removed since.
+    fn is_listed(&self, row: &Self::Row) -> bool;
+
+    /// Returns the path of the song in `row`.
+    fn path(&self, row: &Self::Row) -> Option<String>;
+
+    /// Returns the row taking part listed after `row`.
+    fn after(&self, row: &Self::Row) -> Option<Self::Row>;
+}
+
+/// What plays from a row.
+pub struct Start<R> {
+    pub row: R,
+    pub path: String,
+    /// The file the song is in, which holds others for virtual tracks.
+    pub file: PathBuf,
+    /// The spans of `file` played, or none for the whole of it.
+    pub spans: Vec<Span>,
+}
+
+/// The order songs play in, and the song playing, counted as played or
+/// skipped once left.
+pub struct Playback<R> {
+    /// The rows of the virtual tracks the player goes on to from the current
+    /// one without a gap.
+    continuation: VecDeque<R>,
+    /// The song playing, counted as played or skipped once left.
+    listen: Option<Listen>,
+    /// The rows played next, before the order goes on, the first one first.
+    queue: Vec<R>,
+    repeat: Repeat,
+    /// Whether the next song is picked at random.
+    shuffle: bool,
+    /// The rows played before the current one while shuffling, the last one
+    /// last, which `previous` goes back to.
+    shuffled: Vec<R>,
+}
+
+impl<R> Default for Playback<R> {
+    fn default() -> Playback<R> {
+        Playback {
+            continuation: VecDeque::new(),
+            listen: None,
+            queue: Vec::new(),
+            repeat: Repeat::Off,
+            shuffle: false,
+            shuffled: Vec::new(),
+        }
+    }
+}
+
+impl<R: Clone> Playback<R> {
+    pub fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+        self.shuffled.clear();
+    }
+
+    pub fn queued(&self) -> &[R] {
+        &self.queue
+    }
+
+    /// Plays `rows` once the song playing and those queued before them have.
+    pub fn queue(&mut self, rows: impl IntoIterator<Item = R>) {
+        self.queue.extend(rows);
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Goes through the rows queued and played before, keeping what `update`
+    /// gives back for each of them and forgetting those it gives none for.
+    pub fn update_rows(&mut self, mut update: impl FnMut(&R) -> Option<R>) {
+        self.queue = self.queue.iter().filter_map(&mut update).collect();
+        self.shuffled = self.shuffled.iter().filter_map(&mut update).collect();
+        self.continuation = self.continuation.iter().filter_map(&mut update).collect();
+    }
+
+    /// Tells whether the song after the current one is the one listed after
+    /// it, so that songs split from a single file may play on without a gap.
+    pub fn goes_on(&self) -> bool {
+        self.queue.is_empty() && !self.shuffle && self.repeat != Repeat::Track
+    }
+
+    /// Returns the row to play after the current one: the first one queued,
+    /// or else one picked at random when shuffling, or else the following
+    /// one.
+    pub fn next<S: Songs<Row = R>>(&mut self, songs: &S) -> Option<R> {
+        while !self.queue.is_empty() {
+            let row = self.queue.remove(0);
+            if songs.is_listed(&row) {
+                return Some(row);
+            }
+        }
+
+        let current = songs.current();
+        let position = current.as_ref().and_then(|row| songs.position(row));
+        if self.shuffle {
+            let row = songs.row(order::random_other(position, songs.count())?)?;
+            self.shuffled.extend(current);
+            return Some(row);
+        }
+        songs.row(order::following(position, songs.count(), self.repeat)?)
+    }
+
+    /// Returns the row to play before the current one: the one played before
+    /// it when shuffling, or else the preceding one.
+    pub fn previous<S: Songs<Row = R>>(&mut self, songs: &S) -> Option<R> {
+        if self.shuffle {
+            while let Some(row) = self.shuffled.pop() {
+                if songs.is_listed(&row) {
+                    return Some(row);
+                }
+            }
+        }
+
+        let position = songs.current().and_then(|row| songs.position(&row));
+        songs.row(order::preceding(position, songs.count(), self.repeat)?)
+    }
+
+    /// Returns the row to play once the current song played to its end, as
+    /// the repeat mode says.
+    pub fn ended<S: Songs<Row = R>>(&mut self, songs: &S) -> Option<R> {
+        match songs.current() {
+            Some(current) if self.repeat == Repeat::Track && songs.is_listed(&current) => {
+                Some(current)
+            }
+            _ => self.next(songs),
+        }
+    }
+
+    /// Finds what plays from `row`, or from the first row after it whose file
+    /// is there, as songs listed by playlists may be missing. Returns it with
+    /// the paths of the songs passed over.
+    pub fn start<S: Songs<Row = R>>(
+        &mut self,
+        songs: &S,
+        row: R,
+    ) -> (Option<Start<R>>, Vec<String>) {
+        let mut missing = Vec::new();
+        let mut next = Some(row);
+        while let Some(row) = next {
+            let path = match songs.path(&row) {
+                Some(path) => path,
+                None => break,
+            };
+            let file = cue::file_path(&path);
+            if file.is_file() {
+                self.continuation.clear();
+                let spans = if cue::split_path(&path).is_some() {
+                    self.virtual_run(songs, &row, &file)
+                } else {
+                    Vec::new()
+                };
+                let start = Start {
+                    row,
+                    path,
+                    file,
+                    spans,
+                };
+                return (Some(start), missing);
+            }
+            missing.push(path);
+            next = songs.after(&row);
+        }
+        (None, missing)
+    }
+
+    /// Returns the spans of the virtual track at `row` and of the tracks
+    /// following it both in the playlist and in its file `file`, which play
+    /// on without a gap. Their rows are kept for `next_virtual_track`. Only
+    /// the track itself plays when `next` would not go on to the row after it.
+    fn virtual_run<S: Songs<Row = R>>(&mut self, songs: &S, row: &R, file: &Path) -> Vec<Span> {
+        self.continuation.clear();
+        let sheet = match cue::read(file) {
+            Some(sheet) => sheet,
+            None => return Vec::new(),
+        };
+        let goes_on = self.goes_on();
+        let mut spans = Vec::new();
+        let mut previous_end = None;
+        let mut row = row.clone();
+        loop {
+            let cue_track = songs
+                .path(&row)
+                .and_then(|path| cue::split_path(&path))
+                .filter(|(other, _)| other == file)
+                .and_then(|(_, number)| sheet.track(number));
+            let cue_track = match cue_track {
+                Some(cue_track) => cue_track,
+                None => break,
+            };
+            if previous_end.is_some_and(|end| end != Some(cue_track.start)) {
+                break;
+            }
+            if !spans.is_empty() {
+                self.continuation.push_back(row.clone());
+            }
+            spans.push(Span {
+                start: cue_track.start,
+                end: cue_track.end,
+            });
+            if !goes_on {
+                break;
+            }
+            row = match songs.after(&row) {
+                Some(next) => next,
+                None => break,
+            };
+            previous_end = Some(cue_track.end);
+        }
+        spans
+    }
+
+    /// Works out again which virtual tracks play on from the current one.
+    /// Returns the spans the player is to play, when it is a virtual track.
+    pub fn refresh<S: Songs<Row = R>>(&mut self, songs: &S) -> Option<Vec<Span>> {
+        let current = songs.current()?;
+        let (file, _) = cue::split_path(&songs.path(&current)?)?;
+        Some(self.virtual_run(songs, &current, &file))
+    }
+
+    /// Returns the row of the virtual track the player went on to by itself.
+    pub fn next_virtual_track(&mut self) -> Option<R> {
+        self.continuation.pop_front()
+    }
+
+    /// Forgets the virtual tracks the player was to go on to, as it stopped.
+    pub fn stop(&mut self) {
+        self.continuation.clear();
+    }
+
+    /// Starts counting the song at `path`, which lasts `duration`
+    /// milliseconds, once the song played before has been finished.
+    pub fn listen(&mut self, path: String, duration: Option<u64>) {
+        self.listen = Some(Listen::new(path, duration));
+    }
+
+    /// Gives the length of the song at `path`, in milliseconds, once it is
+    /// known.
+    pub fn set_duration(&mut self, path: &str, duration: u64) {
+        if let Some(ref mut listen) = self.listen {
+            if listen.path == path {
+                listen.set_duration(duration);
+            }
+        }
+    }
+
+    /// Notes that the song playing reached `time`, in milliseconds. Tells
+    /// `scrobbler` when it is first heard, and records it in `library` and
+    /// submits it once enough of it has been heard to count as played, which
+    /// is returned.
+    pub fn listened(&mut self, time: u64, library: &Library, scrobbler: &Scrobbler) -> bool {
+        let now = library::now();
+        let (path, started, milestone) = match self.listen {
+            Some(ref mut listen) => match listen.progress(time, now) {
+                Some(milestone) => (listen.path.clone(), listen.started, milestone),
+                None => return false,
+            },
+            None => return false,
+        };
+        let track = library
+            .track(Path::new(&path))
+            .unwrap_or_else(|| Track::read(Path::new(&path)));
+        if milestone == Milestone::Started {
+            scrobbler.playing_now(&track);
+            return false;
+        }
+        library.record_play(&path, now);
+        scrobbler.listened(&track, started.unwrap_or(now));
+        true
+    }
+
+    /// Counts the song that was playing as skipped in `library` when it was
+    /// left before it counted as played, returning its path when it was.
+    pub fn finish_listen(&mut self, library: &Library) -> Option<String> {
+        let listen = self.listen.take()?;
+        if !listen.skipped() {
+            return None;
+        }
+        library.record_skip(&listen.path);
+        Some(listen.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Songs listed by their position, all taking part.
+    struct Listed {
+        count: usize,
+        current: Option<usize>,
+        /// The paths of the songs, when they matter.
+        paths: Vec<String>,
+    }
+
+    impl Songs for Listed {
+        type Row = usize;
+
+        fn current(&self) -> Option<usize> {
+            self.current
+        }
+
+        fn count(&self) -> usize {
+            self.count
+        }
+
+        fn position(&self, row: &usize) -> Option<usize> {
+            Some(*row).filter(|&row| row < self.count)
+        }
+
+        fn row(&self, position: usize) -> Option<usize> {
+            Some(position).filter(|&position| position < self.count)
+        }
+
+        fn is_listed(&self, row: &usize) -> bool {
+            *row < self.count
+        }
+
+        fn path(&self, row: &usize) -> Option<String> {
+            self.paths.get(*row).cloned()
+        }
+
+        fn after(&self, row: &usize) -> Option<usize> {
+            self.row(row + 1)
+        }
+    }
+
+    fn listed(count: usize, current: Option<usize>) -> Listed {
+        Listed {
+            count,
+            current,
+            paths: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn next_follows_the_order() {
+        let mut playback = Playback::default();
+        assert_eq!(playback.next(&listed(3, None)), Some(0));
+        assert_eq!(playback.next(&listed(3, Some(1))), Some(2));
+        assert_eq!(playback.next(&listed(3, Some(2))), None);
+        playback.set_repeat(Repeat::Playlist);
+        assert_eq!(playback.next(&listed(3, Some(2))), Some(0));
+    }
+
+    #[test]
+    fn previous_follows_the_order() {
+        let mut playback = Playback::default();
+        assert_eq!(playback.previous(&listed(3, Some(1))), Some(0));
+        assert_eq!(playback.previous(&listed(3, Some(0))), None);
+        playback.set_repeat(Repeat::Playlist);
+        assert_eq!(playback.previous(&listed(3, Some(0))), Some(2));
+    }
+
+    #[test]
+    fn queued_rows_play_first() {
+        let mut playback = Playback::default();
+        playback.set_shuffle(true);
+        playback.queue(vec![2, 7, 0]);
+        let songs = listed(5, Some(3));
+        assert_eq!(playback.next(&songs), Some(2));
+        // Rows no longer listed are passed over.
+        assert_eq!(playback.next(&songs), Some(0));
+        assert!(playback.queued().is_empty());
+        assert!(!playback.goes_on());
+    }
+
+    #[test]
+    fn previous_goes_back_through_the_shuffled_rows() {
+        let mut playback = Playback::default();
+        playback.set_shuffle(true);
+        let first = playback.next(&listed(4, Some(1))).unwrap();
+        assert_ne!(first, 1);
+        playback.next(&listed(4, Some(first)));
+        assert_eq!(playback.previous(&listed(4, None)), Some(first));
+        assert_eq!(playback.previous(&listed(4, None)), Some(1));
+        // Past the rows played, it steps back through the order.
+        assert_eq!(playback.previous(&listed(4, Some(1))), Some(0));
+    }
+
+    #[test]
+    fn ended_repeats_the_track() {
+        let mut playback = Playback::default();
+        assert_eq!(playback.ended(&listed(3, Some(1))), Some(2));
+        playback.set_repeat(Repeat::Track);
+        assert_eq!(playback.ended(&listed(3, Some(1))), Some(1));
+        assert_eq!(playback.ended(&listed(3, None)), Some(0));
+    }
+
+    #[test]
+    fn update_rows_follows_removals() {
+        let mut playback = Playback::default();
+        playback.queue(vec![0, 2, 4]);
+        // The row at 2 is removed, and those after it move up.
+        playback.update_rows(|&row| match row {
+            2 => None,
+            row if row > 2 => Some(row - 1),
+            row => Some(row),
+        });
+        assert_eq!(playback.queued(), &[0, 3]);
+    }
+
+    #[test]
+    fn start_passes_over_missing_files() {
+        let file = std::env::temp_dir().join("blue-music-playback-start.flac");
+        std::fs::write(&file, b"").unwrap();
+        let found = file.to_string_lossy().to_string();
+        let songs = Listed {
+            count: 3,
+            current: None,
+            paths: vec![
+                "/nonexistent/first.flac".to_string(),
+                "/nonexistent/second.flac".to_string(),
+                found.clone(),
+            ],
+        };
+        let mut playback = Playback::default();
+        let (start, missing) = playback.start(&songs, 0);
+        let start = start.unwrap();
+        assert_eq!((start.row, start.path), (2, found));
+        assert!(start.spans.is_empty());
+        assert_eq!(missing.len(), 2);
+        assert!(playback.start(&songs, 3).0.is_none());
+        std::fs::remove_file(&file).unwrap();
+    }
+}
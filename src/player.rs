@@ -1,4 +1,5 @@
 use std::cell::Cell;
+use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
@@ -9,15 +10,37 @@ use relm::Sender;
 use self::Action::*;
 use crate::flac;
 use crate::flac::FlacDecoder;
-use crate::playlist::PlayerMsg::{self, PlayerPlay, PlayerStop, PlayerTime};
 
 use pulse_simple::Playback;
 
 const DEFAULT_RATE: u32 = 44100;
 
+/// What the player tells, from the thread it plays in.
+#[derive(Clone)]
+pub enum PlayerMsg {
+    Play,
+    Stop,
+    Time(u64),
+    /// The player went on to the next of the spans it plays, without a gap.
+    NextTrack,
+    /// The song played to its end.
+    End,
+}
+
+/// A part of a file played as a song of its own, such as a track of a CUE
+/// sheet. Times are in samples, so that spans meet exactly.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub start: u64,
+    /// Where the span ends, or `None` for the end of the file.
+    pub end: Option<u64>,
+}
+
 enum Action {
-    Load(PathBuf),
-    Skip(PathBuf, u32),
+    Load(PathBuf, Vec<Span>),
+    Skip(u32),
+    /// Replaces the spans left to play, the current one first.
+    Spans(Vec<Span>),
     Stop,
 }
 
@@ -26,6 +49,8 @@ struct EventLoop {
     condition_variable: Arc<(Mutex<bool>, Condvar)>,
     queue: Arc<SegQueue<Action>>,
     playing: Arc<Mutex<bool>>,
+    /// The factor samples are scaled by, from 0 to 1.
+    volume: Arc<Mutex<f64>>,
 }
 
 pub struct Player {
@@ -43,6 +68,7 @@ impl Player {
             condition_variable: condition_variable.clone(),
             queue: Arc::new(SegQueue::new()),
             playing: Arc::new(Mutex::new(false)),
+            volume: Arc::new(Mutex::new(1.0)),
         };
 
         {
@@ -66,42 +92,81 @@ impl Player {
                     DEFAULT_RATE,
                 );
                 let mut source = None;
+                // The spans left to play, the current one first.
+                let mut spans = VecDeque::new();
 
                 loop {
                     if let Ok(action) = event_loop.queue.pop() {
                         match action {
-                            Load(path) => {
-                                source = Some(FlacDecoder::new(&path));
-                                let rate = source.unwrap().sample_rate();
+                            Load(path, new_spans) => {
+                                let mut decoder = FlacDecoder::new(&path);
 
                                 playback = Playback::new(
                                     "Blue Music",
                                     "The free and open music player",
                                     None,
-                                    rate,
+                                    decoder.sample_rate(),
                                 );
 
-                                send(&mut tx, PlayerPlay);
-                                source = Some(FlacDecoder::new(&path));
+                                send(&mut tx, PlayerMsg::Play);
+                                if let (Some(first), Some(last)) =
+                                    (new_spans.first(), new_spans.last())
+                                {
+                                    decoder.set_range(first.start, last.end);
+                                }
+                                source = Some(decoder);
+                                spans = new_spans.into_iter().collect();
                             }
 
-                            Skip(path, time) => {
+                            Skip(time) => {
                                 if let Some(ref mut source) = source {
-                                    flac::skip_to(&path.as_path(), time, source);
+                                    let offset = spans.front().map_or(0, |span: &Span| span.start);
+                                    let rate = u64::from(source.sample_rate());
+                                    source.seek(offset + u64::from(time) * rate / 1000);
+                                }
+                            }
+
+                            Spans(new_spans) => {
+                                if let Some(ref mut source) = source {
+                                    // The player may have gone on to a span
+                                    // the playlist did not hear of yet.
+                                    let current = spans.front().copied();
+                                    let start = current.map_or(0, |span| span.start);
+                                    spans = new_spans
+                                        .into_iter()
+                                        .filter(|span| span.start >= start)
+                                        .collect();
+                                    if spans.is_empty() {
+                                        spans.extend(current);
+                                    }
+                                    source.set_end(spans.back().and_then(|span| span.end));
                                 }
                             }
 
                             Stop => {
                                 source = None;
+                                spans.clear();
                             }
                         }
                     } else if *event_loop.playing.lock().unwrap() {
                         let mut written = false;
                         if let Some(ref mut source) = source {
-                            if let Some(buf) = iter_to_buffer(source) {
+                            if let Some(mut buf) = iter_to_buffer(source) {
                                 if buf.len() > 0 {
-                                    send(&mut tx, PlayerTime(source.current_time() as u64));
-
+                                    let sample = source.current_sample();
+                                    while spans.len() > 1 && sample >= spans[1].start {
+                                        spans.pop_front();
+                                        send(&mut tx, PlayerMsg::NextTrack);
+                                    }
+                                    let offset = spans.front().map_or(0, |span| span.start);
+                                    let rate = u64::from(source.sample_rate());
+                                    let time = sample.saturating_sub(offset) * 1000 / rate;
+                                    send(&mut tx, PlayerMsg::Time(time));
+
+                                    let volume = *event_loop.volume.lock().unwrap();
+                                    if volume < 1.0 {
+                                        scale(&mut buf, volume);
+                                    }
                                     playback.write(&buf[..]);
 
                                     written = true;
@@ -110,7 +175,13 @@ impl Player {
                         }
 
                         if !written {
-                            send(&mut tx, PlayerStop);
+                            // Unlike being stopped, running out of samples
+                            // lets the playlist go on to another song.
+                            let ended = source.is_some();
+                            send(&mut tx, PlayerMsg::Stop);
+                            if ended {
+                                send(&mut tx, PlayerMsg::End);
+                            }
                             *event_loop.playing.lock().unwrap() = false;
                             source = None;
                             block();
@@ -129,7 +200,9 @@ impl Player {
         }
     }
 
-    pub fn compute_duration(path: &Path) -> u64 {
+    /// Returns the length of the song at `path`, in seconds, or `None` when
+    /// it cannot be told.
+    pub fn compute_duration(path: &Path) -> Option<u64> {
         flac::compute_duration(&path)
     }
 
@@ -141,25 +214,46 @@ impl Player {
         self.paused.get()
     }
 
-    pub fn skip(&self, path: &Path, time: u32) {
-        self.emit(Skip(path.to_path_buf(), time));
+    /// Moves playback to `time` milliseconds into the current span.
+    pub fn skip(&self, time: u32) {
+        self.emit(Skip(time));
     }
 
     pub fn load(&self, path: &Path) {
+        self.load_spans(path, Vec::new());
+    }
+
+    /// Plays the spans of the file at `path` one after the other, without a
+    /// gap between them, as long as each starts where the previous one ends.
+    /// Times are then given from the start of the current span, and moving to
+    /// the next one is reported with `PlayerMsg::NextTrack`.
+    pub fn load_spans(&self, path: &Path, spans: Vec<Span>) {
         let pathbuf = path.to_path_buf();
-        self.emit(Load(pathbuf));
+        // A song paused for another playlist is left for good.
+        self.paused.set(false);
+        self.emit(Load(pathbuf, spans));
         self.set_playing(true);
     }
 
+    /// Replaces the spans left to play in the file loaded, the current one
+    /// first, as the songs following it in the playlist changed.
+    pub fn set_spans(&self, spans: Vec<Span>) {
+        self.emit(Spans(spans));
+    }
+
+    pub fn set_volume(&self, volume: f64) {
+        *self.event_loop.volume.lock().unwrap() = volume.clamp(0.0, 1.0);
+    }
+
     pub fn pause(&mut self) {
         self.paused.set(true);
-        self.send(PlayerStop);
+        self.send(PlayerMsg::Stop);
         self.set_playing(false);
     }
 
     pub fn resume(&mut self) {
         self.paused.set(false);
-        self.send(PlayerPlay);
+        self.send(PlayerMsg::Play);
         self.set_playing(true);
     }
 
@@ -175,8 +269,8 @@ impl Player {
 
     pub fn stop(&mut self) {
         self.paused.set(false);
-        self.send(PlayerTime(0));
-        self.send(PlayerStop);
+        self.send(PlayerMsg::Time(0));
+        self.send(PlayerMsg::Stop);
         self.emit(Stop);
         self.set_playing(false);
     }
@@ -186,10 +280,26 @@ impl Player {
     }
 }
 
+/// Silences the player once the window is closed.
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.emit(Stop);
+        self.set_playing(false);
+    }
+}
+
 fn iter_to_buffer(decoder: &mut FlacDecoder) -> Option<Vec<[i16; 2]>> {
     flac::next_sample(decoder)
 }
 
+fn scale(buffer: &mut [[i16; 2]], volume: f64) {
+    for frame in buffer.iter_mut() {
+        for sample in frame.iter_mut() {
+            *sample = (f64::from(*sample) * volume) as i16;
+        }
+    }
+}
+
 fn send(tx: &mut Sender<PlayerMsg>, msg: PlayerMsg) {
     if let Ok(_) = tx.send(msg) {
 
@@ -1,18 +1,41 @@
-use crate::player::Player;
-use gdk_pixbuf::{InterpType, Pixbuf, PixbufLoader, PixbufLoaderExt};
+use crate::artwork;
+use crate::columns::{natural_cmp, Layout};
+use crate::cue;
+use crate::import::{Outcome, Pool};
+use crate::library::{self, Library, Track, EMBEDDED_ARTWORK};
+use crate::listing::{self, Listing, Rows};
+use crate::organize;
+use crate::order::Repeat;
+use crate::playback::{Playback, Songs};
+use crate::player::{Player, PlayerMsg, Span};
+use crate::playlist_file;
+use crate::query::Query;
+use crate::scanner::{self, Change};
+use crate::scrobble::Scrobbler;
+use crate::smart;
+use crate::stats;
+use crate::tags::{self, Tags, MAX_RATING};
+use gdk::{DragAction, EventButton, ModifierType, SELECTION_CLIPBOARD};
+use gdk_pixbuf::Pixbuf;
 use gtk;
 use gtk::{
-    CellLayoutExt, CellRendererPixbuf, CellRendererText, GtkListStoreExt, GtkListStoreExtManual,
-    ListStore, StaticType, ToValue, TreeIter, TreeModelExt, TreeSelectionExt, TreeViewColumn,
-    TreeViewColumnExt, TreeViewExt, Type, WidgetExt,
+    CellLayoutExt, CellRendererPixbuf, CellRendererText, CheckMenuItem, CheckMenuItemExt,
+    Clipboard, DestDefaults, GtkListStoreExt, GtkListStoreExtManual, GtkMenuExtManual,
+    GtkMenuItemExt, Inhibit, ListStore, Menu, MenuItem, MenuShellExt, SelectionData, SelectionMode,
+    SeparatorMenuItem, SortColumn, SortType, StaticType, TargetEntry, TargetFlags, ToValue,
+    TreeIter, TreeModelExt, TreeModelFilter, TreeModelFilterExt, TreePath, TreeRowReference,
+    TreeSelectionExt, TreeSortableExtManual, TreeView, TreeViewColumn, TreeViewColumnExt,
+    TreeViewDropPosition, TreeViewExt, Type, WidgetExt, WidgetExtManual,
 };
-use m3u;
-use metaflac::Tag;
-use relm::{Channel, Relm, Widget};
+use notify::RecommendedWatcher;
+use relm::{Channel, EventStream, Relm, Widget};
 use relm_derive::widget;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+use std::thread;
 use std::{
-    fs::File,
+    fs,
     path::{Path, PathBuf},
 };
 
@@ -24,10 +47,6 @@ enum Visibility {
     Visible,
 }
 
-const INTERP_HYPER: InterpType = InterpType::Hyper;
-const IMAGE_SIZE: i32 = 256;
-const THUMBNAIL_SIZE: i32 = 64;
-
 const THUMBNAIL_COLUMN: u32 = 0;
 const TITLE_COLUMN: u32 = 1;
 const ARTIST_COLUMN: u32 = 2;
@@ -37,91 +56,661 @@ const YEAR_COLUMN: u32 = 5;
 const TRACK_COLUMN: u32 = 6;
 const PATH_COLUMN: u32 = 7;
 const PIXBUF_COLUMN: u32 = 8;
+const PLAYING_COLUMN: u32 = 9;
+const DURATION_COLUMN: u32 = 10;
+const BITRATE_COLUMN: u32 = 11;
+const FORMAT_COLUMN: u32 = 12;
+const SAMPLE_RATE_COLUMN: u32 = 13;
+const DISC_COLUMN: u32 = 14;
+const COMPOSER_COLUMN: u32 = 15;
+const PLAY_COUNT_COLUMN: u32 = 16;
+const PERFORMER_COLUMN: u32 = 17;
+const RATING_COLUMN: u32 = 18;
+const SKIP_COUNT_COLUMN: u32 = 19;
+const LAST_PLAYED_COLUMN: u32 = 20;
 
-#[derive(Clone)]
-pub enum PlayerMsg {
-    PlayerPlay,
-    PlayerStop,
-    PlayerTime(u64),
+/// A column that can be shown in the view, in its default order.
+struct ColumnSpec {
+    key: &'static str,
+    title: &'static str,
+    column: u32,
+    visibility: Visibility,
 }
 
+const COLUMNS: [ColumnSpec; 19] = [
+    ColumnSpec {
+        key: "cover",
+        title: "Cover",
+        column: THUMBNAIL_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "title",
+        title: "Title",
+        column: TITLE_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "artist",
+        title: "Artist",
+        column: ARTIST_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "album",
+        title: "Album",
+        column: ALBUM_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "genre",
+        title: "Genre",
+        column: GENRE_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "year",
+        title: "Year",
+        column: YEAR_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "track",
+        title: "Track",
+        column: TRACK_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "disc",
+        title: "Disc",
+        column: DISC_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "duration",
+        title: "Duration",
+        column: DURATION_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "bitrate",
+        title: "Bitrate",
+        column: BITRATE_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "format",
+        title: "Format",
+        column: FORMAT_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "samplerate",
+        title: "Sample rate",
+        column: SAMPLE_RATE_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "composer",
+        title: "Composer",
+        column: COMPOSER_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "performer",
+        title: "Performer",
+        column: PERFORMER_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "path",
+        title: "Path",
+        column: PATH_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "playcount",
+        title: "Play count",
+        column: PLAY_COUNT_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "rating",
+        title: "Rating",
+        column: RATING_COLUMN,
+        visibility: Visible,
+    },
+    ColumnSpec {
+        key: "skipcount",
+        title: "Skip count",
+        column: SKIP_COUNT_COLUMN,
+        visibility: Invisible,
+    },
+    ColumnSpec {
+        key: "lastplayed",
+        title: "Last played",
+        column: LAST_PLAYED_COLUMN,
+        visibility: Invisible,
+    },
+];
+
+const PLAYING_INDICATOR: &str = "▶";
+
+const RIGHT_BUTTON: u32 = 3;
+
+/// How long, in milliseconds, changes to a playlist wait before it is written
+/// to its file, so that a burst of changes is written once.
+const SAVE_DELAY: u32 = 1000;
+
+const ROW_TARGET: &str = "BLUE_MUSIC_PLAYLIST_ROWS";
+const URI_TARGET: &str = "text/uri-list";
+const ROW_INFO: u32 = 0;
+const URI_INFO: u32 = 1;
+
 #[derive(Msg)]
 pub enum Msg {
+    ChooseCover(Vec<Track>),
+    ColumnsChanged,
+    CopySelection,
+    CancelImport,
+    CropSelection,
+    DropPaths(Vec<PathBuf>, Option<i32>),
+    Duplicate,
+    Duplicated(Vec<Track>),
+    EditSelection,
+    EditTags(Vec<Track>),
+    EmbedCover(Vec<PathBuf>, PathBuf),
+    ExtractCover(Box<Track>),
+    ExtractCoverSelection,
+    FilesFailed(String, Vec<String>),
+    FilesMoved(Vec<Change>, Vec<String>),
+    FilesWritten(String, Vec<Change>, Vec<String>),
+    Filter(String),
+    Imported(Rc<Vec<Outcome>>),
+    ImportProgress(usize, usize),
+    LibraryChanged(Vec<Change>),
+    MoveSelectionBefore(Option<i32>),
+    MoveSelectionBottom,
+    MoveSelectionTop,
+    MoveColumn(usize, i32),
+    Persist,
+    MoveFiles(Vec<(PathBuf, PathBuf)>),
+    QueueSelection,
+    RenameFiles(Vec<Track>),
+    RenameSelection,
+    SetFile(PathBuf),
+    SetRepeat(Repeat),
+    SetShuffle(bool),
+    ShowHeaderMenu(usize, u32, u32),
+    ShowMenu(u32, u32),
+    SortBy(usize),
+    ToggleColumn(usize),
     SongDuration(u64),
-    DurationComputed(PathBuf, u64),
-    AddSong(PathBuf),
-    LoadSong(PathBuf),
+    // Adds songs and the songs listed by playlists, playing the first one
+    // added when the flag is set.
+    AddPaths(Vec<PathBuf>, bool),
+    AddTracks(Vec<Track>),
     NextSong,
     PauseSong,
     PlayerMsgRecv(PlayerMsg),
     PlaySong,
+    PlayRow(TreePath),
     PreviousSong,
+    RateSelection(Option<u8>),
+    RemoveCoverSelection,
     RemoveSong,
-    SaveSong(PathBuf),
+    ReplaceTracks(Vec<Track>),
+    Resume(String, u64, bool),
+    SaveSong(PathBuf, bool),
+    SaveTags(Vec<(PathBuf, Tags)>),
+    SetCoverSelection,
+    SetRoots(Vec<PathBuf>),
+    SetRule(String),
+    SetVolume(f64),
+    StoreRatingsInFiles(bool),
     Skip(u32),
     SongStarted(Option<Pixbuf>),
-    SongMeta(Vec<String>),
+    SongMeta(Box<Track>),
     StopSong,
+    TagFromPaths(Vec<Track>),
+    TagSelectionFromPaths,
+    UndoMoves,
 }
 
 pub struct Model {
+    /// Where the library tells about the changes found on disk, kept across
+    /// rescans.
+    changes: Option<relm::Sender<Vec<Change>>>,
+    columns: Vec<(usize, TreeViewColumn)>,
+    current_row: Option<TreeRowReference>,
     current_song: Option<String>,
-    durations: HashMap<String, u64>,
+    /// The file the playlist is kept in, or `None` for the playlist of the
+    /// whole library.
+    file: Option<PathBuf>,
+    filter: TreeModelFilter,
+    header_menu: Menu,
+    /// The id of the tab of the playlist.
+    id: usize,
+    /// The songs listed, as they are read.
+    listing: Listing<TreeRowReference>,
+    menu: Menu,
     model: ListStore,
-    player: Player,
+    pending_artwork: HashMap<String, Vec<TreeRowReference>>,
+    /// The order the rows play in, and the song playing, counted once left.
+    playback: Playback<TreeRowReference>,
+    query: Rc<RefCell<Query>>,
     relm: Relm<Playlist>,
+    /// Where the current song picks up once played, when it was restored
+    /// paused.
+    resume_position: Option<u64>,
+    /// The rule songs of the library are listed by, for smart playlists.
+    rule: Option<String>,
+    /// Whether the playlist changed since it was last written to its file.
+    save_pending: Rc<Cell<bool>>,
+    shared: Rc<Shared>,
+    watcher: Option<RecommendedWatcher>,
+}
+
+/// What the playlists of the tabs share: the player, the import workers, the
+/// library, the scrobbler and the artwork loaded. The player plays for one
+/// playlist at a time, the last one to load a song, which alone hears from it
+/// and controls it.
+pub struct Shared {
+    /// The artwork loaded, as a thumbnail and as shown while playing, under
+    /// its key.
+    artwork: RefCell<HashMap<String, Option<(Pixbuf, Pixbuf)>>>,
+    library: Library,
+    player: RefCell<Player>,
+    /// The id of the playlist the player plays for.
+    player_owner: Cell<Option<usize>>,
+    /// The playlists told about the songs imported and about the player, by
+    /// id.
+    playlists: RefCell<HashMap<usize, EventStream<Msg>>>,
+    pool: Pool,
+    scrobbler: RefCell<Scrobbler>,
+}
+
+impl Shared {
+    pub fn new() -> Rc<Shared> {
+        Rc::new_cyclic(|shared: &Weak<Shared>| {
+            let owner = shared.clone();
+            let (_channel, sender) = Channel::new(move |msg| {
+                if let Some(shared) = owner.upgrade() {
+                    shared.tell_owner(msg);
+                }
+            });
+            let importer = shared.clone();
+            let (_channel, pool_sender) = Channel::new(move |outcomes| {
+                if let Some(shared) = importer.upgrade() {
+                    shared.imported(outcomes);
+                }
+            });
+            Shared {
+                artwork: RefCell::new(HashMap::new()),
+                library: Library::open(),
+                player: RefCell::new(Player::new(sender)),
+                player_owner: Cell::new(None),
+                playlists: RefCell::new(HashMap::new()),
+                pool: Pool::new(pool_sender),
+                scrobbler: RefCell::new(Scrobbler::start()),
+            }
+        })
+    }
+
+    /// Forgets the playlist `id`, once closed, silencing the player if it
+    /// played for it.
+    pub fn remove(&self, id: usize) {
+        self.playlists.borrow_mut().remove(&id);
+        if self.player_owner.get() == Some(id) {
+            self.player_owner.set(None);
+            self.player.borrow_mut().stop();
+        }
+    }
+
+    /// Submits the songs played with the scrobbling settings saved since.
+    pub fn restart_scrobbler(&self) {
+        self.scrobbler.borrow_mut().restart();
+    }
+
+    fn register(&self, id: usize, stream: EventStream<Msg>) {
+        self.playlists.borrow_mut().insert(id, stream);
+    }
+
+    /// Plays `spans` of the file at `path` for the playlist `id`.
+    fn load(&self, id: usize, path: &Path, spans: Vec<Span>) {
+        self.player_owner.set(Some(id));
+        self.player.borrow().load_spans(path, spans);
+    }
+
+    /// Returns the player, if it plays for the playlist `id`.
+    fn player(&self, id: usize) -> Option<RefMut<'_, Player>> {
+        if self.player_owner.get() == Some(id) {
+            Some(self.player.borrow_mut())
+        } else {
+            None
+        }
+    }
+
+    fn tell_owner(&self, msg: PlayerMsg) {
+        let owner = self.player_owner.get();
+        let stream = owner.and_then(|id| self.playlists.borrow().get(&id).cloned());
+        if let Some(stream) = stream {
+            stream.emit(PlayerMsgRecv(msg));
+        }
+    }
+
+    /// Caches the artwork loaded and stores the durations computed, then
+    /// tells every playlist about the songs imported, each picking those it
+    /// waits for.
+    fn imported(&self, mut outcomes: Vec<Outcome>) {
+        for outcome in &mut outcomes {
+            match outcome {
+                Outcome::Artwork(key, pixels) => {
+                    let artwork = pixels.take().map(|(thumbnail, pixbuf)| {
+                        (thumbnail.into_pixbuf(), pixbuf.into_pixbuf())
+                    });
+                    self.artwork.borrow_mut().insert(key.clone(), artwork);
+                }
+                Outcome::Duration(path, duration) => self.library.set_duration(path, *duration),
+                _ => (),
+            }
+        }
+        let outcomes = Rc::new(outcomes);
+        let playlists: Vec<EventStream<Msg>> = self.playlists.borrow().values().cloned().collect();
+        for stream in playlists {
+            stream.emit(Imported(outcomes.clone()));
+        }
+    }
 }
 
 #[widget]
 impl Widget for Playlist {
-    fn model(relm: &Relm<Self>, _: ()) -> Model {
-        let stream = relm.stream().clone();
-        let (_channel, sender): (Channel<PlayerMsg>, relm::Sender<PlayerMsg>) =
-            Channel::new(move |msg| {
-                stream.emit(PlayerMsgRecv(msg));
-            });
-        // relm::execute();
-        // relm.execute(rx, PlayerMsgRecv);
+    fn model(
+        relm: &Relm<Self>,
+        (file, id, shared): (Option<PathBuf>, usize, Rc<Shared>),
+    ) -> Model {
+        shared.register(id, relm.stream().clone());
+        let model = ListStore::new(&[
+            Pixbuf::static_type(),
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Pixbuf::static_type(),
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+            Type::String,
+        ]);
+
+        // The view shows the rows of the store matching the search query.
+        let query = Rc::new(RefCell::new(Query::parse("")));
+        let filter = TreeModelFilter::new(&model, None);
+        let visible_query = query.clone();
+        filter.set_visible_func(move |model, iter| {
+            let query = visible_query.borrow();
+            query.is_empty()
+                || query.matches(|field| {
+                    let value = field_column(field)
+                        .and_then(|column| model.get_value(iter, column as i32).get::<String>())
+                        .unwrap_or_default();
+                    // Ratings are shown as stars but compared as numbers.
+                    if field == "rating" && !value.is_empty() {
+                        return value.chars().count().to_string();
+                    }
+                    value
+                })
+        });
+
         Model {
+            changes: None,
+            columns: Vec::new(),
+            current_row: None,
             current_song: None,
-            durations: HashMap::new(),
-            model: ListStore::new(&[
-                Pixbuf::static_type(),
-                Type::String,
-                Type::String,
-                Type::String,
-                Type::String,
-                Type::String,
-                Type::String,
-                Type::String,
-                Pixbuf::static_type(),
-            ]),
+            file,
+            filter,
+            header_menu: Menu::new(),
+            id,
+            listing: Listing::default(),
+            menu: Menu::new(),
+            model,
+            pending_artwork: HashMap::new(),
+            playback: Playback::default(),
             relm: relm.clone(),
-            player: Player::new(sender),
+            resume_position: None,
+            rule: None,
+            save_pending: Rc::new(Cell::new(false)),
+            shared,
+            watcher: None,
+            query,
         }
     }
 
     fn update(&mut self, event: Msg) {
+        let edits = matches!(
+            event,
+            AddPaths(_, _)
+                | AddTracks(_)
+                | CropSelection
+                | DropPaths(_, _)
+                | MoveSelectionBefore(_)
+                | MoveSelectionBottom
+                | MoveSelectionTop
+                | RemoveSong
+                | ReplaceTracks(_)
+        );
+        if edits && !self.editable() {
+            return;
+        }
+        // Which virtual tracks play on from the one playing depends on the
+        // rows following it and on the order songs play in.
+        let reorders = matches!(
+            event,
+            AddPaths(_, _)
+                | AddTracks(_)
+                | CropSelection
+                | DropPaths(_, _)
+                | FilesMoved(_, _)
+                | FilesWritten(_, _, _)
+                | Filter(_)
+                | Imported(_)
+                | LibraryChanged(_)
+                | MoveSelectionBefore(_)
+                | MoveSelectionBottom
+                | MoveSelectionTop
+                | QueueSelection
+                | RemoveSong
+                | SetRepeat(_)
+                | SetShuffle(_)
+                | SortBy(_)
+        );
         match event {
-            AddSong(path) => self.add(&path),
-            DurationComputed(path, duration) => {
-                let path = path.to_string_lossy().to_string();
-                if self.model.current_song.as_ref() == Some(&path) {
-                    self.model.relm.stream().emit(SongDuration(duration * 1000));
+            AddPaths(paths, play) => {
+                let first = self.model.model.iter_n_children(None);
+                self.add_paths(&paths, None);
+                if play {
+                    self.play_added(first);
+                }
+            }
+            AddTracks(tracks) => {
+                for track in &tracks {
+                    listing::add_track(self, track, None);
+                }
+                self.update_indicators();
+            }
+            ColumnsChanged => self.save_layout(),
+            CancelImport => self.cancel_import(),
+            CopySelection => self.copy_selection(),
+            CropSelection => self.crop_selection(),
+            Duplicate => {
+                let tracks = self.tracks();
+                self.model.relm.stream().emit(Duplicated(tracks));
+            }
+            // Listened by Win
+            Duplicated(_) => (),
+            DropPaths(paths, position) => {
+                let position = self.store_position(position);
+                self.add_paths(&paths, position);
+            }
+            EditSelection => {
+                let tracks = self.selected_tracks();
+                if !tracks.is_empty() {
+                    self.model.relm.stream().emit(EditTags(tracks));
+                }
+            }
+            // Listened by Win
+            ChooseCover(_) => (),
+            // Listened by Win
+            EditTags(_) => (),
+            EmbedCover(paths, image) => {
+                let edits = paths
+                    .into_iter()
+                    .map(|path| (path, image.clone()))
+                    .collect();
+                self.write_files(edits, "Could not embed the cover in:", |path, image| {
+                    artwork::embed(path, &image)
+                });
+            }
+            // Listened by Win
+            ExtractCover(_) => (),
+            ExtractCoverSelection => {
+                let track = self
+                    .selected_tracks()
+                    .into_iter()
+                    .find(|track| track.artwork.as_deref() == Some(EMBEDDED_ARTWORK));
+                match track {
+                    Some(track) => self.model.relm.stream().emit(ExtractCover(Box::new(track))),
+                    None => self.model.relm.stream().emit(FilesFailed(
+                        "None of the selected songs has an embedded cover.".to_string(),
+                        Vec::new(),
+                    )),
+                }
+            }
+            // Listened by Win
+            FilesFailed(_, _) => (),
+            FilesWritten(heading, changes, errors) => {
+                self.apply_changes(changes);
+                if !errors.is_empty() {
+                    self.model.relm.stream().emit(FilesFailed(heading, errors));
+                }
+            }
+            FilesMoved(changes, errors) => {
+                self.apply_changes(changes);
+                if !errors.is_empty() {
+                    self.model
+                        .relm
+                        .stream()
+                        .emit(FilesFailed("Could not move:".to_string(), errors));
+                }
+            }
+            Filter(text) => {
+                *self.model.query.borrow_mut() = Query::parse(&text);
+                self.model.filter.refilter();
+            }
+            MoveSelectionBefore(position) => {
+                let position = self.store_position(position);
+                self.move_selection_before(position);
+                self.schedule_save();
+            }
+            MoveSelectionBottom => {
+                self.move_selection_bottom();
+                self.schedule_save();
+            }
+            MoveColumn(index, offset) => self.move_column(index, offset),
+            MoveFiles(moves) => self.move_files(moves),
+            MoveSelectionTop => {
+                self.move_selection_top();
+                self.schedule_save();
+            }
+            Persist => {
+                self.model.save_pending.set(false);
+                // Smart playlists keep their rule rather than their songs.
+                if let (Some(file), None) = (&self.model.file, &self.model.rule) {
+                    self.save(file, false);
+                }
+            }
+            QueueSelection => self.queue_selection(),
+            // Listened by Win
+            RenameFiles(_) => (),
+            SetFile(file) => self.model.file = Some(file),
+            SetRepeat(repeat) => self.model.playback.set_repeat(repeat),
+            SetShuffle(shuffle) => self.model.playback.set_shuffle(shuffle),
+            RenameSelection => {
+                let tracks = self.selected_tracks();
+                if !tracks.is_empty() {
+                    self.model.relm.stream().emit(RenameFiles(tracks));
                 }
-                self.model.durations.insert(path, duration * 1000);
             }
-            LoadSong(path) => self.load(&path),
+            ShowHeaderMenu(index, button, time) => self.show_header_menu(index, button, time),
+            ShowMenu(button, time) => self.model.menu.popup_easy(button, time),
+            SortBy(index) => self.sort_by(index),
+            ToggleColumn(index) => self.toggle_column(index),
+            Imported(outcomes) => self.imported(&outcomes),
+            // Listened by Win
+            ImportProgress(_, _) => (),
+            LibraryChanged(changes) => self.apply_changes(changes),
             NextSong => self.next(),
             PauseSong => self.pause(),
 
+            PlayerMsgRecv(PlayerMsg::NextTrack) => self.next_virtual_track(),
+            PlayerMsgRecv(PlayerMsg::End) => self.song_ended(),
+            PlayerMsgRecv(PlayerMsg::Time(time)) => self.listened(time),
             // Listend by Win
             PlayerMsgRecv(_) => (),
 
             PlaySong => self.play(),
+            PlayRow(path) => {
+                if let Some(iter) = self.model.filter.get_iter(&path) {
+                    let iter = self.model.filter.convert_iter_to_child_iter(&iter);
+                    self.play_iter(&iter);
+                }
+            }
             PreviousSong => self.previous(),
+            RemoveCoverSelection => {
+                let edits = self
+                    .selected_tracks()
+                    .into_iter()
+                    .filter(|track| track.artwork.as_deref() == Some(EMBEDDED_ARTWORK))
+                    .map(|track| (PathBuf::from(track.path), ()))
+                    .collect();
+                self.write_files(edits, "Could not remove the cover of:", |path, ()| {
+                    artwork::remove(path)
+                });
+            }
+            RateSelection(rating) => self.rate_selection(rating),
             RemoveSong => self.remove_selection(),
-            SaveSong(path) => self.save(&path),
+            ReplaceTracks(tracks) => self.replace(&tracks),
+            SaveSong(path, relative) => self.save(&path, relative),
+            SaveTags(edits) => {
+                self.write_files(edits, "Could not save the tags of:", |path, tags| {
+                    tags::write(path, &tags)
+                });
+            }
+            SetCoverSelection => {
+                let tracks = self.selected_tracks();
+                if !tracks.is_empty() {
+                    self.model.relm.stream().emit(ChooseCover(tracks));
+                }
+            }
+            SetRoots(roots) => {
+                library::save_roots(&roots);
+                self.watch_roots();
+            }
+            Resume(path, position, paused) => self.resume(&path, position, paused),
+            SetRule(rule) => self.set_rule(rule),
+            StoreRatingsInFiles(store) => library::set_ratings_in_files(store),
+            SetVolume(volume) => self.model.shared.player.borrow().set_volume(volume),
             Skip(time) => self.skip(time),
 
             // Listened by Win
@@ -131,11 +720,56 @@ impl Widget for Playlist {
             SongStarted(_) => (),
             SongMeta(_) => (),
             StopSong => self.stop(),
+            // Listened by Win
+            TagFromPaths(_) => (),
+            TagSelectionFromPaths => {
+                let tracks = self.selected_tracks();
+                if !tracks.is_empty() {
+                    self.model.relm.stream().emit(TagFromPaths(tracks));
+                }
+            }
+            UndoMoves => self.undo_moves(),
+        }
+        if reorders {
+            self.refresh_continuation();
         }
     }
 
     fn init_view(&mut self) {
         self.create_columns();
+        self.create_menu();
+        self.treeview
+            .get_selection()
+            .set_mode(SelectionMode::Multiple);
+        connect!(
+            self.model.relm,
+            self.treeview,
+            connect_button_press_event(view, event),
+            return context_click(view, event)
+        );
+        self.setup_drag_and_drop();
+
+        match self.model.file.clone() {
+            Some(file) if smart::is_smart(&file) => {
+                match smart::read(&file) {
+                    Ok(rule) => self.model.rule = Some(rule),
+                    Err(error) => eprintln!("Unable to read {}: {}", file.display(), error),
+                }
+                self.apply_rule();
+            }
+            Some(file) => {
+                if file.exists() {
+                    self.restore(&file);
+                }
+                self.watch_changes();
+            }
+            None => {
+                for track in self.model.shared.library.tracks() {
+                    listing::add_track(self, &track, None);
+                }
+                self.watch_roots();
+            }
+        }
     }
 
     view! {
@@ -146,279 +780,1050 @@ impl Widget for Playlist {
             #[name="treeview"]
             gtk::TreeView {
                 hexpand: false,
-                model: &self.model.model,
+                model: &self.model.filter,
                 vexpand: false,
+                row_activated(_, path, _) => PlayRow(path.clone()),
             },
         }
     }
 }
 
 impl Playlist {
+    /// Tells whether songs can be added, moved and removed, which they cannot
+    /// in the playlist of the library, listing every song it holds.
+    fn editable(&self) -> bool {
+        self.model.file.is_some()
+    }
+
+    /// Returns the player, if it plays for this playlist.
+    fn player(&self) -> Option<RefMut<'_, Player>> {
+        self.model.shared.player(self.model.id)
+    }
+
     fn pause(&mut self) {
-        self.model.player.pause();
+        if let Some(mut player) = self.player() {
+            player.pause();
+        }
     }
 
     fn next(&mut self) {
-        let selection = self.treeview.get_selection();
-        let next_iter = if let Some((_, iter)) = selection.get_selected() {
-            if !self.model.model.iter_next(&iter) {
-                return;
-            }
-            Some(iter)
-        } else {
-            self.model.model.get_iter_first()
-        };
-        if let Some(ref iter) = next_iter {
-            selection.select_iter(iter);
-            self.play();
-        }
+        self.step(|playback, rows| playback.next(rows));
     }
 
     fn previous(&mut self) {
-        let selection = self.treeview.get_selection();
-        let previous_iter = if let Some((_, iter)) = selection.get_selected() {
-            if !self.model.model.iter_previous(&iter) {
-                return;
-            }
-            Some(iter)
-        } else {
-            self.model.model.iter_nth_child(
-                None,
-                std::cmp::max(0, self.model.model.iter_n_children(None) - 1),
-            )
-        };
-        if let Some(ref iter) = previous_iter {
-            selection.select_iter(iter);
-            self.play();
-        }
+        self.step(|playback, rows| playback.previous(rows));
     }
 
-    fn save(&self, path: &Path) {
-        let mut file = File::create(path).unwrap();
-        let mut writer = m3u::Writer::new(&mut file);
+    /// Goes on from the song that played to its end as the repeat mode says.
+    fn song_ended(&mut self) {
+        self.step(|playback, rows| playback.ended(rows));
+    }
 
-        let mut write_iter = |iter: &TreeIter| {
-            let value = self.model.model.get_value(&iter, PATH_COLUMN as i32);
-            let path = value.get::<String>().unwrap();
-            writer.write_entry(&m3u::path_entry(path)).unwrap();
+    /// Plays the row `pick` chooses from the playback.
+    fn step<F>(&mut self, pick: F)
+    where
+        F: FnOnce(&mut Playback<TreeRowReference>, &Shown<'_>) -> Option<TreeRowReference>,
+    {
+        let rows = Shown {
+            current: self.model.current_row.as_ref(),
+            filter: &self.model.filter,
+            model: &self.model.model,
         };
-
-        if let Some(iter) = self.model.model.get_iter_first() {
-            write_iter(&iter);
-            while self.model.model.iter_next(&iter) {
-                write_iter(&iter);
-            }
+        let row = pick(&mut self.model.playback, &rows);
+        if let Some(iter) = row.and_then(|row| self.row_iter(&row)) {
+            self.play_iter(&iter);
         }
     }
 
-    fn stop(&mut self) {
-        self.model.current_song = None;
-        self.model.player.stop();
+    fn first_visible(&self) -> Option<TreeIter> {
+        self.model
+            .filter
+            .get_iter_first()
+            .map(|iter| self.model.filter.convert_iter_to_child_iter(&iter))
     }
 
-    fn remove_selection(&self) {
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            self.model.model.remove(&iter);
+    fn is_selected(&self, iter: &TreeIter) -> bool {
+        self.model
+            .filter
+            .convert_child_iter_to_iter(iter)
+            .is_some_and(|iter| self.treeview.get_selection().iter_is_selected(&iter))
+    }
+
+    /// Converts a row position in the view into one in the store. Positions
+    /// past the last shown row become `None`, meaning the end of the store.
+    fn store_position(&self, position: Option<i32>) -> Option<i32> {
+        let iter = self.model.filter.iter_nth_child(None, position?)?;
+        let iter = self.model.filter.convert_iter_to_child_iter(&iter);
+        let path = self.model.model.get_path(&iter)?;
+        path.get_indices().first().cloned()
+    }
+
+    /// Saves the playlist to the playlist file at `path`, listing songs
+    /// by their path from the playlist with `relative`.
+    fn save(&self, path: &Path, relative: bool) {
+        let mut entries = Vec::new();
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                if let Some(entry) = self.playlist_entry(&iter) {
+                    entries.push(entry);
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        if let Err(error) = playlist_file::write(path, &entries, relative) {
+            self.model.relm.stream().emit(FilesFailed(
+                format!("Could not save {}:", path.display()),
+                vec![error.to_string()],
+            ));
         }
     }
 
-    fn load(&self, path: &Path) {
-        let mut reader = m3u::Reader::open(path).unwrap();
-        for entry in reader.entries() {
-            if let Ok(m3u::Entry::Path(path)) = entry {
-                self.add(&path);
+    /// Lists the songs of the file the playlist is kept in, as they were when
+    /// the player was last closed. Missing songs are listed without a fuss.
+    fn restore(&mut self, file: &Path) {
+        match playlist_file::read(file) {
+            Ok(entries) => {
+                listing::add_entries(self, entries, None);
+                self.report_import_progress();
             }
+            Err(error) => eprintln!("Unable to restore {}: {}", file.display(), error),
         }
     }
 
-    fn path(&self) -> Option<String> {
-        self.model.current_song.clone()
+    /// Writes the playlist to its file shortly after it changes, once for a
+    /// burst of changes.
+    fn watch_changes(&self) {
+        let pending = self.model.save_pending.clone();
+        let stream = self.model.relm.stream().clone();
+        self.model
+            .model
+            .connect_row_changed(move |_, _, _| schedule_save(&pending, &stream));
+        let pending = self.model.save_pending.clone();
+        let stream = self.model.relm.stream().clone();
+        self.model
+            .model
+            .connect_row_deleted(move |_, _| schedule_save(&pending, &stream));
     }
 
-    fn skip(&mut self, time: u32) {
-        if let Some(path) = self.selected_path() {
-            self.model.player.skip(&Path::new(&path), time);
+    /// Writes the playlist to its file soon, for changes that do not show as
+    /// changed rows, such as rows being moved.
+    fn schedule_save(&self) {
+        if self.model.file.is_some() {
+            schedule_save(&self.model.save_pending, self.model.relm.stream());
         }
     }
 
-    fn play(&mut self) {
-        if let Some(path) = self.selected_path() {
-            if self.model.player.is_paused() && Some(&path) == self.path().as_ref() {
-                self.model.player.resume();
-            } else {
-                self.model.player.load(&Path::new(&path));
-                if let Some(&duration) = self.model.durations.get(&path) {
-                    self.model.relm.stream().emit(SongDuration(duration));
+    /// Returns the songs of every row, in order.
+    fn tracks(&self) -> Vec<Track> {
+        let mut tracks = Vec::new();
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                if let Some(path) = self.row_path(&iter) {
+                    tracks.push(self.stored_track(Path::new(&path)));
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
                 }
-                self.model.current_song = Some(path.into());
-                self.model.relm.stream().emit(SongStarted(self.pixbuf()));
-
-                // Send metadata
-                self.model
-                    .relm
-                    .stream()
-                    .emit(SongMeta(self.selected_meta()));
             }
         }
+        tracks
     }
 
-    fn pixbuf(&self) -> Option<Pixbuf> {
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            let value = self.model.model.get_value(&iter, PIXBUF_COLUMN as i32);
-            return value.get::<Pixbuf>();
-        }
-        None
+    fn playlist_entry(&self, iter: &TreeIter) -> Option<playlist_file::Entry> {
+        let location = self.row_path(iter)?;
+        let text = |column: u32| {
+            self.model
+                .model
+                .get_value(iter, column as i32)
+                .get::<String>()
+                .unwrap_or_default()
+        };
+        let optional = |column: u32| Some(text(column)).filter(|text| !text.is_empty());
+        // Rows of songs that could not be found may hold URLs.
+        let path = if location.contains("://") {
+            None
+        } else {
+            Some(PathBuf::from(&location))
+        };
+        Some(playlist_file::Entry {
+            duration: self
+                .model
+                .listing
+                .duration(&location)
+                .map(|duration| duration / 1000),
+            location,
+            path,
+            title: optional(TITLE_COLUMN),
+            artist: optional(ARTIST_COLUMN),
+            album: optional(ALBUM_COLUMN),
+        })
     }
 
-    fn selected_meta(&self) -> Vec<String> {
-        let mut metadata = Vec::with_capacity(5);
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            metadata.push(
-                self.model
-                    .model
-                    .get_value(&iter, TITLE_COLUMN as i32)
-                    .get::<String>()
-                    .unwrap_or_default(),
-            );
-            metadata.push(
-                self.model
-                    .model
-                    .get_value(&iter, ARTIST_COLUMN as i32)
-                    .get::<String>()
-                    .unwrap_or_default(),
-            );
-            metadata.push(
-                self.model
-                    .model
-                    .get_value(&iter, ALBUM_COLUMN as i32)
-                    .get::<String>()
-                    .unwrap_or_default(),
-            );
-            metadata.push(
-                self.model
-                    .model
-                    .get_value(&iter, GENRE_COLUMN as i32)
-                    .get::<String>()
-                    .unwrap_or_default(),
-            );
-            metadata.push(
-                self.model
-                    .model
-                    .get_value(&iter, YEAR_COLUMN as i32)
-                    .get::<String>()
-                    .unwrap_or_default(),
-            );
+    fn stop(&mut self) {
+        self.finish_listen();
+        self.model.current_song = None;
+        self.model.resume_position = None;
+        self.model.playback.stop();
+        self.update_indicators();
+        if let Some(mut player) = self.player() {
+            player.stop();
         }
-        metadata
     }
 
-    fn selected_path(&self) -> Option<String> {
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            let value = self.model.model.get_value(&iter, PATH_COLUMN as i32);
-            return value.get::<String>();
-        }
-        None
+    fn remove_selection(&mut self) {
+        let rows = self.selected_rows();
+        self.remove_rows(&rows);
     }
 
-    fn add(&self, path: &Path) {
-        self.compute_duration(path);
+    /// Replaces every row with `tracks`, playing the first of them.
+    fn replace(&mut self, tracks: &[Track]) {
+        self.model.listing.clear(&self.model.shared.pool);
+        self.model.model.clear();
+        self.model.playback.clear_queue();
+        for track in tracks {
+            listing::add_track(self, track, None);
+        }
+        self.report_import_progress();
+        match self.model.model.get_iter_first() {
+            Some(iter) => self.play_iter(&iter),
+            None => self.update_indicators(),
+        }
+    }
 
-        let filename = path
-            .file_stem()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or_default();
+    fn crop_selection(&mut self) {
+        let mut rows = Vec::new();
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                if !self.is_selected(&iter) {
+                    if let Some(row) = self.row_reference(&iter) {
+                        rows.push(row);
+                    }
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        self.remove_rows(&rows);
+    }
 
-        let row = self.model.model.append();
+    fn remove_rows(&mut self, rows: &[TreeRowReference]) {
+        for row in rows {
+            if let Some(iter) = self.row_iter(row) {
+                self.model.model.remove(&iter);
+            }
+        }
 
-        if let Ok(tag) = Tag::read_from_path(path) {
-            let title = match tag.get_vorbis("title") {
-                Some(t) => t.get(0).unwrap(),
-                None => filename,
-            };
+        // Forget durations of files that no longer appear in the playlist, but
+        // keep those still referenced by duplicate rows.
+        let mut remaining = HashSet::new();
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                if let Some(path) = self.row_path(&iter) {
+                    remaining.insert(path);
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        self.model
+            .listing
+            .retain_durations(|path| remaining.contains(path));
+        self.model
+            .playback
+            .update_rows(|row| Some(row.clone()).filter(TreeRowReference::valid));
+        self.update_indicators();
+    }
 
-            let artist = match tag.get_vorbis("artist") {
-                Some(t) => t.get(0).unwrap(),
-                None => "Unknown",
-            };
+    fn move_selection_top(&self) {
+        self.unsort();
+        for row in self.selected_rows().iter().rev() {
+            if let Some(iter) = self.row_iter(row) {
+                self.model.model.move_after(&iter, None);
+            }
+        }
+    }
 
-            let album = match tag.get_vorbis("album") {
-                Some(t) => t.get(0).unwrap(),
-                None => "Unknown",
-            };
+    fn move_selection_bottom(&self) {
+        self.unsort();
+        for row in self.selected_rows() {
+            if let Some(iter) = self.row_iter(&row) {
+                self.model.model.move_before(&iter, None);
+            }
+        }
+    }
 
-            let genre = match tag.get_vorbis("genre") {
-                Some(t) => t.get(0).unwrap(),
-                None => "Unknown",
-            };
+    /// Moves the selected rows, keeping their relative order, so that they end
+    /// up before the row at `position` (or at the bottom when it is `None`).
+    fn move_selection_before(&self, position: Option<i32>) {
+        self.unsort();
+        let anchor = position.and_then(|position| self.model.model.iter_nth_child(None, position));
+        let anchor = anchor.and_then(|iter| loop {
+            if !self.is_selected(&iter) {
+                break self.row_reference(&iter);
+            }
+            if !self.model.model.iter_next(&iter) {
+                break None;
+            }
+        });
 
-            let year = match tag.get_vorbis("year") {
-                Some(t) => t.get(0).unwrap(),
-                None => "Unknown",
-            };
+        for row in self.selected_rows() {
+            if let Some(iter) = self.row_iter(&row) {
+                let anchor = anchor.as_ref().and_then(|anchor| self.row_iter(anchor));
+                self.model.model.move_before(&iter, anchor.as_ref());
+            }
+        }
+    }
 
-            let track = match tag.get_vorbis("tracknumber") {
-                Some(t) => t.get(0).unwrap(),
-                None => "Unknown",
-            };
+    /// Lists the songs at `paths`, the songs of folders and the songs listed
+    /// by playlists, before the row at `position`, or last when it is `None`.
+    /// The user is told about those that could not be.
+    fn add_paths(&mut self, paths: &[PathBuf], position: Option<i32>) {
+        let position = position.map(|position| position as usize);
+        let (_, failures) = listing::add(self, paths, position);
+        for failure in failures {
+            self.model
+                .relm
+                .stream()
+                .emit(FilesFailed(failure.heading, failure.names));
+        }
+        self.report_import_progress();
+        self.update_indicators();
+    }
 
-            let total_tracks = "??";
+    fn queue_selection(&mut self) {
+        let rows = self.selected_rows();
+        self.model.playback.queue(rows);
+        self.update_indicators();
+    }
 
-            let track_value = format!("{} / {}", track, total_tracks);
+    fn copy_selection(&self) {
+        let paths: Vec<String> = self
+            .selected_rows()
+            .iter()
+            .filter_map(|row| self.row_iter(row))
+            .filter_map(|iter| self.row_path(&iter))
+            .collect();
+        if !paths.is_empty() {
+            Clipboard::get(&SELECTION_CLIPBOARD).set_text(&paths.join("\n"));
+        }
+    }
 
-            self.set_pixbuf(&row, &tag);
+    /// Returns the songs of the selected rows, each once.
+    fn selected_tracks(&self) -> Vec<Track> {
+        let mut seen = HashSet::new();
+        self.selected_rows()
+            .iter()
+            .filter_map(|row| self.row_iter(row))
+            .filter_map(|iter| self.row_path(&iter))
+            .filter(|path| seen.insert(path.clone()))
+            .map(|path| self.stored_track(Path::new(&path)))
+            .collect()
+    }
 
-            self.model
-                .model
-                .set_value(&row, TITLE_COLUMN, &title.to_value());
+    /// Changes FLAC files in the background, writing the edit paired with each
+    /// one, then refreshes the rows of the songs written. Failures are reported
+    /// under `heading`.
+    fn write_files<T, F>(&mut self, edits: Vec<(PathBuf, T)>, heading: &str, write: F)
+    where
+        T: Send + 'static,
+        F: Fn(&Path, T) -> metaflac::Result<()> + Send + 'static,
+    {
+        if edits.is_empty() {
+            return;
+        }
+        // The artwork of the songs may change, and with it their keys.
+        for (path, _) in &edits {
+            if let Some(key) = artwork::key(&self.stored_track(path)) {
+                self.model.shared.artwork.borrow_mut().remove(&key);
+                self.model.shared.pool.release_artwork(&key);
+            }
+        }
+        let heading = heading.to_string();
+        let stream = self.model.relm.stream().clone();
+        let (_channel, sender) = Channel::new(move |(changes, errors)| {
+            stream.emit(FilesWritten(heading.clone(), changes, errors));
+        });
+        thread::spawn(move || {
+            let library = Library::open();
+            let mut changes = Vec::new();
+            let mut errors = Vec::new();
+            for (path, edit) in edits {
+                match write(&path, edit) {
+                    Ok(()) => changes.push(Change::Updated(library.reload(&path))),
+                    Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+                }
+            }
+            let _ = sender.send((changes, errors));
+        });
+    }
 
-            self.model
-                .model
-                .set_value(&row, ARTIST_COLUMN, &artist.to_value());
+    /// Moves files in the background, then follows them in the library and
+    /// the playlist.
+    fn move_files(&self, moves: Vec<(PathBuf, PathBuf)>) {
+        self.run_moves(move || organize::move_files(&moves));
+    }
 
-            self.model
-                .model
-                .set_value(&row, ALBUM_COLUMN, &album.to_value());
+    /// Moves back the files of the last rename.
+    fn undo_moves(&self) {
+        self.run_moves(organize::undo_last);
+    }
 
-            self.model
-                .model
-                .set_value(&row, GENRE_COLUMN, &genre.to_value());
+    fn run_moves<F>(&self, moves: F)
+    where
+        F: FnOnce() -> (Vec<(PathBuf, PathBuf)>, Vec<String>) + Send + 'static,
+    {
+        let stream = self.model.relm.stream().clone();
+        let (_channel, sender) = Channel::new(move |(changes, errors)| {
+            stream.emit(FilesMoved(changes, errors));
+        });
+        thread::spawn(move || {
+            let (done, errors) = moves();
+            let library = Library::open();
+            let changes = done
+                .into_iter()
+                .map(|(from, to)| {
+                    library.rename(&from, &to);
+                    Change::Moved(from.to_string_lossy().to_string(), library.reload(&to))
+                })
+                .collect();
+            let _ = sender.send((changes, errors));
+        });
+    }
 
-            self.model
-                .model
-                .set_value(&row, YEAR_COLUMN, &year.to_value());
+    fn selected_rows(&self) -> Vec<TreeRowReference> {
+        let (paths, _) = self.treeview.get_selection().get_selected_rows();
+        paths
+            .iter()
+            .filter_map(|path| self.model.filter.convert_path_to_child_path(path))
+            .filter_map(|path| TreeRowReference::new(&self.model.model, &path))
+            .collect()
+    }
 
-            self.model
-                .model
-                .set_value(&row, TRACK_COLUMN, &track_value.to_value());
-        } else {
-            self.model
-                .model
-                .set_value(&row, TITLE_COLUMN, &filename.to_value());
+    /// Adds a row before the one at `position`, or last when it is `None`.
+    fn new_row(&self, position: Option<usize>) -> TreeIter {
+        match position {
+            Some(position) => self.model.model.insert(position as i32),
+            None => self.model.model.append(),
         }
+    }
 
-        let path = path.to_str().unwrap_or_default();
-
+    fn row_reference(&self, iter: &TreeIter) -> Option<TreeRowReference> {
         self.model
             .model
-            .set_value(&row, PATH_COLUMN, &path.to_value());
+            .get_path(iter)
+            .and_then(|path| TreeRowReference::new(&self.model.model, &path))
     }
 
-    fn add_pixbuf_column(&self, column: i32, visibility: Visibility) {
-        let view_column = TreeViewColumn::new();
-        if visibility == Visible {
-            let cell = CellRendererPixbuf::new();
+    fn row_iter(&self, row: &TreeRowReference) -> Option<TreeIter> {
+        row.get_path()
+            .and_then(|path| self.model.model.get_iter(&path))
+    }
+
+    fn row_path(&self, iter: &TreeIter) -> Option<String> {
+        self.model
+            .model
+            .get_value(iter, PATH_COLUMN as i32)
+            .get::<String>()
+    }
+
+    fn path(&self) -> Option<String> {
+        self.model.current_song.clone()
+    }
+
+    fn skip(&mut self, time: u32) {
+        if self.path().is_some() {
+            if let Some(player) = self.player() {
+                player.skip(time);
+            }
+        }
+    }
+
+    fn play(&mut self) {
+        if let Some(position) = self.model.resume_position.take() {
+            if let Some(iter) = self.current_iter() {
+                self.play_iter(&iter);
+                self.skip(position as u32);
+                return;
+            }
+        }
+        let paused = self.player().is_some_and(|player| player.is_paused());
+        if paused && self.current_iter().is_some() {
+            if let Some(mut player) = self.player() {
+                player.resume();
+            }
+            return;
+        }
+
+        // When stopped, start from the selected row, falling back to the row
+        // that was last playing and then to the top of the playlist.
+        let iter = self
+            .selected_rows()
+            .first()
+            .and_then(|row| self.row_iter(row))
+            .or_else(|| self.current_iter())
+            .or_else(|| self.first_visible());
+        if let Some(ref iter) = iter {
+            self.play_iter(iter);
+        }
+    }
+
+    /// Plays the song at `iter`, or the first one shown after it when its
+    /// file is missing, as songs listed by playlists may be. The user is told
+    /// about the songs passed over.
+    fn play_iter(&mut self, iter: &TreeIter) {
+        let missing = self.play_from(iter);
+        if !missing.is_empty() {
+            self.model.relm.stream().emit(FilesFailed(
+                "Could not play, as the file is missing:".to_string(),
+                missing,
+            ));
+        }
+    }
+
+    /// Plays the song at `iter`, or the first one shown after it whose file
+    /// is there. Returns the paths of the songs passed over.
+    fn play_from(&mut self, iter: &TreeIter) -> Vec<String> {
+        let row = match self.row_reference(iter) {
+            Some(row) => row,
+            None => return Vec::new(),
+        };
+        let rows = Shown {
+            current: self.model.current_row.as_ref(),
+            filter: &self.model.filter,
+            model: &self.model.model,
+        };
+        let (start, missing) = self.model.playback.start(&rows, row);
+        if let Some(start) = start {
+            if let Some(iter) = self.row_iter(&start.row) {
+                self.set_current_row(&iter);
+            }
+            self.model
+                .shared
+                .load(self.model.id, &start.file, start.spans);
+            self.song_changed(start.path);
+        }
+        missing
+    }
+
+    /// Picks up the song at `path` where it was left in a previous session,
+    /// `position` milliseconds in. A paused song only loads once played.
+    fn resume(&mut self, path: &str, position: u64, paused: bool) {
+        let iter = self.model.model.get_iter_first().and_then(|iter| loop {
+            if self.row_path(&iter).as_deref() == Some(path) {
+                return Some(iter);
+            }
+            if !self.model.model.iter_next(&iter) {
+                return None;
+            }
+        });
+        let iter = match iter {
+            Some(iter) => iter,
+            None => return,
+        };
+        if paused {
+            self.set_current_row(&iter);
+            self.model.resume_position = Some(position);
+            self.song_changed(path.to_string());
+            self.update_indicators();
+        } else {
+            self.play_iter(&iter);
+            self.skip(position as u32);
+        }
+    }
+
+    /// Works out again which virtual tracks play on from the one playing,
+    /// and has the player follow.
+    fn refresh_continuation(&mut self) {
+        if self.model.current_song.is_none() {
+            return;
+        }
+        let rows = Shown {
+            current: self.model.current_row.as_ref(),
+            filter: &self.model.filter,
+            model: &self.model.model,
+        };
+        if let Some(spans) = self.model.playback.refresh(&rows) {
+            if let Some(player) = self.player() {
+                player.set_spans(spans);
+            }
+        }
+    }
+
+    /// Moves the playing row on to the next virtual track, which the player
+    /// went on to by itself.
+    fn next_virtual_track(&mut self) {
+        let iter = match self.model.playback.next_virtual_track() {
+            Some(row) => self.row_iter(&row),
+            None => None,
+        };
+        let path = iter.as_ref().and_then(|iter| self.row_path(iter));
+        if let (Some(iter), Some(path)) = (iter, path) {
+            self.set_current_row(&iter);
+            self.song_changed(path);
+        }
+    }
+
+    /// Tells about the song at `path`, which just started playing.
+    fn song_changed(&mut self, path: String) {
+        self.finish_listen();
+        let duration = self.model.listing.duration(&path);
+        self.model.playback.listen(path.clone(), duration);
+        if let Some(duration) = duration {
+            self.model.relm.stream().emit(SongDuration(duration));
+        }
+        let track = self.stored_track(Path::new(&path));
+        self.model.current_song = Some(path);
+        self.model.relm.stream().emit(SongStarted(self.pixbuf()));
+
+        // Send metadata
+        self.model.relm.stream().emit(SongMeta(Box::new(track)));
+    }
+
+    /// Plays the first of the songs appended from the row at `first` on.
+    /// Songs missing from a playlist are passed over, as they were reported
+    /// when it was opened.
+    fn play_added(&mut self, first: i32) {
+        if let Some(iter) = self.model.model.iter_nth_child(None, first) {
+            self.play_from(&iter);
+        }
+    }
+
+    fn set_current_row(&mut self, iter: &TreeIter) {
+        self.model.current_row = self.row_reference(iter);
+        self.update_indicators();
+        let path = self
+            .model
+            .model
+            .get_path(iter)
+            .and_then(|path| self.model.filter.convert_child_path_to_path(&path));
+        if let Some(path) = path {
+            self.treeview
+                .scroll_to_cell(Some(&path), None::<&TreeViewColumn>, false, 0.0, 0.0);
+        }
+    }
+
+    /// Marks the playing row and numbers the queued rows in the indicator
+    /// column, clearing any stale marks.
+    fn update_indicators(&self) {
+        let mut indicators = HashMap::new();
+        for (position, row) in self.model.playback.queued().iter().enumerate() {
+            if let Some(path) = row.get_path() {
+                indicators
+                    .entry(path.get_indices())
+                    .or_insert_with(|| (position + 1).to_string());
+            }
+        }
+        if self.model.current_song.is_some() {
+            if let Some(path) = self
+                .model
+                .current_row
+                .as_ref()
+                .and_then(|row| row.get_path())
+            {
+                indicators.insert(path.get_indices(), PLAYING_INDICATOR.to_string());
+            }
+        }
+
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                let indices = self
+                    .model
+                    .model
+                    .get_path(&iter)
+                    .map(|path| path.get_indices())
+                    .unwrap_or_default();
+                let indicator = indicators.remove(&indices).unwrap_or_default();
+                let current = self
+                    .model
+                    .model
+                    .get_value(&iter, PLAYING_COLUMN as i32)
+                    .get::<String>()
+                    .unwrap_or_default();
+                if current != indicator {
+                    self.model
+                        .model
+                        .set_value(&iter, PLAYING_COLUMN, &indicator.to_value());
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the row that is currently playing, independently of what the
+    /// user has selected in the view.
+    fn current_iter(&self) -> Option<TreeIter> {
+        self.model
+            .current_row
+            .as_ref()
+            .and_then(|row| self.row_iter(row))
+    }
+
+    fn pixbuf(&self) -> Option<Pixbuf> {
+        if let Some(iter) = self.current_iter() {
+            let value = self.model.model.get_value(&iter, PIXBUF_COLUMN as i32);
+            return value.get::<Pixbuf>();
+        }
+        None
+    }
+
+    /// Returns the song at `path` as stored in the library.
+    fn stored_track(&self, path: &Path) -> Track {
+        self.model
+            .shared
+            .library
+            .track(path)
+            .unwrap_or_else(|| Track::read(path))
+    }
+
+    /// Fills the rows waiting for what the import workers sent, which are
+    /// told to every playlist.
+    fn imported(&mut self, outcomes: &[Outcome]) {
+        for outcome in outcomes {
+            if let Outcome::Artwork(key, _) = outcome {
+                let rows = self.model.pending_artwork.remove(key).unwrap_or_default();
+                let artwork = self.model.shared.artwork.borrow().get(key).cloned();
+                let artwork = artwork.unwrap_or_default();
+                for row in rows {
+                    if let Some(iter) = self.row_iter(&row) {
+                        self.set_artwork(&iter, artwork.as_ref());
+                    }
+                }
+            }
+        }
+        let imported = listing::imported(self, outcomes);
+        for (path, duration) in imported.durations {
+            if self.model.current_song.as_ref() == Some(&path) {
+                self.model.relm.stream().emit(SongDuration(duration));
+            }
+            self.model.playback.set_duration(&path, duration);
+        }
+        // An album split while playing starts over from its first track.
+        match imported.restart.and_then(|row| self.row_iter(&row)) {
+            Some(first) => self.play_iter(&first),
+            None if imported.split => self.update_indicators(),
+            None => (),
+        }
+        self.report_import_progress();
+    }
+
+    fn cancel_import(&mut self) {
+        let rows = self.model.listing.cancel_scans(&self.model.shared.pool);
+        self.remove_rows(&rows);
+        self.report_import_progress();
+    }
+
+    /// Tells how many of the songs being imported have been read, starting
+    /// the count over once they all have.
+    fn report_import_progress(&mut self) {
+        let (done, total) = self.model.listing.progress();
+        self.model.relm.stream().emit(ImportProgress(done, total));
+    }
+
+    /// Tells when the song playing is first heard, and counts it as played
+    /// once enough of it has been.
+    fn listened(&mut self, time: u64) {
+        let shared = &self.model.shared;
+        let scrobbler = shared.scrobbler.borrow();
+        if self
+            .model
+            .playback
+            .listened(time, &shared.library, &scrobbler)
+        {
+            if let Some(path) = self.path() {
+                self.show_stats(&path);
+            }
+        }
+    }
+
+    /// Counts the song that was playing as skipped when it was left before it
+    /// counted as played.
+    fn finish_listen(&mut self) {
+        let skipped = self
+            .model
+            .playback
+            .finish_listen(&self.model.shared.library);
+        if let Some(path) = skipped {
+            self.show_stats(&path);
+        }
+    }
+
+    /// Shows the play statistics and rating of the song at `path` as stored.
+    fn show_stats(&self, path: &str) {
+        let track = match self.model.shared.library.track(Path::new(path)) {
+            Some(track) => track,
+            None => return,
+        };
+        for row in self.rows_with_path(path) {
+            if let Some(iter) = self.row_iter(&row) {
+                self.set_stats(&iter, &track);
+            }
+        }
+    }
+
+    fn set_stats(&self, iter: &TreeIter, track: &Track) {
+        let last_played = track.last_played.map(stats::format_time);
+        let values: [(u32, &dyn ToValue); 4] = [
+            (PLAY_COUNT_COLUMN, &track.play_count.to_string()),
+            (SKIP_COUNT_COLUMN, &track.skip_count.to_string()),
+            (LAST_PLAYED_COLUMN, &last_played.unwrap_or_default()),
+            (RATING_COLUMN, &stats::stars(track.rating)),
+        ];
+        for (column, value) in values.iter() {
+            self.model.model.set_value(iter, *column, &value.to_value());
+        }
+    }
+
+    /// Rates the selected songs in the library, and in their files when the
+    /// user asked for it.
+    fn rate_selection(&mut self, rating: Option<u8>) {
+        let tracks = self.selected_tracks();
+        for track in &tracks {
+            self.model.shared.library.set_rating(&track.path, rating);
+            self.show_stats(&track.path);
+        }
+        if !library::ratings_in_files() {
+            return;
+        }
+        let edits = tracks
+            .into_iter()
+            .filter(|track| cue::split_path(&track.path).is_none())
+            .map(|track| (PathBuf::from(track.path), rating))
+            .collect();
+        self.write_files(edits, "Could not rate:", |path, rating| {
+            tags::modify(path, |tag| tags::set_rating(tag, rating))
+        });
+    }
+
+    /// Shows `track` in `row`, but for a length the import workers are yet
+    /// to compute.
+    fn fill_row(&mut self, row: &TreeIter, track: &Track) {
+        self.request_artwork(row, track);
+
+        let sample_rate = track
+            .sample_rate
+            .map(format_sample_rate)
+            .unwrap_or_default();
+        let values: [(u32, &dyn ToValue); 12] = [
+            (TITLE_COLUMN, &track.title()),
+            (ARTIST_COLUMN, &track.artist()),
+            (ALBUM_COLUMN, &track.album()),
+            (GENRE_COLUMN, &track.genre()),
+            (YEAR_COLUMN, &track.year()),
+            (TRACK_COLUMN, &track.track_number()),
+            (DISC_COLUMN, &track.disc_number()),
+            (COMPOSER_COLUMN, &track.composer()),
+            (PERFORMER_COLUMN, &track.performer()),
+            (SAMPLE_RATE_COLUMN, &sample_rate),
+            (PATH_COLUMN, &track.path),
+            (FORMAT_COLUMN, &"FLAC"),
+        ];
+        for (column, value) in values.iter() {
+            self.model.model.set_value(row, *column, &value.to_value());
+        }
+        self.set_stats(row, track);
+        if let Some(duration) = track.duration {
+            self.set_duration(row, duration, track.size);
+        }
+    }
+
+    /// Starts bringing the library up to date with its folders and watching
+    /// them, replacing any previous watcher.
+    fn watch_roots(&mut self) {
+        let roots = library::roots();
+        let stream = self.model.relm.stream().clone();
+        let sender = self
+            .model
+            .changes
+            .get_or_insert_with(|| {
+                let (_channel, sender) = Channel::new(move |changes| {
+                    stream.emit(LibraryChanged(changes));
+                });
+                sender
+            })
+            .clone();
+        // The previous watcher stops before the new one starts, so that a
+        // change is not reported by both.
+        self.model.watcher = None;
+        scanner::rescan(roots.clone(), sender.clone());
+        self.model.watcher = scanner::watch(&roots, sender);
+    }
+
+    /// Updates the rows of songs changed on disk. Songs new to the library are
+    /// appended to the playlist of the library.
+    fn apply_changes(&mut self, changes: Vec<Change>) {
+        for change in changes {
+            match change {
+                Change::Updated(track) => {
+                    if let Some(key) = artwork::key(&track) {
+                        self.model.shared.artwork.borrow_mut().remove(&key);
+                        self.model.shared.pool.release_artwork(&key);
+                    }
+                    if self.model.current_song.as_ref() == Some(&track.path) {
+                        self.model
+                            .relm
+                            .stream()
+                            .emit(SongMeta(Box::new(track.clone())));
+                    }
+                    // Only the playlist of the library grows with it.
+                    let rows = self.rows_with_path(&track.path);
+                    if rows.is_empty() && self.model.file.is_none() {
+                        listing::add_track(self, &track, None);
+                    }
+                    self.fill_rows(&rows, &track);
+                }
+                Change::Moved(from, track) => {
+                    if self.model.current_song.as_ref() == Some(&from) {
+                        self.model.current_song = Some(track.path.clone());
+                    }
+                    self.model.listing.moved(&from, &track.path);
+                    let rows = self.rows_with_path(&from);
+                    self.fill_rows(&rows, &track);
+                }
+                Change::Removed(path) => {
+                    let rows = self.rows_with_path(&path);
+                    self.remove_rows(&rows);
+                }
+            }
+        }
+        self.apply_rule();
+        self.update_indicators();
+    }
+
+    /// Lists the songs of the library matching the rule of a smart playlist,
+    /// keeping the rows of those that already were.
+    fn apply_rule(&mut self) {
+        let tracks = match self.model.rule {
+            Some(ref rule) => smart::matching(&self.model.shared.library, rule),
+            None => return,
+        };
+        // Rows of virtual tracks stand for the file they were split from.
+        let matching: HashSet<PathBuf> = tracks
+            .iter()
+            .map(|track| PathBuf::from(&track.path))
+            .collect();
+        let mut stale = Vec::new();
+        let mut listed = HashSet::new();
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                if let Some(path) = self.row_path(&iter) {
+                    let file = cue::file_path(&path);
+                    if matching.contains(&file) {
+                        listed.insert(file);
+                    } else {
+                        stale.extend(self.row_reference(&iter));
+                    }
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        if !stale.is_empty() {
+            self.remove_rows(&stale);
+        }
+        for track in &tracks {
+            if !listed.contains(Path::new(&track.path)) {
+                listing::add_track(self, track, None);
+            }
+        }
+    }
+
+    fn set_rule(&mut self, rule: String) {
+        if let Some(ref file) = self.model.file {
+            if let Err(error) = smart::write(file, &rule) {
+                self.model.relm.stream().emit(FilesFailed(
+                    format!("Could not save {}:", file.display()),
+                    vec![error.to_string()],
+                ));
+            }
+        }
+        self.model.rule = Some(rule);
+        self.apply_rule();
+        self.update_indicators();
+    }
+
+    fn fill_rows(&mut self, rows: &[TreeRowReference], track: &Track) {
+        for row in rows {
+            listing::fill(self, row, track);
+        }
+    }
+
+    fn rows_with_path(&self, path: &str) -> Vec<TreeRowReference> {
+        let mut rows = Vec::new();
+        if let Some(iter) = self.model.model.get_iter_first() {
+            loop {
+                if self.row_path(&iter).as_deref() == Some(path) {
+                    rows.extend(self.row_reference(&iter));
+                }
+                if !self.model.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        rows
+    }
+
+    fn set_duration(&self, iter: &TreeIter, duration: u64, size: u64) {
+        let bitrate = (size * 8)
+            .checked_div(duration)
+            .map(|bits| format!("{} kbps", bits / 1000))
+            .unwrap_or_default();
+        self.model
+            .model
+            .set_value(iter, DURATION_COLUMN, &format_duration(duration).to_value());
+        self.model
+            .model
+            .set_value(iter, BITRATE_COLUMN, &bitrate.to_value());
+    }
+
+    /// Shows the artwork of a song in `row`, once it has been loaded by the
+    /// import workers. Artwork is cached by album, which usually shares it.
+    fn request_artwork(&mut self, row: &TreeIter, track: &Track) {
+        let key = match artwork::key(track) {
+            Some(key) => key,
+            None => return self.set_artwork(row, None),
+        };
+        if let Some(artwork) = self.model.shared.artwork.borrow().get(&key) {
+            let artwork = artwork.clone();
+            return self.set_artwork(row, artwork.as_ref());
+        }
+        if let Some(row) = self.row_reference(row) {
+            self.model.pending_artwork.entry(key).or_default().push(row);
+        }
+        self.model.shared.pool.load_artwork(track);
+    }
+
+    fn set_artwork(&self, row: &TreeIter, artwork: Option<&(Pixbuf, Pixbuf)>) {
+        let (thumbnail, pixbuf) = match artwork {
+            Some((thumbnail, pixbuf)) => (Some(thumbnail), Some(pixbuf)),
+            None => (None, None),
+        };
+        self.model
+            .model
+            .set_value(row, THUMBNAIL_COLUMN, &thumbnail.to_value());
+        self.model
+            .model
+            .set_value(row, PIXBUF_COLUMN, &pixbuf.to_value());
+    }
+
+    fn add_pixbuf_column(&self, column: i32, visibility: Visibility) -> TreeViewColumn {
+        let view_column = TreeViewColumn::new();
+        if visibility == Visible {
+            let cell = CellRendererPixbuf::new();
             view_column.pack_start(&cell, true);
             view_column.add_attribute(&cell, "pixbuf", column);
         }
         self.treeview.append_column(&view_column);
+        view_column
     }
 
-    fn add_text_column(&self, title: &str, column: i32) {
+    fn add_text_column(&self, title: &str, column: i32) -> TreeViewColumn {
         let view_column = TreeViewColumn::new();
         view_column.set_title(title);
         let cell = CellRendererText::new();
@@ -426,50 +1831,574 @@ impl Playlist {
         view_column.pack_start(&cell, true);
         view_column.add_attribute(&cell, "text", column);
         self.treeview.append_column(&view_column);
+        view_column
     }
 
-    fn compute_duration(&self, path: &Path) {
-        let path = path.to_path_buf();
-        let stream = self.model.relm.stream().clone();
-        let (_channel, sender) = Channel::new(move |(path, duration)| {
-            stream.emit(DurationComputed(path, duration));
-        });
-        std::thread::spawn(move || {
-            let duration = Player::compute_duration(&path);
-            sender
-                .send((path, duration))
-                .expect("Cannot send computed duration");
-        });
-    }
+    fn create_columns(&mut self) {
+        let view_column = TreeViewColumn::new();
+        let cell = CellRendererText::new();
+        view_column.pack_start(&cell, false);
+        view_column.add_attribute(&cell, "text", PLAYING_COLUMN as i32);
+        self.treeview.append_column(&view_column);
+
+        // Saved columns come first in their saved order, followed by any
+        // columns the saved layout does not know about.
+        let layout = Layout::load();
+        let mut order: Vec<(usize, bool)> = layout
+            .iter()
+            .flat_map(|layout| layout.columns.iter())
+            .filter_map(|(key, visible)| {
+                COLUMNS
+                    .iter()
+                    .position(|spec| spec.key == key)
+                    .map(|index| (index, *visible))
+            })
+            .collect();
+        for (index, spec) in COLUMNS.iter().enumerate() {
+            if !order.iter().any(|&(i, _)| i == index) {
+                order.push((index, spec.visibility == Visible));
+            }
+        }
 
-    fn create_columns(&self) {
-        self.add_pixbuf_column(THUMBNAIL_COLUMN as i32, Visible);
-        self.add_text_column("Title", TITLE_COLUMN as i32);
-        self.add_text_column("Artist", ARTIST_COLUMN as i32);
-        self.add_text_column("Album", ALBUM_COLUMN as i32);
-        self.add_text_column("Genre", GENRE_COLUMN as i32);
-        self.add_text_column("Year", YEAR_COLUMN as i32);
-        self.add_text_column("Track", TRACK_COLUMN as i32);
+        for (index, visible) in order {
+            let spec = &COLUMNS[index];
+            let view_column = if spec.column == THUMBNAIL_COLUMN {
+                self.add_pixbuf_column(spec.column as i32, Visible)
+            } else {
+                self.add_sortable_column(index)
+            };
+            view_column.set_title(spec.title);
+            view_column.set_visible(visible);
+            view_column.set_reorderable(true);
+            view_column.set_resizable(true);
+            let width = layout
+                .as_ref()
+                .and_then(|layout| layout.widths.get(spec.key));
+            if let Some(&width) = width {
+                view_column.set_fixed_width(width);
+            }
+            if let Some(button) = view_column.get_button() {
+                connect!(
+                    self.model.relm,
+                    button,
+                    connect_button_press_event(_, event),
+                    return header_click(index, event)
+                );
+            }
+            self.model.columns.push((index, view_column));
+        }
         self.add_pixbuf_column(PIXBUF_COLUMN as i32, Invisible);
+
+        if let Some((key, order)) = layout.and_then(|layout| layout.sort) {
+            if let Some(index) = COLUMNS.iter().position(|spec| spec.key == key) {
+                self.apply_sort(index, order);
+            }
+        }
+
+        connect!(
+            self.model.relm,
+            self.treeview,
+            connect_columns_changed(_),
+            ColumnsChanged
+        );
     }
 
-    fn set_pixbuf(&self, row: &TreeIter, tag: &Tag) {
-        if let Some(picture) = tag.pictures().get(0) {
-            let pixbuf_loader = PixbufLoader::new();
-            pixbuf_loader.set_size(IMAGE_SIZE, IMAGE_SIZE);
-            pixbuf_loader.write(&picture.data).unwrap();
-            if let Some(pixbuf) = pixbuf_loader.get_pixbuf() {
-                let thumbnail = pixbuf
-                    .scale_simple(THUMBNAIL_SIZE, THUMBNAIL_SIZE, INTERP_HYPER)
-                    .unwrap();
-                self.model
-                    .model
-                    .set_value(row, THUMBNAIL_COLUMN, &thumbnail.to_value());
+    fn add_sortable_column(&self, index: usize) -> TreeViewColumn {
+        let spec = &COLUMNS[index];
+        let view_column = self.add_text_column(spec.title, spec.column as i32);
+        view_column.set_clickable(true);
+        connect!(
+            self.model.relm,
+            view_column,
+            connect_clicked(_),
+            SortBy(index)
+        );
+
+        let column = spec.column as i32;
+        self.model
+            .model
+            .set_sort_func(SortColumn::Index(spec.column), move |model, a, b| {
+                let value = |iter, column| {
+                    model
+                        .get_value(iter, column)
+                        .get::<String>()
+                        .unwrap_or_default()
+                };
+                let ordering = natural_cmp(&value(a, column), &value(b, column));
+                if column != ALBUM_COLUMN as i32 {
+                    return ordering;
+                }
+                // Keep the tracks of an album together and in disc order
+                ordering
+                    .then_with(|| {
+                        natural_cmp(&value(a, DISC_COLUMN as i32), &value(b, DISC_COLUMN as i32))
+                    })
+                    .then_with(|| {
+                        natural_cmp(
+                            &value(a, TRACK_COLUMN as i32),
+                            &value(b, TRACK_COLUMN as i32),
+                        )
+                    })
+            });
+        view_column
+    }
+
+    fn sort_by(&self, index: usize) {
+        let order = match self.model.model.get_sort_column_id() {
+            Some((SortColumn::Index(column), SortType::Ascending))
+                if column == COLUMNS[index].column =>
+            {
+                SortType::Descending
+            }
+            _ => SortType::Ascending,
+        };
+        self.apply_sort(index, order);
+        self.save_layout();
+        self.schedule_save();
+    }
+
+    fn apply_sort(&self, index: usize, order: SortType) {
+        self.model
+            .model
+            .set_sort_column_id(SortColumn::Index(COLUMNS[index].column), order);
+        for (i, view_column) in &self.model.columns {
+            view_column.set_sort_indicator(*i == index);
+            view_column.set_sort_order(order);
+        }
+    }
+
+    /// Drops the sort order while keeping the rows where they are, so that they
+    /// can be moved by hand.
+    fn unsort(&self) {
+        if self.model.model.get_sort_column_id().is_some() {
+            self.model.model.set_unsorted();
+            for (_, view_column) in &self.model.columns {
+                view_column.set_sort_indicator(false);
+            }
+            self.save_layout();
+        }
+    }
+
+    fn show_header_menu(&mut self, index: usize, button: u32, time: u32) {
+        let menu = Menu::new();
+        for (i, view_column) in &self.model.columns {
+            let item = CheckMenuItem::new_with_label(COLUMNS[*i].title);
+            item.set_active(view_column.get_visible());
+            let i = *i;
+            connect!(self.model.relm, item, connect_toggled(_), ToggleColumn(i));
+            menu.append(&item);
+        }
+        menu.append(&SeparatorMenuItem::new());
+        let move_left = MenuItem::new_with_label("Move left");
+        connect!(
+            self.model.relm,
+            move_left,
+            connect_activate(_),
+            MoveColumn(index, -1)
+        );
+        menu.append(&move_left);
+        let move_right = MenuItem::new_with_label("Move right");
+        connect!(
+            self.model.relm,
+            move_right,
+            connect_activate(_),
+            MoveColumn(index, 1)
+        );
+        menu.append(&move_right);
+        menu.show_all();
+        menu.popup_easy(button, time);
+        self.model.header_menu = menu;
+    }
+
+    fn toggle_column(&self, index: usize) {
+        if let Some((_, view_column)) = self.model.columns.iter().find(|(i, _)| *i == index) {
+            view_column.set_visible(!view_column.get_visible());
+        }
+        self.save_layout();
+    }
+
+    fn move_column(&self, index: usize, offset: i32) {
+        let columns = self.treeview.get_columns();
+        let position = match self
+            .model
+            .columns
+            .iter()
+            .find(|(i, _)| *i == index)
+            .and_then(|(_, view_column)| columns.iter().position(|c| c == view_column))
+        {
+            Some(position) => position as i32,
+            None => return,
+        };
+
+        // The now-playing indicator stays first and the hidden artwork column
+        // stays last.
+        let target = position + offset;
+        if target < 1 || target as usize >= columns.len() - 1 {
+            return;
+        }
+        let column = &columns[position as usize];
+        let base = if offset < 0 { target - 1 } else { target };
+        self.treeview
+            .move_column_after(column, Some(&columns[base as usize]));
+    }
+
+    fn save_layout(&self) {
+        let widths = self
+            .model
+            .columns
+            .iter()
+            .filter(|(_, c)| c.get_visible() && c.get_width() > 0)
+            .map(|(index, c)| (COLUMNS[*index].key.to_string(), c.get_width()))
+            .collect();
+        let columns = self
+            .treeview
+            .get_columns()
+            .iter()
+            .filter_map(|view_column| {
                 self.model
-                    .model
-                    .set_value(row, PIXBUF_COLUMN, &pixbuf.to_value());
+                    .columns
+                    .iter()
+                    .find(|(_, c)| c == view_column)
+                    .map(|(index, c)| (COLUMNS[*index].key.to_string(), c.get_visible()))
+            })
+            .collect();
+        let sort = match self.model.model.get_sort_column_id() {
+            Some((SortColumn::Index(column), order)) => COLUMNS
+                .iter()
+                .find(|spec| spec.column == column)
+                .map(|spec| (spec.key.to_string(), order)),
+            _ => None,
+        };
+        Layout {
+            columns,
+            widths,
+            sort,
+        }
+        .save();
+    }
+
+    /// Rows can be dragged within the view to reorder them, and files, folders
+    /// and playlists can be dropped in from a file manager.
+    fn setup_drag_and_drop(&self) {
+        if !self.editable() {
+            return;
+        }
+        let row_target = TargetEntry::new(ROW_TARGET, TargetFlags::SAME_WIDGET, ROW_INFO);
+        let uri_target = TargetEntry::new(URI_TARGET, TargetFlags::OTHER_APP, URI_INFO);
+
+        self.treeview.drag_source_set(
+            ModifierType::BUTTON1_MASK,
+            std::slice::from_ref(&row_target),
+            DragAction::MOVE,
+        );
+        self.treeview.drag_dest_set(
+            DestDefaults::MOTION | DestDefaults::DROP,
+            &[row_target, uri_target],
+            DragAction::COPY | DragAction::MOVE,
+        );
+
+        // The rows being moved are taken from the selection when dropped, so the
+        // payload only needs to be non-empty for the drop to succeed.
+        self.treeview
+            .connect_drag_data_get(|_, _, data, _, _| data.set(&data.get_target(), 8, &[0]));
+        self.treeview.connect_drag_motion(|view, _, x, y, _| {
+            let (path, position) = match view.get_dest_row_at_pos(x, y) {
+                Some((path, position)) => (path, position),
+                None => (None, TreeViewDropPosition::Before),
+            };
+            view.set_drag_dest_row(path.as_ref(), position);
+            Inhibit(true)
+        });
+        connect!(
+            self.model.relm,
+            self.treeview,
+            connect_drag_data_received(view, _, x, y, data, info, _),
+            drop_received(view, x, y, data, info)
+        );
+    }
+
+    fn create_menu(&self) {
+        let editable = self.editable();
+        self.add_menu_item("Queue", || QueueSelection);
+        if editable {
+            self.add_menu_item("Move to top", || MoveSelectionTop);
+            self.add_menu_item("Move to bottom", || MoveSelectionBottom);
+        }
+        self.add_menu_item("Copy paths", || CopySelection);
+        self.add_menu_item("Edit tags…", || EditSelection);
+        self.add_menu_item("Tags from file names…", || TagSelectionFromPaths);
+        self.add_menu_item("Rename files from tags…", || RenameSelection);
+        self.add_menu_item("Undo last rename", || UndoMoves);
+        self.model.menu.append(&self.rating_menu());
+        self.model.menu.append(&SeparatorMenuItem::new());
+        self.add_menu_item("Set cover…", || SetCoverSelection);
+        self.add_menu_item("Extract cover…", || ExtractCoverSelection);
+        self.add_menu_item("Remove embedded covers", || RemoveCoverSelection);
+        if editable {
+            self.model.menu.append(&SeparatorMenuItem::new());
+            self.add_menu_item("Crop to selection", || CropSelection);
+            self.add_menu_item("Remove", || RemoveSong);
+        }
+        self.model.menu.show_all();
+    }
+
+    fn rating_menu(&self) -> MenuItem {
+        let menu = Menu::new();
+        let clear = MenuItem::new_with_label("No rating");
+        connect!(
+            self.model.relm,
+            clear,
+            connect_activate(_),
+            RateSelection(None)
+        );
+        menu.append(&clear);
+        for rating in 1..=MAX_RATING {
+            let item = MenuItem::new_with_label(&stats::stars(Some(rating)));
+            connect!(
+                self.model.relm,
+                item,
+                connect_activate(_),
+                RateSelection(Some(rating))
+            );
+            menu.append(&item);
+        }
+        menu.append(&SeparatorMenuItem::new());
+        let in_files = CheckMenuItem::new_with_label("Store ratings in files");
+        in_files.set_active(library::ratings_in_files());
+        connect!(
+            self.model.relm,
+            in_files,
+            connect_toggled(item),
+            StoreRatingsInFiles(item.get_active())
+        );
+        menu.append(&in_files);
+        let item = MenuItem::new_with_label("Rating");
+        item.set_submenu(Some(&menu));
+        item
+    }
+
+    fn add_menu_item(&self, label: &str, msg: fn() -> Msg) {
+        let item = MenuItem::new_with_label(label);
+        connect!(self.model.relm, item, connect_activate(_), msg());
+        self.model.menu.append(&item);
+    }
+}
+
+/// The rows of a playlist shown by its search, as the playback steps through
+/// them.
+struct Shown<'a> {
+    current: Option<&'a TreeRowReference>,
+    filter: &'a TreeModelFilter,
+    model: &'a ListStore,
+}
+
+impl Songs for Shown<'_> {
+    type Row = TreeRowReference;
+
+    fn current(&self) -> Option<TreeRowReference> {
+        self.current.filter(|row| row.valid()).cloned()
+    }
+
+    fn count(&self) -> usize {
+        self.filter.iter_n_children(None) as usize
+    }
+
+    fn position(&self, row: &TreeRowReference) -> Option<usize> {
+        let path = self.filter.convert_child_path_to_path(&row.get_path()?)?;
+        path.get_indices().first().map(|&index| index as usize)
+    }
+
+    fn row(&self, position: usize) -> Option<TreeRowReference> {
+        let iter = self.filter.iter_nth_child(None, position as i32)?;
+        let iter = self.filter.convert_iter_to_child_iter(&iter);
+        TreeRowReference::new(self.model, &self.model.get_path(&iter)?)
+    }
+
+    fn is_listed(&self, row: &TreeRowReference) -> bool {
+        row.valid()
+    }
+
+    fn path(&self, row: &TreeRowReference) -> Option<String> {
+        let iter = self.model.get_iter(&row.get_path()?)?;
+        self.model
+            .get_value(&iter, PATH_COLUMN as i32)
+            .get::<String>()
+    }
+
+    fn after(&self, row: &TreeRowReference) -> Option<TreeRowReference> {
+        let iter = self.model.get_iter(&row.get_path()?)?;
+        while self.model.iter_next(&iter) {
+            if self.filter.convert_child_iter_to_iter(&iter).is_some() {
+                return TreeRowReference::new(self.model, &self.model.get_path(&iter)?);
             }
-            pixbuf_loader.close().unwrap();
+        }
+        None
+    }
+}
+
+impl Rows for Playlist {
+    type Row = TreeRowReference;
+
+    fn listing(&mut self) -> &mut Listing<TreeRowReference> {
+        &mut self.model.listing
+    }
+
+    fn pool(&self) -> &Pool {
+        &self.model.shared.pool
+    }
+
+    fn library(&self) -> &Library {
+        &self.model.shared.library
+    }
+
+    fn insert_row(&mut self, track: &Track, position: Option<usize>) -> Option<TreeRowReference> {
+        let row = self.new_row(position);
+        self.fill_row(&row, track);
+        self.row_reference(&row)
+    }
+
+    /// The row only shows the file name until the song has been read.
+    fn insert_unread(&mut self, path: &Path, position: Option<usize>) -> Option<TreeRowReference> {
+        let row = self.new_row(position);
+        let title = path.file_stem().unwrap_or_default().to_string_lossy();
+        let path_value = path.to_string_lossy();
+        self.model
+            .model
+            .set_value(&row, TITLE_COLUMN, &title.to_value());
+        self.model
+            .model
+            .set_value(&row, PATH_COLUMN, &path_value.to_value());
+        self.row_reference(&row)
+    }
+
+    fn show_row(&mut self, row: &TreeRowReference, track: &Track) {
+        if let Some(iter) = self.row_iter(row) {
+            self.fill_row(&iter, track);
+        }
+    }
+
+    fn show_duration(&mut self, row: &TreeRowReference, duration: u64) {
+        if let Some(iter) = self.row_iter(row) {
+            let size = self
+                .row_path(&iter)
+                .and_then(|path| fs::metadata(path).ok())
+                .map_or(0, |metadata| metadata.len());
+            self.set_duration(&iter, duration, size);
+        }
+    }
+
+    fn remove_row(&mut self, row: &TreeRowReference) {
+        if let Some(iter) = self.row_iter(row) {
+            self.model.model.remove(&iter);
+        }
+    }
+
+    fn row_position(&self, row: &TreeRowReference) -> Option<usize> {
+        let path = row.get_path()?;
+        path.get_indices().first().map(|&index| index as usize)
+    }
+
+    fn is_playing(&self, row: &TreeRowReference) -> bool {
+        let current = self.model.current_row.as_ref();
+        self.model.current_song.is_some()
+            && row.get_path().is_some()
+            && current.and_then(|current| current.get_path()) == row.get_path()
+    }
+}
+
+/// Maps a query field onto the store column holding it.
+fn field_column(field: &str) -> Option<u32> {
+    let column = match field {
+        "title" => TITLE_COLUMN,
+        "artist" => ARTIST_COLUMN,
+        "album" => ALBUM_COLUMN,
+        "genre" => GENRE_COLUMN,
+        "year" => YEAR_COLUMN,
+        "track" => TRACK_COLUMN,
+        "disc" => DISC_COLUMN,
+        "composer" => COMPOSER_COLUMN,
+        "performer" => PERFORMER_COLUMN,
+        "duration" => DURATION_COLUMN,
+        "format" => FORMAT_COLUMN,
+        "path" => PATH_COLUMN,
+        "playcount" => PLAY_COUNT_COLUMN,
+        "rating" => RATING_COLUMN,
+        "skipcount" => SKIP_COUNT_COLUMN,
+        _ => return None,
+    };
+    Some(column)
+}
+
+/// Emits `Persist` once `SAVE_DELAY` has passed, unless it is already due.
+fn schedule_save(pending: &Rc<Cell<bool>>, stream: &EventStream<Msg>) {
+    if !pending.replace(true) {
+        relm::timeout(stream, SAVE_DELAY, || Persist);
+    }
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn format_sample_rate(rate: u32) -> String {
+    format!("{:.1} kHz", f64::from(rate) / 1000.0).replace(".0 ", " ")
+}
+
+/// Opens the column menu on right click of a column header.
+fn header_click(index: usize, event: &EventButton) -> (Option<Msg>, Inhibit) {
+    if event.get_button() != RIGHT_BUTTON {
+        return (None, Inhibit(false));
+    }
+    (
+        Some(ShowHeaderMenu(index, event.get_button(), event.get_time())),
+        Inhibit(true),
+    )
+}
+
+/// Translates a drop on the view into a message, inserting before the row
+/// under the pointer or appending when dropped below the last row.
+fn drop_received(view: &TreeView, x: i32, y: i32, data: &SelectionData, info: u32) -> Option<Msg> {
+    let position = view.get_dest_row_at_pos(x, y).and_then(|(path, position)| {
+        let index = *path?.get_indices().first()?;
+        match position {
+            TreeViewDropPosition::After | TreeViewDropPosition::IntoOrAfter => Some(index + 1),
+            _ => Some(index),
+        }
+    });
+
+    match info {
+        ROW_INFO => Some(MoveSelectionBefore(position)),
+        URI_INFO => {
+            let paths = data
+                .get_uris()
+                .iter()
+                .filter_map(|uri| glib::filename_from_uri(uri).ok())
+                .map(|(path, _)| path)
+                .collect();
+            Some(DropPaths(paths, position))
+        }
+        _ => None,
+    }
+}
+
+/// Opens the context menu on right click. Clicking a row outside the current
+/// selection selects only that row; clicking inside it keeps the selection so
+/// bulk operations apply to every selected row.
+fn context_click(view: &TreeView, event: &EventButton) -> (Option<Msg>, Inhibit) {
+    if event.get_button() != RIGHT_BUTTON {
+        return (None, Inhibit(false));
+    }
+
+    let (x, y) = event.get_position();
+    if let Some((Some(path), _, _, _)) = view.get_path_at_pos(x as i32, y as i32) {
+        let selection = view.get_selection();
+        if !selection.path_is_selected(&path) {
+            selection.unselect_all();
+            selection.select_path(&path);
         }
     }
+    (
+        Some(ShowMenu(event.get_button(), event.get_time())),
+        Inhibit(true),
+    )
 }
@@ -0,0 +1,701 @@
+use roxmltree::Document;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+
+use crate::library::Track;
+use crate::tags::Tags;
+
+/// Extensions of the playlist files that can be opened and saved.
+pub const EXTENSIONS: [&str; 4] = ["m3u", "m3u8", "pls", "xspf"];
+
+const XSPF_NAMESPACE: &str = "http://xspf.org/ns/0/";
+
+/// Characters left as they are in the URLs of XSPF playlists.
+const URL_SAFE: &[u8] = b"-._~/";
+
+enum Format {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Format::M3u),
+            "pls" => Some(Format::Pls),
+            "xspf" => Some(Format::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// An entry of a playlist file.
+#[derive(Default)]
+pub struct Entry {
+    /// The location of the song as written in the playlist.
+    pub location: String,
+    /// The file the entry refers to, or `None` for URLs that are not local.
+    pub path: Option<PathBuf>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// The duration of the song in seconds.
+    pub duration: Option<u64>,
+}
+
+impl Entry {
+    /// Returns a track made of what the playlist says about the song, for
+    /// songs that cannot be found.
+    pub fn placeholder(&self) -> Track {
+        let tags = Tags {
+            title: self.title.clone(),
+            artists: self.artist.iter().cloned().collect(),
+            album: self.album.clone(),
+            ..Tags::default()
+        };
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => PathBuf::from(&self.location),
+        };
+        Track {
+            tags,
+            duration: self.duration,
+            ..Track::unread(&path)
+        }
+    }
+
+    /// Sets the title and artist from a name such as "Artist - Title", which
+    /// is how M3U and PLS playlists show songs.
+    fn set_name(&mut self, name: &str) {
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        match name.split_once(" - ") {
+            Some((artist, title)) => {
+                self.artist = Some(artist.trim().to_string());
+                self.title = Some(title.trim().to_string());
+            }
+            None => self.title = Some(name.to_string()),
+        }
+    }
+
+    fn name(&self) -> String {
+        let title = self.title.as_deref().unwrap_or_default();
+        let name = match &self.artist {
+            Some(artist) => format!("{} - {}", artist, title),
+            None => title.to_string(),
+        };
+        // Line breaks would end the entry early.
+        name.replace(['\n', '\r'], " ")
+    }
+
+    /// Returns the path to write for the entry, relative to `dir` when
+    /// `relative` is set, or `None` for URLs.
+    fn file(&self, dir: &Path, relative: bool) -> Option<PathBuf> {
+        let file = self.path.as_ref()?;
+        if relative {
+            relative_path(file, dir).or_else(|| Some(file.clone()))
+        } else {
+            Some(file.clone())
+        }
+    }
+}
+
+/// Returns whether `path` is a playlist file that can be opened.
+pub fn is_playlist(path: &Path) -> bool {
+    Format::of(path).is_some()
+}
+
+/// Reads the entries of the playlist file at `path`. Relative locations are
+/// resolved against the folder of the playlist.
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let format = Format::of(path)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Not a playlist file"))?;
+    let text = decode(&fs::read(path)?);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    match format {
+        Format::M3u => Ok(read_m3u(&text, dir)),
+        Format::Pls => Ok(read_pls(&text, dir)),
+        Format::Xspf => read_xspf(&text, dir),
+    }
+}
+
+/// Writes `entries` to the playlist file at `path`, in the format its
+/// extension names and in UTF-8. With `relative`, songs are listed by their
+/// path from the folder of the playlist, so that it can be moved along with
+/// them.
+pub fn write(path: &Path, entries: &[Entry], relative: bool) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let text = match Format::of(path) {
+        Some(Format::Pls) => write_pls(entries, dir, relative),
+        Some(Format::Xspf) => write_xspf(entries, dir, relative),
+        Some(Format::M3u) | None => write_m3u(entries, dir, relative),
+    };
+    let mut file = fs::File::create(path)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()
+}
+
+/// Decodes a playlist or a CUE sheet as UTF-8, which `.m3u8` and XSPF files
+/// are always written in, or as Latin-1, which older `.m3u`, PLS and CUE
+/// files often are.
+pub fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+/// Reads a plain or extended M3U playlist. The `#EXTINF` line preceding an
+/// entry gives its duration and name.
+fn read_m3u(text: &str, dir: &Path) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut info = Entry::default();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = Entry::default();
+            // The duration may be followed by attributes.
+            let (duration, name) = extinf.split_once(',').unwrap_or((extinf, ""));
+            info.duration = duration.split_whitespace().next().and_then(parse_duration);
+            info.set_name(name);
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut entry = mem::take(&mut info);
+        entry.location = line.to_string();
+        entry.path = resolve(line, dir);
+        entries.push(entry);
+    }
+    entries
+}
+
+fn write_m3u(entries: &[Entry], dir: &Path, relative: bool) -> String {
+    let mut text = String::from("#EXTM3U\n");
+    for entry in entries {
+        let location = match entry.file(dir, relative) {
+            Some(file) => file.to_string_lossy().to_string(),
+            None => entry.location.clone(),
+        };
+        text.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            format_duration(entry.duration),
+            entry.name(),
+            location
+        ));
+    }
+    text
+}
+
+/// Reads a PLS playlist, made of numbered `FileN`, `TitleN` and `LengthN`
+/// keys.
+fn read_pls(text: &str, dir: &Path) -> Vec<Entry> {
+    let mut entries: Vec<(u32, Entry)> = Vec::new();
+    for line in text.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let number = match key[split..].parse::<u32>() {
+            Ok(number) => number,
+            Err(_) => continue,
+        };
+        let index = match entries.iter().position(|(n, _)| *n == number) {
+            Some(index) => index,
+            None => {
+                entries.push((number, Entry::default()));
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[index].1;
+        match &key[..split] {
+            "file" => {
+                entry.location = value.to_string();
+                entry.path = resolve(value, dir);
+            }
+            "title" => entry.set_name(value),
+            "length" => entry.duration = parse_duration(value),
+            _ => (),
+        }
+    }
+    entries.sort_by_key(|(number, _)| *number);
+    entries
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| !entry.location.is_empty())
+        .collect()
+}
+
+fn write_pls(entries: &[Entry], dir: &Path, relative: bool) -> String {
+    let mut text = String::from("[playlist]\n");
+    for (index, entry) in entries.iter().enumerate() {
+        let number = index + 1;
+        let location = match entry.file(dir, relative) {
+            Some(file) => file.to_string_lossy().to_string(),
+            None => entry.location.clone(),
+        };
+        text.push_str(&format!("File{}={}\n", number, location));
+        text.push_str(&format!("Title{}={}\n", number, entry.name()));
+        text.push_str(&format!(
+            "Length{}={}\n",
+            number,
+            format_duration(entry.duration)
+        ));
+    }
+    text.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    text
+}
+
+/// Reads an XSPF playlist, whose locations are URLs and durations are in
+/// milliseconds.
+fn read_xspf(text: &str, dir: &Path) -> io::Result<Vec<Entry>> {
+    let document =
+        Document::parse(text).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?;
+    let entries = document
+        .descendants()
+        .filter(|node| node.has_tag_name("track"))
+        .filter_map(|track| {
+            let child = |name: &str| {
+                track
+                    .children()
+                    .find(|child| child.has_tag_name(name))
+                    .and_then(|child| child.text())
+                    .map(|text| text.trim().to_string())
+                    .filter(|text| !text.is_empty())
+            };
+            let location = child("location")?;
+            Some(Entry {
+                path: resolve_url(&location, dir),
+                location,
+                title: child("title"),
+                artist: child("creator"),
+                album: child("album"),
+                duration: child("duration")
+                    .and_then(|duration| duration.parse::<u64>().ok())
+                    .map(|duration| (duration + 500) / 1000),
+            })
+        })
+        .collect();
+    Ok(entries)
+}
+
+fn write_xspf(entries: &[Entry], dir: &Path, relative: bool) -> String {
+    let mut text = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"{}\">\n  <trackList>\n",
+        XSPF_NAMESPACE
+    );
+    for entry in entries {
+        let location = match entry.file(dir, relative) {
+            Some(file) if file.is_absolute() => {
+                format!("file://{}", percent_encode(&file.to_string_lossy()))
+            }
+            Some(file) => percent_encode(&file.to_string_lossy()),
+            None => entry.location.clone(),
+        };
+        text.push_str("    <track>\n");
+        let fields = [
+            ("location", Some(&location)),
+            ("title", entry.title.as_ref()),
+            ("creator", entry.artist.as_ref()),
+            ("album", entry.album.as_ref()),
+        ];
+        for (name, value) in fields.iter() {
+            if let Some(value) = value {
+                text.push_str(&format!("      <{0}>{1}</{0}>\n", name, escape_xml(value)));
+            }
+        }
+        if let Some(duration) = entry.duration {
+            text.push_str(&format!("      <duration>{}</duration>\n", duration * 1000));
+        }
+        text.push_str("    </track>\n");
+    }
+    text.push_str("  </trackList>\n</playlist>\n");
+    text
+}
+
+/// Parses a duration in seconds, which is -1 when unknown.
+fn parse_duration(duration: &str) -> Option<u64> {
+    duration
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|duration| *duration >= 0.0)
+        .map(|duration| duration.round() as u64)
+}
+
+fn format_duration(duration: Option<u64>) -> String {
+    duration.map_or_else(|| "-1".to_string(), |duration| duration.to_string())
+}
+
+/// Returns the file a location refers to, resolving relative paths against
+/// `dir`. Returns `None` for URLs other than `file://` ones.
+pub fn resolve(location: &str, dir: &Path) -> Option<PathBuf> {
+    let path = if location.contains("://") {
+        return resolve_url(location, dir);
+    } else if location.contains('\\') && !location.contains('/') {
+        // Written on Windows.
+        PathBuf::from(location.replace('\\', "/"))
+    } else {
+        PathBuf::from(location)
+    };
+    Some(normalize(&dir.join(path)))
+}
+
+/// Returns the file a URL refers to, resolving relative URLs against `dir`.
+/// Returns `None` for URLs other than `file://` ones.
+fn resolve_url(url: &str, dir: &Path) -> Option<PathBuf> {
+    let path = if let Some(url) = url.strip_prefix("file://") {
+        // The host, usually empty or "localhost", comes before the path.
+        PathBuf::from(percent_decode(&url[url.find('/')?..]))
+    } else if url.contains("://") {
+        return None;
+    } else {
+        PathBuf::from(percent_decode(url))
+    };
+    Some(normalize(&dir.join(path)))
+}
+
+/// Removes the `.` and `..` components of `path`, so that songs have the same
+/// path whichever playlist they were listed in.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Returns the path of `file` from `dir`, both being absolute.
+fn relative_path(file: &Path, dir: &Path) -> Option<PathBuf> {
+    if !file.is_absolute() || !dir.is_absolute() {
+        return None;
+    }
+    let file: Vec<Component> = file.components().collect();
+    let dir: Vec<Component> = dir.components().collect();
+    let common = file
+        .iter()
+        .zip(dir.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // Paths on different drives have nothing in common.
+    if common == 0 {
+        return None;
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for component in &file[common..] {
+        relative.push(component);
+    }
+    Some(relative)
+}
+
+/// Decodes the `%XX` escapes of a URL.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Escapes the characters of a path that cannot appear as they are in a URL.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || URL_SAFE.contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(entries: &[Entry]) -> Vec<Option<PathBuf>> {
+        entries.iter().map(|entry| entry.path.clone()).collect()
+    }
+
+    #[test]
+    fn plain_m3u_lists_locations() {
+        let text = "# A comment\n\nsong.flac\n  /music/other.flac  \n../up/third.flac\n";
+        let entries = read_m3u(text, Path::new("/music/lists"));
+        assert_eq!(
+            paths(&entries),
+            vec![
+                Some(PathBuf::from("/music/lists/song.flac")),
+                Some(PathBuf::from("/music/other.flac")),
+                Some(PathBuf::from("/music/up/third.flac")),
+            ]
+        );
+        assert_eq!(entries[0].location, "song.flac");
+        assert_eq!(entries[0].title, None);
+        assert_eq!(entries[0].duration, None);
+    }
+
+    #[test]
+    fn extended_m3u_names_the_next_entry() {
+        let text = "#EXTM3U\n\
+                    #EXTINF:331,Miles Davis - So What\n\
+                    so what.flac\n\
+                    #EXTINF:-1 tvg-id=\"x\",Untitled\n\
+                    untitled.flac\n\
+                    plain.flac\n";
+        let entries = read_m3u(text, Path::new("/music"));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(entries[0].title.as_deref(), Some("So What"));
+        assert_eq!(entries[0].duration, Some(331));
+        assert_eq!(entries[1].artist, None);
+        assert_eq!(entries[1].title.as_deref(), Some("Untitled"));
+        assert_eq!(entries[1].duration, None);
+        // The information of an entry does not carry over to the next one.
+        assert_eq!(entries[2].title, None);
+    }
+
+    #[test]
+    fn m3u_locations_may_be_urls_or_windows_paths() {
+        let text = "http://radio.example/stream\nfile:///music/a%20b.flac\nAlbum\\01.flac\n";
+        let entries = read_m3u(text, Path::new("/music"));
+        assert_eq!(
+            paths(&entries),
+            vec![
+                None,
+                Some(PathBuf::from("/music/a b.flac")),
+                Some(PathBuf::from("/music/Album/01.flac")),
+            ]
+        );
+        assert_eq!(entries[0].location, "http://radio.example/stream");
+    }
+
+    #[test]
+    fn decode_falls_back_to_latin_1() {
+        assert_eq!(decode(b"\xEF\xBB\xBFcaf\xC3\xA9.flac"), "café.flac");
+        assert_eq!(decode(b"caf\xE9.flac"), "café.flac");
+    }
+
+    #[test]
+    fn normalize_drops_dot_components() {
+        assert_eq!(
+            normalize(Path::new("/music/./a/../b/c.flac")),
+            PathBuf::from("/music/b/c.flac")
+        );
+        assert_eq!(normalize(Path::new("/../a.flac")), PathBuf::from("/a.flac"));
+    }
+
+    fn song(path: &str, title: &str, artist: Option<&str>, duration: Option<u64>) -> Entry {
+        Entry {
+            location: path.to_string(),
+            path: Some(PathBuf::from(path)),
+            title: Some(title.to_string()),
+            artist: artist.map(str::to_string),
+            album: None,
+            duration,
+        }
+    }
+
+    #[test]
+    fn m3u_export_writes_extinf_lines() {
+        let entries = [
+            song(
+                "/music/a/so what.flac",
+                "So What",
+                Some("Miles Davis"),
+                Some(562),
+            ),
+            song("/music/b.flac", "Line\nbreak", None, None),
+        ];
+        let text = write_m3u(&entries, Path::new("/lists"), false);
+        assert_eq!(
+            text,
+            "#EXTM3U\n\
+             #EXTINF:562,Miles Davis - So What\n/music/a/so what.flac\n\
+             #EXTINF:-1,Line break\n/music/b.flac\n"
+        );
+    }
+
+    #[test]
+    fn m3u_export_can_be_relative() {
+        let entries = [
+            song("/music/a/one.flac", "One", None, Some(1)),
+            song("/music/lists/two.flac", "Two", None, Some(2)),
+            song("/elsewhere/three.flac", "Three", None, Some(3)),
+        ];
+        let text = write_m3u(&entries, Path::new("/music/lists"), true);
+        let read = read_m3u(&text, Path::new("/music/lists"));
+        let locations: Vec<&str> = read.iter().map(|entry| entry.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            vec!["../a/one.flac", "two.flac", "../../elsewhere/three.flac"]
+        );
+        assert_eq!(paths(&read), paths(&entries));
+    }
+
+    #[test]
+    fn relative_path_walks_up_to_the_common_folder() {
+        let relative = |file, dir| relative_path(Path::new(file), Path::new(dir));
+        assert_eq!(
+            relative("/music/a/b.flac", "/music/a"),
+            Some(PathBuf::from("b.flac"))
+        );
+        assert_eq!(
+            relative("/music/a/b.flac", "/music/c/d"),
+            Some(PathBuf::from("../../a/b.flac"))
+        );
+        assert_eq!(
+            relative("/music/a.flac", "/"),
+            Some(PathBuf::from("music/a.flac"))
+        );
+        assert_eq!(relative("music/a.flac", "/music"), None);
+        assert_eq!(relative("/music/a.flac", "lists"), None);
+    }
+
+    #[test]
+    fn pls_entries_are_ordered_by_number() {
+        let text = "[playlist]\n\
+                    File2=two.flac\n\
+                    Title2=Two\n\
+                    file1 = /music/one.flac\n\
+                    Title1=Artist - One\n\
+                    Length1=61\n\
+                    Length2=-1\n\
+                    Title3=No file\n\
+                    NumberOfEntries=2\n\
+                    Version=2\n";
+        let entries = read_pls(text, Path::new("/music/lists"));
+        assert_eq!(
+            paths(&entries),
+            vec![
+                Some(PathBuf::from("/music/one.flac")),
+                Some(PathBuf::from("/music/lists/two.flac")),
+            ]
+        );
+        assert_eq!(entries[0].artist.as_deref(), Some("Artist"));
+        assert_eq!(entries[0].title.as_deref(), Some("One"));
+        assert_eq!(entries[0].duration, Some(61));
+        assert_eq!(entries[1].duration, None);
+    }
+
+    #[test]
+    fn pls_round_trips() {
+        let entries = [
+            song("/music/a.flac", "One", Some("Artist"), Some(61)),
+            song("/music/b.flac", "Two", None, None),
+        ];
+        let text = write_pls(&entries, Path::new("/music"), true);
+        assert!(text.starts_with("[playlist]\nFile1=a.flac\nTitle1=Artist - One\nLength1=61\n"));
+        assert!(text.ends_with("NumberOfEntries=2\nVersion=2\n"));
+        let read = read_pls(&text, Path::new("/music"));
+        assert_eq!(paths(&read), paths(&entries));
+        assert_eq!(read[1].title.as_deref(), Some("Two"));
+        assert_eq!(read[1].duration, None);
+    }
+
+    #[test]
+    fn xspf_round_trips() {
+        let mut remote = song("http://radio.example/live", "Live & <loud>", None, None);
+        remote.path = None;
+        remote.album = Some("Album \"1\"".to_string());
+        let entries = [
+            song("/music/a b/ü.flac", "One", Some("Artist"), Some(61)),
+            song("/music/lists/two.flac", "Two", None, None),
+            remote,
+        ];
+        let text = write_xspf(&entries, Path::new("/music/lists"), false);
+        assert!(text.contains("<location>file:///music/a%20b/%C3%BC.flac</location>"));
+        assert!(text.contains("<title>Live &amp; &lt;loud&gt;</title>"));
+        assert!(text.contains("<duration>61000</duration>"));
+
+        let read = read_xspf(&text, Path::new("/music/lists")).unwrap();
+        assert_eq!(paths(&read), paths(&entries));
+        assert_eq!(read[0].artist.as_deref(), Some("Artist"));
+        assert_eq!(read[0].duration, Some(61));
+        assert_eq!(read[2].location, "http://radio.example/live");
+        assert_eq!(read[2].title.as_deref(), Some("Live & <loud>"));
+        assert_eq!(read[2].album.as_deref(), Some("Album \"1\""));
+
+        let relative = write_xspf(&entries, Path::new("/music/lists"), true);
+        assert!(relative.contains("<location>../a%20b/%C3%BC.flac</location>"));
+        assert!(relative.contains("<location>two.flac</location>"));
+        let read = read_xspf(&relative, Path::new("/music/lists")).unwrap();
+        assert_eq!(paths(&read), paths(&entries));
+    }
+
+    #[test]
+    fn xspf_skips_tracks_without_a_location() {
+        let text = "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\"><trackList>\
+                    <track><title>Nowhere</title></track>\
+                    <track><location>file://localhost/music/a.flac</location>\
+                    <duration>1499</duration></track>\
+                    </trackList></playlist>";
+        let entries = read_xspf(text, Path::new("/")).unwrap();
+        assert_eq!(paths(&entries), vec![Some(PathBuf::from("/music/a.flac"))]);
+        assert_eq!(entries[0].duration, Some(1));
+        assert!(read_xspf("<playlist><track></playlist>", Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("%C3%BC"), "ü");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn percent_encode_round_trips() {
+        let path = "/music/AC/DC & Co – ü?#%.flac";
+        let encoded = percent_encode(path);
+        assert!(encoded.starts_with("/music/AC/DC%20%26%20Co%20"));
+        assert!(encoded.is_ascii());
+        assert_eq!(percent_decode(&encoded), path);
+    }
+}
@@ -0,0 +1,294 @@
+use std::cmp::Ordering;
+
+use crate::columns::natural_cmp;
+
+/// Fields that can be named in a query as `field:value`. `added` and `played`
+/// count the days since a song was added to the library and last played, so
+/// that `added:<30` matches the songs added in the last month.
+pub const FIELDS: [&str; 17] = [
+    "title",
+    "artist",
+    "album",
+    "genre",
+    "year",
+    "track",
+    "disc",
+    "composer",
+    "performer",
+    "duration",
+    "format",
+    "path",
+    "playcount",
+    "rating",
+    "skipcount",
+    "added",
+    "played",
+];
+
+/// Fields searched by terms that do not name a field.
+const TEXT_FIELDS: [&str; 7] = [
+    "title",
+    "artist",
+    "album",
+    "genre",
+    "year",
+    "composer",
+    "performer",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Contains,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+struct Term {
+    field: Option<&'static str>,
+    negated: bool,
+    op: Op,
+    value: String,
+}
+
+/// A search over track fields. Whitespace separated terms must all match:
+/// free text matches any tag, `field:value` matches a substring of that field,
+/// `field:>value` (or `<`, `>=`, `<=`, `=`) compares it, treating numbers
+/// numerically, and a leading `-` negates a term. Values containing spaces can
+/// be quoted, e.g. `artist:"miles davis" year:<1970 -genre:jazz`.
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Query {
+        let terms = tokenize(text)
+            .into_iter()
+            .filter_map(|token| parse_term(&token))
+            .collect();
+        Query { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Tests a track, looking its fields up through `field`.
+    pub fn matches<F: Fn(&str) -> String>(&self, field: F) -> bool {
+        self.terms.iter().all(|term| {
+            let matched = match term.field {
+                Some(name) => compare(&field(name), term.op, &term.value),
+                None => TEXT_FIELDS
+                    .iter()
+                    .any(|name| compare(&field(name), term.op, &term.value)),
+            };
+            matched != term.negated
+        })
+    }
+}
+
+fn compare(actual: &str, op: Op, expected: &str) -> bool {
+    let actual = actual.to_lowercase();
+    if op == Op::Contains {
+        return actual.contains(expected);
+    }
+    if actual.is_empty() {
+        return false;
+    }
+    // Dates such as "1994-05-02" and tracks such as "3 / 12" compare by their
+    // leading number when the query value is a number.
+    let ordering = match (leading_number(&actual), expected.parse::<f64>()) {
+        (Some(actual), Ok(expected)) => actual.partial_cmp(&expected).unwrap_or(Ordering::Equal),
+        _ => natural_cmp(&actual, expected),
+    };
+    match op {
+        Op::Equal => ordering == Ordering::Equal,
+        Op::Greater => ordering == Ordering::Greater,
+        Op::GreaterEqual => ordering != Ordering::Less,
+        Op::Less => ordering == Ordering::Less,
+        Op::LessEqual => ordering != Ordering::Greater,
+        Op::Contains => unreachable!(),
+    }
+}
+
+fn leading_number(value: &str) -> Option<f64> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+/// Splits on whitespace outside of double quotes, dropping the quotes.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(token.clone());
+                    token.clear();
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn parse_term(token: &str) -> Option<Term> {
+    let (negated, token) = match token.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, token),
+    };
+
+    let (field, value) = match token.find(':') {
+        Some(colon) => {
+            let name = token[..colon].to_lowercase();
+            match FIELDS.iter().find(|field| **field == name) {
+                Some(field) => (Some(*field), &token[colon + 1..]),
+                None => (None, token),
+            }
+        }
+        None => (None, token),
+    };
+
+    let (op, value) = if field.is_none() {
+        (Op::Contains, value)
+    } else if let Some(value) = value.strip_prefix(">=") {
+        (Op::GreaterEqual, value)
+    } else if let Some(value) = value.strip_prefix("<=") {
+        (Op::LessEqual, value)
+    } else if let Some(value) = value.strip_prefix('>') {
+        (Op::Greater, value)
+    } else if let Some(value) = value.strip_prefix('<') {
+        (Op::Less, value)
+    } else if let Some(value) = value.strip_prefix('=') {
+        (Op::Equal, value)
+    } else {
+        (Op::Contains, value)
+    };
+
+    if value.is_empty() {
+        return None;
+    }
+    Some(Term {
+        field,
+        negated,
+        op,
+        value: value.to_lowercase(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+
+    fn song(field: &str) -> String {
+        match field {
+            "title" => "So What",
+            "artist" => "Miles Davis",
+            "album" => "Kind of Blue",
+            "genre" => "Jazz",
+            "year" => "1959-08-17",
+            "track" => "1 / 5",
+            "playcount" => "12",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    fn matches(query: &str) -> bool {
+        Query::parse(query).matches(song)
+    }
+
+    #[test]
+    fn free_text_searches_the_text_fields() {
+        assert!(matches("miles"));
+        assert!(matches("KIND blue"));
+        assert!(matches("1959"));
+        assert!(!matches("coltrane"));
+        // The track number is not a text field.
+        assert!(!matches("/"));
+    }
+
+    #[test]
+    fn fields_match_substrings() {
+        assert!(matches("artist:davis"));
+        assert!(matches("ARTIST:Davis"));
+        assert!(!matches("album:davis"));
+    }
+
+    #[test]
+    fn unknown_fields_are_free_text() {
+        assert!(!matches("mood:cool"));
+        assert!(matches("so"));
+    }
+
+    #[test]
+    fn numbers_compare_numerically() {
+        assert!(matches("playcount:>9"));
+        assert!(matches("playcount:>=12"));
+        assert!(matches("playcount:=12"));
+        assert!(!matches("playcount:<12"));
+        assert!(matches("playcount:<=12"));
+    }
+
+    #[test]
+    fn dates_and_tracks_compare_by_their_leading_number() {
+        assert!(matches("year:<1960"));
+        assert!(matches("year:=1959"));
+        assert!(!matches("year:>1959"));
+        assert!(matches("track:=1"));
+        assert!(matches("track:<2"));
+    }
+
+    #[test]
+    fn text_compares_naturally() {
+        assert!(matches("title:>s"));
+        assert!(matches("title:<t"));
+        assert!(matches("title:=\"so what\""));
+    }
+
+    #[test]
+    fn empty_fields_never_compare() {
+        assert!(!matches("rating:<3"));
+        assert!(!matches("rating:>=0"));
+        assert!(matches("-rating:<3"));
+    }
+
+    #[test]
+    fn terms_can_be_negated() {
+        assert!(matches("-genre:rock"));
+        assert!(!matches("-genre:jazz"));
+        assert!(!matches("-miles"));
+    }
+
+    #[test]
+    fn quoted_values_keep_their_spaces() {
+        assert!(matches("artist:\"miles davis\""));
+        assert!(!matches("artist:\"davis miles\""));
+        assert!(matches("\"kind of\" miles"));
+    }
+
+    #[test]
+    fn every_term_must_match() {
+        assert!(matches("artist:miles year:<1970 -genre:rock"));
+        assert!(!matches("artist:miles year:>1970"));
+    }
+
+    #[test]
+    fn empty_terms_are_dropped() {
+        assert!(Query::parse("").is_empty());
+        assert!(Query::parse("   ").is_empty());
+        assert!(Query::parse("artist: year:>").is_empty());
+        assert!(!Query::parse("-").is_empty());
+    }
+}
@@ -0,0 +1,433 @@
+//! Lets the command line control the player already running, through a Unix
+//! socket, so that a single instance plays.
+//!
+//! A request is sent over its own connection as fields split by `SEPARATOR`:
+//! its name, then its argument or the paths it opens. The answer is `ok` or
+//! `error`, followed by what the player said.
+
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config;
+use crate::library::Track;
+use crate::order::Repeat;
+use crate::stats::millis_to_minutes;
+
+const SOCKET_FILE: &str = "control";
+/// Locked by the player looking for another one running, until it either
+/// found one or bound the socket.
+const LOCK_FILE: &str = "control.lock";
+
+/// How long either side waits for the other.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Splits the fields of a request, as the one byte paths cannot hold.
+const SEPARATOR: u8 = 0;
+
+const OK: &str = "ok";
+const ERROR: &str = "error";
+
+/// What the player answers, the text printed by the command line.
+pub type Reply = Result<String, String>;
+
+/// Where to move in the current song.
+#[derive(Clone, Copy)]
+pub enum Seek {
+    /// Milliseconds forward, or back when negative.
+    By(i64),
+    /// Milliseconds from the start.
+    To(u64),
+}
+
+impl Seek {
+    /// Reads `+SECONDS` and `-SECONDS` as relative, `SECONDS` and
+    /// `MINUTES:SECONDS` as absolute.
+    pub fn parse(text: &str) -> Option<Seek> {
+        if let Some(seconds) = text.strip_prefix('+') {
+            return seconds_to_millis(seconds).map(|millis| Seek::By(millis as i64));
+        }
+        if let Some(seconds) = text.strip_prefix('-') {
+            return seconds_to_millis(seconds).map(|millis| Seek::By(-(millis as i64)));
+        }
+        match text.find(':') {
+            Some(colon) => {
+                let minutes = text[..colon].parse::<u64>().ok()?;
+                let millis = seconds_to_millis(&text[colon + 1..])?;
+                Some(Seek::To(minutes * 60_000 + millis))
+            }
+            None => seconds_to_millis(text).map(Seek::To),
+        }
+    }
+
+    fn encode(self) -> String {
+        match self {
+            Seek::By(offset) => format!("{:+}", offset),
+            Seek::To(position) => position.to_string(),
+        }
+    }
+
+    fn decode(text: &str) -> Option<Seek> {
+        if text.starts_with('+') || text.starts_with('-') {
+            text.parse().ok().map(Seek::By)
+        } else {
+            text.parse().ok().map(Seek::To)
+        }
+    }
+}
+
+fn seconds_to_millis(text: &str) -> Option<u64> {
+    let seconds = text.parse::<f64>().ok()?;
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some((seconds * 1000.0).round() as u64)
+    } else {
+        None
+    }
+}
+
+pub enum Request {
+    /// Adds songs, folders and playlists to the playlist shown, playing the
+    /// first song when the flag is set.
+    Open(Vec<PathBuf>, bool),
+    /// Brings the window to the front.
+    Raise,
+    PlayPause,
+    Next,
+    Previous,
+    Stop,
+    Seek(Seek),
+    /// Asks what is playing.
+    Status,
+    Quit,
+}
+
+impl Request {
+    fn encode(&self) -> Vec<u8> {
+        let name = match self {
+            Request::Open(_, true) => "play",
+            Request::Open(_, false) => "enqueue",
+            Request::Raise => "raise",
+            Request::PlayPause => "play-pause",
+            Request::Next => "next",
+            Request::Previous => "previous",
+            Request::Stop => "stop",
+            Request::Seek(_) => "seek",
+            Request::Status => "status",
+            Request::Quit => "quit",
+        };
+        let arguments = match self {
+            Request::Open(files, _) => files
+                .iter()
+                .map(|file| file.as_os_str().as_bytes().to_vec())
+                .collect(),
+            Request::Seek(seek) => vec![seek.encode().into_bytes()],
+            _ => Vec::new(),
+        };
+        let mut bytes = name.as_bytes().to_vec();
+        for argument in arguments {
+            bytes.push(SEPARATOR);
+            bytes.extend(argument);
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Request> {
+        let mut fields = bytes.split(|&byte| byte == SEPARATOR);
+        let name = fields.next()?;
+        let mut arguments = fields.filter(|field| !field.is_empty());
+        let request = match name {
+            b"play" | b"enqueue" => {
+                let files = arguments
+                    .map(|field| PathBuf::from(OsStr::from_bytes(field)))
+                    .collect();
+                return Some(Request::Open(files, name == b"play"));
+            }
+            b"raise" => Request::Raise,
+            b"play-pause" => Request::PlayPause,
+            b"next" => Request::Next,
+            b"previous" => Request::Previous,
+            b"stop" => Request::Stop,
+            b"seek" => {
+                let argument = arguments.next()?;
+                Request::Seek(Seek::decode(std::str::from_utf8(argument).ok()?)?)
+            }
+            b"status" => Request::Status,
+            b"quit" => Request::Quit,
+            _ => return None,
+        };
+        Some(request)
+    }
+}
+
+/// What `--status` tells about the player, one `name value` line per field
+/// like the session file, so that scripts can read it.
+pub struct Report<'a> {
+    /// Either `playing`, `paused` or `stopped`.
+    pub state: &'static str,
+    pub track: Option<&'a Track>,
+    /// Where the song is, in milliseconds.
+    pub position: u64,
+    /// The length of the song, in milliseconds.
+    pub duration: u64,
+    pub volume: f64,
+    pub repeat: Repeat,
+    pub shuffle: bool,
+}
+
+impl Report<'_> {
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("status {}", self.state)];
+        if let Some(track) = self.track {
+            lines.push(format!("title {}", track.title()));
+            lines.push(format!("artist {}", track.artist()));
+            lines.push(format!("album {}", track.album()));
+            lines.push(format!("file {}", track.path));
+            lines.push(format!("position {}", millis_to_minutes(self.position)));
+            lines.push(format!("duration {}", millis_to_minutes(self.duration)));
+        }
+        lines.push(format!("volume {}", (self.volume * 100.0).round()));
+        lines.push(format!("repeat {}", self.repeat.name()));
+        lines.push(format!("shuffle {}", self.shuffle));
+        lines.join("\n") + "\n"
+    }
+}
+
+fn socket_path() -> PathBuf {
+    config::runtime_dir().join(SOCKET_FILE)
+}
+
+/// Keeps the players started at the same time from each finding no other
+/// one and binding the socket: each waits for the previous one to have
+/// bound it before looking for a player running, until the lock is dropped.
+pub struct Lock {
+    _file: Option<File>,
+}
+
+impl Lock {
+    /// Waits for the players starting to have bound the socket or given up.
+    /// Players are then told apart by the socket alone, if the lock cannot
+    /// be taken.
+    pub fn take() -> Lock {
+        let path = config::runtime_dir().join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .and_then(|file| {
+                // The lock goes with the file, closed when the player quits.
+                if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                    Ok(file)
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        match file {
+            Ok(file) => Lock { _file: Some(file) },
+            Err(error) => {
+                eprintln!("Unable to lock {}: {}", path.display(), error);
+                Lock { _file: None }
+            }
+        }
+    }
+}
+
+/// Connects to the player already running, returning `None` if there is
+/// none.
+pub fn connect() -> Option<UnixStream> {
+    UnixStream::connect(socket_path()).ok()
+}
+
+/// Sends `request` to the player at the other end of `stream` and waits for
+/// its answer.
+pub fn send(mut stream: UnixStream, request: &Request) -> io::Result<Reply> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(&request.encode())?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    let (status, text) = match answer.find('\n') {
+        Some(newline) => (&answer[..newline], answer[newline + 1..].to_string()),
+        None => (&answer[..], String::new()),
+    };
+    match status {
+        OK => Ok(Ok(text)),
+        ERROR => Ok(Err(text)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected answer",
+        )),
+    }
+}
+
+/// Answers the requests of the command line while it is kept, passing them on
+/// with the sender of their reply.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Server {
+    /// Listens on the socket of the player, returning `None` if it cannot be
+    /// bound. A socket left behind by a player that did not quit is replaced,
+    /// `connect` having found nobody listening while `lock` was held. The
+    /// lock is released once the socket is bound.
+    pub fn start(lock: Lock, requests: relm::Sender<(Request, Sender<Reply>)>) -> Option<Server> {
+        let path = socket_path();
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path);
+        drop(lock);
+        let listener = match listener {
+            Ok(listener) => listener,
+            Err(error) => {
+                eprintln!("Unable to listen on {}: {}", path.display(), error);
+                return None;
+            }
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve(stream, &requests));
+                if let Err(error) = result {
+                    eprintln!("Unable to answer the command line: {}", error);
+                }
+            }
+        });
+        Some(Server { path })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve(
+    mut stream: UnixStream,
+    requests: &relm::Sender<(Request, Sender<Reply>)>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut bytes = Vec::new();
+    stream.read_to_end(&mut bytes)?;
+    let reply = match Request::decode(&bytes) {
+        Some(request) => {
+            let (sender, receiver) = mpsc::channel();
+            // The reply is never sent if the window is gone.
+            let _ = requests.send((request, sender));
+            receiver
+                .recv_timeout(TIMEOUT)
+                .unwrap_or_else(|_| Err("The player did not answer".to_string()))
+        }
+        None => Err("Unknown request".to_string()),
+    };
+    let answer = match reply {
+        Ok(text) => format!("{}\n{}", OK, text),
+        Err(text) => format!("{}\n{}", ERROR, text),
+    };
+    stream.write_all(answer.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(request: &Request) -> Request {
+        Request::decode(&request.encode()).expect("request not decoded")
+    }
+
+    #[test]
+    fn parses_relative_seeks() {
+        assert!(matches!(Seek::parse("+10"), Some(Seek::By(10_000))));
+        assert!(matches!(Seek::parse("-2.5"), Some(Seek::By(-2_500))));
+        assert!(matches!(Seek::parse("+0"), Some(Seek::By(0))));
+    }
+
+    #[test]
+    fn parses_absolute_seeks() {
+        assert!(matches!(Seek::parse("90"), Some(Seek::To(90_000))));
+        assert!(matches!(Seek::parse("1:30"), Some(Seek::To(90_000))));
+        assert!(matches!(Seek::parse("1:02.5"), Some(Seek::To(62_500))));
+        assert!(matches!(Seek::parse("0:00"), Some(Seek::To(0))));
+        assert!(matches!(Seek::parse("0.0004"), Some(Seek::To(0))));
+    }
+
+    #[test]
+    fn rejects_bad_seeks() {
+        let bad = [
+            "", "+", "-", "abc", "1:", ":30", "1:x", "1:30:00", "1:-5", "-1:00", "+-1", "inf",
+            "NaN",
+        ];
+        for text in &bad {
+            assert!(Seek::parse(text).is_none(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn round_trips_opened_files() {
+        let files = vec![
+            PathBuf::from("/music/a b.flac"),
+            PathBuf::from(OsStr::from_bytes(b"/music/\xff.flac")),
+            PathBuf::from("relative/c.m3u"),
+            PathBuf::from("/music/two\nlines.flac"),
+        ];
+        for &play in &[true, false] {
+            match round_trip(&Request::Open(files.clone(), play)) {
+                Request::Open(decoded, decoded_play) => {
+                    assert_eq!(decoded, files);
+                    assert_eq!(decoded_play, play);
+                }
+                _ => panic!("not an open request"),
+            }
+        }
+        assert!(matches!(
+            round_trip(&Request::Open(Vec::new(), true)),
+            Request::Open(ref files, true) if files.is_empty()
+        ));
+    }
+
+    #[test]
+    fn round_trips_seeks() {
+        let seeks = [
+            (Seek::By(-1_500), "-1500"),
+            (Seek::By(0), "+0"),
+            (Seek::By(2_000), "+2000"),
+            (Seek::To(90_000), "90000"),
+        ];
+        for &(seek, encoded) in &seeks {
+            assert_eq!(seek.encode(), encoded);
+            match (round_trip(&Request::Seek(seek)), seek) {
+                (Request::Seek(Seek::By(decoded)), Seek::By(offset)) => assert_eq!(decoded, offset),
+                (Request::Seek(Seek::To(decoded)), Seek::To(position)) => {
+                    assert_eq!(decoded, position)
+                }
+                _ => panic!("{} not decoded", encoded),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_commands() {
+        assert!(matches!(round_trip(&Request::Raise), Request::Raise));
+        assert!(matches!(round_trip(&Request::PlayPause), Request::PlayPause));
+        assert!(matches!(round_trip(&Request::Next), Request::Next));
+        assert!(matches!(round_trip(&Request::Previous), Request::Previous));
+        assert!(matches!(round_trip(&Request::Stop), Request::Stop));
+        assert!(matches!(round_trip(&Request::Status), Request::Status));
+        assert!(matches!(round_trip(&Request::Quit), Request::Quit));
+    }
+
+    #[test]
+    fn rejects_unknown_requests() {
+        let unknown: [&[u8]; 6] = [b"", b"dance", b"seek", b"seek\0soon", b"Stop", b"stop\n"];
+        for bytes in &unknown {
+            assert!(Request::decode(bytes).is_none());
+        }
+    }
+}
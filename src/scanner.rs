@@ -0,0 +1,131 @@
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use relm::Sender;
+use std::collections::HashSet;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::library::{is_track, Library, Track};
+use crate::listing::collect_files;
+
+/// Number of changes sent at once while rescanning.
+const BATCH_SIZE: usize = 100;
+
+/// Delay used by the watcher to merge the events of a file being written.
+const WATCH_DELAY: Duration = Duration::from_secs(2);
+
+/// A change of the library found on disk.
+#[derive(Clone)]
+pub enum Change {
+    /// A song was added or its tags were modified.
+    Updated(Track),
+    /// A song was moved from the given path.
+    Moved(String, Track),
+    Removed(String),
+}
+
+/// Brings the library up to date with the songs below `roots` in the
+/// background, reading only files that are new or were modified since they
+/// were last stored.
+pub fn rescan(roots: Vec<PathBuf>, sender: Sender<Vec<Change>>) {
+    thread::spawn(move || {
+        let library = Library::open();
+        let mut found = HashSet::new();
+        let mut changes = Vec::new();
+        for root in &roots {
+            for path in collect_files(root)
+                .into_iter()
+                .filter(|path| is_track(path))
+            {
+                if let Some(track) = library.refresh(&path) {
+                    changes.push(Change::Updated(track));
+                }
+                found.insert(path.to_string_lossy().to_string());
+                if changes.len() >= BATCH_SIZE && sender.send(mem::take(&mut changes)).is_err() {
+                    return;
+                }
+            }
+        }
+
+        let missing: Vec<String> = library
+            .paths()
+            .into_iter()
+            .filter(|path| roots.iter().any(|root| Path::new(path).starts_with(root)))
+            .filter(|path| !found.contains(path))
+            .collect();
+        library.forget(&missing);
+        changes.extend(missing.into_iter().map(Change::Removed));
+        if !changes.is_empty() {
+            let _ = sender.send(changes);
+        }
+    });
+}
+
+/// Watches `roots` for songs being added, modified, moved or deleted, keeping
+/// the library up to date until the returned watcher is dropped.
+pub fn watch(roots: &[PathBuf], sender: Sender<Vec<Change>>) -> Option<RecommendedWatcher> {
+    let (events_sender, events) = mpsc::channel();
+    let mut watcher = match notify::watcher(events_sender, WATCH_DELAY) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            eprintln!("Unable to watch the library: {}", error);
+            return None;
+        }
+    };
+    for root in roots {
+        if let Err(error) = watcher.watch(root, RecursiveMode::Recursive) {
+            eprintln!("Unable to watch {}: {}", root.display(), error);
+        }
+    }
+
+    thread::spawn(move || {
+        let library = Library::open();
+        for event in events {
+            let changes = apply(&library, event);
+            if !changes.is_empty() && sender.send(changes).is_err() {
+                return;
+            }
+        }
+    });
+    Some(watcher)
+}
+
+fn apply(library: &Library, event: DebouncedEvent) -> Vec<Change> {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => update(library, &path),
+        DebouncedEvent::Remove(path) => library
+            .remove(&path)
+            .into_iter()
+            .map(Change::Removed)
+            .collect(),
+        DebouncedEvent::Rename(from, to) => {
+            let mut changes: Vec<Change> = library
+                .rename(&from, &to)
+                .into_iter()
+                .map(|(from, to)| Change::Moved(from, library.scan(Path::new(&to))))
+                .collect();
+            // Files moved in from outside the library, or renamed from a name
+            // that was not a song, are new to it.
+            changes.extend(update(library, &to));
+            changes
+        }
+        DebouncedEvent::Error(error, path) => {
+            let path = path.map(|path| path.display().to_string());
+            eprintln!("Error watching {}: {}", path.unwrap_or_default(), error);
+            Vec::new()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Refreshes the songs at or below `path`.
+fn update(library: &Library, path: &Path) -> Vec<Change> {
+    collect_files(path)
+        .into_iter()
+        .filter(|path| is_track(path))
+        .filter_map(|path| library.refresh(&path))
+        .map(Change::Updated)
+        .collect()
+}
@@ -0,0 +1,586 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::config;
+use crate::library::Track;
+
+const SETTINGS_FILE: &str = "scrobbling";
+
+/// Keeps the listens not yet submitted, one per line, across sessions.
+const QUEUE_FILE: &str = "scrobble-queue";
+
+/// Speaks the ListenBrainz API, which other services such as Maloja also
+/// offer. A local server can stand in for it, see
+/// `examples/scrobble_server.rs`.
+pub const DEFAULT_ENDPOINT: &str = "https://api.listenbrainz.org";
+const SUBMIT_PATH: &str = "/1/submit-listens";
+
+/// Songs shorter than this are never submitted, in seconds.
+const MIN_DURATION: u64 = 30;
+
+/// The most listens submitted at once.
+const BATCH_SIZE: usize = 100;
+
+/// How long queued listens wait before being submitted again after a
+/// failure.
+const RETRY_DELAY: Duration = Duration::from_secs(120);
+
+const TIMEOUT: u64 = 10_000;
+
+const CLIENT: &str = "Blue Music";
+
+/// Where and as whom listens are submitted.
+#[derive(Clone, Default)]
+pub struct Settings {
+    pub enabled: bool,
+    pub endpoint: String,
+    pub token: String,
+}
+
+impl Settings {
+    /// Reads the settings, saved as `enabled`, `endpoint` and `token` lines
+    /// each followed by their value.
+    pub fn load() -> Settings {
+        let mut settings = Settings {
+            endpoint: DEFAULT_ENDPOINT.to_string(),
+            ..Settings::default()
+        };
+        for line in config::read(SETTINGS_FILE).unwrap_or_default().lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            match key {
+                "enabled" => settings.enabled = value == "true",
+                "endpoint" if !value.is_empty() => settings.endpoint = value.to_string(),
+                "token" => settings.token = value.to_string(),
+                _ => (),
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let contents = format!(
+            "enabled {}\nendpoint {}\ntoken {}\n",
+            self.enabled, self.endpoint, self.token
+        );
+        // The token lets anyone submit listens in the user's name.
+        config::write_private(SETTINGS_FILE, &contents);
+    }
+}
+
+enum Submission {
+    PlayingNow(String),
+    Listen(String),
+}
+
+/// Submits what is playing and what was listened to from a background
+/// thread. Listens are queued on disk first, so that none is lost while the
+/// network or the service is down.
+pub struct Scrobbler {
+    sender: Option<Sender<Submission>>,
+    /// The thread submitting, which may still be at it once stopped.
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Scrobbler {
+    /// Starts submitting with the saved settings, unless scrobbling is
+    /// disabled.
+    pub fn start() -> Scrobbler {
+        Scrobbler::after(None)
+    }
+
+    /// Stops submitting and starts again with the saved settings, once the
+    /// listens being submitted have been, so that the queue is never
+    /// submitted twice at once.
+    pub fn restart(&mut self) {
+        self.sender = None;
+        *self = Scrobbler::after(self.worker.take());
+    }
+
+    fn after(previous: Option<JoinHandle<()>>) -> Scrobbler {
+        let settings = Settings::load();
+        if !settings.enabled || settings.token.is_empty() {
+            return Scrobbler {
+                sender: None,
+                worker: previous,
+            };
+        }
+        let (sender, receiver) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            if let Some(previous) = previous {
+                let _ = previous.join();
+            }
+            run(&settings, &receiver);
+        });
+        Scrobbler {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Tells the service that `track` started playing.
+    pub fn playing_now(&self, track: &Track) {
+        if let Some(metadata) = track_metadata(track) {
+            self.send(Submission::PlayingNow(format!(
+                "{{\"track_metadata\":{}}}",
+                metadata
+            )));
+        }
+    }
+
+    /// Submits a listen of `track`, which started at `started`, in seconds
+    /// since the Unix epoch.
+    pub fn listened(&self, track: &Track, started: i64) {
+        if track
+            .duration
+            .is_some_and(|duration| duration < MIN_DURATION)
+        {
+            return;
+        }
+        if let Some(metadata) = track_metadata(track) {
+            self.send(Submission::Listen(format!(
+                "{{\"listened_at\":{},\"track_metadata\":{}}}",
+                started, metadata
+            )));
+        }
+    }
+
+    fn send(&self, submission: Submission) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(submission);
+        }
+    }
+}
+
+/// Submits what comes through `receiver` until the scrobbler is dropped,
+/// retrying the queue from time to time while it holds listens.
+fn run(settings: &Settings, receiver: &Receiver<Submission>) {
+    let url = format!("{}{}", settings.endpoint.trim_end_matches('/'), SUBMIT_PATH);
+    let path = queue_path();
+    let mut queue = read_queue(&path);
+    let mut failed = !queue.is_empty() && !flush(&url, &settings.token, &mut queue, &path);
+    loop {
+        let submission = if failed {
+            receiver.recv_timeout(RETRY_DELAY)
+        } else {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match submission {
+            Ok(Submission::PlayingNow(listen)) => {
+                // Playing now notices are of no use later, so they are never
+                // queued.
+                let body = format!(
+                    "{{\"listen_type\":\"playing_now\",\"payload\":[{}]}}",
+                    listen
+                );
+                if let Err(failure) = submit(&url, &settings.token, &body) {
+                    eprintln!("Unable to submit the song playing: {}", failure.message());
+                }
+                continue;
+            }
+            Ok(Submission::Listen(listen)) => {
+                append_queue(&path, &listen);
+                queue.push(listen);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        failed = !flush(&url, &settings.token, &mut queue, &path);
+    }
+}
+
+/// Submits the queued listens in batches, dropping them from the queue, kept
+/// at `path`, as they are accepted. Returns whether the queue was emptied.
+fn flush(url: &str, token: &str, queue: &mut Vec<String>, path: &Path) -> bool {
+    while !queue.is_empty() {
+        let count = queue.len().min(BATCH_SIZE);
+        let kind = if count == 1 { "single" } else { "import" };
+        let body = format!(
+            "{{\"listen_type\":\"{}\",\"payload\":[{}]}}",
+            kind,
+            queue[..count].join(",")
+        );
+        match submit(url, token, &body) {
+            Ok(()) => (),
+            // Listens the service refuses would block the queue forever.
+            Err(Failure::Refused(error)) => {
+                eprintln!("Dropping {} listens the service refused: {}", count, error)
+            }
+            Err(Failure::Unavailable(error)) => {
+                eprintln!("Unable to submit listens, will retry: {}", error);
+                return false;
+            }
+        }
+        queue.drain(..count);
+        write_queue(path, queue);
+    }
+    true
+}
+
+enum Failure {
+    /// The service rejected the submission itself.
+    Refused(String),
+    /// The service could not be reached, is unavailable or refused the token.
+    Unavailable(String),
+}
+
+impl Failure {
+    fn message(&self) -> &str {
+        match self {
+            Failure::Refused(message) | Failure::Unavailable(message) => message,
+        }
+    }
+}
+
+fn submit(url: &str, token: &str, body: &str) -> Result<(), Failure> {
+    let response = ureq::post(url)
+        .set("Authorization", &format!("Token {}", token))
+        .set("Content-Type", "application/json")
+        .timeout_connect(TIMEOUT)
+        .timeout_read(TIMEOUT)
+        .timeout_write(TIMEOUT)
+        .send_string(body);
+    if response.ok() {
+        return Ok(());
+    }
+    // Failures to connect come as made up responses.
+    if let Some(error) = response.synthetic_error() {
+        return Err(Failure::Unavailable(error.to_string()));
+    }
+    let status = response.status();
+    let error = format!("{} {}", status, response.status_text());
+    // Bad tokens and rate limits are worth retrying, unlike bad listens.
+    if response.client_error() && status != 401 && status != 429 {
+        Err(Failure::Refused(error))
+    } else {
+        Err(Failure::Unavailable(error))
+    }
+}
+
+/// Describes `track` as ListenBrainz expects. Returns `None` for songs
+/// missing an artist or a title, which cannot be submitted.
+fn track_metadata(track: &Track) -> Option<String> {
+    let tags = &track.tags;
+    let title = tags.title.as_ref()?;
+    if tags.artists.is_empty() {
+        return None;
+    }
+    let mut metadata = vec![
+        format!("\"artist_name\":{}", quote(&track.artist())),
+        format!("\"track_name\":{}", quote(title)),
+    ];
+    if let Some(ref album) = tags.album {
+        metadata.push(format!("\"release_name\":{}", quote(album)));
+    }
+
+    let mut info = vec![
+        format!("\"media_player\":{}", quote(CLIENT)),
+        format!("\"submission_client\":{}", quote(CLIENT)),
+        format!(
+            "\"submission_client_version\":{}",
+            quote(env!("CARGO_PKG_VERSION"))
+        ),
+    ];
+    if let Some(duration) = track.duration {
+        info.push(format!("\"duration_ms\":{}", duration * 1000));
+    }
+    if let Some(number) = tags
+        .track_number
+        .as_ref()
+        .and_then(|n| n.parse::<u32>().ok())
+    {
+        info.push(format!("\"tracknumber\":{}", number));
+    }
+    let ids = &tags.musicbrainz;
+    if let Some(ref recording) = ids.recording {
+        info.push(format!("\"recording_mbid\":{}", quote(recording)));
+    }
+    if let Some(ref release) = ids.release {
+        info.push(format!("\"release_mbid\":{}", quote(release)));
+    }
+    if !ids.artists.is_empty() {
+        let artists: Vec<String> = ids.artists.iter().map(|id| quote(id)).collect();
+        info.push(format!("\"artist_mbids\":[{}]", artists.join(",")));
+    }
+    metadata.push(format!("\"additional_info\":{{{}}}", info.join(",")));
+    Some(format!("{{{}}}", metadata.join(",")))
+}
+
+/// Writes `text` as a JSON string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn queue_path() -> PathBuf {
+    config::data_dir().join(QUEUE_FILE)
+}
+
+fn read_queue(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|contents| {
+            contents
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn append_queue(path: &Path, listen: &str) {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", listen));
+    if let Err(error) = result {
+        eprintln!("Unable to queue listen in {}: {}", path.display(), error);
+    }
+}
+
+fn write_queue(path: &Path, queue: &[String]) {
+    let mut contents = queue.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    if let Err(error) = fs::write(path, contents) {
+        eprintln!("Unable to save {}: {}", path.display(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::Tags;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    fn track(tags: Tags, duration: Option<u64>) -> Track {
+        Track {
+            path: "/music/so what.flac".to_string(),
+            tags,
+            sample_rate: None,
+            duration,
+            size: 0,
+            mtime: 0,
+            artwork: None,
+            play_count: 0,
+            added: 0,
+            skip_count: 0,
+            last_played: None,
+            rating: None,
+            cue_sheet: false,
+        }
+    }
+
+    fn song() -> Tags {
+        Tags {
+            title: Some("So What".to_string()),
+            artists: vec!["Miles Davis".to_string()],
+            ..Tags::default()
+        }
+    }
+
+    /// Returns a file in a folder of its own, removed beforehand.
+    fn queue_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blue-music-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(QUEUE_FILE)
+    }
+
+    /// Answers requests with the given statuses, one per request, keeping the
+    /// bodies received. Returns the URL served and the bodies.
+    fn serve(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}{}", listener.local_addr().unwrap(), SUBMIT_PATH);
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        (url, bodies)
+    }
+
+    fn listens(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|index| format!("{{\"listened_at\":{}}}", index))
+            .collect()
+    }
+
+    #[test]
+    fn describes_songs() {
+        let tags = Tags {
+            album: Some("Kind of Blue".to_string()),
+            track_number: Some("1".to_string()),
+            ..song()
+        };
+        assert_eq!(
+            track_metadata(&track(tags, Some(562))).unwrap(),
+            format!(
+                "{{\"artist_name\":\"Miles Davis\",\"track_name\":\"So What\",\
+                 \"release_name\":\"Kind of Blue\",\"additional_info\":{{\
+                 \"media_player\":\"Blue Music\",\"submission_client\":\"Blue Music\",\
+                 \"submission_client_version\":\"{}\",\"duration_ms\":562000,\
+                 \"tracknumber\":1}}}}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn describes_musicbrainz_ids() {
+        let mut tags = song();
+        tags.track_number = Some("A1".to_string());
+        tags.musicbrainz.recording = Some("r".to_string());
+        tags.musicbrainz.release = Some("l".to_string());
+        tags.musicbrainz.artists = vec!["a".to_string(), "b".to_string()];
+        let metadata = track_metadata(&track(tags, None)).unwrap();
+        assert!(metadata.ends_with(
+            "\"recording_mbid\":\"r\",\"release_mbid\":\"l\",\"artist_mbids\":[\"a\",\"b\"]}}"
+        ));
+        // Track numbers which are not numbers are left out, as are unknown
+        // durations.
+        assert!(!metadata.contains("tracknumber"));
+        assert!(!metadata.contains("duration_ms"));
+    }
+
+    #[test]
+    fn needs_artist_and_title() {
+        let untitled = Tags {
+            title: None,
+            ..song()
+        };
+        assert!(track_metadata(&track(untitled, None)).is_none());
+        let anonymous = Tags {
+            artists: Vec::new(),
+            ..song()
+        };
+        assert!(track_metadata(&track(anonymous, None)).is_none());
+    }
+
+    #[test]
+    fn quotes_strings() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(quote("1\n2\t3\u{1}"), "\"1\\n2\\t3\\u0001\"");
+        assert_eq!(quote("Sigur Rós"), "\"Sigur Rós\"");
+    }
+
+    #[test]
+    fn keeps_queue_in_file() {
+        let path = queue_file("queue");
+        assert!(read_queue(&path).is_empty());
+        append_queue(&path, "{\"listened_at\":1}");
+        append_queue(&path, "{\"listened_at\":2}");
+        assert_eq!(read_queue(&path), listens(3)[1..].to_vec());
+        write_queue(&path, &listens(1));
+        assert_eq!(read_queue(&path), listens(1));
+        write_queue(&path, &[]);
+        assert!(read_queue(&path).is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn submits_queue_in_batches() {
+        let path = queue_file("batches");
+        let mut queue = listens(BATCH_SIZE + 1);
+        write_queue(&path, &queue);
+        let (url, bodies) = serve(vec![200, 200]);
+        assert!(flush(&url, "token", &mut queue, &path));
+        assert!(queue.is_empty());
+        assert!(read_queue(&path).is_empty());
+        let bodies = bodies.lock().unwrap();
+        assert!(bodies[0].starts_with("{\"listen_type\":\"import\",\"payload\":[{"));
+        assert_eq!(bodies[0].matches("listened_at").count(), BATCH_SIZE);
+        assert_eq!(
+            bodies[1],
+            format!(
+                "{{\"listen_type\":\"single\",\"payload\":[{{\"listened_at\":{}}}]}}",
+                BATCH_SIZE
+            )
+        );
+    }
+
+    #[test]
+    fn drops_refused_listens() {
+        let path = queue_file("refused");
+        let mut queue = listens(2);
+        let (url, _) = serve(vec![400]);
+        assert!(flush(&url, "token", &mut queue, &path));
+        assert!(queue.is_empty());
+        assert!(read_queue(&path).is_empty());
+    }
+
+    #[test]
+    fn keeps_listens_while_unavailable() {
+        for status in &[401, 429, 503] {
+            let path = queue_file("unavailable");
+            let mut queue = listens(BATCH_SIZE + 1);
+            write_queue(&path, &queue);
+            let (url, _) = serve(vec![200, *status]);
+            assert!(!flush(&url, "token", &mut queue, &path));
+            assert_eq!(queue, listens(BATCH_SIZE + 1)[BATCH_SIZE..].to_vec());
+            assert_eq!(read_queue(&path), queue);
+        }
+    }
+
+    #[test]
+    fn keeps_listens_while_unreachable() {
+        let path = queue_file("unreachable");
+        let mut queue = listens(1);
+        // Nothing listens on the port once the listener is dropped.
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}{}", listener.local_addr().unwrap(), SUBMIT_PATH)
+        };
+        assert!(!flush(&url, "token", &mut queue, &path));
+        assert_eq!(queue, listens(1));
+    }
+}
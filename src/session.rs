@@ -0,0 +1,166 @@
+use crate::config;
+use crate::order::Repeat;
+
+const SESSION_FILE: &str = "session";
+
+/// What the player was showing and playing when it was closed, so that it can
+/// pick up where it left off.
+pub struct Session {
+    /// The title of the tab shown.
+    pub tab: Option<String>,
+    /// The title of the tab playing, and the song it played.
+    pub playing_tab: Option<String>,
+    pub song: Option<String>,
+    /// How far into the song playback was, in milliseconds.
+    pub position: u64,
+    pub paused: bool,
+    pub volume: f64,
+    pub repeat: Repeat,
+    pub shuffle: bool,
+    /// The size and then the position of the window.
+    pub geometry: Option<(i32, i32, i32, i32)>,
+    pub maximized: bool,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session {
+            tab: None,
+            playing_tab: None,
+            song: None,
+            position: 0,
+            paused: false,
+            volume: 1.0,
+            repeat: Repeat::Off,
+            shuffle: false,
+            geometry: None,
+            maximized: false,
+        }
+    }
+}
+
+impl Session {
+    /// Reads the saved session.
+    pub fn load() -> Session {
+        Session::parse(&config::read(SESSION_FILE).unwrap_or_default())
+    }
+
+    pub fn save(&self) {
+        config::write(SESSION_FILE, &self.serialize());
+    }
+
+    /// Reads a session written by `serialize`. Each line is a key followed by
+    /// its value, as in `position 81250`.
+    fn parse(contents: &str) -> Session {
+        let mut session = Session::default();
+        for line in contents.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "tab" => session.tab = Some(value.to_string()),
+                "playing-tab" => session.playing_tab = Some(value.to_string()),
+                "song" => session.song = Some(value.to_string()),
+                "position" => session.position = value.parse().unwrap_or(0),
+                "paused" => session.paused = value == "true",
+                "volume" => session.volume = value.parse().unwrap_or(1.0),
+                "repeat" => {
+                    session.repeat = match value {
+                        "track" => Repeat::Track,
+                        "playlist" => Repeat::Playlist,
+                        _ => Repeat::Off,
+                    }
+                }
+                "shuffle" => session.shuffle = value == "true",
+                "geometry" => {
+                    let numbers: Vec<i32> = value
+                        .split_whitespace()
+                        .filter_map(|number| number.parse().ok())
+                        .collect();
+                    if let [width, height, x, y] = numbers[..] {
+                        session.geometry = Some((width, height, x, y));
+                    }
+                }
+                "maximized" => session.maximized = value == "true",
+                _ => (),
+            }
+        }
+        session
+    }
+
+    fn serialize(&self) -> String {
+        let mut contents = String::new();
+        if let Some(ref tab) = self.tab {
+            contents.push_str(&format!("tab {}\n", tab));
+        }
+        if let (Some(tab), Some(song)) = (&self.playing_tab, &self.song) {
+            contents.push_str(&format!("playing-tab {}\nsong {}\n", tab, song));
+            contents.push_str(&format!(
+                "position {}\npaused {}\n",
+                self.position, self.paused
+            ));
+        }
+        contents.push_str(&format!("volume {}\n", self.volume));
+        contents.push_str(&format!(
+            "repeat {}\nshuffle {}\n",
+            self.repeat.name(),
+            self.shuffle
+        ));
+        if let Some((width, height, x, y)) = self.geometry {
+            contents.push_str(&format!("geometry {} {} {} {}\n", width, height, x, y));
+        }
+        contents.push_str(&format!("maximized {}\n", self.maximized));
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_read_back_as_saved() {
+        let session = Session {
+            tab: Some("Jazz".to_string()),
+            playing_tab: Some("Library".to_string()),
+            song: Some("/music/Kind of Blue/01 So What.flac".to_string()),
+            position: 81250,
+            paused: true,
+            volume: 0.5,
+            repeat: Repeat::Playlist,
+            shuffle: true,
+            geometry: Some((800, 600, -10, 20)),
+            maximized: true,
+        };
+        let restored = Session::parse(&session.serialize());
+        assert_eq!(restored.tab, session.tab);
+        assert_eq!(restored.playing_tab, session.playing_tab);
+        assert_eq!(restored.song, session.song);
+        assert_eq!(restored.position, 81250);
+        assert!(restored.paused);
+        assert_eq!(restored.volume, 0.5);
+        assert!(restored.repeat == Repeat::Playlist);
+        assert!(restored.shuffle);
+        assert_eq!(restored.geometry, Some((800, 600, -10, 20)));
+        assert!(restored.maximized);
+    }
+
+    #[test]
+    fn damaged_sessions_fall_back_to_defaults() {
+        let session = Session::parse("position soon\nvolume\ngeometry 800 600\nunknown 1\n");
+        assert_eq!(session.position, 0);
+        assert_eq!(session.volume, 1.0);
+        assert_eq!(session.geometry, None);
+        assert!(session.song.is_none());
+    }
+
+    #[test]
+    fn songs_are_only_restored_with_their_tab() {
+        let session = Session {
+            song: Some("/music/a.flac".to_string()),
+            position: 1000,
+            ..Session::default()
+        };
+        let restored = Session::parse(&session.serialize());
+        assert!(restored.song.is_none());
+        assert_eq!(restored.position, 0);
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::library::{self, Library, Track};
+use crate::playlist::format_duration;
+use crate::query::Query;
+
+/// Extension of the files smart playlists keep their rule in.
+pub const EXTENSION: &str = "smart";
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Tells whether `path` holds the rule of a smart playlist.
+pub fn is_smart(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some(EXTENSION)
+}
+
+/// Reads the rule of the smart playlist kept at `path`: a query such as
+/// `genre:jazz year:<1970`.
+pub fn read(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+pub fn write(path: &Path, rule: &str) -> io::Result<()> {
+    fs::write(path, format!("{}\n", rule))
+}
+
+/// Returns the songs of the library matching `rule`, in the order of the
+/// library.
+pub fn matching(library: &Library, rule: &str) -> Vec<Track> {
+    let query = Query::parse(rule);
+    let now = library::now();
+    library
+        .tracks()
+        .into_iter()
+        .filter(|track| query.matches(|name| field(track, name, now)))
+        .collect()
+}
+
+/// Returns a field of `track` as shown in the playlist, which is what queries
+/// compare.
+fn field(track: &Track, name: &str, now: i64) -> String {
+    match name {
+        "title" => track.title(),
+        "artist" => track.artist(),
+        "album" => track.album(),
+        "genre" => track.genre(),
+        "year" => track.year(),
+        "track" => track.track_number(),
+        "disc" => track.disc_number(),
+        "composer" => track.composer(),
+        "performer" => track.performer(),
+        "duration" => track.duration.map(format_duration).unwrap_or_default(),
+        "format" => "FLAC".to_string(),
+        "path" => track.path.clone(),
+        "playcount" => track.play_count.to_string(),
+        "rating" => track
+            .rating
+            .map(|rating| rating.to_string())
+            .unwrap_or_default(),
+        "skipcount" => track.skip_count.to_string(),
+        "added" => days_since(track.added, now),
+        "played" => track
+            .last_played
+            .map(|played| days_since(played, now))
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn days_since(time: i64, now: i64) -> String {
+    ((now - time).max(0) / SECONDS_PER_DAY).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 100 * SECONDS_PER_DAY;
+
+    fn song(genre: &str, year: &str) -> Track {
+        let mut track = Track::unread(Path::new("/music/song.flac"));
+        track.tags.genres = vec![genre.to_string()];
+        track.tags.date = Some(year.to_string());
+        track.added = NOW - 10 * SECONDS_PER_DAY;
+        track
+    }
+
+    fn matches(rule: &str, track: &Track) -> bool {
+        Query::parse(rule).matches(|name| field(track, name, NOW))
+    }
+
+    #[test]
+    fn rules_compare_the_fields_of_songs() {
+        let old = song("Jazz", "1959");
+        let new = song("Jazz", "1985");
+        assert!(matches("genre:jazz year:<1970", &old));
+        assert!(!matches("genre:jazz year:<1970", &new));
+        assert!(!matches("genre:rock", &old));
+    }
+
+    #[test]
+    fn rules_count_plays_ratings_and_days() {
+        let mut track = song("Jazz", "1959");
+        assert!(matches("playcount:0", &track));
+        assert!(matches("added:<30", &track));
+        assert!(!matches("rating:>=4", &track));
+        assert!(!matches("played:<7", &track));
+
+        track.play_count = 3;
+        track.rating = Some(4);
+        track.last_played = Some(NOW - 2 * SECONDS_PER_DAY);
+        track.added = 0;
+        assert!(!matches("playcount:0", &track));
+        assert!(!matches("added:<30", &track));
+        assert!(matches("rating:>=4", &track));
+        assert!(matches("played:<7", &track));
+    }
+}
@@ -0,0 +1,183 @@
+use glib::DateTime;
+
+/// Shown for each star of a rating.
+const STAR: char = '★';
+
+/// Songs count as played once this much of them has been heard, in
+/// milliseconds, even when they are longer than twice as much.
+const PLAYED_TIME: u64 = 4 * 60 * 1000;
+
+/// Largest step between two playback times still counted as listening rather
+/// than seeking, in milliseconds.
+const MAX_STEP: u64 = 2000;
+
+/// What a song being listened to just came to.
+#[derive(PartialEq)]
+pub enum Milestone {
+    /// The song was first heard.
+    Started,
+    /// The song counts as played.
+    Played,
+}
+
+/// A song being listened to. It counts as played once half of it, or four
+/// minutes of it, has been heard, and as skipped when left before that.
+pub struct Listen {
+    pub path: String,
+    /// When the song was first heard, in seconds since the Unix epoch.
+    pub started: Option<i64>,
+    /// The length of the song, in milliseconds.
+    duration: Option<u64>,
+    heard: u64,
+    last_time: u64,
+    played: bool,
+}
+
+impl Listen {
+    pub fn new(path: String, duration: Option<u64>) -> Listen {
+        Listen {
+            path,
+            started: None,
+            duration,
+            heard: 0,
+            last_time: 0,
+            played: false,
+        }
+    }
+
+    pub fn set_duration(&mut self, duration: u64) {
+        self.duration = Some(duration);
+    }
+
+    /// Notes that playback reached `time`, in milliseconds, at `now`, in
+    /// seconds since the Unix epoch. Tells when the song was first heard,
+    /// and once when it comes to count as played.
+    pub fn progress(&mut self, time: u64, now: i64) -> Option<Milestone> {
+        if time > self.last_time && time - self.last_time <= MAX_STEP {
+            self.heard += time - self.last_time;
+        }
+        self.last_time = time;
+        if self.started.is_none() {
+            self.started = Some(now);
+            return Some(Milestone::Started);
+        }
+        if self.played {
+            return None;
+        }
+        let needed = match self.duration {
+            Some(duration) if duration > 0 => (duration / 2).min(PLAYED_TIME),
+            _ => PLAYED_TIME,
+        };
+        self.played = self.heard >= needed;
+        if self.played {
+            Some(Milestone::Played)
+        } else {
+            None
+        }
+    }
+
+    /// Tells whether the song was left after being heard, but before it
+    /// counted as played.
+    pub fn skipped(&self) -> bool {
+        !self.played && self.heard > 0
+    }
+}
+
+/// Shows a rating as stars, or nothing for songs not rated.
+pub fn stars(rating: Option<u8>) -> String {
+    let count = rating.unwrap_or(0) as usize;
+    STAR.to_string().repeat(count)
+}
+
+/// Formats a time in seconds since the Unix epoch as a local date and time.
+pub fn format_time(time: i64) -> String {
+    DateTime::new_from_unix_local(time)
+        .format("%Y-%m-%d %H:%M")
+        .map(|text| text.to_string())
+        .unwrap_or_default()
+}
+
+/// Formats a time in milliseconds as minutes and seconds, as in `3:07`.
+pub fn millis_to_minutes(millis: u64) -> String {
+    let mut seconds = millis / 1_000;
+    let minutes = seconds / 60;
+    seconds %= 60;
+    format!("{}:{:02}", minutes, seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays `listen` from `from` to `to`, one second at a time, returning the
+    /// time at which it counted as played.
+    fn play(listen: &mut Listen, from: u64, to: u64) -> Option<u64> {
+        let mut played = None;
+        for time in (from..=to).step_by(1000) {
+            if listen.progress(time, 0) == Some(Milestone::Played) {
+                assert!(played.is_none(), "played twice");
+                played = Some(time);
+            }
+        }
+        played
+    }
+
+    #[test]
+    fn starts_once_heard() {
+        let mut listen = Listen::new("a.flac".to_string(), Some(60_000));
+        assert!(listen.progress(0, 100) == Some(Milestone::Started));
+        assert_eq!(listen.started, Some(100));
+        assert!(listen.progress(1000, 101).is_none());
+        assert_eq!(listen.started, Some(100));
+    }
+
+    #[test]
+    fn plays_short_songs_once_half_heard() {
+        let mut listen = Listen::new("a.flac".to_string(), Some(180_000));
+        assert_eq!(play(&mut listen, 0, 180_000), Some(90_000));
+        assert!(!listen.skipped());
+    }
+
+    #[test]
+    fn plays_long_songs_after_four_minutes() {
+        let mut listen = Listen::new("a.flac".to_string(), Some(600_000));
+        assert_eq!(play(&mut listen, 0, 600_000), Some(240_000));
+    }
+
+    #[test]
+    fn plays_songs_of_unknown_length_after_four_minutes() {
+        let mut listen = Listen::new("a.flac".to_string(), None);
+        assert_eq!(play(&mut listen, 0, 300_000), Some(240_000));
+        let mut listen = Listen::new("a.flac".to_string(), Some(0));
+        assert_eq!(play(&mut listen, 0, 300_000), Some(240_000));
+    }
+
+    #[test]
+    fn uses_duration_learnt_later() {
+        let mut listen = Listen::new("a.flac".to_string(), None);
+        assert_eq!(play(&mut listen, 0, 30_000), None);
+        listen.set_duration(100_000);
+        assert_eq!(play(&mut listen, 31_000, 100_000), Some(50_000));
+    }
+
+    #[test]
+    fn does_not_count_seeking() {
+        let mut listen = Listen::new("a.flac".to_string(), Some(180_000));
+        assert_eq!(play(&mut listen, 0, 10_000), None);
+        // Seeking forward and back is not listening.
+        assert_eq!(play(&mut listen, 170_000, 180_000), None);
+        assert_eq!(play(&mut listen, 0, 60_000), None);
+        assert!(listen.skipped());
+        assert_eq!(play(&mut listen, 61_000, 180_000), Some(70_000));
+    }
+
+    #[test]
+    fn skips_songs_left_early() {
+        let mut listen = Listen::new("a.flac".to_string(), Some(180_000));
+        assert!(!listen.skipped());
+        listen.progress(0, 0);
+        assert!(!listen.skipped());
+        play(&mut listen, 1000, 30_000);
+        assert!(listen.skipped());
+    }
+}
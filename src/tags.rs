@@ -0,0 +1,471 @@
+use metaflac::Tag;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::Path;
+
+/// Shown between the values of fields holding several, such as artists.
+pub const SEPARATOR: &str = "; ";
+
+/// Common alternatives to the recommended keys, read when the recommended one
+/// is missing and removed when the field is written.
+const ALIASES: [(&str, &str); 6] = [
+    ("ALBUMARTIST", "ALBUM ARTIST"),
+    ("DATE", "YEAR"),
+    ("ORIGINALDATE", "ORIGINALYEAR"),
+    ("TRACKTOTAL", "TOTALTRACKS"),
+    ("DISCTOTAL", "TOTALDISCS"),
+    ("COMMENT", "DESCRIPTION"),
+];
+
+/// Numbers and their totals, which are written together since a number may
+/// carry its total, as in "3/12".
+const NUMBERS: [(&str, &str); 2] = [("TRACKNUMBER", "TRACKTOTAL"), ("DISCNUMBER", "DISCTOTAL")];
+
+/// Ratings are written as FMPS_RATING, from 0 to 1, and also read from
+/// RATING, which players write either from 0 to 5 or from 0 to 100.
+const FMPS_RATING: &str = "FMPS_RATING";
+const RATING: &str = "RATING";
+
+/// The most stars a song can be rated.
+pub const MAX_RATING: u8 = 5;
+
+/// The MusicBrainz identifiers written by taggers such as Picard.
+#[derive(Clone, Default)]
+pub struct MusicBrainzIds {
+    pub recording: Option<String>,
+    pub release_track: Option<String>,
+    pub release: Option<String>,
+    pub release_group: Option<String>,
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+}
+
+/// The Vorbis comments of a song. Fields may hold several values, each stored
+/// as its own comment.
+#[derive(Clone, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub album_artists: Vec<String>,
+    pub genres: Vec<String>,
+    pub date: Option<String>,
+    pub original_date: Option<String>,
+    pub track_number: Option<String>,
+    pub track_total: Option<String>,
+    pub disc_number: Option<String>,
+    pub disc_total: Option<String>,
+    pub composers: Vec<String>,
+    pub performers: Vec<String>,
+    pub comments: Vec<String>,
+    pub musicbrainz: MusicBrainzIds,
+}
+
+impl Tags {
+    pub fn read(tag: &Tag) -> Tags {
+        Tags::from_comments(|key| tag.get_vorbis(key).cloned().unwrap_or_default())
+    }
+
+    /// Parses comments written by `Tags::serialize`.
+    pub fn parse(text: &str) -> Tags {
+        let mut comments: HashMap<String, Vec<String>> = HashMap::new();
+        for line in text.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                comments
+                    .entry(key.to_ascii_uppercase())
+                    .or_default()
+                    .push(unescape(value));
+            }
+        }
+        Tags::from_comments(|key| comments.get(key).cloned().unwrap_or_default())
+    }
+
+    /// Reads the fields from comments looked up by upper case key, falling
+    /// back to the aliases of the recommended keys.
+    fn from_comments<F: Fn(&str) -> Vec<String>>(get: F) -> Tags {
+        let all = |key: &'static str| -> Vec<String> {
+            keys(key)
+                .into_iter()
+                .map(&get)
+                .map(|values| {
+                    values
+                        .into_iter()
+                        .filter(|value| !value.trim().is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .find(|values| !values.is_empty())
+                .unwrap_or_default()
+        };
+        let first = |key| all(key).into_iter().next();
+
+        let (track_number, track_total) = split_number(first("TRACKNUMBER"));
+        let (disc_number, disc_total) = split_number(first("DISCNUMBER"));
+
+        Tags {
+            title: first("TITLE"),
+            artists: all("ARTIST"),
+            album: first("ALBUM"),
+            album_artists: all("ALBUMARTIST"),
+            genres: all("GENRE"),
+            date: first("DATE"),
+            original_date: first("ORIGINALDATE"),
+            track_number,
+            track_total: first("TRACKTOTAL").or(track_total),
+            disc_number,
+            disc_total: first("DISCTOTAL").or(disc_total),
+            composers: all("COMPOSER"),
+            performers: all("PERFORMER"),
+            comments: all("COMMENT"),
+            musicbrainz: MusicBrainzIds {
+                recording: first("MUSICBRAINZ_TRACKID"),
+                release_track: first("MUSICBRAINZ_RELEASETRACKID"),
+                release: first("MUSICBRAINZ_ALBUMID"),
+                release_group: first("MUSICBRAINZ_RELEASEGROUPID"),
+                artists: all("MUSICBRAINZ_ARTISTID"),
+                album_artists: all("MUSICBRAINZ_ALBUMARTISTID"),
+            },
+        }
+    }
+
+    /// Returns every field under its recommended key, empty fields included.
+    pub fn to_comments(&self) -> Vec<(&'static str, Vec<String>)> {
+        let one = |value: &Option<String>| value.iter().cloned().collect();
+        let ids = &self.musicbrainz;
+        vec![
+            ("TITLE", one(&self.title)),
+            ("ARTIST", self.artists.clone()),
+            ("ALBUM", one(&self.album)),
+            ("ALBUMARTIST", self.album_artists.clone()),
+            ("GENRE", self.genres.clone()),
+            ("DATE", one(&self.date)),
+            ("ORIGINALDATE", one(&self.original_date)),
+            ("TRACKNUMBER", one(&self.track_number)),
+            ("TRACKTOTAL", one(&self.track_total)),
+            ("DISCNUMBER", one(&self.disc_number)),
+            ("DISCTOTAL", one(&self.disc_total)),
+            ("COMPOSER", self.composers.clone()),
+            ("PERFORMER", self.performers.clone()),
+            ("COMMENT", self.comments.clone()),
+            ("MUSICBRAINZ_TRACKID", one(&ids.recording)),
+            ("MUSICBRAINZ_RELEASETRACKID", one(&ids.release_track)),
+            ("MUSICBRAINZ_ALBUMID", one(&ids.release)),
+            ("MUSICBRAINZ_RELEASEGROUPID", one(&ids.release_group)),
+            ("MUSICBRAINZ_ARTISTID", ids.artists.clone()),
+            ("MUSICBRAINZ_ALBUMARTISTID", ids.album_artists.clone()),
+        ]
+    }
+
+    /// Writes the fields as `KEY=value` lines, one per value.
+    pub fn serialize(&self) -> String {
+        let mut text = String::new();
+        for (key, values) in self.to_comments() {
+            for value in values {
+                text.push_str(&format!("{}={}\n", key, escape(&value)));
+            }
+        }
+        text
+    }
+
+    /// Returns the year of the release date.
+    pub fn year(&self) -> Option<&str> {
+        self.date.as_ref().map(|date| date.get(..4).unwrap_or(date))
+    }
+}
+
+/// Writes `tags` to the FLAC file at `path`, changing only the comments that
+/// differ from those in the file.
+pub fn write(path: &Path, tags: &Tags) -> metaflac::Result<()> {
+    modify(path, |tag| update(tag, tags))
+}
+
+/// Applies `change` to the metadata of the FLAC file at `path`. The changes
+/// are saved to a copy which then replaces the file, so that it is never left
+/// half written.
+pub fn modify<F: FnOnce(&mut Tag)>(path: &Path, change: F) -> metaflac::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let copy = path.with_file_name(format!(".{}.tmp", name));
+    let result = modify_copy(path, &copy, change);
+    if result.is_err() {
+        let _ = fs::remove_file(&copy);
+    }
+    result
+}
+
+fn modify_copy<F: FnOnce(&mut Tag)>(path: &Path, copy: &Path, change: F) -> metaflac::Result<()> {
+    fs::copy(path, copy)?;
+    let mut tag = Tag::read_from_path(copy)?;
+    change(&mut tag);
+    tag.save()?;
+    File::open(copy)?.sync_all()?;
+    fs::rename(copy, path)?;
+    Ok(())
+}
+
+fn update(tag: &mut Tag, tags: &Tags) {
+    let current = Tags::read(tag).to_comments();
+    let mut changed: HashSet<&str> = tags
+        .to_comments()
+        .iter()
+        .zip(current.iter())
+        .filter(|(new, old)| new.1 != old.1)
+        .map(|(new, _)| new.0)
+        .collect();
+    for (number, total) in NUMBERS.iter() {
+        if changed.contains(number) || changed.contains(total) {
+            changed.insert(number);
+            changed.insert(total);
+        }
+    }
+
+    for (key, values) in tags.to_comments() {
+        if !changed.contains(key) {
+            continue;
+        }
+        for key in keys(key) {
+            tag.remove_vorbis(key);
+        }
+        if !values.is_empty() {
+            tag.set_vorbis(key, values);
+        }
+    }
+}
+
+/// Reads the rating of a song, in stars.
+pub fn rating(tag: &Tag) -> Option<u8> {
+    let value = |key| {
+        tag.get_vorbis(key)?
+            .first()?
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|value| *value >= 0.0)
+    };
+    let stars = match (value(FMPS_RATING), value(RATING)) {
+        (Some(fraction), _) => fraction.min(1.0) * f64::from(MAX_RATING),
+        (None, Some(stars)) if stars <= f64::from(MAX_RATING) => stars,
+        (None, Some(percent)) => percent.min(100.0) / 100.0 * f64::from(MAX_RATING),
+        (None, None) => return None,
+    };
+    Some(stars.round() as u8)
+}
+
+/// Writes the rating of a song in stars, or removes it.
+pub fn set_rating(tag: &mut Tag, rating: Option<u8>) {
+    tag.remove_vorbis(FMPS_RATING);
+    tag.remove_vorbis(RATING);
+    if let Some(stars) = rating {
+        let fraction = f64::from(stars.min(MAX_RATING)) / f64::from(MAX_RATING);
+        tag.set_vorbis(FMPS_RATING, vec![fraction.to_string()]);
+    }
+}
+
+/// Returns a recommended key followed by its aliases.
+fn keys(key: &'static str) -> Vec<&'static str> {
+    let aliases = ALIASES
+        .iter()
+        .filter(|(recommended, _)| *recommended == key)
+        .map(|(_, alias)| *alias);
+    std::iter::once(key).chain(aliases).collect()
+}
+
+/// Joins the values of a field for display, or returns `None` without any.
+pub fn join(values: &[String]) -> Option<String> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(SEPARATOR))
+    }
+}
+
+/// Splits "3/12" into a number and a total.
+fn split_number(value: Option<String>) -> (Option<String>, Option<String>) {
+    match value {
+        Some(value) => match value.split_once('/') {
+            Some((number, total)) => {
+                let total = total.trim();
+                let total = if total.is_empty() {
+                    None
+                } else {
+                    Some(total.to_string())
+                };
+                (Some(number.trim().to_string()), total)
+            }
+            None => (Some(value.trim().to_string()), None),
+        },
+        None => (None, None),
+    }
+}
+
+/// Keeps values on a single line, as comments may span several.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn serialized_tags_parse_back() {
+        let tags = Tags {
+            title: some("Blue in Green"),
+            artists: vec!["Miles Davis".to_string(), "Bill Evans".to_string()],
+            date: some("1959-03-02"),
+            track_number: some("3"),
+            track_total: some("5"),
+            comments: vec!["First line\nsecond line with a \\".to_string()],
+            musicbrainz: MusicBrainzIds {
+                release: some("a1b2"),
+                ..MusicBrainzIds::default()
+            },
+            ..Tags::default()
+        };
+
+        let text = tags.serialize();
+        assert!(text.contains("ARTIST=Miles Davis\nARTIST=Bill Evans\n"));
+        assert!(text.contains("COMMENT=First line\\nsecond line with a \\\\\n"));
+
+        let parsed = Tags::parse(&text);
+        assert_eq!(parsed.to_comments(), tags.to_comments());
+    }
+
+    #[test]
+    fn keys_ignore_case_and_fall_back_to_aliases() {
+        let tags = Tags::parse("title=So What\nYEAR=1959\nALBUM ARTIST=Miles Davis\n");
+        assert_eq!(tags.title, some("So What"));
+        assert_eq!(tags.date, some("1959"));
+        assert_eq!(tags.album_artists, vec!["Miles Davis".to_string()]);
+
+        let tags = Tags::parse("DATE=1997\nYEAR=1959\n");
+        assert_eq!(tags.date, some("1997"));
+    }
+
+    #[test]
+    fn blank_values_are_dropped() {
+        let tags = Tags::parse("TITLE=  \nGENRE=\nGENRE=Jazz\nnot a comment\n");
+        assert_eq!(tags.title, None);
+        assert_eq!(tags.genres, vec!["Jazz".to_string()]);
+    }
+
+    #[test]
+    fn numbers_carry_their_totals() {
+        let tags = Tags::parse("TRACKNUMBER=3/12\nDISCNUMBER=1\n");
+        assert_eq!(tags.track_number, some("3"));
+        assert_eq!(tags.track_total, some("12"));
+        assert_eq!(tags.disc_number, some("1"));
+        assert_eq!(tags.disc_total, None);
+
+        // An explicit total wins over the one carried by the number.
+        let tags = Tags::parse("TRACKNUMBER=3/12\nTOTALTRACKS=13\n");
+        assert_eq!(tags.track_total, some("13"));
+    }
+
+    #[test]
+    fn split_number_trims_and_drops_empty_totals() {
+        assert_eq!(split_number(None), (None, None));
+        assert_eq!(split_number(some("7")), (some("7"), None));
+        assert_eq!(split_number(some(" 4 / 10 ")), (some("4"), some("10")));
+        assert_eq!(split_number(some("4/")), (some("4"), None));
+        assert_eq!(split_number(some("/10")), (some(""), some("10")));
+    }
+
+    #[test]
+    fn unescape_keeps_unknown_escapes() {
+        assert_eq!(unescape("a\\nb"), "a\nb");
+        assert_eq!(unescape("a\\\\nb"), "a\\nb");
+        assert_eq!(unescape("a\\tb"), "atb");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn year_is_taken_from_the_date() {
+        let mut tags = Tags::default();
+        assert_eq!(tags.year(), None);
+        tags.date = some("1959-08-17");
+        assert_eq!(tags.year(), Some("1959"));
+        tags.date = some("59");
+        assert_eq!(tags.year(), Some("59"));
+    }
+
+    #[test]
+    fn ratings_read_every_scale() {
+        let rated = |key: &str, value: &str| {
+            let mut tag = Tag::new();
+            tag.set_vorbis(key, vec![value]);
+            rating(&tag)
+        };
+        assert_eq!(rated(FMPS_RATING, "0.6"), Some(3));
+        assert_eq!(rated(FMPS_RATING, "2"), Some(5));
+        assert_eq!(rated(RATING, "4"), Some(4));
+        assert_eq!(rated(RATING, "80"), Some(4));
+        assert_eq!(rated(RATING, "-1"), None);
+        assert_eq!(rated(RATING, "good"), None);
+        assert_eq!(rating(&Tag::new()), None);
+    }
+
+    #[test]
+    fn ratings_are_written_as_fractions() {
+        let mut tag = Tag::new();
+        tag.set_vorbis(RATING, vec!["100"]);
+        set_rating(&mut tag, Some(4));
+        assert_eq!(tag.get_vorbis(RATING), None);
+        assert_eq!(rating(&tag), Some(4));
+        set_rating(&mut tag, None);
+        assert_eq!(rating(&tag), None);
+    }
+
+    /// Returns a file in a folder of its own holding `contents`.
+    fn song_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("blue-music-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("song.flac");
+        fs::write(&file, contents).unwrap();
+        file
+    }
+
+    #[test]
+    fn modified_files_keep_their_audio() {
+        // A lone STREAMINFO block, followed by what stands for the frames.
+        let mut contents = b"fLaC\x80\x00\x00\x22".to_vec();
+        contents.extend(&[0; 34]);
+        contents.extend(b"frames");
+        let file = song_file("modify", &contents);
+        modify(&file, |tag| tag.set_vorbis("TITLE", vec!["So What"])).unwrap();
+
+        let tag = Tag::read_from_path(&file).unwrap();
+        assert_eq!(Tags::read(&tag).title, Some("So What".to_string()));
+        assert!(fs::read(&file).unwrap().ends_with(b"frames"));
+        assert_eq!(fs::read_dir(file.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_modifications_leave_the_file_untouched() {
+        let file = song_file("modify-failed", b"not a FLAC file");
+        assert!(modify(&file, |tag| tag.set_vorbis("TITLE", vec!["So What"])).is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"not a FLAC file");
+        // The copy the changes were to be saved to is gone.
+        assert_eq!(fs::read_dir(file.parent().unwrap()).unwrap().count(), 1);
+    }
+}
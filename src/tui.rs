@@ -0,0 +1,328 @@
+//! Shows the player run headless in the terminal: the song playing, a seek
+//! bar and the playlist, driven from the keyboard.
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, stdin, stdout, Read, Seek, SeekFrom, Stdout, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::{Rc, Weak};
+use std::thread;
+
+use glib::{Continue, MainLoop};
+use relm::Channel;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::AlternateScreen;
+use termion::{clear, cursor, style};
+
+use crate::config;
+use crate::engine::Engine;
+use crate::stats::millis_to_minutes;
+
+/// Where errors are written while the terminal shows the player, as they
+/// would otherwise be drawn over it.
+const LOG_FILE: &str = "errors.log";
+
+/// How often the screen is drawn again as the song plays, in milliseconds.
+const REFRESH_INTERVAL: u32 = 250;
+
+/// How far the arrow keys seek, in milliseconds.
+const SEEK_STEP: i64 = 5000;
+const VOLUME_STEP: f64 = 0.05;
+
+/// The lines above the playlist: the title, the artist and album, the seek
+/// bar, the settings and a rule.
+const HEADER_LINES: u16 = 5;
+/// The line below the playlist, listing the keys.
+const FOOTER_LINES: u16 = 1;
+
+const HELP: &str = "enter play  space pause  x stop  n/p next/previous  \u{2190}/\u{2192} seek  \
+                    +/- volume  r repeat  s shuffle  a queue  d remove  c current  q quit";
+
+/// The terminal the player is shown in, given back as it was once dropped.
+pub struct Screen {
+    engine: Rc<RefCell<Engine>>,
+    /// The song played last, which the cursor follows when it changes.
+    followed: Option<usize>,
+    log: Log,
+    /// What went wrong last, shown until the next key is pressed.
+    message: String,
+    output: AlternateScreen<RawTerminal<Stdout>>,
+    /// How many songs the playlist showed when last drawn.
+    rows: usize,
+    /// The first song shown.
+    scroll: usize,
+    /// The song the cursor is on.
+    selected: usize,
+}
+
+impl Screen {
+    /// Takes over the terminal, showing `message` first. Pressing `q` quits
+    /// `main_loop`.
+    pub fn start(
+        engine: Rc<RefCell<Engine>>,
+        main_loop: MainLoop,
+        message: String,
+    ) -> io::Result<Rc<RefCell<Screen>>> {
+        let log = Log::start()?;
+        let output = AlternateScreen::from(stdout().into_raw_mode()?);
+        let screen = Rc::new(RefCell::new(Screen {
+            engine,
+            followed: None,
+            log,
+            message,
+            output,
+            rows: 0,
+            scroll: 0,
+            selected: 0,
+        }));
+        screen.borrow_mut().draw();
+
+        // The callbacks let the screen go, so that the terminal is given back
+        // once the player quits.
+        let weak = Rc::downgrade(&screen);
+        let (_channel, keys) = Channel::new(move |key| {
+            if let Some(screen) = weak.upgrade() {
+                if !screen.borrow_mut().key(key) {
+                    main_loop.quit();
+                }
+            }
+        });
+        thread::spawn(move || {
+            for key in stdin().keys().flatten() {
+                if keys.send(key).is_err() {
+                    return;
+                }
+            }
+        });
+        let weak: Weak<RefCell<Screen>> = Rc::downgrade(&screen);
+        glib::timeout_add_local(REFRESH_INTERVAL, move || match weak.upgrade() {
+            Some(screen) => {
+                screen.borrow_mut().draw();
+                Continue(true)
+            }
+            None => Continue(false),
+        });
+        Ok(screen)
+    }
+
+    /// Does what `key` stands for, returning `false` when it quits.
+    fn key(&mut self, key: Key) -> bool {
+        self.message.clear();
+        let mut engine = self.engine.borrow_mut();
+        let report = engine.report();
+        let (repeat, shuffle, volume) = (report.repeat, report.shuffle, report.volume);
+        let last = engine.songs().len().saturating_sub(1);
+        let page = self.rows.max(1);
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => return false,
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.selected = (self.selected + 1).min(last),
+            Key::PageUp => self.selected = self.selected.saturating_sub(page),
+            Key::PageDown => self.selected = (self.selected + page).min(last),
+            Key::Home | Key::Char('g') => self.selected = 0,
+            Key::End | Key::Char('G') => self.selected = last,
+            Key::Char('c') => self.selected = engine.current().unwrap_or(self.selected),
+            Key::Char('\n') => engine.play_index(self.selected),
+            Key::Char(' ') => engine.play_pause(),
+            Key::Char('x') => engine.stop(),
+            Key::Char('n') => engine.next(),
+            Key::Char('p') => engine.previous(),
+            Key::Left => engine.seek_by(-SEEK_STEP),
+            Key::Right => engine.seek_by(SEEK_STEP),
+            Key::Char('+') | Key::Char('=') => engine.set_volume(volume + VOLUME_STEP),
+            Key::Char('-') => engine.set_volume(volume - VOLUME_STEP),
+            Key::Char('r') => engine.set_repeat(repeat.next()),
+            Key::Char('s') => engine.set_shuffle(!shuffle),
+            Key::Char('a') => engine.queue(self.selected),
+            Key::Char('d') | Key::Delete => engine.remove(self.selected),
+            _ => return true,
+        }
+        drop(engine);
+        self.draw();
+        true
+    }
+
+    fn draw(&mut self) {
+        if let Some(error) = self.log.last_error() {
+            self.message = error;
+        }
+        if let Err(error) = self.try_draw() {
+            eprintln!("Unable to draw the player: {}", error);
+        }
+    }
+
+    fn try_draw(&mut self) -> io::Result<()> {
+        let (width, height) = termion::terminal_size()?;
+        let width = width as usize;
+        let engine = self.engine.borrow();
+        let report = engine.report();
+        let songs = engine.songs();
+
+        if engine.current() != self.followed {
+            self.followed = engine.current();
+            self.selected = self.followed.unwrap_or(self.selected);
+        }
+        self.selected = self.selected.min(songs.len().saturating_sub(1));
+        self.rows = height.saturating_sub(HEADER_LINES + FOOTER_LINES) as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.rows > 0 && self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+
+        let mut lines = Vec::new();
+        match report.track {
+            Some(track) => {
+                let title = fit(&track.title(), width);
+                lines.push(format!("{}{}{}", style::Bold, title, style::Reset));
+                lines.push(fit(
+                    &format!("{} \u{2014} {}", track.artist(), track.album()),
+                    width,
+                ));
+            }
+            None => {
+                lines.push("Not playing".to_string());
+                lines.push(String::new());
+            }
+        }
+        lines.push(seek_bar(
+            report.state,
+            report.position,
+            report.duration,
+            width,
+        ));
+        let settings = format!(
+            "Volume {}%  Repeat {}  Shuffle {}  {}",
+            (report.volume * 100.0).round(),
+            report.repeat.name(),
+            if report.shuffle { "on" } else { "off" },
+            self.message
+        );
+        lines.push(fit(&settings, width));
+        lines.push("\u{2500}".repeat(width));
+
+        let shown = songs.iter().enumerate().skip(self.scroll).take(self.rows);
+        for (index, track) in shown {
+            // The song playing is marked, and those queued numbered.
+            let queued = engine.queued().iter().position(|&queued| queued == index);
+            let marker = match engine.current() {
+                Some(current) if current == index && report.state != "stopped" => {
+                    "\u{25b6} ".to_string()
+                }
+                _ => match queued {
+                    Some(position) => format!("{:<2}", position + 1),
+                    None => "  ".to_string(),
+                },
+            };
+            let duration = track
+                .duration
+                .map(|duration| millis_to_minutes(duration * 1000))
+                .unwrap_or_default();
+            let name = format!("{}{} \u{2014} {}", marker, track.title(), track.artist());
+            let room = width.saturating_sub(duration.chars().count() + 1);
+            let line = format!("{} {}", fit(&name, room), duration);
+            if index == self.selected {
+                lines.push(format!("{}{}{}", style::Invert, line, style::Reset));
+            } else {
+                lines.push(line);
+            }
+        }
+
+        let mut text = format!("{}{}", cursor::Hide, clear::All);
+        for (number, line) in lines.iter().enumerate() {
+            text.push_str(&format!("{}{}", cursor::Goto(1, number as u16 + 1), line));
+        }
+        text.push_str(&format!("{}{}", cursor::Goto(1, height), fit(HELP, width)));
+        self.output.write_all(text.as_bytes())?;
+        self.output.flush()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = write!(self.output, "{}", cursor::Show);
+        let _ = self.output.flush();
+    }
+}
+
+/// Sends what is written to the standard error to the log file until
+/// dropped, keeping track of the errors written since last read.
+struct Log {
+    /// The log file, read from where it was last read.
+    file: File,
+    /// The standard error as it was, given back once dropped.
+    saved: RawFd,
+}
+
+impl Log {
+    fn start() -> io::Result<Log> {
+        let path = config::data_dir().join(LOG_FILE);
+        let output = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::End(0))?;
+        let saved = unsafe { libc::dup(libc::STDERR_FILENO) };
+        if saved < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO) } < 0 {
+            let error = io::Error::last_os_error();
+            unsafe { libc::close(saved) };
+            return Err(error);
+        }
+        Ok(Log { file, saved })
+    }
+
+    /// Returns the last error written since last asked, if any.
+    fn last_error(&mut self) -> Option<String> {
+        let mut written = String::new();
+        self.file.read_to_string(&mut written).ok()?;
+        written
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map(str::to_string)
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dup2(self.saved, libc::STDERR_FILENO);
+            libc::close(self.saved);
+        }
+    }
+}
+
+/// Cuts `text` to `width` characters, padding it with spaces when shorter.
+fn fit(text: &str, width: usize) -> String {
+    let mut text: String = text.chars().take(width).collect();
+    let count = text.chars().count();
+    text.extend(std::iter::repeat_n(' ', width - count));
+    text
+}
+
+/// Shows how far the song is, as in `▶ 1:23 [=====-----] 4:56`.
+fn seek_bar(state: &str, position: u64, duration: u64, width: usize) -> String {
+    let icon = match state {
+        "playing" => "\u{25b6}",
+        "paused" => "\u{2016}",
+        _ => "\u{25a0}",
+    };
+    let elapsed = millis_to_minutes(position);
+    let total = millis_to_minutes(duration);
+    let room = width.saturating_sub(elapsed.len() + total.len() + 6);
+    let done = match duration {
+        0 => 0,
+        _ => (room as u64 * position.min(duration) / duration) as usize,
+    };
+    format!(
+        "{} {} [{}{}] {}",
+        icon,
+        elapsed,
+        "=".repeat(done),
+        "-".repeat(room - done),
+        total
+    )
+}